@@ -1,58 +1,96 @@
 //! Wallet Manager implementation with BDK
-use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use bdk::bitcoin::absolute::LockTime;
 use bdk::bitcoin::bip32::ExtendedPrivKey;
-use bdk::bitcoin::consensus::serialize;
-use bdk::bitcoin::{Amount, ScriptBuf};
+use bdk::bitcoin::blockdata::constants::genesis_block;
+use bdk::bitcoin::consensus::{deserialize as bdk_deserialize, serialize};
+use bdk::bitcoin::hashes::Hash as _;
+use bdk::bitcoin::{Amount, ScriptBuf, Sequence, Txid};
+use bdk::chain::local_chain::{self, CheckPoint};
+use bdk::chain::{BlockId, ChainPosition, ConfirmationTime};
 use bdk::keys::bip39::{Language, Mnemonic, WordCount};
 use bdk::keys::GeneratableKey;
 use bdk::keys::{DerivableKey, ExtendedKey, GeneratedKey};
 use bdk::template::Bip84;
 use bdk::wallet::{ChangeSet, Update};
 use bdk::{FeeRate, KeychainKind, SignOptions, Wallet};
+use bdk_esplora::esplora_client::BlockingClient;
 use bdk_esplora::EsploraExt;
 use bdk_file_store::Store;
 
-use lampo_common::bitcoin::consensus::deserialize;
+use lampo_common::backend::{Backend, BackendKind, BlockData};
+use lampo_common::bitcoin::consensus::{deserialize, serialize as lampo_serialize};
 use lampo_common::bitcoin::hashes::hex::ToHex;
-use lampo_common::bitcoin::{PrivateKey, Script, Transaction};
-use lampo_common::conf::{LampoConf, Network};
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::bitcoin::{Block, PrivateKey, Script, Transaction};
+use lampo_common::conf::{LampoConf, Network, DEFAULT_USER_AGENT};
 use lampo_common::error;
 use lampo_common::keys::LampoKeys;
-use lampo_common::model::response::{NewAddress, Utxo};
-use lampo_common::wallet::WalletManager;
+use lampo_common::model::response::{Balance, Coin, NewAddress, Utxo};
+use lampo_common::types::Keychain;
+use lampo_common::utils::network;
+use lampo_common::wallet::{
+    hex_entropy, TransactionOptions, WalletError, WalletManager, WalletTransaction,
+};
+
+/// The network of bdk with the same genesis block of `network`.
+fn bdk_network(network: Network) -> Result<bdk::bitcoin::Network, bdk::Error> {
+    let genesis = network::genesis_block_hash(network).to_byte_array();
+    [
+        bdk::bitcoin::Network::Bitcoin,
+        bdk::bitcoin::Network::Testnet,
+        bdk::bitcoin::Network::Signet,
+        bdk::bitcoin::Network::Regtest,
+    ]
+    .into_iter()
+    .find(|bdk_network| genesis_block(*bdk_network).block_hash().to_byte_array() == genesis)
+    .ok_or(bdk::Error::Generic(format!(
+        "network `{network}` not supported"
+    )))
+}
 
 pub struct BDKWalletManager {
-    pub wallet: RefCell<Mutex<Wallet<Store<'static, ChangeSet>>>>,
+    pub wallet: Mutex<Wallet<Store<'static, ChangeSet>>>,
     pub keymanager: Arc<LampoKeys>,
     pub network: Network,
+    /// When the backend is bitcoind, the wallet is synced
+    /// through it instead of esplora.
+    pub backend: Option<Arc<dyn Backend + Send + Sync>>,
+    /// The `User-Agent` header sent to the esplora server.
+    pub user_agent: String,
+    /// The esplora server chosen by the user with `esplora-url`.
+    pub esplora_url: Option<String>,
+    /// Unix timestamp of the last sync, zero if never synced.
+    last_sync: AtomicU64,
+    /// A `SyncWorker` keeps the wallet in sync, so the
+    /// reads do not scan the chain on their own.
+    background_sync: AtomicBool,
+    /// The index of the next external address after a reset,
+    /// while it is below the last one revealed by bdk.
+    reset_index: Mutex<Option<u32>>,
 }
 
-// SAFETY: It is safe to do because the `LampoWalletManager`
-// is not send and sync due the RefCell, but we use the Mutex
-// inside, so we are safe to share across threads.
-unsafe impl Send for BDKWalletManager {}
-unsafe impl Sync for BDKWalletManager {}
-
 impl BDKWalletManager {
     /// from mnemonic_words build or bkd::Wallet or return an bdk::Error
     fn build_wallet(
         conf: Arc<LampoConf>,
         mnemonic_words: &str,
     ) -> Result<(Wallet<Store<'static, ChangeSet>>, LampoKeys), bdk::Error> {
-        // Parse a mnemonic
-        let mnemonic =
-            Mnemonic::parse(mnemonic_words).map_err(|err| bdk::Error::Generic(format!("{err}")))?;
+        // Parse a mnemonic, or the hex of its entropy
+        let entropy =
+            hex_entropy(mnemonic_words).map_err(|err| bdk::Error::Generic(format!("{err}")))?;
+        let mnemonic = match entropy {
+            Some(entropy) => Mnemonic::from_entropy(&entropy),
+            None => Mnemonic::parse(mnemonic_words),
+        }
+        .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
         // Generate the extended key
         let xkey: ExtendedKey = mnemonic.into_extended_key()?;
-        let network = match conf.network.to_string().as_str() {
-            "bitcoin" => bdk::bitcoin::Network::Bitcoin,
-            "testnet" => bdk::bitcoin::Network::Testnet,
-            "signet" => bdk::bitcoin::Network::Signet,
-            "regtest" => bdk::bitcoin::Network::Regtest,
-            _ => unreachable!(),
-        };
+        let network = bdk_network(conf.network)?;
         // Get xprv from the extended key
         let xprv = xkey.into_xprv(network).ok_or(bdk::Error::Generic(
             "wrong convertion to a private key".to_string(),
@@ -63,7 +101,8 @@ impl BDKWalletManager {
             format!("{}/onchain", conf.path()),
         )
         .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
-        let ldk_keys = LampoKeys::new(xprv.private_key.secret_bytes());
+        let ldk_keys = LampoKeys::from_secret(xprv.private_key.secret_bytes())
+            .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
         // Create a BDK wallet structure using BIP 84 descriptor ("m/84h/1h/0h/0" and "m/84h/1h/0h/1")
         let wallet = Wallet::new(
             Bip84(xprv, KeychainKind::External),
@@ -82,28 +121,257 @@ impl BDKWalletManager {
         xprv: PrivateKey,
         channel_keys: Option<String>,
     ) -> Result<(Wallet<Store<'static, ChangeSet>>, LampoKeys), bdk::Error> {
+        LampoKeys::validate_secret(&xprv.inner.secret_bytes())
+            .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
         let ldk_keys = if channel_keys.is_some() {
             LampoKeys::with_channel_keys(xprv.inner.secret_bytes(), channel_keys.unwrap())
         } else {
             LampoKeys::new(xprv.inner.secret_bytes())
         };
 
-        // FIXME: Get a tmp path
-        let db = Store::new_from_path("lampo".as_bytes(), "/tmp/onchain")
+        // A store for each key and network, so the wallets do not collide.
+        let public_key = xprv.public_key(&lampo_common::secp256k1::Secp256k1::new());
+        let path =
+            std::env::temp_dir().join(format!("lampo-{}-{public_key}-onchain", xprv.network));
+        let db = Store::new_from_path("lampo".as_bytes(), path)
             .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
-        let network = match xprv.network.to_string().as_str() {
-            "bitcoin" => bdk::bitcoin::Network::Bitcoin,
-            "testnet" => bdk::bitcoin::Network::Testnet,
-            "signet" => bdk::bitcoin::Network::Signet,
-            "regtest" => bdk::bitcoin::Network::Regtest,
-            _ => unreachable!(),
-        };
+        let network = bdk_network(xprv.network)?;
         let key = ExtendedPrivKey::new_master(network, &xprv.inner.secret_bytes())?;
         let key = ExtendedKey::from(key);
         let wallet = Wallet::new(Bip84(key, KeychainKind::External), None, db, network)
             .map_err(|err| bdk::Error::Generic(err.to_string()))?;
         Ok((wallet, ldk_keys))
     }
+
+    /// Use the `backend` to sync the wallet when it is a bitcoind
+    /// node, otherwise esplora is still used.
+    pub fn with_backend(mut self, backend: Arc<dyn Backend + Send + Sync>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Sync the wallet by scanning the blocks served by the bitcoind
+    /// backend, from the last block known by the wallet to the tip.
+    // FIXME: this is not handling the reorgs.
+    fn sync_with_backend(&self, backend: &Arc<dyn Backend + Send + Sync>) -> error::Result<()> {
+        let mut wallet = self.wallet.lock().unwrap();
+        let last_height = wallet
+            .latest_checkpoint()
+            .map(|checkpoint| checkpoint.height());
+        let (tip_hash, tip_height) = backend.get_best_block()?;
+        let Some(tip_height) = tip_height else {
+            error::bail!("backend do not know the tip height");
+        };
+        log::info!("bdk start to sync with bitcoind");
+
+        // walk back from the tip up to the last block known by the wallet.
+        let mut blocks = Vec::new();
+        let (mut hash, mut height) = (tip_hash, tip_height);
+        while last_height.map_or(true, |last| height > last) {
+            let BlockData::FullBlock(block) = backend.get_block(&hash)? else {
+                error::bail!("backend returned the block `{hash}` without transactions");
+            };
+            hash = block.header.prev_blockhash;
+            blocks.push((height, block));
+            if height == 0 {
+                break;
+            }
+            height -= 1;
+        }
+
+        for (height, block) in blocks.into_iter().rev() {
+            let hash = bdk_deserialize(&lampo_serialize(&block.block_hash()))?;
+            wallet
+                .insert_checkpoint(BlockId { height, hash })
+                .map_err(|err| error::anyhow!("{err:?}"))?;
+            Self::insert_block_txs(&mut wallet, height, &block)?;
+        }
+        wallet.commit()?;
+        log::info!("bdk in sync at height {tip_height}!");
+        Ok(())
+    }
+
+    /// Insert inside the wallet the transactions of the block
+    /// that pay to us or that spend our outputs.
+    fn insert_block_txs(
+        wallet: &mut Wallet<Store<'static, ChangeSet>>,
+        height: u32,
+        block: &Block,
+    ) -> error::Result<()> {
+        for tx in block.txdata.iter() {
+            let tx: bdk::bitcoin::Transaction = bdk_deserialize(&lampo_serialize(tx))?;
+            let is_relevant = tx
+                .output
+                .iter()
+                .any(|output| wallet.is_mine(&output.script_pubkey))
+                || tx
+                    .input
+                    .iter()
+                    .any(|input| wallet.get_utxo(input.previous_output).is_some());
+            if !is_relevant {
+                continue;
+            }
+            let confirmation = ConfirmationTime::Confirmed {
+                height,
+                time: block.header.time as u64,
+            };
+            wallet
+                .insert_tx(tx, confirmation)
+                .map_err(|err| error::anyhow!("{err:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// Compare the blocks of the wallet with the chain of the bitcoind
+    /// backend, and replace the ones removed by a reorg, so the
+    /// transactions inside them are dropped from the wallet.
+    ///
+    /// This is as expensive as the first sync, because the blocks are
+    /// compared from the tip down to the first block of the wallet.
+    fn rescan_with_backend(&self, backend: &Arc<dyn Backend + Send + Sync>) -> error::Result<()> {
+        let mut wallet = self.wallet.lock().unwrap();
+        let known = wallet
+            .latest_checkpoint()
+            .map(|tip| {
+                tip.iter()
+                    .map(|checkpoint| (checkpoint.height(), checkpoint.hash()))
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .unwrap_or_default();
+        let Some(lowest) = known.keys().next().copied() else {
+            drop(wallet);
+            return self.sync_with_backend(backend);
+        };
+        let (tip_hash, tip_height) = backend.get_best_block()?;
+        let Some(tip_height) = tip_height else {
+            error::bail!("backend do not know the tip height");
+        };
+
+        let mut blocks = Vec::new();
+        let (mut hash, mut height) = (tip_hash, tip_height);
+        loop {
+            let BlockData::FullBlock(block) = backend.get_block(&hash)? else {
+                error::bail!("backend returned the block `{hash}` without transactions");
+            };
+            hash = block.header.prev_blockhash;
+            let block_hash: bdk::bitcoin::BlockHash =
+                bdk_deserialize(&lampo_serialize(&block.block_hash()))?;
+            blocks.push((
+                BlockId {
+                    height,
+                    hash: block_hash,
+                },
+                block,
+            ));
+            if height <= lowest {
+                break;
+            }
+            height -= 1;
+        }
+        blocks.reverse();
+
+        let diverged = blocks.iter().position(|(block_id, _)| {
+            known
+                .get(&block_id.height)
+                .is_some_and(|hash| *hash != block_id.hash)
+        });
+        let Some(diverged) = diverged else {
+            drop(wallet);
+            return self.sync_with_backend(backend);
+        };
+        if diverged == 0 {
+            error::bail!("the first block of the wallet is not inside the chain of the backend");
+        }
+        log::warn!(
+            "the wallet diverged from the backend at height {}, replacing the blocks above it",
+            blocks[diverged].0.height
+        );
+        let tip = CheckPoint::from_block_ids(blocks[diverged - 1..].iter().map(|(id, _)| *id))
+            .map_err(|_| error::anyhow!("the blocks of the backend are not in order"))?;
+        wallet.apply_update(Update {
+            chain: Some(local_chain::Update {
+                tip,
+                introduce_older_blocks: true,
+            }),
+            ..Default::default()
+        })?;
+        for (block_id, block) in blocks[diverged..].iter() {
+            Self::insert_block_txs(&mut wallet, block_id.height, block)?;
+        }
+        wallet.commit()?;
+        log::info!("bdk rescanned up to height {tip_height}!");
+        Ok(())
+    }
+
+    /// Move back the next derivation index of the `keychain` to `to`,
+    /// refusing to do it if an address above `to` already received funds.
+    pub fn reset_address_index(&self, keychain: KeychainKind, to: u32) -> error::Result<()> {
+        let mut wallet = self.wallet.lock().unwrap();
+        // Every transaction is looked at, also the ones
+        // whose outputs are already spent.
+        let funded = wallet
+            .transactions()
+            .flat_map(|canonical| canonical.tx_node.tx.output.iter())
+            .filter_map(|output| wallet.derivation_of_spk(&output.script_pubkey))
+            .filter(|(kind, _)| *kind == keychain)
+            .map(|(_, index)| index)
+            .max();
+        if let Some(funded) = funded {
+            if funded >= to {
+                error::bail!(
+                    "impossible reset the {:?} keychain to index `{to}`, the address at index `{funded}` already received funds",
+                    keychain
+                );
+            }
+        }
+        let next_index = wallet
+            .derivation_index(keychain)
+            .map_or(0, |index| index + 1);
+        if to > next_index {
+            error::bail!(
+                "impossible reset the {:?} keychain to index `{to}`, bdk can not skip the addresses from index `{next_index}`",
+                keychain
+            );
+        }
+        if to == next_index {
+            if keychain == KeychainKind::External {
+                *self.reset_index.lock().unwrap() = None;
+            }
+            return Ok(());
+        }
+        let last_revealed = next_index - 1;
+        match keychain {
+            // bdk can not rewind the revealed index, so the addresses
+            // from `to` are peeked again by `get_onchain_address`.
+            KeychainKind::External => *self.reset_index.lock().unwrap() = Some(to),
+            // The change is sent to the first unused address, so
+            // the range is marked as unused.
+            KeychainKind::Internal => {
+                for index in to..=last_revealed {
+                    wallet.unmark_used(keychain, index);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the next derivation index of the external and internal
+    /// keychains, useful to see if the funds are above the gap limit.
+    pub fn address_indices(&self) -> error::Result<(u32, u32)> {
+        let wallet = self.wallet.lock().unwrap();
+        let next_index = |keychain| {
+            wallet
+                .derivation_index(keychain)
+                .map(|index| index + 1)
+                .unwrap_or_default()
+        };
+        let external = self
+            .reset_index
+            .lock()
+            .unwrap()
+            .unwrap_or(next_index(KeychainKind::External));
+        Ok((external, next_index(KeychainKind::Internal)))
+    }
 }
 
 impl WalletManager for BDKWalletManager {
@@ -118,9 +386,15 @@ impl WalletManager for BDKWalletManager {
         let (wallet, keymanager) = BDKWalletManager::build_wallet(conf.clone(), &mnemonic_words)?;
         Ok((
             Self {
-                wallet: RefCell::new(Mutex::new(wallet)),
+                wallet: Mutex::new(wallet),
                 keymanager: Arc::new(keymanager),
                 network: conf.network,
+                backend: None,
+                user_agent: conf.esplora_user_agent.clone(),
+                esplora_url: conf.esplora_url.clone(),
+                last_sync: AtomicU64::new(0),
+                background_sync: AtomicBool::new(false),
+                reset_index: Mutex::new(None),
             },
             mnemonic_words,
         ))
@@ -129,9 +403,15 @@ impl WalletManager for BDKWalletManager {
     fn restore(conf: Arc<LampoConf>, mnemonic_words: &str) -> error::Result<Self> {
         let (wallet, keymanager) = BDKWalletManager::build_wallet(conf.clone(), mnemonic_words)?;
         Ok(Self {
-            wallet: RefCell::new(Mutex::new(wallet)),
+            wallet: Mutex::new(wallet),
             keymanager: Arc::new(keymanager),
             network: conf.network,
+            backend: None,
+            user_agent: conf.esplora_user_agent.clone(),
+            esplora_url: conf.esplora_url.clone(),
+            last_sync: AtomicU64::new(0),
+            background_sync: AtomicBool::new(false),
+            reset_index: Mutex::new(None),
         })
     }
 
@@ -140,76 +420,457 @@ impl WalletManager for BDKWalletManager {
     }
 
     fn get_onchain_address(&self) -> error::Result<NewAddress> {
-        let address = self
-            .wallet
-            .borrow_mut()
-            .lock()
-            .unwrap()
-            .get_address(bdk::wallet::AddressIndex::New);
+        let mut wallet = self.wallet.lock().unwrap();
+        let mut reset_index = self.reset_index.lock().unwrap();
+        let last_revealed = wallet.derivation_index(KeychainKind::External);
+        let address = match *reset_index {
+            Some(index) if last_revealed.map_or(false, |last| index <= last) => {
+                *reset_index = Some(index + 1);
+                wallet.get_address(bdk::wallet::AddressIndex::Peek(index))
+            }
+            _ => {
+                *reset_index = None;
+                wallet.get_address(bdk::wallet::AddressIndex::New)
+            }
+        };
+        // Persist the revealed index, so it is restored after a restart.
+        wallet.commit()?;
         Ok(NewAddress {
             address: address.address.to_string(),
+            label: None,
         })
     }
 
+    fn reset_address_index(&self, keychain: Keychain, index: u32) -> error::Result<()> {
+        let keychain = match keychain {
+            Keychain::External => KeychainKind::External,
+            Keychain::Internal => KeychainKind::Internal,
+        };
+        BDKWalletManager::reset_address_index(self, keychain, index)
+    }
+
+    fn address_indices(&self) -> error::Result<(u32, u32)> {
+        BDKWalletManager::address_indices(self)
+    }
+
     fn get_onchain_balance(&self) -> error::Result<u64> {
-        self.sync()?;
-        let balance = self.wallet.borrow().lock().unwrap().get_balance();
+        self.sync_before_read()?;
+        let balance = self.wallet.lock().unwrap().get_balance();
         Ok(balance.confirmed)
     }
 
+    fn balance_by_keychain(&self) -> error::Result<(Balance, Balance)> {
+        self.sync_before_read()?;
+        let wallet = self.wallet.lock().unwrap();
+        let (mut external, mut internal) = (Balance::default(), Balance::default());
+        for utxo in wallet.list_unspent().filter(|utxo| !utxo.is_spent) {
+            let balance = match utxo.keychain {
+                KeychainKind::External => &mut external,
+                KeychainKind::Internal => &mut internal,
+            };
+            match utxo.confirmation_time {
+                ConfirmationTime::Confirmed { .. } => balance.confirmed_sat += utxo.txout.value,
+                ConfirmationTime::Unconfirmed { .. } => balance.unconfirmed_sat += utxo.txout.value,
+            }
+        }
+        Ok((external, internal))
+    }
+
     fn create_transaction(
         &self,
         script: Script,
         amount: u64,
         fee_rate: u32,
-    ) -> error::Result<Transaction> {
-        self.sync()?;
-        let wallet = self.wallet.borrow_mut();
-        let mut wallet = wallet.lock().unwrap();
-        let mut tx = wallet.build_tx();
-        tx.add_recipient(ScriptBuf::from_bytes(script.into_bytes()), amount)
-            .fee_rate(FeeRate::from_sat_per_kvb(fee_rate as f32))
-            .enable_rbf();
-        let mut psbt = tx.finish()?;
-        if !wallet.sign(&mut psbt, SignOptions::default())? {
-            error::bail!("wallet not able to sing the psbt {psbt}");
-        }
-        if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
-            error::bail!("wallet impossible finalize the psbt: {psbt}");
-        };
-        let tx: Transaction = deserialize(&serialize(&psbt.extract_tx()))?;
-        Ok(tx)
+        options: TransactionOptions,
+    ) -> Result<Transaction, WalletError> {
+        options.validate()?;
+        self.sync_before_read()?;
+        Ok(self.build_transaction(script, amount, fee_rate, options)?)
+    }
+
+    fn consolidate(
+        &self,
+        fee_rate: u32,
+        max_inputs: Option<usize>,
+    ) -> Result<Transaction, WalletError> {
+        self.sync_before_read()?;
+        Ok(self.build_consolidation(fee_rate, max_inputs)?)
     }
 
     fn list_transactions(&self) -> error::Result<Vec<Utxo>> {
-        self.sync()?;
-        let wallet = self.wallet.borrow();
-        let wallet = wallet.lock().unwrap();
+        self.sync_before_read()?;
+        let wallet = self.wallet.lock().unwrap();
+        let confirmations = confirmations(&wallet);
         let txs = wallet
             .list_unspent()
             .map(|tx| Utxo {
                 txid: tx.outpoint.txid.to_hex(),
                 vout: tx.outpoint.vout,
                 reserved: tx.is_spent,
-                confirmed: 0,
+                confirmed: confirmations
+                    .get(&tx.outpoint.txid)
+                    .copied()
+                    .unwrap_or_default(),
                 amount_msat: Amount::from_btc(tx.txout.value as f64).unwrap().to_sat() * 1000_u64,
             })
             .collect::<Vec<_>>();
         Ok(txs)
     }
 
-    fn sync(&self) -> error::Result<()> {
-        // Scanning the chain...
-        let esplora_url = match self.network {
+    fn list_coins(&self) -> error::Result<Vec<Coin>> {
+        self.sync_before_read()?;
+        let wallet = self.wallet.lock().unwrap();
+        let confirmations = confirmations(&wallet);
+        // The wallet derives the addresses with the BIP 84 templates.
+        let coin_type = if self.network == Network::Bitcoin {
+            0
+        } else {
+            1
+        };
+        let coins = wallet
+            .list_unspent()
+            .map(|utxo| {
+                let (keychain, change) = match utxo.keychain {
+                    KeychainKind::External => (Keychain::External, 0),
+                    KeychainKind::Internal => (Keychain::Internal, 1),
+                };
+                Coin {
+                    txid: utxo.outpoint.txid.to_hex(),
+                    vout: utxo.outpoint.vout,
+                    reserved: utxo.is_spent,
+                    confirmed: confirmations
+                        .get(&utxo.outpoint.txid)
+                        .copied()
+                        .unwrap_or_default(),
+                    amount_msat: utxo.txout.value * 1000,
+                    address: bdk::bitcoin::Address::from_script(
+                        &utxo.txout.script_pubkey,
+                        wallet.network(),
+                    )
+                    .ok()
+                    .map(|address| address.to_string()),
+                    derivation_path: Some(format!(
+                        "m/84h/{coin_type}h/0h/{change}/{}",
+                        utxo.derivation_index
+                    )),
+                    keychain: Some(keychain),
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(coins)
+    }
+
+    fn transaction_history(&self) -> error::Result<Vec<WalletTransaction>> {
+        self.sync_before_read()?;
+        let wallet = self.wallet.lock().unwrap();
+        // Our outputs are always created by one of our transactions.
+        let mut our_txos = BTreeMap::new();
+        for canonical in wallet.transactions() {
+            let tx: &bdk::bitcoin::Transaction = &canonical.tx_node.tx;
+            for (vout, output) in tx.output.iter().enumerate() {
+                if wallet.is_mine(&output.script_pubkey) {
+                    our_txos.insert((tx.txid(), vout as u32), output.value);
+                }
+            }
+        }
+        let mut history = Vec::new();
+        for canonical in wallet.transactions() {
+            let tx: &bdk::bitcoin::Transaction = &canonical.tx_node.tx;
+            let height = match canonical.chain_position {
+                ChainPosition::Confirmed(anchor) => Some(anchor.confirmation_height),
+                ChainPosition::Unconfirmed(_) => None,
+            };
+            let spent = tx
+                .input
+                .iter()
+                .map(|input| {
+                    let prevout = input.previous_output;
+                    our_txos.get(&(prevout.txid, prevout.vout)).copied()
+                })
+                .collect::<Vec<_>>();
+            history.push(WalletTransaction {
+                tx: deserialize(&serialize(tx))?,
+                height,
+                our_inputs: spent.iter().map(Option::is_some).collect(),
+                our_outputs: tx
+                    .output
+                    .iter()
+                    .map(|output| wallet.is_mine(&output.script_pubkey))
+                    .collect(),
+                sent_sat: spent.iter().flatten().sum(),
+            });
+        }
+        Ok(history)
+    }
+
+    fn sync(&self) -> Result<(), WalletError> {
+        match self.backend.as_ref() {
+            Some(backend) if matches!(backend.kind(), BackendKind::Core) => {
+                self.sync_with_backend(backend).map_err(WalletError::Sync)?
+            }
+            _ => self.sync_with_esplora().map_err(WalletError::Sync)?,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(WalletError::other)?
+            .as_secs();
+        self.last_sync.store(now, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn last_sync(&self) -> Option<u64> {
+        let last_sync = self.last_sync.load(Ordering::SeqCst);
+        (last_sync > 0).then_some(last_sync)
+    }
+
+    fn rescan(&self) -> Result<(), WalletError> {
+        match self.backend.as_ref() {
+            Some(backend) if matches!(backend.kind(), BackendKind::Core) => self
+                .rescan_with_backend(backend)
+                .map_err(WalletError::Sync)?,
+            // The esplora sync already replaces the blocks removed by a reorg.
+            _ => return self.sync(),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(WalletError::other)?
+            .as_secs();
+        self.last_sync.store(now, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn set_background_sync(&self, enabled: bool) {
+        self.background_sync.store(enabled, Ordering::SeqCst);
+    }
+}
+
+impl BDKWalletManager {
+    /// Scan the chain before reading the wallet, unless
+    /// the background worker already keeps it in sync.
+    fn sync_before_read(&self) -> Result<(), WalletError> {
+        if self.background_sync.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.sync()
+    }
+
+    /// Build and sign the transaction that pays `amount` to `script`.
+    fn build_transaction(
+        &self,
+        script: Script,
+        amount: u64,
+        fee_rate: u32,
+        options: TransactionOptions,
+    ) -> error::Result<Transaction> {
+        let mut wallet = self.wallet.lock().unwrap();
+        // Our change is always on the internal keychain, so any other
+        // unconfirmed output was received from someone else.
+        let mut unspendable = wallet
+            .list_unspent()
+            .filter(|utxo| matches!(utxo.confirmation_time, ConfirmationTime::Unconfirmed { .. }))
+            .filter(|utxo| !options.allow_unconfirmed || utxo.keychain != KeychainKind::Internal)
+            .map(|utxo| utxo.outpoint)
+            .collect::<Vec<_>>();
+        for utxo in options.excluded_utxos.iter() {
+            unspendable.push(bdk_deserialize(&lampo_serialize(utxo))?);
+        }
+        let selected = options
+            .utxos
+            .iter()
+            .map(|utxo| -> error::Result<_> {
+                let outpoint: bdk::bitcoin::OutPoint = bdk_deserialize(&lampo_serialize(utxo))?;
+                match wallet.get_utxo(outpoint) {
+                    Some(local) if !local.is_spent => Ok(outpoint),
+                    Some(_) => Err(WalletError::InvalidOptions(format!(
+                        "the output `{utxo}` is reserved"
+                    ))
+                    .into()),
+                    None => Err(WalletError::InvalidOptions(format!(
+                        "the output `{utxo}` is not a spendable output of the wallet"
+                    ))
+                    .into()),
+                }
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        // BDK can not take the fee from a recipient, so the coins are
+        // selected without fee, and the recipient pays the fee of the
+        // transaction that spends them.
+        let (amount, fee, selected) = if options.subtract_fee_from_amount {
+            let mut tx = wallet.build_tx();
+            tx.unspendable(unspendable.clone())
+                .add_recipient(ScriptBuf::from_bytes(script.to_bytes()), amount)
+                .fee_absolute(0);
+            if !selected.is_empty() {
+                tx.add_utxos(&selected)?.manually_selected_only();
+            }
+            let mut psbt = tx.finish().map_err(fund_error)?;
+            let inputs = psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|input| input.previous_output)
+                .collect::<Vec<_>>();
+            // Signed, so the weight includes the witnesses.
+            wallet.sign(&mut psbt, SignOptions::default())?;
+            let vsize = (psbt.extract_tx().weight().to_wu() + 3) / 4;
+            let fee = fee_rate as u64 * vsize / 1000;
+            let dust = script.dust_value().to_sat();
+            if amount < fee + dust {
+                return Err(WalletError::InvalidOptions(format!(
+                    "the amount of {amount} sats minus the fee of {fee} sats is below the dust limit of {dust} sats"
+                ))
+                .into());
+            }
+            (amount - fee, Some(fee), inputs)
+        } else {
+            (amount, None, selected)
+        };
+        let mut tx = wallet.build_tx();
+        tx.unspendable(unspendable)
+            .add_recipient(ScriptBuf::from_bytes(script.into_bytes()), amount);
+        match fee {
+            Some(fee) => tx.fee_absolute(fee),
+            None => tx.fee_rate(FeeRate::from_sat_per_kvb(fee_rate as f32)),
+        };
+        if !selected.is_empty() {
+            // The outputs selected by the user are the only ones spent.
+            tx.add_utxos(&selected)?.manually_selected_only();
+        }
+        if let Some(address) = &options.change_address {
+            // With a recipient the drain output is the change.
+            tx.drain_to(ScriptBuf::from_bytes(address.script_pubkey().to_bytes()));
+        }
+        if options.rbf {
+            tx.enable_rbf();
+        }
+        if let Some(locktime) = options.locktime {
+            tx.nlocktime(LockTime::from_consensus(locktime));
+        }
+        let mut psbt = tx.finish().map_err(fund_error)?;
+        // The sequence is set on every input chosen by
+        // the coin selection, before signing.
+        if let Some(sequence) = options.input_sequence() {
+            psbt.unsigned_tx
+                .input
+                .iter_mut()
+                .for_each(|input| input.sequence = Sequence(sequence.0));
+        }
+        if !wallet.sign(&mut psbt, SignOptions::default())? {
+            return Err(WalletError::Signing(error::anyhow!(
+                "wallet not able to sing the psbt {psbt}"
+            ))
+            .into());
+        }
+        if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
+            return Err(WalletError::Signing(error::anyhow!(
+                "wallet impossible finalize the psbt: {psbt}"
+            ))
+            .into());
+        };
+        let tx = psbt.extract_tx();
+        // BDK pays the change to the first unused address of the change
+        // keychain, and it is unused until the transaction is synced, so
+        // we mark it as used to not reuse it in the next transaction.
+        for output in tx.output.iter() {
+            if let Some((keychain, index)) = wallet.derivation_of_spk(&output.script_pubkey) {
+                log::debug!(
+                    "change sent to the {:?} keychain at index `{index}`",
+                    keychain
+                );
+                wallet.mark_used(keychain, index);
+            }
+        }
+        // Persist the revealed index of the change keychain.
+        wallet.commit()?;
+        let tx: Transaction = deserialize(&serialize(&tx))?;
+        Ok(tx)
+    }
+
+    /// Build and sign the transaction that spends the
+    /// smallest confirmed outputs into a fresh address.
+    fn build_consolidation(
+        &self,
+        fee_rate: u32,
+        max_inputs: Option<usize>,
+    ) -> error::Result<Transaction> {
+        let mut wallet = self.wallet.lock().unwrap();
+        let mut utxos = wallet
+            .list_unspent()
+            .filter(|utxo| !utxo.is_spent)
+            .filter(|utxo| matches!(utxo.confirmation_time, ConfirmationTime::Confirmed { .. }))
+            .collect::<Vec<_>>();
+        utxos.sort_by_key(|utxo| utxo.txout.value);
+        if let Some(max_inputs) = max_inputs {
+            utxos.truncate(max_inputs);
+        }
+        if utxos.is_empty() {
+            return Err(WalletError::InsufficientFunds(
+                "there are no confirmed outputs to consolidate".to_owned(),
+            )
+            .into());
+        }
+        let outpoints = utxos.iter().map(|utxo| utxo.outpoint).collect::<Vec<_>>();
+        let script = wallet
+            .get_address(bdk::wallet::AddressIndex::New)
+            .address
+            .script_pubkey();
+        let mut tx = wallet.build_tx();
+        tx.add_utxos(&outpoints)?
+            .manually_selected_only()
+            .drain_to(script)
+            .fee_rate(FeeRate::from_sat_per_vb(fee_rate as f32 / 250.0));
+        let mut psbt = tx.finish().map_err(fund_error)?;
+        if !wallet.sign(&mut psbt, SignOptions::default())? {
+            return Err(WalletError::Signing(error::anyhow!(
+                "wallet not able to sing the psbt {psbt}"
+            ))
+            .into());
+        }
+        if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
+            return Err(WalletError::Signing(error::anyhow!(
+                "wallet impossible finalize the psbt: {psbt}"
+            ))
+            .into());
+        };
+        // Persist the revealed index of the consolidation address.
+        wallet.commit()?;
+        let tx: Transaction = deserialize(&serialize(&psbt.extract_tx()))?;
+        Ok(tx)
+    }
+
+    /// Build the esplora client that identifies itself
+    /// with the configured `User-Agent`.
+    fn esplora_client(&self, url: &str) -> error::Result<BlockingClient> {
+        let client = bdk_esplora::esplora_client::Builder::new(url)
+            .header("User-Agent", &self.user_agent)
+            .build_blocking()?;
+        Ok(client)
+    }
+
+    /// The esplora server configured by the user, otherwise
+    /// the mempool.space one for bitcoin and testnet.
+    fn esplora_url(&self) -> error::Result<String> {
+        if let Some(url) = self.esplora_url.as_ref() {
+            return Ok(url.clone());
+        }
+        let url = match self.network {
             Network::Bitcoin => "https://mempool.space/api",
             Network::Testnet => "https://mempool.space/testnet/api",
             _ => {
-                error::bail!("network `{:?}` not supported", self.network);
+                error::bail!(
+                    "there is no default esplora server for the network `{}`, set one with `esplora-url`",
+                    self.network
+                );
             }
         };
-        let wallet = self.wallet.borrow();
-        let mut wallet = wallet.lock().unwrap();
-        let client = bdk_esplora::esplora_client::Builder::new(esplora_url).build_blocking()?;
+        Ok(url.to_owned())
+    }
+
+    fn sync_with_esplora(&self) -> error::Result<()> {
+        // Scanning the chain...
+        let esplora_url = self.esplora_url()?;
+        let mut wallet = self.wallet.lock().unwrap();
+        let client = self.esplora_client(&esplora_url)?;
         let checkpoints = wallet.latest_checkpoint();
         let spks = wallet
             .spks_of_all_keychains()
@@ -257,21 +918,58 @@ impl TryFrom<(PrivateKey, Option<String>)> for BDKWalletManager {
     fn try_from(value: (PrivateKey, Option<String>)) -> Result<Self, Self::Error> {
         let (wallet, keymanager) = BDKWalletManager::build_from_private_key(value.0, value.1)?;
         Ok(Self {
-            wallet: RefCell::new(Mutex::new(wallet)),
+            wallet: Mutex::new(wallet),
             keymanager: Arc::new(keymanager),
             // This should be possible only during integration testing
             // FIXME: fix the sync method in bdk, the esplora client will crash!
             network: Network::Regtest,
+            backend: None,
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            esplora_url: None,
+            last_sync: AtomicU64::new(0),
+            background_sync: AtomicBool::new(false),
+            reset_index: Mutex::new(None),
         })
     }
 }
 
+/// How many confirmations the transactions of `wallet` have at
+/// its tip, the unconfirmed transactions are not inside the map.
+fn confirmations(wallet: &Wallet<Store<'static, ChangeSet>>) -> HashMap<Txid, u32> {
+    let Some(tip_height) = wallet.latest_checkpoint().map(|tip| tip.height()) else {
+        return HashMap::new();
+    };
+    wallet
+        .transactions()
+        .filter_map(|canonical| match canonical.chain_position {
+            ChainPosition::Confirmed(anchor) => Some((
+                canonical.tx_node.txid,
+                (tip_height + 1).saturating_sub(anchor.confirmation_height),
+            )),
+            ChainPosition::Unconfirmed(_) => None,
+        })
+        .collect()
+}
+
+/// Tell apart the coin selection of BDK that has
+/// not enough funds from the other failures.
+fn fund_error(err: bdk::Error) -> WalletError {
+    match err {
+        bdk::Error::InsufficientFunds { needed, available } => WalletError::InsufficientFunds(
+            format!("needed {needed} sats, available {available} sats"),
+        ),
+        err => WalletError::other(err),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
+    use bdk::KeychainKind;
     use lampo_common::bitcoin;
     use lampo_common::bitcoin::PrivateKey;
+    use lampo_common::conf::DEFAULT_USER_AGENT;
     use lampo_common::secp256k1::SecretKey;
 
     use super::{BDKWalletManager, WalletManager};
@@ -288,4 +986,460 @@ mod tests {
         let wallet = wallet.unwrap();
         assert!(wallet.get_onchain_address().is_ok());
     }
+
+    #[test]
+    fn onchain_address_is_encoded() {
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000009")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let wallet = BDKWalletManager::try_from((pkey, None)).unwrap();
+        let address = wallet.get_onchain_address().unwrap().address;
+        // A bech32 address of regtest, not the debug output of the script.
+        assert!(address.starts_with("bcrt1"), "{address}");
+        let parsed = bitcoin::Address::from_str(&address).unwrap();
+        assert!(parsed.require_network(bitcoin::Network::Regtest).is_ok());
+    }
+
+    #[test]
+    fn restore_from_hex_entropy() {
+        use std::sync::Arc;
+
+        use lampo_common::conf::{LampoConf, Network};
+
+        let restore = |seed: &str, name: &str| {
+            let root = std::env::temp_dir().join(format!("lampo-{name}-{}", std::process::id()));
+            std::fs::create_dir_all(root.join("regtest")).unwrap();
+            let conf = LampoConf {
+                network: Network::Regtest,
+                root_path: root.to_string_lossy().into_owned(),
+                ..Default::default()
+            };
+            BDKWalletManager::restore(Arc::new(conf), seed)
+        };
+        let from_entropy = restore("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f", "entropy").unwrap();
+        let from_words = restore(
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+            "words",
+        )
+        .unwrap();
+        assert_eq!(
+            from_entropy.get_onchain_address().unwrap().address,
+            from_words.get_onchain_address().unwrap().address
+        );
+        assert!(restore(&"7f".repeat(20), "short-entropy").is_err());
+    }
+
+    #[test]
+    fn restore_reuses_the_derivation_index() {
+        use std::sync::Arc;
+
+        use lampo_common::conf::{LampoConf, Network};
+
+        let root = std::env::temp_dir().join(format!("lampo-reopen-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("regtest")).unwrap();
+        let conf = Arc::new(LampoConf {
+            network: Network::Regtest,
+            root_path: root.to_string_lossy().into_owned(),
+            ..Default::default()
+        });
+        let words = "legal winner thank year wave sausage worth useful legal winner thank yellow";
+        let wallet = BDKWalletManager::restore(conf.clone(), words).unwrap();
+        let mut last = None;
+        for _ in 0..3 {
+            last = Some(wallet.get_onchain_address().unwrap().address);
+        }
+        let indices = wallet.address_indices().unwrap();
+        drop(wallet);
+
+        // The wallet is opened again from the store inside the data directory.
+        let wallet = BDKWalletManager::restore(conf, words).unwrap();
+        assert_eq!(wallet.address_indices().unwrap(), indices);
+        let next = wallet.get_onchain_address().unwrap().address;
+        assert_ne!(Some(next), last);
+        assert_eq!(wallet.address_indices().unwrap().0, indices.0 + 1);
+    }
+
+    #[test]
+    fn esplora_url_from_the_conf() {
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000008")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let mut wallet = BDKWalletManager::try_from((pkey, None)).unwrap();
+        // There is no default esplora server for regtest.
+        let err = wallet.esplora_url().unwrap_err();
+        assert!(err.to_string().contains("`regtest`"), "{err}");
+        assert!(err.to_string().contains("esplora-url"), "{err}");
+
+        wallet.esplora_url = Some("http://127.0.0.1:3002".to_owned());
+        assert_eq!(wallet.esplora_url().unwrap(), "http://127.0.0.1:3002");
+    }
+
+    #[test]
+    fn address_indices_advance_per_keychain() {
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000003")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let wallet = BDKWalletManager::try_from((pkey, None)).unwrap();
+        let (external, internal) = wallet.address_indices().unwrap();
+        for _ in 0..3 {
+            wallet.get_onchain_address().unwrap();
+        }
+        assert_eq!(wallet.address_indices().unwrap(), (external + 3, internal));
+    }
+
+    #[test]
+    fn reset_address_index_returns_the_same_addresses() {
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("000000000000000000000000000000000000000000000000000000000000000a")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let wallet = BDKWalletManager::try_from((pkey, None)).unwrap();
+        let (next, _) = wallet.address_indices().unwrap();
+        let addresses = (0..3)
+            .map(|_| wallet.get_onchain_address().unwrap().address)
+            .collect::<Vec<_>>();
+
+        wallet
+            .reset_address_index(KeychainKind::External, next + 1)
+            .unwrap();
+        assert_eq!(wallet.address_indices().unwrap().0, next + 1);
+        assert_eq!(wallet.get_onchain_address().unwrap().address, addresses[1]);
+        assert_eq!(wallet.get_onchain_address().unwrap().address, addresses[2]);
+        // After the reused range the addresses are fresh again.
+        let fresh = wallet.get_onchain_address().unwrap().address;
+        assert!(!addresses.contains(&fresh));
+        assert!(wallet
+            .reset_address_index(KeychainKind::External, next + 10)
+            .is_err());
+    }
+
+    #[test]
+    fn over_budget_transaction_is_insufficient_funds() {
+        use lampo_common::wallet::{TransactionOptions, WalletError};
+
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000006")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let wallet = BDKWalletManager::try_from((pkey, None)).unwrap();
+        let script = wallet
+            .wallet
+            .lock()
+            .unwrap()
+            .get_address(bdk::wallet::AddressIndex::New)
+            .address
+            .script_pubkey();
+        // The wallet was never funded, so nothing can be spent.
+        let err = wallet
+            .build_transaction(
+                lampo_common::bitcoin::ScriptBuf::from_bytes(script.to_bytes()),
+                100_000,
+                1000,
+                TransactionOptions::default(),
+            )
+            .unwrap_err();
+        let err = WalletError::from(err);
+        assert!(matches!(err, WalletError::InsufficientFunds(_)), "{err:?}");
+    }
+
+    #[test]
+    fn concurrent_wallet_calls() {
+        use std::collections::HashSet;
+        use std::sync::Arc;
+
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000005")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let wallet = Arc::new(BDKWalletManager::try_from((pkey, None)).unwrap());
+        let (external, internal) = wallet.address_indices().unwrap();
+        let workers = (0..8)
+            .map(|_| {
+                let wallet = wallet.clone();
+                std::thread::spawn(move || {
+                    (0..4)
+                        .map(|_| {
+                            let address = wallet.get_onchain_address().unwrap().address;
+                            wallet.address_indices().unwrap();
+                            address
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let addresses = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect::<HashSet<_>>();
+        // Every call revealed a different address.
+        assert_eq!(addresses.len(), 32);
+        assert_eq!(wallet.address_indices().unwrap(), (external + 32, internal));
+    }
+
+    #[test]
+    fn esplora_client_sends_the_user_agent() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        // A mock esplora server that answers a single request
+        // and gives back what it received.
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let size = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n\r\n101")
+                .unwrap();
+            String::from_utf8_lossy(&request[..size]).to_lowercase()
+        });
+
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000004")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let mut wallet = BDKWalletManager::try_from((pkey, None)).unwrap();
+        assert_eq!(wallet.user_agent, DEFAULT_USER_AGENT);
+        wallet.user_agent = "lampo-test/1.0".to_owned();
+        let height = wallet.esplora_client(&url).unwrap().get_height().unwrap();
+        assert_eq!(height, 101);
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("get /blocks/tip/height"), "{request}");
+        assert!(
+            request.contains("user-agent: lampo-test/1.0\r\n"),
+            "{request}"
+        );
+    }
+
+    #[test]
+    fn sync_with_core_backend() {
+        use std::sync::Arc;
+
+        use clightning_testing::btc::BtcNode;
+        use clightning_testing::prelude::bitcoincore_rpc::RpcApi;
+        use lampo_bitcoind::BitcoinCore;
+        use lampo_common::conf::CoreAuth;
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let btc = rt.block_on(BtcNode::tmp("regtest")).unwrap();
+        let backend = BitcoinCore::new(
+            &format!("127.0.0.1:{}", btc.port),
+            CoreAuth::UserPass(btc.user.clone(), btc.pass.clone()),
+            Arc::new(false),
+            Some(1),
+        )
+        .unwrap();
+
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000002")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        // There is no esplora endpoint for regtest, so
+        // the sync can work only through bitcoind.
+        let wallet = BDKWalletManager::try_from((pkey, None))
+            .unwrap()
+            .with_backend(Arc::new(backend));
+        let address = wallet.get_onchain_address().unwrap();
+        let address = clightning_testing::prelude::bitcoincore_rpc::bitcoin::Address::from_str(
+            &address.address,
+        )
+        .unwrap()
+        .assume_checked();
+        btc.rpc().generate_to_address(101, &address).unwrap();
+
+        let balance = wallet.get_onchain_balance().unwrap();
+        assert!(balance > 0);
+    }
+
+    #[test]
+    fn reads_use_the_background_sync() {
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        use clightning_testing::btc::BtcNode;
+        use clightning_testing::prelude::bitcoincore_rpc::RpcApi;
+        use lampo_bitcoind::BitcoinCore;
+        use lampo_common::conf::CoreAuth;
+        use lampo_common::wallet::SyncWorker;
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let btc = rt.block_on(BtcNode::tmp("regtest")).unwrap();
+        let backend = BitcoinCore::new(
+            &format!("127.0.0.1:{}", btc.port),
+            CoreAuth::UserPass(btc.user.clone(), btc.pass.clone()),
+            Arc::new(false),
+            Some(1),
+        )
+        .unwrap();
+
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("000000000000000000000000000000000000000000000000000000000000000b")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let wallet = Arc::new(
+            BDKWalletManager::try_from((pkey, None))
+                .unwrap()
+                .with_backend(Arc::new(backend)),
+        );
+        let address = wallet.get_onchain_address().unwrap();
+        let address = clightning_testing::prelude::bitcoincore_rpc::bitcoin::Address::from_str(
+            &address.address,
+        )
+        .unwrap()
+        .assume_checked();
+        btc.rpc().generate_to_address(101, &address).unwrap();
+
+        // The interval is long enough that the worker runs only once.
+        let _worker = SyncWorker::spawn(wallet.clone(), Duration::from_secs(3600));
+        let started = Instant::now();
+        while wallet.last_sync().is_none() {
+            assert!(started.elapsed() < Duration::from_secs(60));
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        let balance = wallet.get_onchain_balance().unwrap();
+        assert!(balance > 0);
+
+        // The new blocks are seen only by the next sync, the
+        // reads keep using the state of the worker.
+        btc.rpc().generate_to_address(10, &address).unwrap();
+        assert_eq!(wallet.get_onchain_balance().unwrap(), balance);
+        assert!(!wallet.list_transactions().unwrap().is_empty());
+        assert_eq!(wallet.get_onchain_balance().unwrap(), balance);
+
+        wallet.sync().unwrap();
+        assert!(wallet.get_onchain_balance().unwrap() > balance);
+    }
+
+    #[test]
+    fn coins_report_their_confirmations() {
+        use std::sync::Arc;
+
+        use clightning_testing::btc::BtcNode;
+        use clightning_testing::prelude::bitcoincore_rpc::RpcApi;
+        use lampo_bitcoind::BitcoinCore;
+        use lampo_common::conf::CoreAuth;
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let btc = rt.block_on(BtcNode::tmp("regtest")).unwrap();
+        let backend = BitcoinCore::new(
+            &format!("127.0.0.1:{}", btc.port),
+            CoreAuth::UserPass(btc.user.clone(), btc.pass.clone()),
+            Arc::new(false),
+            Some(1),
+        )
+        .unwrap();
+
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("000000000000000000000000000000000000000000000000000000000000000c")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let wallet = BDKWalletManager::try_from((pkey, None))
+            .unwrap()
+            .with_backend(Arc::new(backend));
+        let address = wallet.get_onchain_address().unwrap();
+        let address = clightning_testing::prelude::bitcoincore_rpc::bitcoin::Address::from_str(
+            &address.address,
+        )
+        .unwrap()
+        .assume_checked();
+        btc.rpc().generate_to_address(101, &address).unwrap();
+
+        // Every block pays the wallet, so the coinbase of the
+        // first block has all the confirmations of the chain.
+        let mut confirmed = wallet
+            .list_coins()
+            .unwrap()
+            .iter()
+            .map(|coin| coin.confirmed)
+            .collect::<Vec<_>>();
+        confirmed.sort();
+        assert_eq!(confirmed, (1..=101).collect::<Vec<_>>());
+        let utxos = wallet.list_transactions().unwrap();
+        assert!(utxos.iter().all(|utxo| utxo.confirmed > 0));
+    }
+
+    #[test]
+    fn reconcile_after_a_reorg() {
+        use std::sync::Arc;
+
+        use clightning_testing::btc::BtcNode;
+        use clightning_testing::prelude::bitcoincore_rpc::bitcoin as core_bitcoin;
+        use clightning_testing::prelude::bitcoincore_rpc::RpcApi;
+        use lampo_bitcoind::BitcoinCore;
+        use lampo_common::conf::CoreAuth;
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let btc = rt.block_on(BtcNode::tmp("regtest")).unwrap();
+        let backend = Arc::new(
+            BitcoinCore::new(
+                &format!("127.0.0.1:{}", btc.port),
+                CoreAuth::UserPass(btc.user.clone(), btc.pass.clone()),
+                Arc::new(false),
+                Some(1),
+            )
+            .unwrap(),
+        );
+
+        let pkey = PrivateKey::new(
+            SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000007")
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let wallet = BDKWalletManager::try_from((pkey, None))
+            .unwrap()
+            .with_backend(backend.clone());
+        let address = wallet.get_onchain_address().unwrap();
+        let address = core_bitcoin::Address::from_str(&address.address)
+            .unwrap()
+            .assume_checked();
+        btc.rpc().generate_to_address(101, &address).unwrap();
+        wallet.sync().unwrap();
+        assert!(wallet
+            .reconcile(backend.as_ref(), false)
+            .unwrap()
+            .is_empty());
+
+        // Replace the last block with a longer chain that pays
+        // someone else, so its coinbase is gone.
+        let stale = btc.rpc().get_best_block_hash().unwrap();
+        let coinbase = btc.rpc().get_block(&stale).unwrap().txdata[0].txid();
+        btc.rpc().invalidate_block(&stale).unwrap();
+        let secp = core_bitcoin::secp256k1::Secp256k1::new();
+        let other = core_bitcoin::PrivateKey::new(
+            core_bitcoin::secp256k1::SecretKey::from_slice(&[8; 32]).unwrap(),
+            core_bitcoin::Network::Regtest,
+        );
+        let other =
+            core_bitcoin::Address::p2wpkh(&other.public_key(&secp), core_bitcoin::Network::Regtest)
+                .unwrap();
+        btc.rpc().generate_to_address(2, &other).unwrap();
+
+        let discrepancies = wallet.reconcile(backend.as_ref(), false).unwrap();
+        assert_eq!(discrepancies.len(), 1, "{discrepancies:?}");
+        assert_eq!(discrepancies[0].txid, coinbase.to_string());
+        assert!(!discrepancies[0].fixed);
+
+        let discrepancies = wallet.reconcile(backend.as_ref(), true).unwrap();
+        assert_eq!(discrepancies.len(), 1, "{discrepancies:?}");
+        assert!(discrepancies[0].fixed);
+        assert!(wallet
+            .reconcile(backend.as_ref(), false)
+            .unwrap()
+            .is_empty());
+    }
 }
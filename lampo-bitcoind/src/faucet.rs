@@ -0,0 +1,73 @@
+//! Regtest faucet used by the integration tests to fund the
+//! wallets and to mine blocks, it is built only with the
+//! `test-utils` feature.
+use std::str::FromStr;
+
+use bitcoincore_rpc::bitcoin::{Address, Amount};
+use bitcoincore_rpc::{Client, RpcApi};
+
+use lampo_common::bitcoin::Txid;
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::wallet::WalletManager;
+
+use crate::{BitcoinCore, CoreClient};
+
+/// The bitcoind wallet that receives the coinbase of the mined blocks.
+const FAUCET_WALLET: &str = "lampo-faucet";
+
+/// How many blocks a coinbase output needs to be spent.
+const COINBASE_MATURITY: u64 = 100;
+
+/// Connect to the faucet wallet, creating it the first time.
+fn faucet(backend: &BitcoinCore) -> error::Result<Client> {
+    let wallets: Vec<String> = backend.inner.call("listwallets", &[])?;
+    if !wallets.iter().any(|wallet| wallet == FAUCET_WALLET) {
+        let loaded: Result<json::Value, _> =
+            backend.inner.call("loadwallet", &[FAUCET_WALLET.into()]);
+        if loaded.is_err() {
+            let _: json::Value = backend
+                .inner
+                .call("createwallet", &[FAUCET_WALLET.into()])?;
+        }
+    }
+    CoreClient::connect(
+        &format!("{}/wallet/{FAUCET_WALLET}", backend.inner.url),
+        &backend.inner.auth,
+    )
+}
+
+fn faucet_address(faucet: &Client) -> error::Result<Address> {
+    Ok(faucet.get_new_address(None, None)?.assume_checked())
+}
+
+/// Mine `blocks` on regtest, the coinbase is paid to the faucet.
+pub fn mine_blocks(backend: &BitcoinCore, blocks: u64) -> error::Result<()> {
+    let faucet = faucet(backend)?;
+    let address = faucet_address(&faucet)?;
+    let _ = faucet.generate_to_address(blocks, &address)?;
+    log::debug!(target: "lampo-bitcoind", "faucet mined {blocks} blocks");
+    Ok(())
+}
+
+/// Send `amount_sat` to a fresh address of `wallet` and mine a
+/// block to confirm it, the faucet mines the coins that it needs.
+pub fn fund_wallet(
+    backend: &BitcoinCore,
+    wallet: &dyn WalletManager,
+    amount_sat: u64,
+) -> error::Result<Txid> {
+    let faucet = faucet(backend)?;
+    let amount = Amount::from_sat(amount_sat);
+    if faucet.get_balance(None, None)? < amount {
+        // Enough blocks to spend at least one coinbase.
+        let address = faucet_address(&faucet)?;
+        let _ = faucet.generate_to_address(COINBASE_MATURITY + 1, &address)?;
+    }
+    let address = wallet.get_onchain_address()?.address;
+    let address = Address::from_str(&address)?.assume_checked();
+    let txid = faucet.send_to_address(&address, amount, None, None, None, None, None, None)?;
+    mine_blocks(backend, 1)?;
+    log::debug!(target: "lampo-bitcoind", "faucet sent {amount_sat} sats to `{address}` with `{txid}`");
+    Ok(Txid::from_str(&txid.to_string())?)
+}
@@ -2,30 +2,88 @@
 //! lampo.
 use std::cell::RefCell;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::JoinHandle;
 use std::time::Duration;
 
 use bitcoincore_rpc::bitcoin::hashes::Hash;
 use bitcoincore_rpc::bitcoin::ScriptBuf;
 use bitcoincore_rpc::bitcoincore_rpc_json::GetTxOutResult;
-use bitcoincore_rpc::Client;
 use bitcoincore_rpc::RpcApi;
+use bitcoincore_rpc::{Auth, Client};
 
 use lampo_common::backend::{deserialize, serialize};
-use lampo_common::backend::{Backend, TxResult};
+use lampo_common::backend::{
+    get_block_or_archived, Backend, BlockArchive, BroadcastStatus, MempoolStats, SyncProgress,
+    TxResult, TxStatus,
+};
 use lampo_common::backend::{Block, BlockData, BlockHash};
 use lampo_common::bitcoin::absolute::Height;
-use lampo_common::bitcoin::{Transaction, Txid};
+use lampo_common::bitcoin::{OutPoint, Transaction, TxOut, Txid};
+use lampo_common::conf::{CoreAuth, Network};
 use lampo_common::error;
 use lampo_common::event::onchain::OnChainEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
 use lampo_common::json;
+use lampo_common::utils::network;
+
+#[cfg(feature = "test-utils")]
+pub mod faucet;
+
+/// Client of bitcoin core that connects again with fresh credentials
+/// when the connection fails, because bitcoind writes a new cookie
+/// file every time that it restarts.
+struct CoreClient {
+    url: String,
+    auth: CoreAuth,
+    client: RwLock<Client>,
+}
+
+impl CoreClient {
+    fn new(url: &str, auth: CoreAuth) -> error::Result<Self> {
+        let client = Self::connect(url, &auth)?;
+        Ok(Self {
+            url: url.to_owned(),
+            auth,
+            client: RwLock::new(client),
+        })
+    }
+
+    fn connect(url: &str, auth: &CoreAuth) -> error::Result<Client> {
+        let (user, pass) = auth.credentials()?;
+        Ok(Client::new(url, Auth::UserPass(user, pass))?)
+    }
+}
+
+impl RpcApi for CoreClient {
+    fn call<T: for<'a> json::Deserialize<'a>>(
+        &self,
+        cmd: &str,
+        args: &[json::Value],
+    ) -> bitcoincore_rpc::Result<T> {
+        // SAFETY: the lock can not be poisoned.
+        let result = self.client.read().unwrap().call(cmd, args);
+        match result {
+            Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Transport(
+                err,
+            ))) if matches!(self.auth, CoreAuth::Cookie(_)) => {
+                log::info!(target: "bitcoind", "connection with bitcoind failed `{err}`, reading the cookie file again");
+                let client = Self::connect(&self.url, &self.auth)
+                    .map_err(|err| bitcoincore_rpc::Error::ReturnedError(err.to_string()))?;
+                *self.client.write().unwrap() = client;
+                self.client.read().unwrap().call(cmd, args)
+            }
+            result => result,
+        }
+    }
+}
 
 pub struct BitcoinCore {
-    inner: Client,
+    inner: CoreClient,
     handler: RefCell<Option<Arc<dyn Handler>>>,
+    /// Where the blocks are fetched when bitcoind pruned them.
+    block_archive: RefCell<Option<Arc<dyn BlockArchive>>>,
     ours_txs: Mutex<RefCell<Vec<Txid>>>,
     others_txs: Mutex<RefCell<Vec<(Txid, ScriptBuf)>>>,
     // receive notification if the
@@ -52,22 +110,22 @@ unsafe impl Send for BitcoinCore {}
 unsafe impl Sync for BitcoinCore {}
 
 impl BitcoinCore {
+    /// Connect to bitcoin core at `url`, authenticating with the
+    /// user and the password or with the cookie file.
     pub fn new(
         url: &str,
-        user: &str,
-        pass: &str,
+        auth: CoreAuth,
         stop: Arc<bool>,
         pool_time: Option<u8>,
-    ) -> bitcoincore_rpc::Result<Self> {
+    ) -> error::Result<Self> {
         // FIXME: the bitcoincore_rpc do not support the https protocol.
-        use bitcoincore_rpc::Auth;
-
         log::debug!(target: "lampo-bitcoind", "Connecting to bitcoin backend at `{url}`");
-        let client = Client::new(url, Auth::UserPass(user.to_owned(), pass.to_owned()))?;
+        let client = CoreClient::new(url, auth)?;
         // FIXME: grab some information from the blockchain, eg. Network
         Ok(Self {
             inner: client,
             handler: RefCell::new(None),
+            block_archive: RefCell::new(None),
             ours_txs: Mutex::new(RefCell::new(Vec::new())),
             others_txs: Mutex::new(RefCell::new(Vec::new())),
             // by default we pool bitcoind each 2 minutes
@@ -105,6 +163,17 @@ impl BitcoinCore {
         Ok(())
     }
 
+    /// The feerate in sats per kw of the package of a mempool entry
+    /// with its ancestors, and the virtual size of the entry.
+    fn ancestor_fee_rate(entry: &json::Value) -> Option<(u32, u64)> {
+        let fee = entry["fees"]["ancestor"].as_f64()?;
+        let ancestor_size = entry["ancestorsize"].as_u64()?;
+        let vsize = entry["vsize"].as_u64()?;
+        // From BTC per vbyte to sats per kw: 1e8 sats per BTC and 250 vbytes per kw.
+        let fee_rate = fee * 100_000_000_f64 * 250_f64 / ancestor_size.max(1) as f64;
+        Some((fee_rate.round() as u32, vsize))
+    }
+
     pub fn get_block_hash(&self, height: u64) -> error::Result<BlockHash> {
         let block_hash: BlockHash = self.inner.call("getblockhash", &[height.into()])?;
         Ok(block_hash)
@@ -178,8 +247,7 @@ impl Backend for BitcoinCore {
         let result = self.inner.estimate_smart_fee(blocks as u16, None)?;
 
         // FIXME: store the network inside the self
-        let chain_info = self.inner.get_blockchain_info()?;
-        let network_str = chain_info.chain.as_str();
+        let network = network::network_from_genesis_block_hash(&self.inner.get_block_hash(0)?);
 
         if let Some(errors) = &result.errors {
             // Thanks to bitcoin core that will not process in time
@@ -187,7 +255,7 @@ impl Backend for BitcoinCore {
             // or conditional code skipping.
             //
             // What next LND? :)
-            if network_str == "regtest" {
+            if network == Some(Network::Regtest) {
                 return Ok(253);
             }
 
@@ -200,9 +268,9 @@ impl Backend for BitcoinCore {
             );
         }
         let result: u32 = result.fee_rate.unwrap_or_default().to_sat() as u32;
-        let result = match network_str {
+        let result = match network {
             // in the regtest case that it is useful for integration testing
-            "regtest" => {
+            Some(Network::Regtest) => {
                 if result == 0 {
                     253
                 } else {
@@ -213,7 +281,10 @@ impl Backend for BitcoinCore {
                 if result != 0 {
                     result
                 } else {
-                    error::bail!("Estimated fee is `{result}` on `{network_str}`")
+                    error::bail!(
+                        "Estimated fee is `{result}` on `{}`",
+                        network.map_or("unknown network".to_owned(), |network| network.to_string())
+                    )
                 }
             }
         };
@@ -225,8 +296,9 @@ impl Backend for BitcoinCore {
 
         let fee: MinimumMempoolFee = self.inner.call("getmempoolinfo", &[])?;
         // FIXME: adds the trait for conversion from and to BTC
-        let fee = fee.mempoolminfee;
-        Ok((fee * 10000_f32) as u32)
+        // From BTC/kvB to sats per kw: 1e8 sats per BTC and 4 kw per kvB.
+        let fee = fee.mempoolminfee as f64;
+        Ok((fee * 25_000_000_f64).round() as u32)
     }
 
     fn get_best_block(&self) -> error::Result<(lampo_common::backend::BlockHash, Option<u32>)> {
@@ -238,19 +310,31 @@ impl Backend for BitcoinCore {
         Ok((hash, Some(block.blocks as u32)))
     }
 
+    fn sync_progress(&self) -> error::Result<SyncProgress> {
+        let info = self.inner.get_blockchain_info()?;
+        Ok(SyncProgress {
+            blocks: info.blocks as u32,
+            headers: info.headers as u32,
+            verification_progress: info.verification_progress,
+        })
+    }
+
     fn get_block(
         &self,
         header_hash: &lampo_common::backend::BlockHash,
     ) -> error::Result<lampo_common::backend::BlockData> {
         use bitcoincore_rpc::bitcoin::consensus::serialize as inner_serialize;
 
-        // FIXME: change the version of rust bitcoin in nakamoto and in lampod_common.
-        let bytes = serialize(header_hash);
-        let hash = BlockHash::from_slice(bytes.as_slice())?;
-        let result = self.inner.get_block(&hash)?;
-        let block: Block = deserialize(&inner_serialize(&result))?;
-        log::debug!(target: "bitcoind", "decode blocks {}", header_hash.to_string());
-        Ok(BlockData::FullBlock(block))
+        let archive = self.block_archive.borrow().clone();
+        get_block_or_archived(header_hash, archive.as_ref(), || {
+            // FIXME: change the version of rust bitcoin in nakamoto and in lampod_common.
+            let bytes = serialize(header_hash);
+            let hash = BlockHash::from_slice(bytes.as_slice())?;
+            let result = self.inner.get_block(&hash)?;
+            let block: Block = deserialize(&inner_serialize(&result))?;
+            log::debug!(target: "bitcoind", "decode blocks {}", header_hash.to_string());
+            Ok(BlockData::FullBlock(block))
+        })
     }
 
     fn get_header<'a>(
@@ -365,10 +449,181 @@ impl Backend for BitcoinCore {
         Ok(TxResult::Unconfirmed(raw_tx))
     }
 
+    fn broadcast_status(&self, tx: &Transaction) -> error::Result<BroadcastStatus> {
+        let txid = tx.txid().to_string();
+        let entry: bitcoincore_rpc::Result<json::Value> =
+            self.inner.call("getmempoolentry", &[txid.clone().into()]);
+        if entry.is_ok() {
+            return Ok(BroadcastStatus::Mempool);
+        }
+        // Without the `txindex` we can look only at the utxo set, so if all the
+        // inputs are still unspent the transaction was evicted from the mempool.
+        let mut inputs_spent = false;
+        for input in tx.input.iter() {
+            let prevout: Option<json::Value> = self.inner.call(
+                "gettxout",
+                &[
+                    input.previous_output.txid.to_string().into(),
+                    input.previous_output.vout.into(),
+                    false.into(),
+                ],
+            )?;
+            inputs_spent |= prevout.is_none();
+        }
+        if !inputs_spent {
+            return Ok(BroadcastStatus::Evicted);
+        }
+        for vout in 0..tx.output.len() {
+            let output: Option<json::Value> = self.inner.call(
+                "gettxout",
+                &[txid.clone().into(), vout.into(), false.into()],
+            )?;
+            if output.is_some() {
+                return Ok(BroadcastStatus::Confirmed);
+            }
+        }
+        // FIXME: when all the outputs are spent too, we can not tell
+        // if the transaction was confirmed or a conflicting one was.
+        Ok(BroadcastStatus::Conflicted)
+    }
+
+    fn tx_status(&self, txid: &Txid) -> error::Result<TxStatus> {
+        let entry: Result<json::Value, _> = self
+            .inner
+            .call("getmempoolentry", &[txid.to_string().into()]);
+        if let Ok(entry) = entry {
+            let Some((fee_rate, vsize)) = Self::ancestor_fee_rate(&entry) else {
+                error::bail!("invalid mempool entry for `{txid}`: {entry}");
+            };
+            return Ok(TxStatus::Mempool { fee_rate, vsize });
+        }
+        // Without the `txindex` bitcoind knows only the confirmed
+        // transactions of its wallet.
+        let tx: json::Value = match self
+            .inner
+            .call("getrawtransaction", &[txid.to_string().into(), true.into()])
+        {
+            Ok(tx) => tx,
+            Err(_) => match self
+                .inner
+                .call("gettransaction", &[txid.to_string().into()])
+            {
+                Ok(tx) => tx,
+                Err(_) => return Ok(TxStatus::Unknown),
+            },
+        };
+        match tx["confirmations"].as_i64() {
+            Some(confirmations) if confirmations > 0 => Ok(TxStatus::Confirmed {
+                confirmations: confirmations as u32,
+            }),
+            _ => Ok(TxStatus::Unknown),
+        }
+    }
+
+    fn fee_histogram(&self) -> error::Result<Vec<(u32, u64)>> {
+        let mempool: json::Value = self.inner.call("getrawmempool", &[true.into()])?;
+        let Some(entries) = mempool.as_object() else {
+            error::bail!("invalid mempool from bitcoind: {mempool}");
+        };
+        Ok(entries
+            .values()
+            .filter_map(Self::ancestor_fee_rate)
+            .collect())
+    }
+
+    fn mempool_stats(&self) -> error::Result<MempoolStats> {
+        use lampo_common::btc_rpc::MempoolInfo;
+
+        let info: MempoolInfo = self.inner.call("getmempoolinfo", &[])?;
+        // From BTC/kvB to sats per kw: 1e8 sats per BTC and 4 kw per kvB.
+        let min_relay_fee = (info.minrelaytxfee as f64 * 25_000_000_f64).round() as u32;
+        Ok(MempoolStats {
+            size: info.size,
+            bytes: info.bytes,
+            min_relay_fee,
+        })
+    }
+
+    fn invalidate_blocks(&self, count: u32) -> error::Result<Vec<Block>> {
+        let height = self.inner.get_block_count()?;
+        if count == 0 || count as u64 > height {
+            error::bail!("impossible invalidate {count} blocks with the tip at height {height}");
+        }
+        let mut blocks = Vec::new();
+        for height in (height + 1 - count as u64..=height).rev() {
+            let hash = self.get_block_hash(height)?;
+            let BlockData::FullBlock(block) = self.get_block(&hash)? else {
+                error::bail!("the block `{hash}` has no transactions");
+            };
+            blocks.push(block);
+        }
+        // Invalidating the oldest block drops the blocks on top of it.
+        let oldest = blocks[blocks.len() - 1].block_hash();
+        let _: json::Value = self
+            .inner
+            .call("invalidateblock", &[oldest.to_string().into()])?;
+        let tip = height - count as u64;
+        *self.best_height.borrow_mut() = tip;
+        *self.last_bloch_hash.borrow_mut() = Some(self.get_block_hash(tip)?);
+        Ok(blocks)
+    }
+
+    fn scan_utxos(
+        &self,
+        scripts: &[lampo_common::backend::ScriptBuf],
+    ) -> error::Result<Vec<(OutPoint, TxOut)>> {
+        let descriptors = scripts
+            .iter()
+            .map(|script| json::json!(format!("raw({script:x})")))
+            .collect::<Vec<_>>();
+        let scan: json::Value = self
+            .inner
+            .call("scantxoutset", &["start".into(), descriptors.into()])?;
+        let Some(unspents) = scan["unspents"].as_array() else {
+            error::bail!("invalid scan of the utxo set from bitcoind: {scan}");
+        };
+        unspents
+            .iter()
+            .map(|unspent| {
+                let (Some(txid), Some(vout), Some(script), Some(amount)) = (
+                    unspent["txid"].as_str(),
+                    unspent["vout"].as_u64(),
+                    unspent["scriptPubKey"].as_str(),
+                    unspent["amount"].as_f64(),
+                ) else {
+                    error::bail!("invalid unspent output from bitcoind: {unspent}");
+                };
+                let outpoint = OutPoint::new(Txid::from_str(txid)?, vout as u32);
+                let output = TxOut {
+                    // From BTC to sats.
+                    value: (amount * 100_000_000_f64).round() as u64,
+                    script_pubkey: lampo_common::backend::ScriptBuf::from_hex(script)?,
+                };
+                Ok((outpoint, output))
+            })
+            .collect()
+    }
+
+    fn is_unspent(&self, txid: &Txid, vout: u32) -> error::Result<bool> {
+        let output: Option<json::Value> = self.inner.call(
+            "gettxout",
+            &[txid.to_string().into(), vout.into(), true.into()],
+        )?;
+        Ok(output.is_some())
+    }
+
     fn set_handler(&self, handler: Arc<dyn Handler>) {
         self.handler.replace(Some(handler));
     }
 
+    fn is_pruned(&self) -> error::Result<bool> {
+        Ok(self.inner.get_blockchain_info()?.pruned)
+    }
+
+    fn set_block_archive(&self, archive: Arc<dyn BlockArchive>) {
+        self.block_archive.replace(Some(archive));
+    }
+
     fn process_transactions(&self) -> lampo_common::error::Result<()> {
         let handler = self
             .handler
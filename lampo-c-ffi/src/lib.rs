@@ -177,8 +177,8 @@ pub extern "C" fn new_lampod(conf_path: *const libc::c_char) -> *mut LampoDaemon
         "core" => Arc::new(
             BitcoinCore::new(
                 &conf.core_url.clone().expect("please add the url"),
-                &conf.core_user.clone().expect("plaase add the user"),
-                &conf.core_pass.clone().expect("please add the pass"),
+                conf.core_auth()
+                    .expect("please add the user and the pass, or the cookie"),
                 Arc::new(false),
                 Some(1),
             )
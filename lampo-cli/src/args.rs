@@ -10,6 +10,7 @@ pub struct LampoCliArgs {
     pub socket: String,
     pub method: String,
     pub args: HashMap<String, json::Value>,
+    pub auth: Option<String>,
 }
 
 struct Help {
@@ -33,6 +34,7 @@ Options
     -d | --data-dir     Specify lampo data directory (used to get socket path)
     -n | --network      Set the network for lampo (default: testnet)
     -s | --socket       Specify Unix Socket patch of the lampod node directely
+    -a | --auth         The token that identifies the caller with the RPC access control list
     -h | --help         Print help
 "#,
 };
@@ -43,6 +45,7 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
     let mut data_dir: Option<String> = None;
     let mut network: Option<String> = None;
     let mut socket: Option<String> = None;
+    let mut auth: Option<String> = None;
     let mut method: Option<String> = None;
     let mut args = HashMap::<String, json::Value>::new();
 
@@ -61,6 +64,10 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
                 let val: String = parser.value()?.parse()?;
                 socket = Some(val);
             }
+            Short('a') | Long("auth") => {
+                let val: String = parser.value()?.parse()?;
+                auth = Some(val);
+            }
             Long("help") => {
                 let _ = print_help();
                 std::process::exit(0);
@@ -132,6 +139,7 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
             ),
         })?,
         args,
+        auth,
     })
 }
 
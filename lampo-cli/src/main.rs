@@ -35,7 +35,10 @@ fn main() -> error::Result<()> {
 }
 
 fn run(args: LampoCliArgs) -> Result<json::Value, lampo_client::errors::Error> {
-    let client = UnixClient::new(&args.socket).unwrap();
+    let mut client = UnixClient::new(&args.socket).unwrap();
+    if let Some(token) = args.auth.as_deref() {
+        client = client.with_auth(token);
+    }
     let resp = client.call(&args.method, args.args)?;
     Ok(resp)
 }
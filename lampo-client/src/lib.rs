@@ -1,15 +1,29 @@
 pub use clightningrpc_common::errors;
 
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
 use clightningrpc_common::client;
 use clightningrpc_common::errors::Error;
+use clightningrpc_common::types::Response;
 use lampo_common::error;
+use lampo_jsonrpc::json_rpc2::Request;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 pub struct UnixClient {
-    #[allow(dead_code)]
     socket_path: String,
     inner: client::Client,
+    /// The idle connections kept open with the server.
+    pool: Mutex<Vec<UnixStream>>,
+    /// How many idle connections are kept, with zero a new
+    /// connection is open for every call.
+    pool_size: usize,
+    opened_sockets: AtomicUsize,
+    /// The token that identifies us with the access control list of the server.
+    auth: Option<String>,
 }
 
 impl UnixClient {
@@ -18,30 +32,130 @@ impl UnixClient {
         Ok(Self {
             socket_path: path.to_string(),
             inner: client,
+            pool: Mutex::new(Vec::new()),
+            pool_size: 0,
+            opened_sockets: AtomicUsize::new(0),
+            auth: None,
         })
     }
 
+    /// Reuse up to `size` connections across the calls.
+    pub fn with_pool_size(mut self, size: usize) -> Self {
+        self.pool_size = size;
+        self
+    }
+
+    /// Identify the calls with the `token`.
+    pub fn with_auth(mut self, token: &str) -> Self {
+        self.auth = Some(token.to_owned());
+        self
+    }
+
+    /// The number of connections opened by the pool.
+    pub fn opened_sockets(&self) -> usize {
+        self.opened_sockets.load(Ordering::SeqCst)
+    }
+
     pub fn call<T: Serialize, U: DeserializeOwned>(
         &self,
         method: &str,
         input: T,
     ) -> Result<U, Error> {
+        // The plain client does not know the lampo extensions.
+        if self.pool_size > 0 || self.auth.is_some() {
+            return self.pooled_call(method, input);
+        }
         let res = self
             .inner
             .send_request(method, input)
             .and_then(|res| res.into_result())?;
         Ok(res)
     }
+
+    fn pooled_call<T: Serialize, U: DeserializeOwned>(
+        &self,
+        method: &str,
+        input: T,
+    ) -> Result<U, Error> {
+        let mut request = Request::new(method, input).with_keep_alive();
+        if let Some(token) = self.auth.as_deref() {
+            request = request.with_auth(token);
+        }
+        let buff = serde_json::to_vec(&request)?;
+        // SAFETY: the lock can not be poisoned.
+        let idle = self.pool.lock().unwrap().pop().filter(Self::is_alive);
+        let (mut stream, reused) = match idle {
+            Some(stream) => (stream, true),
+            None => (self.connect()?, false),
+        };
+        // The request is sent again on a fresh connection only when the
+        // server did not receive any byte of it, otherwise a payment could
+        // be executed twice. Every error after that goes to the caller.
+        match stream.write(&buff) {
+            Ok(written) if written > 0 => stream.write_all(&buff[written..])?,
+            first_write => {
+                let err = first_write
+                    .err()
+                    .unwrap_or_else(|| io::ErrorKind::WriteZero.into());
+                if !reused {
+                    return Err(err.into());
+                }
+                log::debug!("pooled connection failed with `{err}`, reconnecting");
+                stream = self.connect()?;
+                stream.write_all(&buff)?;
+            }
+        }
+        stream.flush()?;
+        let resp = Self::read_response(&mut stream)?;
+        let mut pool = self.pool.lock().unwrap();
+        if pool.len() < self.pool_size {
+            pool.push(stream);
+        }
+        let resp: Response<U> = serde_json::from_slice(&resp)?;
+        resp.into_result()
+    }
+
+    fn connect(&self) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(&self.socket_path)?;
+        self.opened_sockets.fetch_add(1, Ordering::SeqCst);
+        Ok(stream)
+    }
+
+    /// Tell if the idle connection is still open, the server closes
+    /// it with an EOF and it never sends a byte that we did not ask.
+    fn is_alive(stream: &UnixStream) -> bool {
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut byte = [0; 1];
+        let alive = matches!(
+            (&*stream).read(&mut byte),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock
+        );
+        alive && stream.set_nonblocking(false).is_ok()
+    }
+
+    /// Read the response, that is prefixed by its length.
+    fn read_response(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+        let mut resp = vec![0; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut resp)?;
+        Ok(resp)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::sync::Arc;
 
-    use serde_json::Value;
+    use serde_json::{json, Value};
 
     use lampo_common::logger;
     use lampo_common::model::Connect;
+    use lampo_jsonrpc::command::Context;
+    use lampo_jsonrpc::JSONRPCv2;
 
     use crate::UnixClient;
 
@@ -70,4 +184,83 @@ mod tests {
         let resp: HashMap<String, Value> = client.call("connect", input).unwrap();
         log::info!("`connect` response: `{:?}`", resp)
     }
+
+    struct DummyCtx;
+
+    impl Context for DummyCtx {
+        type Ctx = DummyCtx;
+
+        fn ctx(&self) -> &Self::Ctx {
+            self
+        }
+    }
+
+    #[test]
+    fn pooled_calls_reuse_the_connection() {
+        let path = "/tmp/lampo-client-pool.sock";
+        let _ = std::fs::remove_file(path);
+        let server = JSONRPCv2::new(Arc::new(DummyCtx), path).unwrap();
+        server
+            .add_rpc("echo", |_: &DummyCtx, request| Ok(request.clone()))
+            .unwrap();
+        let handler = server.handler();
+        let _worker = server.spawn();
+
+        let client = UnixClient::new(path).unwrap().with_pool_size(2);
+        for i in 0..100 {
+            let resp: Value = client.call("echo", json!({ "call": i })).unwrap();
+            assert_eq!(resp, json!({ "call": i }));
+        }
+        assert!(client.opened_sockets() <= 2, "{}", client.opened_sockets());
+        handler.stop();
+    }
+
+    #[test]
+    fn requests_are_not_replayed() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixListener;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let path = "/tmp/lampo-client-replay.sock";
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buff = [0; 4096];
+                loop {
+                    match stream.read(&mut buff) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => {}
+                    }
+                    // The first request is answered, the next one is
+                    // consumed and the connection is dropped without answer.
+                    if received.fetch_add(1, Ordering::SeqCst) > 0 {
+                        break;
+                    }
+                    let resp = serde_json::to_vec(&json!({
+                        "jsonrpc": "2.0",
+                        "id": "lampo/jsonrpc/1",
+                        "result": { "paid": true },
+                    }))
+                    .unwrap();
+                    stream
+                        .write_all(&(resp.len() as u32).to_be_bytes())
+                        .unwrap();
+                    stream.write_all(&resp).unwrap();
+                }
+            }
+        });
+
+        let client = UnixClient::new(path).unwrap().with_pool_size(1);
+        let resp: Value = client.call("pay", json!({})).unwrap();
+        assert_eq!(resp, json!({ "paid": true }));
+        let resp: Result<Value, _> = client.call("pay", json!({}));
+        assert!(resp.is_err(), "{:?}", resp);
+        // The request was not sent again on a fresh connection.
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(client.opened_sockets(), 1);
+    }
 }
@@ -8,7 +8,7 @@ use bitcoin::absolute::Height;
 use bitcoin::block::Header as BlockHeader;
 
 pub use bitcoin::consensus::{deserialize, serialize};
-pub use bitcoin::{Block, BlockHash, Script, Transaction, Txid};
+pub use bitcoin::{Block, BlockHash, OutPoint, Script, ScriptBuf, Transaction, TxOut, Txid};
 pub use lightning::chain::WatchedOutput;
 pub use lightning::routing::utxo::UtxoResult;
 pub use lightning_block_sync::{
@@ -26,6 +26,104 @@ pub enum TxResult {
     Discarded,
 }
 
+/// The status of a transaction that we broadcast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastStatus {
+    /// The transaction is inside the mempool.
+    Mempool,
+    /// The transaction is not inside the mempool, e.g. it was evicted.
+    Evicted,
+    Confirmed,
+    /// A transaction that spends the same inputs was confirmed.
+    Conflicted,
+}
+
+/// Where a transaction is for the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    /// The transaction is inside a block.
+    Confirmed {
+        confirmations: u32,
+    },
+    /// The transaction is inside the mempool, the feerate in sats
+    /// per kw is the one of the package with its ancestors.
+    Mempool {
+        fee_rate: u32,
+        vsize: u64,
+    },
+    Unknown,
+}
+
+/// The stats of the mempool of the backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MempoolStats {
+    /// How many transactions are inside the mempool.
+    pub size: u64,
+    /// The virtual size of the transactions inside the mempool.
+    pub bytes: u64,
+    /// The min relay fee in sats per kw.
+    pub min_relay_fee: u32,
+}
+
+/// How far the backend is from the tip of the network.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncProgress {
+    /// The height of the last validated block.
+    pub blocks: u32,
+    /// The height of the best known header.
+    pub headers: u32,
+    /// The estimated progress of the validation, from 0 to 1.
+    pub verification_progress: f64,
+}
+
+impl SyncProgress {
+    /// The validation progress from which the backend is at the tip,
+    /// bitcoind never reports exactly 1 on a live network.
+    pub const SYNCED_VERIFICATION_PROGRESS: f64 = 0.9999;
+
+    /// How many blocks are known by the headers but not validated yet.
+    pub fn blocks_behind(&self) -> u32 {
+        self.headers.saturating_sub(self.blocks)
+    }
+
+    /// The backend is in sync when it validated all the blocks, but
+    /// at most `max_blocks_behind`.
+    pub fn is_synced(&self, max_blocks_behind: u32) -> bool {
+        self.blocks_behind() <= max_blocks_behind
+            && self.verification_progress >= Self::SYNCED_VERIFICATION_PROGRESS
+    }
+}
+
+/// Source of the blocks that a pruned backend does not store anymore.
+pub trait BlockArchive: Send + Sync {
+    fn get_archived_block(&self, header_hash: &BlockHash) -> error::Result<Block>;
+}
+
+/// Fetch the block with `fetch`, and fall back to the `archive`
+/// when the backend does not have the block anymore.
+pub fn get_block_or_archived(
+    header_hash: &BlockHash,
+    archive: Option<&Arc<dyn BlockArchive>>,
+    fetch: impl FnOnce() -> error::Result<BlockData>,
+) -> error::Result<BlockData> {
+    let err = match fetch() {
+        Ok(block) => return Ok(block),
+        Err(err) => err,
+    };
+    let Some(archive) = archive else {
+        return Err(err);
+    };
+    log::debug!(target: "backend", "block `{header_hash}` not found in the backend ({err}), fetching it from the archive");
+    let block = archive.get_archived_block(header_hash)?;
+    if block.block_hash() != *header_hash {
+        error::bail!(
+            "the archive returned the block `{}` instead of `{header_hash}`",
+            block.block_hash()
+        );
+    }
+    Ok(BlockData::FullBlock(block))
+}
+
 /// Backend kind supported by the lampo
 pub enum BackendKind {
     Core,
@@ -40,6 +138,8 @@ pub trait Backend {
     /// Fetch feerate give a number of blocks
     fn fee_rate_estimation(&self, blocks: u64) -> error::Result<u32>;
 
+    /// The min relay fee of the mempool in sats per kw, a
+    /// transaction below it is not relayed.
     fn minimum_mempool_fee(&self) -> error::Result<u32>;
 
     fn brodcast_tx(&self, tx: &Transaction);
@@ -66,12 +166,33 @@ pub trait Backend {
 
     fn get_best_block(&self) -> error::Result<(BlockHash, Option<u32>)>;
 
+    /// Return how far the backend is from the tip of the network.
+    ///
+    /// By default the backend is always at the tip with its best block.
+    fn sync_progress(&self) -> error::Result<SyncProgress> {
+        let (_, height) = self.get_best_block()?;
+        let height = height.unwrap_or_default();
+        Ok(SyncProgress {
+            blocks: height,
+            headers: height,
+            verification_progress: 1.0,
+        })
+    }
+
     fn get_utxo(&self, block: &BlockHash, idx: u64) -> UtxoResult;
 
     fn get_utxo_by_txid(&self, txid: &Txid, script: &Script) -> error::Result<TxResult>;
 
     fn set_handler(&self, _: Arc<dyn Handler>) {}
 
+    /// Return true when the backend deletes the old blocks.
+    fn is_pruned(&self) -> error::Result<bool> {
+        Ok(false)
+    }
+
+    /// Fetch the blocks pruned by the backend from `archive`.
+    fn set_block_archive(&self, _: Arc<dyn BlockArchive>) {}
+
     /// Ask to the backend to watch the following UTXO and notify you
     /// when somethings changes
     fn manage_transactions(&self, txs: &mut Vec<Txid>) -> error::Result<()>;
@@ -82,4 +203,24 @@ pub trait Backend {
     fn get_transaction(&self, txid: &Txid) -> error::Result<TxResult>;
     /// Process the transactions
     fn process_transactions(&self) -> error::Result<()>;
+    /// Return the status of a transaction that we broadcast, so
+    /// it can be broadcast again if it was evicted from the mempool.
+    fn broadcast_status(&self, tx: &Transaction) -> error::Result<BroadcastStatus>;
+    /// Return true when the output is inside the utxo set of the
+    /// backend, the outputs created in the mempool included.
+    fn is_unspent(&self, txid: &Txid, vout: u32) -> error::Result<bool>;
+    /// Return where the transaction is for the backend.
+    fn tx_status(&self, txid: &Txid) -> error::Result<TxStatus>;
+    /// Return the `(fee_rate, vsize)` of the transactions inside the
+    /// mempool, the feerate in sats per kw of their ancestors package.
+    fn fee_histogram(&self) -> error::Result<Vec<(u32, u64)>>;
+    /// Return the stats of the mempool.
+    fn mempool_stats(&self) -> error::Result<MempoolStats>;
+    /// Invalidate the last `count` blocks, so the backend follows
+    /// the chain without them, and return them from the tip.
+    ///
+    /// Used to simulate a reorg on regtest.
+    fn invalidate_blocks(&self, count: u32) -> error::Result<Vec<Block>>;
+    /// Scan the confirmed utxo set for the outputs locked by `scripts`.
+    fn scan_utxos(&self, scripts: &[ScriptBuf]) -> error::Result<Vec<(OutPoint, TxOut)>>;
 }
@@ -1,10 +1,36 @@
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::time::Duration;
 
 use clightningrpc_conf::{CLNConf, SyncCLNConf};
 
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::Address;
+use lightning::ln::msgs::SocketAddress;
+use lightning::ln::script::ShutdownScript;
+use lightning::routing::scoring::ProbabilisticScoringDecayParameters;
+
 pub use bitcoin::Network;
 pub use lightning::util::config::UserConfig;
 
+/// The `User-Agent` sent to the esplora servers by default.
+pub const DEFAULT_USER_AGENT: &str = concat!("lampo/", env!("CARGO_PKG_VERSION"));
+
+/// The first feature bit that is not assigned by LDK, the
+/// bits below it are owned by LDK and can not be advertised.
+pub const FIRST_CUSTOM_FEATURE_BIT: usize = 256;
+/// The `option_anchors_zero_fee_htlc_tx` feature bit.
+pub const ANCHORS_FEATURE_BIT: usize = 22;
+/// The `option_scid_alias` feature bit.
+pub const SCID_PRIVACY_FEATURE_BIT: usize = 46;
+/// The max number of hops of a route allowed by BOLT 4.
+pub const MAX_ROUTE_HOPS: u8 = 20;
+/// The features required by LDK, `option_data_loss_protect`,
+/// `var_onion_optin`, `option_static_remotekey` and `payment_secret`.
+const MANDATORY_FEATURE_BITS: [usize; 4] = [0, 8, 12, 14];
+/// The features that LDK advertises only when they are enabled by the config.
+const CONFIGURABLE_FEATURE_BITS: [usize; 2] = [ANCHORS_FEATURE_BIT, SCID_PRIVACY_FEATURE_BIT];
+
 #[derive(Clone, Debug)]
 pub struct LampoConf {
     pub inner: Option<CLNConf>,
@@ -17,12 +43,158 @@ pub struct LampoConf {
     pub core_url: Option<String>,
     pub core_user: Option<String>,
     pub core_pass: Option<String>,
+    /// The path of the bitcoind cookie file, used
+    /// in place of `core_user` and `core_pass`.
+    pub core_cookie: Option<String>,
     pub private_key: Option<String>,
     pub channels_keys: Option<String>,
     pub log_file: Option<String>,
     pub log_level: String,
     pub alias: Option<String>,
-    pub announce_addr: Option<String>,
+    /// The addresses announced to the network in order of preference,
+    /// as `host` or `host:port`, without port the `port` is used.
+    pub announce_addresses: Vec<String>,
+    /// The address where we listen for the p2p connections, by
+    /// default the first announce address.
+    pub bind_addr: Option<String>,
+    /// The amount of on chain funds that must be kept to pay the
+    /// fees of the force close or sweep transactions, zero disables it.
+    pub onchain_fee_reserve_sat: u64,
+    /// How many on chain UTXOs must be kept to bump the commitment
+    /// transactions (CPFP) while anchor channels are open, each one pays
+    /// a CPFP at `max_feerate_per_kw`, zero disables it.
+    pub anchor_reserve_utxos: u64,
+    /// The address where all the cooperative closes pay to, it is
+    /// committed to the peer when the channel is opened.
+    pub upfront_shutdown_script: Option<String>,
+    /// The highest feerate in sats per kw that we accept to
+    /// use for the funding and commitment transactions.
+    pub max_feerate_per_kw: u32,
+    /// How many seconds the last wallet sync is considered
+    /// fresh before funding a channel.
+    pub wallet_sync_staleness_secs: u64,
+    /// Sync the wallet in background every this many seconds, so
+    /// the reads use the last sync. Zero disables the worker.
+    pub wallet_sync_interval_secs: u64,
+    /// The confirmation target in blocks used to estimate the
+    /// feerate of the transactions that sweep our outputs.
+    pub sweep_confirmation_target: u16,
+    /// The feerate in sats per kw used to sweep our outputs,
+    /// it overrides the estimation.
+    pub sweep_feerate: Option<u32>,
+    /// How many seconds a transaction that we broadcast can be
+    /// out of the mempool before we broadcast it again, zero
+    /// disables the rebroadcast.
+    pub rebroadcast_interval_secs: u64,
+    /// How many seconds between two reads of the min relay fee
+    /// of the backend, zero reads it only at startup.
+    pub feerate_floor_refresh_secs: u64,
+    /// How many seconds between two writes of the network
+    /// graph on disk, zero disables the periodic writes.
+    pub graph_persist_interval_secs: u64,
+    /// The port of the Tor control, when specified the node
+    /// is announced with an onion address too.
+    pub tor_control_port: Option<u16>,
+    pub tor_control_password: Option<String>,
+    /// How many confirmations the funding transaction of an inbound
+    /// channel needs before the channel is usable, by default the
+    /// one of `ldk_conf`.
+    pub minimum_depth: Option<u32>,
+    /// The minimum depth required to the inbound channels of a
+    /// peer in place of `minimum_depth`, zero accepts them as
+    /// zero conf channels.
+    pub peer_minimum_depth: HashMap<PublicKey, u32>,
+    /// The half life of the liquidity bounds learned by the
+    /// scorer from the payments.
+    pub scorer_liquidity_half_life_secs: u64,
+    /// The half life of the historical liquidity data of the scorer,
+    /// used when there are no new payments through a channel.
+    pub scorer_historical_half_life_secs: u64,
+    /// The `User-Agent` header sent to the esplora server, some
+    /// providers block the clients that do not identify themselves.
+    pub esplora_user_agent: String,
+    /// The esplora server used by the wallet, by default the
+    /// mempool.space one for bitcoin and testnet.
+    pub esplora_url: Option<String>,
+    /// How many seconds we wait the peer to sign the funding of a
+    /// channel that we are opening before abandoning it, zero
+    /// waits forever.
+    pub channel_open_timeout_secs: u64,
+    /// How many blocks the backend can be behind the network tip
+    /// when lampod starts.
+    pub startup_sync_max_blocks_behind: u32,
+    /// How many seconds lampod waits the backend to sync at
+    /// startup before giving up, zero waits forever.
+    pub startup_sync_timeout_secs: u64,
+    /// Enable the RPCs that can lose funds when they are misused,
+    /// e.g. the broadcast of our commitment transaction, and the
+    /// invoices below `min_invoice_msat`.
+    pub allow_unsafe_rpc: bool,
+    /// How many seconds between two rounds of probes of the liquidity
+    /// of the channels of our peers, zero disables the prober.
+    pub probe_interval_secs: u64,
+    /// The amount of every probe.
+    pub probe_amount_msat: u64,
+    /// A fixed max exposure to the dust HTLCs of a channel, in place
+    /// of the LDK default that is a multiple of the feerate.
+    pub max_dust_htlc_exposure_msat: Option<u64>,
+    /// How many outbound payments can be in flight at the same
+    /// time, a new payment above it is rejected.
+    pub max_inflight_payments: Option<usize>,
+    /// The smallest amount of the invoices that we issue, a
+    /// request for a smaller invoice is rejected.
+    pub min_invoice_msat: Option<u64>,
+    /// How many channels a peer can have with us, an
+    /// inbound channel above it is rejected.
+    pub max_channels_per_peer: Option<usize>,
+    /// How many hops a route of our payments can have, the
+    /// router searches only the paths within it.
+    pub max_route_hops: Option<u8>,
+    /// The biggest HTLC that we accept for our payments, the
+    /// payments with a bigger HTLC are failed back.
+    pub max_inbound_htlc_msat: Option<u64>,
+    /// The custom feature bits that we advertise in the init and
+    /// node announcement messages, an even bit is required.
+    pub feature_bits: Vec<usize>,
+    /// The feature bits that we do not advertise, only the
+    /// features that LDK enables with the config can be disabled.
+    pub disable_feature_bits: Vec<usize>,
+    /// The RPC methods that every auth token can call, when
+    /// empty everybody can call all the methods.
+    pub rpc_acl: HashMap<String, Vec<String>>,
+    /// The http uris of the watchtowers that receive
+    /// the monitors of our channels.
+    pub watchtowers: Vec<String>,
+}
+
+/// How we authenticate with bitcoin core.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CoreAuth {
+    UserPass(String, String),
+    /// The path of the cookie file, bitcoind writes a new
+    /// one every time that it starts.
+    Cookie(String),
+}
+
+impl CoreAuth {
+    /// Return the user and the password, the cookie
+    /// file is read every time.
+    pub fn credentials(&self) -> Result<(String, String), anyhow::Error> {
+        match self {
+            Self::UserPass(user, pass) => Ok((user.clone(), pass.clone())),
+            Self::Cookie(path) => {
+                let cookie = std::fs::read_to_string(path).map_err(|err| {
+                    anyhow::anyhow!("impossible to read the bitcoind cookie file `{path}`: {err}")
+                })?;
+                let Some((user, pass)) = cookie.trim().split_once(':') else {
+                    anyhow::bail!(
+                        "the bitcoind cookie file `{path}` is not in the `user:password` format"
+                    );
+                };
+                Ok((user.to_owned(), pass.to_owned()))
+            }
+        }
+    }
 }
 
 impl Default for LampoConf {
@@ -45,17 +217,247 @@ impl Default for LampoConf {
             core_url: None,
             core_user: None,
             core_pass: None,
+            core_cookie: None,
             private_key: None,
             channels_keys: None,
             log_level: "info".to_string(),
             log_file: None,
             alias: None,
-            announce_addr: None,
+            announce_addresses: Vec::new(),
+            bind_addr: None,
+            onchain_fee_reserve_sat: 0,
+            anchor_reserve_utxos: 0,
+            upfront_shutdown_script: None,
+            max_feerate_per_kw: 50_000,
+            wallet_sync_staleness_secs: 600,
+            wallet_sync_interval_secs: 0,
+            sweep_confirmation_target: 12,
+            sweep_feerate: None,
+            rebroadcast_interval_secs: 600,
+            feerate_floor_refresh_secs: 600,
+            graph_persist_interval_secs: 600,
+            tor_control_port: None,
+            tor_control_password: None,
+            minimum_depth: None,
+            peer_minimum_depth: HashMap::new(),
+            scorer_liquidity_half_life_secs: 6 * 60 * 60,
+            scorer_historical_half_life_secs: 14 * 24 * 60 * 60,
+            esplora_user_agent: DEFAULT_USER_AGENT.to_owned(),
+            esplora_url: None,
+            channel_open_timeout_secs: 300,
+            startup_sync_max_blocks_behind: 2,
+            startup_sync_timeout_secs: 3600,
+            allow_unsafe_rpc: false,
+            probe_interval_secs: 0,
+            probe_amount_msat: 1_000_000,
+            max_dust_htlc_exposure_msat: None,
+            max_inflight_payments: None,
+            min_invoice_msat: None,
+            max_channels_per_peer: None,
+            max_route_hops: None,
+            max_inbound_htlc_msat: None,
+            feature_bits: Vec::new(),
+            disable_feature_bits: Vec::new(),
+            rpc_acl: HashMap::new(),
+            watchtowers: Vec::new(),
         }
     }
 }
 
 impl LampoConf {
+    /// The authentication with bitcoin core, the cookie file
+    /// is an alternative to the user and the password.
+    pub fn core_auth(&self) -> Result<CoreAuth, anyhow::Error> {
+        match (&self.core_cookie, &self.core_user, &self.core_pass) {
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+                anyhow::bail!(
+                    "`core-cookie` can not be used together with `core-user` and `core-pass`"
+                )
+            }
+            (Some(cookie), None, None) => Ok(CoreAuth::Cookie(cookie.clone())),
+            (None, Some(user), Some(pass)) => Ok(CoreAuth::UserPass(user.clone(), pass.clone())),
+            (None, None, _) => anyhow::bail!("Miss the bitcoin user or the cookie file for auth"),
+            (None, Some(_), None) => anyhow::bail!("Miss the bitcoin password for auth"),
+        }
+    }
+
+    /// The minimum depth required to the inbound channels of `node_id`.
+    pub fn minimum_depth(&self, node_id: &PublicKey) -> u32 {
+        self.peer_minimum_depth
+            .get(node_id)
+            .copied()
+            .unwrap_or(self.global_minimum_depth())
+    }
+
+    /// The minimum depth required to the inbound channels of the
+    /// peers without a depth of their own.
+    pub fn global_minimum_depth(&self) -> u32 {
+        self.minimum_depth
+            .unwrap_or(self.ldk_conf.channel_handshake_config.minimum_depth)
+    }
+
+    /// LDK applies the global minimum depth to every channel that is
+    /// not zero conf, so a peer can only have a depth of zero or the
+    /// global one.
+    pub fn check_peer_minimum_depth(&self) -> anyhow::Result<()> {
+        let global = self.global_minimum_depth();
+        if let Some((node_id, depth)) = self
+            .peer_minimum_depth
+            .iter()
+            .find(|(_, depth)| **depth != 0 && **depth != global)
+        {
+            anyhow::bail!(
+                "peer minimum depth `{node_id}:{depth}` is not supported, a peer can have a depth of 0 or the global minimum depth {global}"
+            );
+        }
+        Ok(())
+    }
+
+    /// How fast the scorer forgets what it learned from the payments.
+    pub fn scoring_decay_parameters(&self) -> ProbabilisticScoringDecayParameters {
+        ProbabilisticScoringDecayParameters {
+            liquidity_offset_half_life: Duration::from_secs(self.scorer_liquidity_half_life_secs),
+            historical_no_updates_half_life: Duration::from_secs(
+                self.scorer_historical_half_life_secs,
+            ),
+        }
+    }
+
+    /// Parse the list of `<node_id>:<depth>` separated by comma.
+    fn parse_peer_minimum_depth(value: &str) -> anyhow::Result<HashMap<PublicKey, u32>> {
+        value
+            .split(',')
+            .map(|peer| {
+                let Some((node_id, depth)) = peer.trim().split_once(':') else {
+                    anyhow::bail!(
+                        "peer minimum depth `{peer}` is not in the `<node_id>:<depth>` format"
+                    );
+                };
+                let node_id = PublicKey::from_str(node_id)
+                    .map_err(|err| anyhow::anyhow!("peer minimum depth `{peer}`: {err}"))?;
+                Ok((node_id, u32::from_str(depth)?))
+            })
+            .collect()
+    }
+
+    /// Parse the `<token>:<method>,<method>` entries of the RPC
+    /// access control list, the `*` method allows all of them.
+    fn parse_rpc_acl(entries: &[String]) -> anyhow::Result<HashMap<String, Vec<String>>> {
+        let mut acl: HashMap<String, Vec<String>> = HashMap::new();
+        for entry in entries.iter() {
+            let Some((token, methods)) = entry.trim().split_once(':') else {
+                anyhow::bail!("rpc acl `{entry}` is not in the `<token>:<method>,<method>` format");
+            };
+            if token.is_empty() {
+                anyhow::bail!("rpc acl `{entry}` has an empty token");
+            }
+            acl.entry(token.to_owned()).or_default().extend(
+                methods
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|method| !method.is_empty())
+                    .map(str::to_owned),
+            );
+        }
+        Ok(acl)
+    }
+
+    /// The watchtowers are reached over http.
+    pub fn parse_watchtower(uri: &str) -> anyhow::Result<String> {
+        let uri = uri.trim();
+        if !uri.starts_with("http://") && !uri.starts_with("https://") {
+            anyhow::bail!("watchtower `{uri}` is not a http uri");
+        }
+        Ok(uri.trim_end_matches('/').to_owned())
+    }
+
+    /// Parse an announce address, the `port` is used
+    /// when the address does not have one.
+    fn parse_announce_address(addr: &str, port: u64) -> anyhow::Result<SocketAddress> {
+        let addr = addr.trim();
+        SocketAddress::from_str(addr)
+            .or_else(|_| SocketAddress::from_str(&format!("{addr}:{port}")))
+            .map_err(|err| anyhow::anyhow!("invalid announce address `{addr}`: {err:?}"))
+    }
+
+    /// Parse the max hops of a route, that must be
+    /// inside the bounds of BOLT 4.
+    fn parse_max_route_hops(hops: &str) -> anyhow::Result<u8> {
+        let hops = u8::from_str(hops)
+            .map_err(|err| anyhow::anyhow!("invalid max route hops `{hops}`: {err}"))?;
+        if hops == 0 || hops > MAX_ROUTE_HOPS {
+            anyhow::bail!("max route hops `{hops}` must be between 1 and {MAX_ROUTE_HOPS}");
+        }
+        Ok(hops)
+    }
+
+    fn split_feature_bits(bits: &str) -> anyhow::Result<Vec<usize>> {
+        bits.split(',')
+            .map(str::trim)
+            .filter(|bit| !bit.is_empty())
+            .map(|bit| {
+                usize::from_str(bit)
+                    .map_err(|err| anyhow::anyhow!("invalid feature bit `{bit}`: {err}"))
+            })
+            .collect()
+    }
+
+    /// Parse the comma separated feature bits to advertise, only
+    /// the custom bits are accepted because LDK owns the other ones.
+    fn parse_feature_bits(bits: &str) -> anyhow::Result<Vec<usize>> {
+        let bits = Self::split_feature_bits(bits)?;
+        if let Some(bit) = bits.iter().find(|bit| **bit < FIRST_CUSTOM_FEATURE_BIT) {
+            anyhow::bail!(
+                "feature bit `{bit}` is owned by LDK, only the bits from {FIRST_CUSTOM_FEATURE_BIT} can be advertised"
+            );
+        }
+        Ok(bits)
+    }
+
+    /// Parse the comma separated feature bits to disable, a mandatory
+    /// feature can never be disabled.
+    fn parse_disabled_feature_bits(bits: &str) -> anyhow::Result<Vec<usize>> {
+        let bits = Self::split_feature_bits(bits)?;
+        for bit in bits.iter() {
+            let feature = bit & !1;
+            if MANDATORY_FEATURE_BITS.contains(&feature) {
+                anyhow::bail!("feature bit `{bit}` is mandatory, it can not be disabled");
+            }
+            if !CONFIGURABLE_FEATURE_BITS.contains(&feature) {
+                anyhow::bail!(
+                    "feature bit `{bit}` is always advertised by LDK, it can not be disabled"
+                );
+            }
+        }
+        Ok(bits)
+    }
+
+    /// The feature of `bit` must not be advertised, both
+    /// the required and the optional bits are disabled.
+    pub fn feature_disabled(&self, bit: usize) -> bool {
+        self.disable_feature_bits
+            .iter()
+            .any(|disabled| disabled & !1 == bit & !1)
+    }
+
+    /// The addresses that we announce in the configured order,
+    /// without duplicates.
+    ///
+    /// LDK sorts the addresses of the node announcement by type, as
+    /// required by BOLT 7, so the order is kept between the addresses
+    /// of the same type, e.g. the IPv4 addresses are always before the
+    /// onion ones.
+    pub fn announce_socket_addresses(&self) -> anyhow::Result<Vec<SocketAddress>> {
+        let mut addresses: Vec<SocketAddress> = Vec::new();
+        for addr in self.announce_addresses.iter() {
+            let addr = Self::parse_announce_address(addr, self.port)?;
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        Ok(addresses)
+    }
+
     pub fn prepare_dirs(&self) -> Result<(), anyhow::Error> {
         Self::prepare_directories(&self.root_path, Some(self.network))
     }
@@ -176,6 +578,7 @@ impl TryFrom<String> for LampoConf {
         let mut core_url = None;
         let mut core_user = None;
         let mut core_pass = None;
+        let mut core_cookie = None;
         if node == "core" {
             core_url = conf
                 .get_conf("core-url")
@@ -192,6 +595,11 @@ impl TryFrom<String> for LampoConf {
                 .get_conf("core-pass")
                 .map_err(|err| anyhow::anyhow!("{err}"))?;
             core_pass = core_pass.map(|pass| pass.to_trimmed());
+
+            core_cookie = conf
+                .get_conf("core-cookie")
+                .map_err(|err| anyhow::anyhow!("{err}"))?;
+            core_cookie = core_cookie.map(|cookie| cookie.to_trimmed());
         }
         // Dev options
         #[allow(unused_mut, unused_assignments)]
@@ -219,9 +627,211 @@ impl TryFrom<String> for LampoConf {
         };
         let log_file = conf.get_conf("log-file").unwrap_or(None);
         let alias = conf.get_conf("alias").unwrap_or(None);
-        let announce_addr = conf.get_conf("announce-addr").unwrap_or(None);
+        let announce_addresses = conf
+            .get_conf("announce-addr")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|addresses| {
+                addresses
+                    .split(',')
+                    .map(|addr| addr.trim().to_owned())
+                    .filter(|addr| !addr.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        for addr in announce_addresses.iter() {
+            Self::parse_announce_address(addr, u64::from_str(&port)?)?;
+        }
+        let bind_addr = conf.get_conf("bind-addr").unwrap_or(None);
+        let onchain_fee_reserve_sat = conf
+            .get_conf("onchain-fee-reserve-sat")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|reserve| u64::from_str(&reserve.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().onchain_fee_reserve_sat);
+        let anchor_reserve_utxos = conf
+            .get_conf("anchor-reserve-utxos")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|utxos| u64::from_str(&utxos.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().anchor_reserve_utxos);
+        let upfront_shutdown_script = conf
+            .get_conf("upfront-shutdown-script")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|script| script.to_trimmed());
+        if let Some(ref script) = upfront_shutdown_script {
+            Self::parse_shutdown_script(script, network)?;
+        }
+        let max_feerate_per_kw = conf
+            .get_conf("max-feerate-per-kw")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|feerate| u32::from_str(&feerate.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().max_feerate_per_kw);
+        let wallet_sync_staleness_secs = conf
+            .get_conf("wallet-sync-staleness-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().wallet_sync_staleness_secs);
+        let wallet_sync_interval_secs = conf
+            .get_conf("wallet-sync-interval-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().wallet_sync_interval_secs);
+        let sweep_confirmation_target = conf
+            .get_conf("sweep-confirmation-target")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|blocks| u16::from_str(&blocks.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().sweep_confirmation_target);
+        let sweep_feerate = conf
+            .get_conf("sweep-feerate")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|feerate| u32::from_str(&feerate.to_trimmed()))
+            .transpose()?;
+        let rebroadcast_interval_secs = conf
+            .get_conf("rebroadcast-interval-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().rebroadcast_interval_secs);
+        let feerate_floor_refresh_secs = conf
+            .get_conf("feerate-floor-refresh-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().feerate_floor_refresh_secs);
+        let graph_persist_interval_secs = conf
+            .get_conf("graph-persist-interval-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().graph_persist_interval_secs);
+        let scorer_liquidity_half_life_secs = conf
+            .get_conf("scorer-liquidity-half-life-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().scorer_liquidity_half_life_secs);
+        let scorer_historical_half_life_secs = conf
+            .get_conf("scorer-historical-half-life-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().scorer_historical_half_life_secs);
+        let channel_open_timeout_secs = conf
+            .get_conf("channel-open-timeout-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().channel_open_timeout_secs);
+        let startup_sync_max_blocks_behind = conf
+            .get_conf("startup-sync-max-blocks-behind")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|blocks| u32::from_str(&blocks.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().startup_sync_max_blocks_behind);
+        let startup_sync_timeout_secs = conf
+            .get_conf("startup-sync-timeout-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().startup_sync_timeout_secs);
+        let allow_unsafe_rpc = conf
+            .get_conf("allow-unsafe-rpc")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|allow| bool::from_str(&allow.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().allow_unsafe_rpc);
+        let probe_interval_secs = conf
+            .get_conf("probe-interval-secs")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|secs| u64::from_str(&secs.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().probe_interval_secs);
+        let probe_amount_msat = conf
+            .get_conf("probe-amount-msat")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|amount| u64::from_str(&amount.to_trimmed()))
+            .transpose()?
+            .unwrap_or(Self::default().probe_amount_msat);
+        let max_dust_htlc_exposure_msat = conf
+            .get_conf("max-dust-htlc-exposure-msat")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|limit| u64::from_str(&limit.to_trimmed()))
+            .transpose()?;
+        let max_inflight_payments = conf
+            .get_conf("max-inflight-payments")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|limit| usize::from_str(&limit.to_trimmed()))
+            .transpose()?;
+        let min_invoice_msat = conf
+            .get_conf("min-invoice-msat")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|amount| u64::from_str(&amount.to_trimmed()))
+            .transpose()?;
+        let max_channels_per_peer = conf
+            .get_conf("max-channels-per-peer")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|limit| usize::from_str(&limit.to_trimmed()))
+            .transpose()?;
+        let max_route_hops = conf
+            .get_conf("max-route-hops")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|hops| Self::parse_max_route_hops(&hops.to_trimmed()))
+            .transpose()?;
+        let max_inbound_htlc_msat = conf
+            .get_conf("max-inbound-htlc-msat")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|amount| u64::from_str(&amount.to_trimmed()))
+            .transpose()?;
+        let feature_bits = conf
+            .get_conf("feature-bits")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|bits| Self::parse_feature_bits(&bits))
+            .transpose()?
+            .unwrap_or_default();
+        let disable_feature_bits = conf
+            .get_conf("disable-feature-bits")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|bits| Self::parse_disabled_feature_bits(&bits))
+            .transpose()?
+            .unwrap_or_default();
+        let rpc_acl = Self::parse_rpc_acl(&conf.get_confs("rpc-acl"))?;
+        let watchtowers = conf
+            .get_confs("watchtower")
+            .iter()
+            .map(|uri| Self::parse_watchtower(uri))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let esplora_user_agent = conf
+            .get_conf("esplora-user-agent")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|agent| agent.to_trimmed())
+            .unwrap_or(Self::default().esplora_user_agent);
+        let esplora_url = conf
+            .get_conf("esplora-url")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|url| url.to_trimmed());
+        let tor_control_port = conf
+            .get_conf("tor-control-port")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|port| u16::from_str(&port.to_trimmed()))
+            .transpose()?;
+        let tor_control_password = conf.get_conf("tor-control-password").unwrap_or(None);
+        let minimum_depth = conf
+            .get_conf("minimum-depth")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|depth| u32::from_str(&depth.to_trimmed()))
+            .transpose()?;
+        let peer_minimum_depth = conf
+            .get_conf("peer-minimum-depth")
+            .map_err(|err| anyhow::anyhow!("{err}"))?
+            .map(|peers| Self::parse_peer_minimum_depth(&peers.to_trimmed()))
+            .transpose()?
+            .unwrap_or_default();
 
-        Ok(Self {
+        let lampo_conf = Self {
             inner: Some(conf),
             root_path,
             network,
@@ -231,13 +841,52 @@ impl TryFrom<String> for LampoConf {
             core_url,
             core_user,
             core_pass,
+            core_cookie,
             private_key,
             channels_keys,
             log_file,
             log_level: level,
             alias,
-            announce_addr,
-        })
+            announce_addresses,
+            bind_addr,
+            onchain_fee_reserve_sat,
+            anchor_reserve_utxos,
+            upfront_shutdown_script,
+            max_feerate_per_kw,
+            wallet_sync_staleness_secs,
+            wallet_sync_interval_secs,
+            sweep_confirmation_target,
+            sweep_feerate,
+            rebroadcast_interval_secs,
+            feerate_floor_refresh_secs,
+            graph_persist_interval_secs,
+            tor_control_port,
+            tor_control_password,
+            minimum_depth,
+            peer_minimum_depth,
+            scorer_liquidity_half_life_secs,
+            scorer_historical_half_life_secs,
+            esplora_user_agent,
+            esplora_url,
+            channel_open_timeout_secs,
+            startup_sync_max_blocks_behind,
+            startup_sync_timeout_secs,
+            allow_unsafe_rpc,
+            probe_interval_secs,
+            probe_amount_msat,
+            max_dust_htlc_exposure_msat,
+            max_inflight_payments,
+            min_invoice_msat,
+            max_channels_per_peer,
+            max_route_hops,
+            max_inbound_htlc_msat,
+            feature_bits,
+            disable_feature_bits,
+            rpc_acl,
+            watchtowers,
+        };
+        lampo_conf.check_peer_minimum_depth()?;
+        Ok(lampo_conf)
     }
 }
 
@@ -266,6 +915,29 @@ impl LampoConf {
         self.network = Network::from_str(network)?;
         Ok(())
     }
+
+    /// Return the upfront shutdown script if any, checking that
+    /// it is a standard script for the node network.
+    pub fn upfront_shutdown_script(&self) -> anyhow::Result<Option<ShutdownScript>> {
+        self.upfront_shutdown_script
+            .as_ref()
+            .map(|script| Self::parse_shutdown_script(script, self.network))
+            .transpose()
+    }
+
+    /// Return the shutdown script that pays `address`, checking that
+    /// it is a standard script for the node network.
+    pub fn shutdown_script(&self, address: &str) -> anyhow::Result<ShutdownScript> {
+        Self::parse_shutdown_script(address, self.network)
+    }
+
+    fn parse_shutdown_script(address: &str, network: Network) -> anyhow::Result<ShutdownScript> {
+        let address = Address::from_str(address)?
+            .require_network(network)
+            .map_err(|err| anyhow::anyhow!("shutdown script `{address}`: {err}"))?;
+        ShutdownScript::try_from(address.script_pubkey())
+            .map_err(|err| anyhow::anyhow!("shutdown script `{address}` is not standard: {err:?}"))
+    }
 }
 
 // A trait to trim a String
@@ -278,3 +950,156 @@ impl TrimmedString for String {
         self.trim().to_owned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::PublicKey;
+
+    use super::{CoreAuth, LampoConf, ANCHORS_FEATURE_BIT, SCID_PRIVACY_FEATURE_BIT};
+
+    #[test]
+    fn cookie_file_credentials() {
+        let path = std::env::temp_dir().join(format!("lampo-cookie-{}", std::process::id()));
+        std::fs::write(&path, "__cookie__:secret\n").unwrap();
+        let conf = LampoConf {
+            core_cookie: Some(path.to_string_lossy().to_string()),
+            ..LampoConf::default()
+        };
+        let auth = conf.core_auth().unwrap();
+        assert_eq!(auth, CoreAuth::Cookie(path.to_string_lossy().to_string()));
+        assert_eq!(
+            auth.credentials().unwrap(),
+            ("__cookie__".to_owned(), "secret".to_owned())
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let err = auth.credentials().unwrap_err();
+        assert!(err.to_string().contains("cookie file"), "{err}");
+    }
+
+    #[test]
+    fn cookie_with_user_and_pass_is_an_error() {
+        let conf = LampoConf {
+            core_user: Some("lampo".to_owned()),
+            core_pass: Some("lampo".to_owned()),
+            core_cookie: Some("/tmp/.cookie".to_owned()),
+            ..LampoConf::default()
+        };
+        assert!(conf.core_auth().is_err());
+    }
+
+    #[test]
+    fn peer_minimum_depth() {
+        let node_id = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let peers = LampoConf::parse_peer_minimum_depth(&format!("{node_id}:0")).unwrap();
+        let conf = LampoConf {
+            minimum_depth: Some(3),
+            peer_minimum_depth: peers,
+            ..LampoConf::default()
+        };
+        assert_eq!(
+            conf.minimum_depth(&PublicKey::from_str(node_id).unwrap()),
+            0
+        );
+        let other = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        assert_eq!(conf.minimum_depth(&PublicKey::from_str(other).unwrap()), 3);
+        assert!(LampoConf::parse_peer_minimum_depth(node_id).is_err());
+        assert!(LampoConf::parse_peer_minimum_depth("not_a_node_id:1").is_err());
+        assert!(conf.check_peer_minimum_depth().is_ok());
+
+        // Without `minimum-depth` the depth of LDK is used.
+        let default = LampoConf::default();
+        assert_eq!(
+            default.minimum_depth(&PublicKey::from_str(other).unwrap()),
+            default.ldk_conf.channel_handshake_config.minimum_depth
+        );
+
+        // LDK can not apply a depth of 1 to a single peer.
+        let conf = LampoConf {
+            peer_minimum_depth: LampoConf::parse_peer_minimum_depth(&format!("{node_id}:1"))
+                .unwrap(),
+            ..conf
+        };
+        assert!(conf.check_peer_minimum_depth().is_err());
+        let conf = LampoConf {
+            minimum_depth: Some(1),
+            ..conf
+        };
+        assert!(conf.check_peer_minimum_depth().is_ok());
+    }
+
+    #[test]
+    fn announce_addresses_in_order() {
+        let onion = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion:9735";
+        let conf = LampoConf {
+            port: 9735,
+            announce_addresses: vec![
+                "10.0.0.2".to_owned(),
+                onion.to_owned(),
+                "10.0.0.1:9736".to_owned(),
+                "10.0.0.2:9735".to_owned(),
+            ],
+            ..LampoConf::default()
+        };
+        let addresses = conf
+            .announce_socket_addresses()
+            .unwrap()
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            addresses,
+            vec![
+                "10.0.0.2:9735".to_owned(),
+                onion.to_owned(),
+                "10.0.0.1:9736".to_owned(),
+            ]
+        );
+        assert!(LampoConf::parse_announce_address("10.0.0.1:port", 9735).is_err());
+    }
+
+    #[test]
+    fn feature_bits_overrides() {
+        assert_eq!(
+            LampoConf::parse_feature_bits("257, 300").unwrap(),
+            vec![257, 300]
+        );
+        // The bits of LDK can not be advertised.
+        assert!(LampoConf::parse_feature_bits("23").is_err());
+        assert!(LampoConf::parse_feature_bits("two").is_err());
+
+        let conf = LampoConf {
+            disable_feature_bits: LampoConf::parse_disabled_feature_bits("23").unwrap(),
+            ..LampoConf::default()
+        };
+        assert!(conf.feature_disabled(ANCHORS_FEATURE_BIT));
+        assert!(!conf.feature_disabled(SCID_PRIVACY_FEATURE_BIT));
+        // `payment_secret` is mandatory.
+        assert!(LampoConf::parse_disabled_feature_bits("15").is_err());
+        // `basic_mpp` is always advertised by LDK.
+        assert!(LampoConf::parse_disabled_feature_bits("17").is_err());
+    }
+
+    #[test]
+    fn max_route_hops_bounds() {
+        assert_eq!(LampoConf::parse_max_route_hops("3").unwrap(), 3);
+        assert_eq!(LampoConf::parse_max_route_hops("20").unwrap(), 20);
+        assert!(LampoConf::parse_max_route_hops("0").is_err());
+        assert!(LampoConf::parse_max_route_hops("21").is_err());
+        assert!(LampoConf::parse_max_route_hops("-1").is_err());
+    }
+
+    #[test]
+    fn rpc_acl_entries() {
+        let entries = ["reader:getinfo, channels", "reader:funds", "admin:*"]
+            .map(str::to_owned)
+            .to_vec();
+        let acl = LampoConf::parse_rpc_acl(&entries).unwrap();
+        assert_eq!(acl["reader"], vec!["getinfo", "channels", "funds"]);
+        assert_eq!(acl["admin"], vec!["*"]);
+        assert!(LampoConf::parse_rpc_acl(&["getinfo".to_owned()]).is_err());
+        assert!(LampoConf::parse_rpc_acl(&[":getinfo".to_owned()]).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+//! Lampo error types.
+pub use anyhow::*;
+
+/// The codes of the errors returned by the lampo JSON RPC
+/// methods, so a client can branch on the failure type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LampoErrorCode {
+    /// An error without a more specific code.
+    Generic,
+    /// The parameters of the request are not valid.
+    InvalidParams,
+    /// The on chain funds are not enough.
+    InsufficientFunds,
+    /// There is no route to the payment destination.
+    NoRoute,
+    /// The peer is not connected, or it is not reachable.
+    PeerOffline,
+    /// The channel was not found.
+    ChannelNotFound,
+    /// The wallet was not synced recently.
+    WalletNotSynced,
+    /// The invoice or payment hash was not found.
+    InvoiceNotFound,
+    /// There are too many outbound payments in flight.
+    TooManyPayments,
+}
+
+impl LampoErrorCode {
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Generic => -1,
+            // Same code of the JSON RPC 2.0 specification.
+            Self::InvalidParams => -32602,
+            Self::InsufficientFunds => 1001,
+            Self::NoRoute => 1002,
+            Self::PeerOffline => 1003,
+            Self::ChannelNotFound => 1004,
+            Self::WalletNotSynced => 1005,
+            Self::InvoiceNotFound => 1006,
+            Self::TooManyPayments => 1007,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Self {
+        match code {
+            -32602 => Self::InvalidParams,
+            1001 => Self::InsufficientFunds,
+            1002 => Self::NoRoute,
+            1003 => Self::PeerOffline,
+            1004 => Self::ChannelNotFound,
+            1005 => Self::WalletNotSynced,
+            1006 => Self::InvoiceNotFound,
+            1007 => Self::TooManyPayments,
+            _ => Self::Generic,
+        }
+    }
+}
+
+impl From<LampoErrorCode> for i32 {
+    fn from(value: LampoErrorCode) -> Self {
+        value.code()
+    }
+}
@@ -35,6 +35,8 @@ pub enum LightningEvent {
         path: Vec<PaymentHop>,
     },
     ChannelEvent {
+        /// The temporary id while the channel is not funded.
+        channel_id: ChannelId,
         state: ChannelState,
         message: String,
     },
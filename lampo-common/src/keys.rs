@@ -1,8 +1,12 @@
-use std::{sync::Arc, time::SystemTime};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
+use bitcoin::hashes::{sha256, Hash, HashEngine};
 use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use lightning::ln::script::ShutdownScript;
 use lightning::sign::{InMemorySigner, NodeSigner, OutputSpender, SignerProvider};
 
+use crate::error;
 use crate::ldk::sign::{EntropySource, KeysManager};
 
 /// Lampo keys implementations
@@ -26,6 +30,24 @@ impl LampoKeys {
         }
     }
 
+    /// Build the keys from the secret derived by the wallet,
+    /// checking that it is a valid secp256k1 secret key.
+    pub fn from_secret(secret: [u8; 32]) -> error::Result<Self> {
+        Self::validate_secret(&secret)?;
+        Ok(Self::new(secret))
+    }
+
+    /// Return an error if `secret` is zero or it is not
+    /// lower than the order of the secp256k1 curve.
+    pub fn validate_secret(secret: &[u8; 32]) -> error::Result<()> {
+        SecretKey::from_slice(secret).map_err(|err| {
+            error::anyhow!(
+                "the secret key derived by the wallet is not a valid secp256k1 key: {err}"
+            )
+        })?;
+        Ok(())
+    }
+
     #[cfg(debug_assertions)]
     pub fn with_channel_keys(seed: [u8; 32], channels_keys: String) -> Self {
         // Fill in random_32_bytes with secure random data, or, on restart, reload the seed from disk.
@@ -64,6 +86,8 @@ pub struct LampoKeysManager {
     delayed_payment_base_secret: Option<SecretKey>,
     htlc_base_secret: Option<SecretKey>,
     shachain_seed: Option<[u8; 32]>,
+
+    shutdown_script: RwLock<Option<ShutdownScript>>,
 }
 
 impl LampoKeysManager {
@@ -77,9 +101,25 @@ impl LampoKeysManager {
             delayed_payment_base_secret: None,
             htlc_base_secret: None,
             shachain_seed: None,
+            shutdown_script: RwLock::new(None),
         }
     }
 
+    /// Key used to encrypt the static channel backups, derived
+    /// from the node secret so it survives a restore from the seed.
+    pub fn channel_backup_key(&self) -> [u8; 32] {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.inner.get_node_secret_key().secret_bytes());
+        engine.input(b"lampo channel backup");
+        sha256::Hash::from_engine(engine).to_byte_array()
+    }
+
+    /// Set the script used by all the cooperative closes
+    /// instead of the one derived by the inner keys manager.
+    pub fn set_shutdown_script(&self, script: ShutdownScript) {
+        *self.shutdown_script.write().unwrap() = Some(script);
+    }
+
     // FIXME: put this under a debug a feature flag like `unsafe_channel_keys`
     #[cfg(debug_assertions)]
     pub fn set_channels_keys(
@@ -228,7 +268,10 @@ impl SignerProvider for LampoKeysManager {
         self.inner.get_destination_script(channel_keys_id)
     }
 
-    fn get_shutdown_scriptpubkey(&self) -> Result<lightning::ln::script::ShutdownScript, ()> {
+    fn get_shutdown_scriptpubkey(&self) -> Result<ShutdownScript, ()> {
+        if let Some(script) = self.shutdown_script.read().unwrap().as_ref() {
+            return Ok(script.clone());
+        }
         self.inner.get_shutdown_scriptpubkey()
     }
 
@@ -239,3 +282,21 @@ impl SignerProvider for LampoKeysManager {
         self.inner.read_chan_signer(reader)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::LampoKeys;
+
+    #[test]
+    fn reject_invalid_secret() {
+        let Err(err) = LampoKeys::from_secret([0; 32]) else {
+            panic!("an all zeros secret must be rejected");
+        };
+        assert!(
+            err.to_string().contains("not a valid secp256k1 key"),
+            "{err}"
+        );
+        assert!(LampoKeys::from_secret([0xff; 32]).is_err());
+        assert!(LampoKeys::from_secret([1; 32]).is_ok());
+    }
+}
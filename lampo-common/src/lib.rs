@@ -1,5 +1,6 @@
 pub mod backend;
 pub mod conf;
+pub mod error;
 pub mod event;
 pub mod handler;
 pub mod keys;
@@ -17,10 +18,6 @@ pub mod ldk {
     pub use lightning_persister as persister;
 }
 
-pub mod error {
-    pub use anyhow::*;
-}
-
 pub mod json {
     pub use serde::de::DeserializeOwned;
     pub use serde::{Deserialize, Serialize};
@@ -47,4 +44,14 @@ pub mod btc_rpc {
         /// Minimum fee rate in BTC/kB for tx to be accepted. Is the maximum of minrelaytxfee and minimum mempool fee
         pub mempoolminfee: f32,
     }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct MempoolInfo {
+        /// Current tx count
+        pub size: u64,
+        /// Sum of all virtual transaction sizes
+        pub bytes: u64,
+        /// Current minimum relay fee for transactions in BTC/kB
+        pub minrelaytxfee: f32,
+    }
 }
@@ -4,6 +4,7 @@
 use std::io;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::RwLock;
 use std::time::SystemTime;
 // FIXME: this is not async we should modify it
 use std::fs::File;
@@ -11,16 +12,38 @@ use std::fs::File;
 use chrono::prelude::*;
 use colored::*;
 
-pub use log::{Level, Log, Metadata, Record, SetLoggerError};
+pub use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// The runtime filter of the logger, that can be changed
+/// while the process is running.
+struct Filter {
+    level: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(filter, _)| target == filter || target.starts_with(&format!("{filter}::")))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+}
+
+static FILTER: RwLock<Filter> = RwLock::new(Filter {
+    level: LevelFilter::Info,
+    targets: Vec::new(),
+});
 
 struct Logger {
-    level: Level,
     file: Option<File>,
 }
 
 impl Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        // SAFETY: the lock can not be poisoned.
+        metadata.level() <= FILTER.read().unwrap().level(metadata.target())
     }
 
     fn log(&self, record: &Record) {
@@ -74,10 +97,51 @@ pub fn init(level: &str, file: Option<PathBuf>) -> anyhow::Result<()> {
         None
     };
     let level = Level::from_str(level).map_err(|err| anyhow::anyhow!("{err}"))?;
-    let logger = Logger { level, file };
+    // SAFETY: the lock can not be poisoned.
+    FILTER.write().unwrap().level = level.to_level_filter();
+    let logger = Logger { file };
 
     log::set_boxed_logger(Box::new(logger)).map_err(|err| anyhow::anyhow!("{err}"))?;
-    log::set_max_level(level.to_level_filter());
+    // The filtering is done by the logger, so it can change at runtime.
+    log::set_max_level(LevelFilter::Trace);
 
     Ok(())
 }
+
+/// Change the log level at runtime for the whole process, or
+/// only for the `target` when specified, and return the previous level.
+pub fn set_level(target: Option<&str>, level: &str) -> anyhow::Result<LevelFilter> {
+    let level = LevelFilter::from_str(level).map_err(|err| anyhow::anyhow!("{err}"))?;
+    // SAFETY: the lock can not be poisoned.
+    let mut filter = FILTER.write().unwrap();
+    let Some(target) = target else {
+        let previous = filter.level;
+        filter.level = level;
+        return Ok(previous);
+    };
+    let previous = filter.level(target);
+    filter.targets.retain(|(filter, _)| filter != target);
+    filter.targets.push((target.to_owned(), level));
+    Ok(previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_level, Level, LevelFilter, Log, Logger, Metadata};
+
+    fn enabled(target: &str, level: Level) -> bool {
+        let metadata = Metadata::builder().target(target).level(level).build();
+        Logger { file: None }.enabled(&metadata)
+    }
+
+    #[test]
+    fn set_level_for_target() {
+        assert!(!enabled("wallet", Level::Trace));
+        let previous = set_level(Some("wallet"), "trace").unwrap();
+        assert_eq!(previous, LevelFilter::Info);
+        assert!(enabled("wallet", Level::Trace));
+        assert!(enabled("wallet::core", Level::Trace));
+        assert!(!enabled("ldk", Level::Trace));
+        assert!(!enabled("wallets", Level::Debug));
+    }
+}
@@ -1,37 +1,85 @@
+mod channel_backup;
+mod channel_config;
+mod channel_fee;
+mod channel_funding;
 mod close_channel;
+mod commitment;
 mod connect;
+mod dump_channel;
+mod dust_exposure;
+mod forward_error;
 mod getinfo;
+mod health;
 mod invoice;
 mod keysend;
+mod labels;
+mod lnurl;
+mod log_level;
 mod network;
 mod new_addr;
 mod on_chain;
 mod open_channel;
+mod payment_failure;
+mod peer;
+mod probe;
+mod route;
+mod tower;
 
 pub use connect::Connect;
 pub use getinfo::GetInfo;
 
 pub mod request {
+    pub use crate::model::channel_backup::request::*;
+    pub use crate::model::channel_config::request::*;
+    pub use crate::model::channel_fee::request::*;
+    pub use crate::model::channel_funding::request::*;
     pub use crate::model::close_channel::request::*;
+    pub use crate::model::commitment::request::*;
     pub use crate::model::connect::Connect;
+    pub use crate::model::dump_channel::request::*;
+    pub use crate::model::dust_exposure::request::*;
+    pub use crate::model::forward_error::request::*;
     pub use crate::model::getinfo::*;
     pub use crate::model::invoice::request::*;
     pub use crate::model::keysend::request::*;
+    pub use crate::model::labels::request::*;
+    pub use crate::model::lnurl::request::*;
+    pub use crate::model::log_level::request::*;
     pub use crate::model::network::request::*;
     pub use crate::model::new_addr::request::*;
-    #[allow(unused_imports)]
     pub use crate::model::on_chain::request::*;
     pub use crate::model::open_channel::request::*;
+    pub use crate::model::payment_failure::request::*;
+    pub use crate::model::peer::request::*;
+    pub use crate::model::route::request::*;
+    pub use crate::model::tower::request::*;
 }
 
 pub mod response {
+    pub use crate::model::channel_backup::response::*;
+    pub use crate::model::channel_config::response::*;
+    pub use crate::model::channel_fee::response::*;
+    pub use crate::model::channel_funding::response::*;
     pub use crate::model::close_channel::response::*;
+    pub use crate::model::commitment::response::*;
     pub use crate::model::connect::Connect;
+    pub use crate::model::dump_channel::response::*;
+    pub use crate::model::dust_exposure::response::*;
+    pub use crate::model::forward_error::response::*;
     pub use crate::model::getinfo::*;
+    pub use crate::model::health::response::*;
     pub use crate::model::invoice::response::*;
     pub use crate::model::keysend::response::*;
+    pub use crate::model::labels::response::*;
+    pub use crate::model::lnurl::response::*;
+    pub use crate::model::log_level::response::*;
     pub use crate::model::network::response::*;
     pub use crate::model::new_addr::response::*;
     pub use crate::model::on_chain::response::*;
     pub use crate::model::open_channel::response::*;
+    pub use crate::model::payment_failure::response::*;
+    pub use crate::model::peer::response::*;
+    pub use crate::model::probe::response::*;
+    pub use crate::model::route::response::*;
+    pub use crate::model::tower::response::*;
 }
@@ -0,0 +1,34 @@
+//! Static Channel Backup Model
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ImportChannelBackup {
+        /// The hex of the encrypted backup returned by `exportchannelbackup`.
+        pub backup: String,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ChannelBackup {
+        pub backup: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct RecoveredChannel {
+        pub funding_utxo: String,
+        pub peer_id: Option<String>,
+        /// If we reconnected to the peer to ask the force
+        /// close of the channel.
+        pub reconnected: bool,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ImportChannelBackup {
+        pub recovered: Vec<RecoveredChannel>,
+    }
+}
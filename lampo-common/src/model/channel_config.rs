@@ -0,0 +1,35 @@
+//! The forwarding config of our channels.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    /// Change the LDK channel config of a channel, the
+    /// missing values are not changed.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct UpdateChannelConfig {
+        pub channel_id: String,
+        pub base_fee_msat: Option<u32>,
+        pub fee_ppm: Option<u32>,
+        pub cltv_delta: Option<u16>,
+        pub max_dust_htlc_exposure_msat: Option<u64>,
+        pub force_close_avoidance_max_fee_sat: Option<u64>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// The channel config applied by LDK, the forwarding
+    /// values are announced with a channel update.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct UpdateChannelConfig {
+        pub channel_id: String,
+        pub peer_id: String,
+        pub base_fee_msat: u32,
+        pub fee_ppm: u32,
+        pub cltv_delta: u16,
+        /// Only one of the dust limits is set, like in `setdustexposure`.
+        pub max_dust_htlc_exposure_msat: Option<u64>,
+        pub feerate_multiplier: Option<u64>,
+        pub force_close_avoidance_max_fee_sat: u64,
+    }
+}
@@ -0,0 +1,45 @@
+//! The routing fees that we charge for the forwards.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ListChannelFees {
+        /// Only the fees of this channel.
+        pub channel_id: Option<String>,
+    }
+
+    /// Change the fees of a channel, the missing
+    /// values are not changed.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SetChannelFee {
+        pub channel_id: String,
+        pub base_fee_msat: Option<u32>,
+        pub fee_ppm: Option<u32>,
+        pub cltv_delta: Option<u16>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// The fee policy of a channel as set in LDK, that is
+    /// what we announce in the channel updates.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChannelFee {
+        pub channel_id: String,
+        pub short_channel_id: Option<u64>,
+        pub peer_id: String,
+        pub base_fee_msat: u32,
+        pub fee_ppm: u32,
+        pub cltv_delta: u16,
+        /// The HTLC limits of the HTLCs that we forward in the channel, set
+        /// by the peer, they are `None` until the channel is accepted.
+        pub htlc_min_msat: Option<u64>,
+        pub htlc_max_msat: Option<u64>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChannelFees {
+        pub channels: Vec<ChannelFee>,
+    }
+}
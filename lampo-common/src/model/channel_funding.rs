@@ -0,0 +1,46 @@
+//! The funding transaction of a channel.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChannelFundingTx {
+        pub channel_id: String,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct VerifyChannelFunding {
+        pub channel_id: String,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChannelFundingTx {
+        pub channel_id: String,
+        pub funding_txid: String,
+        pub funding_vout: u16,
+        /// The confirmations of the funding, `None` when it is
+        /// still unconfirmed.
+        pub confirmations: Option<u32>,
+        /// The raw transaction, `None` when the
+        /// backend does not know it.
+        pub tx_hex: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct VerifyChannelFunding {
+        pub channel_id: String,
+        pub funding_txid: String,
+        pub funding_vout: u16,
+        pub capacity_sat: u64,
+        /// The value of the funding output on chain, `None`
+        /// when the funding transaction has no such output.
+        pub funding_output_sat: Option<u64>,
+        /// The funding output carries the channel capacity.
+        pub verified: bool,
+        /// Why the funding output does not match the channel.
+        pub discrepancy: Option<String>,
+    }
+}
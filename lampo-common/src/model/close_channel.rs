@@ -13,6 +13,9 @@ pub mod request {
         pub node_id: String,
         // Hex of the channel
         pub channel_id: Option<String>,
+        /// The address that receives our funds of a cooperative
+        /// close, a fresh wallet address when it is missing.
+        pub destination: Option<String>,
     }
 
     impl CloseChannel {
@@ -57,6 +60,55 @@ pub mod response {
         pub peer_id: String,
         pub funding_utxo: String,
     }
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum CloseType {
+        Cooperative,
+        Force,
+        /// The peer broadcast a revoked commitment transaction,
+        /// so our penalty transactions claim its funds.
+        Breach,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ClosedChannel {
+        pub channel_id: String,
+        pub peer_id: Option<String>,
+        pub capacity_sat: Option<u64>,
+        pub close_type: CloseType,
+        pub reason: String,
+        pub funding_utxo: Option<String>,
+        // The closing information are known only when
+        // the closing transaction is confirmed.
+        pub closing_txid: Option<String>,
+        pub closing_height: Option<u32>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ClosedChannels {
+        pub closed_channels: Vec<ClosedChannel>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct CloseEstimate {
+        pub channel_id: String,
+        pub peer_id: String,
+        /// The fee of the cooperative close is paid by the funder.
+        pub is_funder: bool,
+        pub feerate_sat_per_kw: u32,
+        /// The fee proposed at the start of the close negotiation.
+        pub fee_sat: u64,
+        /// The highest fee that we are willing to accept during the negotiation.
+        pub max_fee_sat: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct EstimateCloseAll {
+        pub channels: Vec<CloseEstimate>,
+        pub total_fee_sat: u64,
+        pub total_max_fee_sat: u64,
+    }
 }
 
 pub mod tests {
@@ -72,6 +124,7 @@ pub mod tests {
         let req = crate::model::request::CloseChannel {
             node_id: node_id.clone(),
             channel_id: channel_hex,
+            destination: None,
         };
         let channel_bytes = [
             10, 68, 103, 117, 38, 172, 140, 96, 118, 22, 189, 145, 37, 141, 126, 93, 241, 216, 111,
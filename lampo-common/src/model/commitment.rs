@@ -0,0 +1,40 @@
+//! Broadcast of our commitment transaction, used for disaster
+//! recovery, and the fee bump of the commitment of the anchor channels.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct RebroadcastCommitment {
+        pub channel_id: String,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct BumpChannelClose {
+        pub channel_id: String,
+        /// The feerate in sats per kw of the commitment with its child.
+        pub fee_rate: u32,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct RebroadcastCommitment {
+        pub channel_id: String,
+        /// The txid of the commitment transaction.
+        pub txid: String,
+        pub tx_hex: String,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct BumpChannelClose {
+        pub channel_id: String,
+        pub commitment_txid: String,
+        /// The txid of the child that spends our anchor output.
+        pub child_txid: String,
+        pub child_fee_sat: u64,
+        /// The feerate in sats per kw of the commitment with its child.
+        pub package_fee_rate: u64,
+    }
+}
@@ -0,0 +1,47 @@
+//! Dump of the channel state used to debug stuck channels.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct DumpChannel {
+        pub channel_id: String,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// The state of a channel, without any secret.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChannelDump {
+        pub channel_id: String,
+        pub peer_id: String,
+        pub short_channel_id: Option<u64>,
+        pub funding_txid: Option<String>,
+        pub funding_vout: Option<u16>,
+        pub confirmations: Option<u32>,
+        pub is_outbound: bool,
+        pub is_channel_ready: bool,
+        pub is_usable: bool,
+        pub is_public: bool,
+        /// The shutdown state of the channel, when the shutdown started.
+        pub shutdown_state: Option<String>,
+        /// The id of the latest update applied to the channel monitor.
+        pub latest_monitor_update_id: Option<u64>,
+        pub feerate_sat_per_kw: Option<u32>,
+        pub pending_inbound_htlcs: Vec<PendingHtlc>,
+        pub pending_outbound_htlcs: Vec<PendingHtlc>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct PendingHtlc {
+        /// The outbound HTLCs do not have an id until they are
+        /// committed in the channel.
+        pub htlc_id: Option<u64>,
+        pub payment_hash: String,
+        pub amount_msat: u64,
+        pub cltv_expiry: u32,
+        pub state: Option<String>,
+        pub is_dust: bool,
+    }
+}
@@ -0,0 +1,34 @@
+//! The max exposure to the dust HTLCs of our channels.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    /// Query or change the max dust HTLC exposure of a channel,
+    /// or of all the channels when `channel_id` is missing.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SetDustExposure {
+        pub channel_id: Option<String>,
+        /// The new limit, when missing the limit is only returned.
+        pub max_dust_htlc_exposure_msat: Option<u64>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// The max dust HTLC exposure of a channel, only one of the
+    /// limits is set, like in the LDK channel config.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct DustExposure {
+        pub channel_id: String,
+        pub peer_id: String,
+        /// A fixed limit.
+        pub max_dust_htlc_exposure_msat: Option<u64>,
+        /// A limit that is this multiplier of the feerate in sats per kw.
+        pub feerate_multiplier: Option<u64>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct DustExposures {
+        pub channels: Vec<DustExposure>,
+    }
+}
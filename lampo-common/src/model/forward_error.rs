@@ -0,0 +1,44 @@
+//! The errors of the HTLCs that we failed to forward.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct LastForwardError {
+        /// Only the error of the HTLCs incoming from this channel,
+        /// the errors of all the channels when missing.
+        pub channel_id: Option<String>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// The error that we sent back upstream for the last
+    /// failed forward of an incoming channel.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ForwardError {
+        /// The channel of the incoming HTLC.
+        pub incoming_channel_id: String,
+        /// The BOLT 4 failure code, like `0x1007`.
+        pub failure_code: u16,
+        /// The name of the failure code, like `temporary_channel_failure`.
+        ///
+        /// LDK does not give back the onion failure that it sent, so the
+        /// code is decoded from the destination that failed.
+        pub failure: String,
+        /// The channel that was not able to forward the HTLC, when known.
+        pub outgoing_channel_id: Option<String>,
+        /// The node of the outgoing channel, when known.
+        pub outgoing_node_id: Option<String>,
+        /// The short channel id requested by the onion when
+        /// there is no channel that matches it.
+        pub requested_forward_scid: Option<u64>,
+        /// The unix timestamp of the failure.
+        pub timestamp: u64,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ForwardErrors {
+        pub errors: Vec<ForwardError>,
+    }
+}
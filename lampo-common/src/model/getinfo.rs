@@ -10,6 +10,15 @@ pub struct GetInfo {
     pub blockheight: u32,
     pub lampo_dir: String,
     pub address: Vec<NetworkInfo>,
+    /// The number of channels and nodes inside the network graph.
+    pub graph_channels: usize,
+    pub graph_nodes: usize,
+    /// The feature bits of our node announcement.
+    pub feature_bits: Vec<usize>,
+    /// The backend deletes the old blocks, they are fetched from esplora.
+    pub backend_pruned: bool,
+    /// The biggest HTLC that our channels accept, from `max-inbound-htlc-msat`.
+    pub max_inbound_htlc_msat: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -0,0 +1,21 @@
+//! Node Health Model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct Health {
+        /// Unix timestamp of the last wallet sync.
+        pub wallet_last_sync: Option<u64>,
+        /// If the last wallet sync is inside the staleness window.
+        pub wallet_synced: bool,
+        /// How many outbound payments are in flight.
+        pub inflight_payments: usize,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct WalletSync {
+        /// Unix timestamp of the sync.
+        pub last_sync: u64,
+    }
+}
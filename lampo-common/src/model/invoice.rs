@@ -3,11 +3,96 @@
 pub mod request {
     use serde::{Deserialize, Serialize};
 
+    use crate::error;
+
     #[derive(Serialize, Deserialize, Debug)]
     pub struct GenerateInvoice {
         pub amount_msat: Option<u64>,
         pub description: String,
         pub expiring_in: Option<u32>,
+        /// Issue the invoice even when the amount is below the
+        /// `min-invoice-msat` of the node, only with `allow-unsafe-rpc`.
+        #[serde(default)]
+        pub ignore_min_amount: bool,
+    }
+
+    /// Invoice for a payment hash generated outside lampo, the
+    /// payment is held until the preimage is provided with `SettleInvoice`.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct CreateInvoice {
+        pub payment_hash: String,
+        pub amount_msat: Option<u64>,
+        pub description: String,
+        pub expiry: Option<u32>,
+    }
+
+    impl CreateInvoice {
+        pub fn payment_hash(&self) -> error::Result<[u8; 32]> {
+            decode_32_bytes(&self.payment_hash)
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct SettleInvoice {
+        pub preimage: String,
+    }
+
+    impl SettleInvoice {
+        pub fn preimage(&self) -> error::Result<[u8; 32]> {
+            decode_32_bytes(&self.preimage)
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct InvoiceStatus {
+        pub payment_hash: String,
+    }
+
+    impl InvoiceStatus {
+        pub fn payment_hash(&self) -> error::Result<[u8; 32]> {
+            decode_32_bytes(&self.payment_hash)
+        }
+    }
+
+    /// Generate again an invoice of lampo, referenced by the
+    /// `payment_hash` or by the original `bolt11`, with a fresh expiry.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ReissueInvoice {
+        pub payment_hash: Option<String>,
+        pub bolt11: Option<String>,
+        /// The expiry in seconds, by default the one of the original invoice.
+        pub expiry: Option<u32>,
+    }
+
+    impl ReissueInvoice {
+        pub fn payment_hash(&self) -> error::Result<Option<[u8; 32]>> {
+            self.payment_hash
+                .as_deref()
+                .map(decode_32_bytes)
+                .transpose()
+        }
+    }
+
+    /// Wait until the payment with `payment_hash` that we sent
+    /// succeeds or fails, up to `timeout_secs`.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct WaitSendPay {
+        pub payment_hash: String,
+        pub timeout_secs: Option<u64>,
+    }
+
+    impl WaitSendPay {
+        pub fn payment_hash(&self) -> error::Result<[u8; 32]> {
+            decode_32_bytes(&self.payment_hash)
+        }
+    }
+
+    pub(crate) fn decode_32_bytes(value: &str) -> error::Result<[u8; 32]> {
+        let bytes = hex::decode(value)?;
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| error::anyhow!("`{value}` is not 32 bytes long"))?;
+        Ok(bytes)
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -25,6 +110,31 @@ pub mod request {
     pub struct Pay {
         pub invoice_str: String,
         pub amount: Option<u64>,
+        /// The channels that must not be used as first hop.
+        #[serde(default)]
+        pub exclude_channels: Vec<String>,
+        /// The channel that must be used as first hop.
+        pub use_channel: Option<String>,
+        /// Private routes to the destination, merged with the
+        /// route hints of the invoice.
+        #[serde(default)]
+        pub route_hints: Vec<Vec<RouteHintHop>>,
+        /// Ignore the route hints of the invoice and use only `route_hints`.
+        #[serde(default)]
+        pub replace_route_hints: bool,
+    }
+
+    /// A hop of a private route, from `node_id` through
+    /// the channel `short_channel_id`.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct RouteHintHop {
+        pub node_id: String,
+        pub short_channel_id: u64,
+        pub fee_base_msat: u32,
+        pub fee_proportional_millionths: u32,
+        pub cltv_expiry_delta: u16,
+        pub htlc_minimum_msat: Option<u64>,
+        pub htlc_maximum_msat: Option<u64>,
     }
 }
 
@@ -71,11 +181,43 @@ pub mod response {
     }
 
     #[derive(Serialize, Deserialize, Debug)]
+    pub struct SettleInvoice {
+        pub payment_hash: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum InvoiceState {
+        /// The payment hash was not generated by lampo.
+        Unknown,
+        Unpaid,
+        /// The payment is claimable but we are waiting the preimage.
+        Held,
+        Paid,
+        /// The invoice expired without being paid.
+        Expired,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct InvoiceStatus {
+        pub payment_hash: String,
+        pub state: InvoiceState,
+        /// The amount of the invoice, or the amount received
+        /// when the invoice is held or paid.
+        pub amount_msat: Option<u64>,
+        /// Unix timestamp of the expiry of the invoice.
+        pub expires_at: Option<u64>,
+        /// The preimage, available only when the invoice is paid.
+        pub preimage: Option<String>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct PayResult {
         pub path: Vec<PaymentHop>,
         pub payment_hash: Option<String>,
         pub state: PaymentState,
-        // FIXME: missing payment preimage
+        /// The preimage, available only when the payment succeeded.
+        pub payment_preimage: Option<String>,
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,6 +225,8 @@ pub mod response {
         Success,
         Pending,
         Failure,
+        /// The payment is claimable but we are waiting the preimage.
+        Held,
     }
 
     #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -4,10 +4,20 @@ pub mod request {
     use bitcoin::secp256k1::PublicKey;
     use serde::{Deserialize, Serialize};
 
+    use crate::model::invoice::request::RouteHintHop;
+
     #[derive(Serialize, Deserialize)]
     pub struct KeySend {
         pub destination: PublicKey,
         pub amount_msat: u64,
+        /// Private routes to the destination, there is
+        /// no invoice that can carry them.
+        #[serde(default)]
+        pub route_hints: Vec<Vec<RouteHintHop>>,
+        /// The max number of parts of the payment, 1 disables the
+        /// multi part payment. By default LDK chooses up to 10 parts.
+        #[serde(default)]
+        pub max_parts: Option<u8>,
     }
 }
 
@@ -15,14 +25,17 @@ pub mod response {
 
     use serde::{Deserialize, Serialize};
 
-    #[derive(Serialize, Deserialize)]
+    use crate::model::response::PaymentState;
+
+    #[derive(Debug, Serialize, Deserialize)]
     pub struct KeySendInfo {
-        pub payment_preimage: String,
+        pub payment_preimage: Option<String>,
         pub payment_hash: String,
-        pub created_at: String,
-        pub parts: String,
-        pub amount_msat: String,
+        /// How many parts reached the destination.
+        pub parts: usize,
+        pub amount_msat: u64,
+        /// The amount delivered plus the routing fees.
         pub amount_sent_msat: Option<u64>,
-        pub status: String,
+        pub status: PaymentState,
     }
 }
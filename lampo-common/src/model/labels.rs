@@ -0,0 +1,30 @@
+//! The BIP 329 labels of the wallet.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ImportLabels {
+        /// The labels encoded as BIP 329 JSON Lines.
+        pub labels: String,
+        /// Drop the labels already stored before the import.
+        #[serde(default)]
+        pub replace: bool,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ExportLabels {
+        /// The labels encoded as BIP 329 JSON Lines.
+        pub labels: String,
+        pub count: usize,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ImportLabels {
+        /// How many labels were imported.
+        pub imported: usize,
+    }
+}
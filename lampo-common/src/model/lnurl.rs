@@ -0,0 +1,36 @@
+//! LNURL-pay model
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct LnurlPay {
+        /// A bech32 LNURL, or a lightning address like `user@domain`.
+        pub lnurl: String,
+        pub amount_msat: u64,
+        /// The comment for the payee, the service must allow it.
+        pub comment: Option<String>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    use crate::model::response::PaymentState;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct LnurlPay {
+        /// The invoice returned by the service.
+        pub invoice: String,
+        pub amount_msat: u64,
+        /// The `text/plain` description inside the metadata.
+        pub description: Option<String>,
+        pub payment_hash: String,
+        pub state: PaymentState,
+        /// The preimage, available only when the payment succeeded.
+        pub payment_preimage: Option<String>,
+        /// The action that the service asks to show after
+        /// the payment (LUD-09), as returned by the service.
+        pub success_action: Option<serde_json::Value>,
+    }
+}
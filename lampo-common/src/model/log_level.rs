@@ -0,0 +1,24 @@
+//! Log Level Model
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct SetLogLevel {
+        /// The log target to change, if missing the level
+        /// is changed for the whole process.
+        pub target: Option<String>,
+        pub level: String,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct SetLogLevel {
+        pub target: Option<String>,
+        pub level: String,
+        pub previous_level: String,
+    }
+}
@@ -1,4 +1,31 @@
-pub mod request {}
+pub mod request {
+    use std::str::FromStr;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::error;
+    use crate::types::NodeId;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ListAddresses;
+
+    /// Sync the gossip from `node_id`, or from the first
+    /// connected peer when it is not specified.
+    #[derive(Serialize, Deserialize, Debug, Default)]
+    pub struct GossipSync {
+        pub node_id: Option<String>,
+    }
+
+    impl GossipSync {
+        pub fn node_id(&self) -> error::Result<Option<NodeId>> {
+            Ok(self
+                .node_id
+                .as_ref()
+                .map(|node_id| NodeId::from_str(node_id))
+                .transpose()?)
+        }
+    }
+}
 
 pub mod response {
     use lightning::routing::gossip::ChannelInfo;
@@ -9,6 +36,26 @@ pub mod response {
         pub channels: Vec<NetworkChannel>,
     }
 
+    /// The addresses where the node listens and the
+    /// ones that it announces to the network.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ListAddresses {
+        pub bind: Vec<String>,
+        pub announce: Vec<String>,
+        pub onion: Option<String>,
+    }
+
+    /// The result of a gossip sync from `peer`, with the
+    /// size of the network graph after the sync.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct GossipSync {
+        pub peer: String,
+        pub channels_learned: usize,
+        pub nodes_learned: usize,
+        pub channels: usize,
+        pub nodes: usize,
+    }
+
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct NetworkChannel {
         pub node_one: String,
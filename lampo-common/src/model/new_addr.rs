@@ -2,15 +2,55 @@
 pub mod request {
     use serde::{Deserialize, Serialize};
 
+    use crate::types::Keychain;
+
     #[derive(Serialize, Deserialize)]
     pub struct NewAddress;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct NewAddresses {
+        pub count: u32,
+        /// When specified, every address is labeled as `{label_prefix}-{n}`.
+        pub label_prefix: Option<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ResetAddressIndex {
+        pub keychain: Keychain,
+        pub index: u32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct AddressIndices;
 }
 
 pub mod response {
     use serde::{Deserialize, Serialize};
 
-    #[derive(Serialize, Deserialize)]
+    use crate::types::Keychain;
+
+    #[derive(Debug, Serialize, Deserialize)]
     pub struct NewAddress {
         pub address: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub label: Option<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct NewAddresses {
+        pub addresses: Vec<NewAddress>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ResetAddressIndex {
+        pub keychain: Keychain,
+        pub index: u32,
+    }
+
+    /// The next derivation index of each keychain.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct AddressIndices {
+        pub external: u32,
+        pub internal: u32,
     }
 }
@@ -1,8 +1,99 @@
-pub mod request {}
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    use crate::types::TxKind;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Consolidate {
+        /// The feerate in sats per kw of the consolidation.
+        pub fee_rate: u32,
+        /// How many UTXOs can be spent at most, the smallest
+        /// are spent first. By default all of them are spent.
+        pub max_inputs: Option<usize>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Withdraw {
+        /// The address that receives the funds.
+        pub address: String,
+        pub amount_sat: u64,
+        /// The feerate in sats per kw, by default it is
+        /// estimated by the backend.
+        pub fee_rate: Option<u32>,
+        /// The only outputs spent by the transaction, as
+        /// `txid:vout`. By default the wallet chooses them.
+        pub utxos: Option<Vec<String>>,
+        /// Take the fee from the amount, e.g. to withdraw
+        /// all the value of the selected outputs.
+        #[serde(default)]
+        pub subtract_fee_from_amount: bool,
+        /// The address that receives the change, by
+        /// default a fresh address of the wallet.
+        pub change_address: Option<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct TxConfirmationEta {
+        pub txid: String,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct SimulateReorg {
+        /// How many blocks are dropped from the tip.
+        pub blocks: u32,
+    }
+
+    /// Without `Debug`, so the key does not end inside the logs.
+    #[derive(Serialize, Deserialize)]
+    pub struct SweepPrivateKey {
+        /// The private key in the wallet import format.
+        pub wif: String,
+        /// The address that receives the funds, by
+        /// default a fresh address of the wallet.
+        pub destination: Option<String>,
+        /// The feerate in sats per kw, by default it is
+        /// estimated by the backend.
+        pub fee_rate: Option<u32>,
+    }
+
+    /// Experimental, the funds are locked until the timelock
+    /// elapses and only the key of `destination_pubkey` can spend them.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CreateTimelockOutput {
+        pub amount_sat: u64,
+        /// The blocks of the relative timelock (`OP_CSV`), or the
+        /// absolute locktime (`OP_CLTV`) when `absolute` is true.
+        pub locktime_or_blocks: u32,
+        /// The public key that can spend the output after the timelock.
+        pub destination_pubkey: String,
+        #[serde(default)]
+        pub absolute: bool,
+        /// The feerate in sats per kw, by default it is
+        /// estimated by the backend.
+        pub fee_rate: Option<u32>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ReconcileWallet {
+        /// Scan again the chain when some outputs of the
+        /// wallet are not known by the backend.
+        #[serde(default)]
+        pub rescan: bool,
+    }
+
+    /// The transactions of the wallet, only the
+    /// ones of `kind` when it is specified.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct TxHistory {
+        pub kind: Option<TxKind>,
+    }
+}
 
 pub mod response {
     use serde::{Deserialize, Serialize};
 
+    use crate::types::{Keychain, TxKind};
+
     #[derive(Debug, Serialize, Deserialize)]
     pub struct Utxo {
         pub txid: String,
@@ -12,8 +103,193 @@ pub mod response {
         pub amount_msat: u64,
     }
 
+    /// An unspent output of the wallet with the
+    /// address where it was received.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Coin {
+        pub txid: String,
+        pub vout: u32,
+        pub reserved: bool,
+        pub confirmed: u32,
+        pub amount_msat: u64,
+        pub address: Option<String>,
+        /// The derivation path of the address, e.g. `m/84h/1h/0h/0/2`,
+        /// `None` when the address is not derived by the wallet.
+        pub derivation_path: Option<String>,
+        pub keychain: Option<Keychain>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Coins {
+        pub coins: Vec<Coin>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct HistoryTx {
+        pub txid: String,
+        pub kind: TxKind,
+        /// The height of the block that confirms the
+        /// transaction, `None` when it is unconfirmed.
+        pub height: Option<u32>,
+        /// The value of our outputs spent by the transaction.
+        pub sent_sat: u64,
+        /// The value of the outputs of the transaction that are ours.
+        pub received_sat: u64,
+        /// The fee, known only when all the inputs are ours.
+        pub fee_sat: Option<u64>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct TxHistory {
+        pub transactions: Vec<HistoryTx>,
+    }
+
+    /// The balance of the outputs of a keychain.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Balance {
+        pub confirmed_sat: u64,
+        pub unconfirmed_sat: u64,
+    }
+
     #[derive(Debug, Serialize, Deserialize)]
     pub struct Utxos {
         pub transactions: Vec<Utxo>,
+        #[serde(default)]
+        pub onchain_fee_reserve_sat: u64,
+        /// The balance of the receive addresses.
+        #[serde(default)]
+        pub external_balance: Balance,
+        /// The balance of the change addresses.
+        #[serde(default)]
+        pub internal_balance: Balance,
+        /// Our balance inside the channels.
+        #[serde(default)]
+        pub channels: Vec<ChannelBalance>,
+    }
+
+    /// Our balance inside a channel, and how much of it we can send.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChannelBalance {
+        pub channel_id: String,
+        pub peer_id: String,
+        /// Our balance that we can claim on chain, `None`
+        /// until the channel is funded.
+        pub our_amount_msat: Option<u64>,
+        /// Our balance minus the reserve and the fee of the commitment.
+        pub spendable_msat: u64,
+        pub reserve_sat: Option<u64>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Consolidate {
+        pub txid: String,
+        /// How many UTXOs were spent.
+        pub inputs: usize,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Withdraw {
+        pub txid: String,
+        pub tx_hex: String,
+    }
+
+    /// An output of the wallet that the backend considers spent or missing.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct UtxoDiscrepancy {
+        pub txid: String,
+        pub vout: u32,
+        pub amount_msat: u64,
+        /// The output is gone from the wallet after the rescan.
+        pub fixed: bool,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ReconcileWallet {
+        pub discrepancies: Vec<UtxoDiscrepancy>,
+        pub rescanned: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum TxConfirmationStatus {
+        /// The transaction is already confirmed.
+        Confirmed,
+        Mempool,
+        /// The backend does not know the transaction.
+        Unknown,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct TxConfirmationEta {
+        pub txid: String,
+        pub status: TxConfirmationStatus,
+        pub confirmations: Option<u32>,
+        /// The feerate in sats per kw of the transaction with its ancestors.
+        pub fee_rate: Option<u32>,
+        /// How many blocks until the transaction is confirmed.
+        pub eta_blocks: Option<u32>,
+        /// How many transactions of the mempool pay a better feerate.
+        pub txs_ahead: Option<usize>,
+        /// The virtual size of the transactions of the mempool
+        /// that pay a better feerate.
+        pub vsize_ahead: Option<u64>,
+    }
+
+    /// The transactions of the mempool that pay a feerate
+    /// between `fee_rate` and the feerate of the previous bucket.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct FeeHistogramBucket {
+        /// The min feerate of the bucket in sats per kw.
+        pub fee_rate: u32,
+        pub count: usize,
+        pub vsize: u64,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct SweepPrivateKey {
+        pub txid: String,
+        pub tx_hex: String,
+        /// How many outputs of the key were swept.
+        pub inputs: usize,
+        /// The amount received by the destination.
+        pub amount_sat: u64,
+        pub fee_sat: u64,
+        pub destination: String,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CreateTimelockOutput {
+        pub txid: String,
+        pub tx_hex: String,
+        /// The index of the timelocked output.
+        pub vout: u32,
+        /// The P2WSH address of the timelocked output.
+        pub address: String,
+        /// The witness script that must be revealed to spend the output.
+        pub redeem_script: String,
+        pub amount_sat: u64,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct SimulateReorg {
+        /// The hashes of the dropped blocks, from the tip.
+        pub invalidated_blocks: Vec<String>,
+        /// The height of the tip after the reorg.
+        pub height: u32,
+        /// The transactions of the wallet and of the channels
+        /// that are unconfirmed by the reorg.
+        pub affected_txids: Vec<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct MempoolInfo {
+        /// How many transactions are inside the mempool.
+        pub size: u64,
+        /// The virtual size of the transactions inside the mempool.
+        pub bytes: u64,
+        /// The min relay fee in sats per kw.
+        pub min_relay_fee: u32,
+        /// The buckets ordered from the best feerate, the empty ones omitted.
+        pub fee_histogram: Vec<FeeHistogramBucket>,
     }
 }
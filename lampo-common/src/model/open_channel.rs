@@ -8,13 +8,50 @@ pub mod request {
     use crate::error;
     use crate::types::NodeId;
 
-    #[derive(Clone, Serialize, Deserialize)]
+    #[derive(Clone, Default, Serialize, Deserialize)]
     pub struct OpenChannel {
         pub node_id: String,
         pub addr: Option<String>,
         pub port: Option<u64>,
+        /// The capacity of the channel in sats, it must be zero
+        /// when the channel is funded with `amount_percent`.
+        #[serde(default)]
         pub amount: u64,
         pub public: bool,
+        /// Build and sign the funding transaction without
+        /// broadcasting it, the channel is dropped after that.
+        #[serde(default)]
+        pub dry_run: bool,
+        /// The feerate in sats per kw of the funding transaction,
+        /// by default the feerate to confirm in 6 blocks.
+        pub funding_feerate: Option<u32>,
+        /// The feerate in sats per kw of the commitment transactions.
+        pub commitment_feerate: Option<u32>,
+        /// The smallest HTLC that the peer can send us in this channel.
+        pub htlc_minimum_msat: Option<u64>,
+        /// The max value of the HTLCs in flight that the peer can send us in
+        /// this channel, so also of a single HTLC. It is rounded down to a
+        /// percentage of the channel capacity.
+        pub max_htlc_value_in_flight_msat: Option<u64>,
+        /// Send a keysend of this amount to the peer as soon as
+        /// the channel is usable.
+        pub then_keysend_msat: Option<u64>,
+        /// How long to wait the channel to be usable and the keysend
+        /// to be resolved, by default one hour.
+        pub then_keysend_timeout_secs: Option<u64>,
+        /// Allow the funding transaction to spend our own unconfirmed
+        /// change, by default only confirmed outputs are spent.
+        #[serde(default)]
+        pub allow_unconfirmed: bool,
+        /// The nLockTime of the funding transaction, a block height
+        /// when below 500000000, a unix timestamp otherwise.
+        pub locktime: Option<u32>,
+        /// The nSequence of every input of the funding transaction.
+        pub sequence: Option<u32>,
+        /// Fund the channel with a percentage of the spendable balance,
+        /// from `1` to `100` or `all`. The spendable balance does not
+        /// include the on chain fee reserve and the funding fee.
+        pub amount_percent: Option<String>,
     }
 
     impl OpenChannel {
@@ -22,6 +59,49 @@ pub mod request {
             let node_id = NodeId::from_str(&self.node_id)?;
             Ok(node_id)
         }
+
+        /// The percentage of the spendable balance that funds the channel.
+        pub fn amount_percent(&self) -> error::Result<Option<u64>> {
+            let Some(percent) = self.amount_percent.as_ref() else {
+                return Ok(None);
+            };
+            let percent = percent.trim();
+            if percent == "all" {
+                return Ok(Some(100));
+            }
+            let value = u64::from_str(percent.trim_end_matches('%'))
+                .map_err(|err| error::anyhow!("invalid `amount_percent` `{percent}`: {err}"))?;
+            if !(1..=100).contains(&value) {
+                error::bail!("`amount_percent` `{percent}` must be between 1 and 100");
+            }
+            Ok(Some(value))
+        }
+    }
+
+    /// Cancel the open of the channel `channel_id`, the
+    /// temporary one or the final one, before its funding
+    /// transaction is broadcast.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct CancelOpen {
+        pub channel_id: String,
+    }
+
+    /// Estimate the smallest channel that we can open, with
+    /// `node_id` when the peer is known.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct MinChannelCapacity {
+        pub node_id: Option<String>,
+    }
+
+    impl MinChannelCapacity {
+        pub fn node_id(&self) -> error::Result<Option<NodeId>> {
+            let node_id = self
+                .node_id
+                .as_ref()
+                .map(|node_id| NodeId::from_str(node_id))
+                .transpose()?;
+            Ok(node_id)
+        }
     }
 }
 
@@ -32,6 +112,7 @@ pub mod response {
 
     use crate::bitcoin::{Transaction, Txid};
     use crate::error;
+    use crate::model::response::PaymentState;
     use crate::types::NodeId;
 
     #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -48,6 +129,17 @@ pub mod response {
         pub to_self_delay: u64,
         pub tx: Option<Transaction>,
         pub txid: Option<Txid>,
+        pub tx_hex: Option<String>,
+        /// The absolute fee of the funding transaction,
+        /// reported only on a dry run.
+        pub fee_sat: Option<u64>,
+        /// The id of the channel, reported only when we waited
+        /// the channel to be usable for `then_keysend_msat`.
+        pub channel_id: Option<String>,
+        /// The payment hash of the keysend sent with `then_keysend_msat`.
+        pub keysend_payment_hash: Option<String>,
+        /// The outcome of the keysend sent with `then_keysend_msat`.
+        pub keysend_state: Option<PaymentState>,
     }
 
     impl OpenChannel {
@@ -57,6 +149,35 @@ pub mod response {
         }
     }
 
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct CancelOpen {
+        pub channel_id: String,
+        pub peer_id: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct MinChannelCapacity {
+        pub node_id: Option<String>,
+        /// True when the limits of the peer were queried
+        /// because it is connected with us.
+        pub peer_connected: bool,
+        /// True when the channel uses the anchor outputs.
+        pub anchors: bool,
+        /// The smallest capacity that leaves both sides with a balance
+        /// above their reserve and the dust limit.
+        pub min_capacity_sat: u64,
+        /// The reserve that the peer requires us to keep in the channel.
+        pub reserve_sat: u64,
+        /// The reserve that we require the peer to keep in the channel.
+        pub counterparty_reserve_sat: u64,
+        pub dust_limit_sat: u64,
+        /// The fee of the commitment transaction, paid by us as funder.
+        pub commitment_fee_sat: u64,
+        /// The value of the two anchor outputs, paid by us as funder.
+        pub anchor_outputs_sat: u64,
+        pub feerate_sat_per_kw: u32,
+    }
+
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct Channel {
         // Channel_id needs to be string as it currently does not derive Serialize
@@ -70,5 +191,52 @@ pub mod response {
         pub public: bool,
         pub available_balance_for_send_msat: u64,
         pub available_balance_for_recv_msat: u64,
+        pub inbound_capacity_msat: u64,
+        pub outbound_capacity_msat: u64,
+        pub next_outbound_htlc_limit_msat: u64,
+        /// Our balance that we can claim on chain, `None`
+        /// until the channel is funded.
+        pub our_amount_msat: Option<u64>,
+        /// What we can send in a single HTLC, our balance minus the
+        /// reserve and the fee of the commitment with the new HTLC.
+        pub spendable_msat: u64,
+        /// The reserve that we must keep in the channel, it is
+        /// `None` until the counterparty accept the channel.
+        pub reserve_sat: Option<u64>,
+        /// The reserve that the counterparty must keep in the channel.
+        pub counterparty_reserve_sat: u64,
+        /// The feerate in sats per kw of the commitment transaction.
+        pub feerate_sat_per_kw: Option<u32>,
+        pub inbound_htlc_minimum_msat: Option<u64>,
+        pub inbound_htlc_maximum_msat: Option<u64>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::request::OpenChannel;
+
+    #[test]
+    fn parse_amount_percent() {
+        let open_channel = |percent: Option<&str>| OpenChannel {
+            node_id: String::new(),
+            amount: 0,
+            public: true,
+            amount_percent: percent.map(str::to_owned),
+            ..Default::default()
+        };
+        assert_eq!(open_channel(None).amount_percent().unwrap(), None);
+        assert_eq!(open_channel(Some("50")).amount_percent().unwrap(), Some(50));
+        assert_eq!(
+            open_channel(Some("25%")).amount_percent().unwrap(),
+            Some(25)
+        );
+        assert_eq!(
+            open_channel(Some("all")).amount_percent().unwrap(),
+            Some(100)
+        );
+        assert!(open_channel(Some("0")).amount_percent().is_err());
+        assert!(open_channel(Some("101")).amount_percent().is_err());
+        assert!(open_channel(Some("half")).amount_percent().is_err());
     }
 }
@@ -0,0 +1,53 @@
+//! The failures reported by the nodes of the path of a payment.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    use crate::error;
+    use crate::model::invoice::request::decode_32_bytes;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct PaymentFailures {
+        /// Only the failures of this payment, all of them when missing.
+        pub payment_hash: Option<String>,
+        /// Forget the returned failures.
+        #[serde(default)]
+        pub clear: bool,
+    }
+
+    impl PaymentFailures {
+        pub fn payment_hash(&self) -> error::Result<Option<[u8; 32]>> {
+            self.payment_hash
+                .as_deref()
+                .map(decode_32_bytes)
+                .transpose()
+        }
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// A failed attempt to send a payment through a path.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct PaymentFailure {
+        pub payment_hash: String,
+        /// The failure code, like `fee_insufficient` or `temporary_channel_failure`.
+        ///
+        /// LDK does not give back the raw onion failure code, so the code
+        /// is decoded from the network update that comes with the failure.
+        pub failure: String,
+        /// The node that reported the failure, when known.
+        pub erring_node: Option<String>,
+        /// The channel that failed, when known.
+        pub erring_channel: Option<u64>,
+        /// The payment can not succeed retrying on another path.
+        pub permanent: bool,
+        /// The unix timestamp of the failure.
+        pub timestamp: u64,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct PaymentFailures {
+        pub failures: Vec<PaymentFailure>,
+    }
+}
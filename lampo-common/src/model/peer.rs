@@ -0,0 +1,38 @@
+//! Stored Peers Model
+
+pub mod request {
+    use std::str::FromStr;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::error;
+    use crate::types::NodeId;
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ForgetPeer {
+        pub node_id: String,
+    }
+
+    impl ForgetPeer {
+        pub fn node_id(&self) -> error::Result<NodeId> {
+            Ok(NodeId::from_str(&self.node_id)?)
+        }
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct StoredPeer {
+        pub node_id: String,
+        /// The last known addresses of the peer, the most
+        /// recent one is the last.
+        pub addresses: Vec<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct StoredPeers {
+        pub peers: Vec<StoredPeer>,
+    }
+}
@@ -0,0 +1,24 @@
+//! The results of the probes of the channels liquidity.
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// The latest result of a probe through a path.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ProbeResult {
+        pub destination: String,
+        /// The channels of the path, from our channel.
+        pub short_channel_ids: Vec<u64>,
+        pub amount_msat: u64,
+        /// The probe reached the destination.
+        pub success: bool,
+        /// The channel that did not have the liquidity, when the probe failed.
+        pub failed_channel: Option<u64>,
+        /// The unix timestamp of the result.
+        pub timestamp: u64,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Probes {
+        pub probes: Vec<ProbeResult>,
+    }
+}
@@ -0,0 +1,55 @@
+pub mod request {
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::PublicKey;
+    use serde::{Deserialize, Serialize};
+
+    use crate::error;
+
+    /// Find a route to `node_id` that is able
+    /// to deliver `amount_msat`.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct GetRoute {
+        pub node_id: String,
+        pub amount_msat: u64,
+    }
+
+    impl GetRoute {
+        pub fn node_id(&self) -> error::Result<PublicKey> {
+            Ok(PublicKey::from_str(&self.node_id)?)
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug, Default)]
+    pub struct ResetScorer {}
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Route {
+        pub amount_msat: u64,
+        pub fee_msat: u64,
+        pub hops: Vec<RouteHop>,
+    }
+
+    /// A hop of the route, with the liquidity of the channel that
+    /// the scorer learned from the previous payments.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct RouteHop {
+        pub node_id: String,
+        pub short_channel_id: u64,
+        /// The fee paid to this hop, for the last hop
+        /// this is the amount delivered.
+        pub fee_msat: u64,
+        pub cltv_expiry_delta: u32,
+        pub liquidity_min_msat: Option<u64>,
+        pub liquidity_max_msat: Option<u64>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ResetScorer {
+        pub reset: bool,
+    }
+}
@@ -0,0 +1,30 @@
+//! The watchtowers that receive the monitors of our channels.
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct AddTower {
+        /// The http uri of the tower.
+        pub uri: String,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Tower {
+        pub uri: String,
+        /// How many channels have a state not received by the tower.
+        pub pending_updates: usize,
+        /// The unix timestamp of the last state received by the tower.
+        pub last_update: Option<u64>,
+        /// Why the tower was unreachable the last time.
+        pub last_error: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Towers {
+        pub towers: Vec<Tower>,
+    }
+}
@@ -1,4 +1,6 @@
 //! Lampo Common Types
+use serde::{Deserialize, Serialize};
+
 use crate::bitcoin::secp256k1::PublicKey;
 use crate::ldk;
 
@@ -11,3 +13,31 @@ pub enum ChannelState {
     Ready,
     OpeningError,
 }
+
+/// How a transaction of the wallet moves the funds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxKind {
+    /// The wallet pays somebody else.
+    Send,
+    /// Somebody else pays the wallet.
+    Receive,
+    /// All the inputs and the outputs are of the wallet,
+    /// e.g. a consolidation.
+    SelfTransfer,
+    /// The transaction creates the funding output of a channel.
+    ChannelFunding,
+    /// The transaction spends the funding output of a channel.
+    ChannelClose,
+}
+
+/// The keychain of the wallet where an address is derived,
+/// following the BIP 84 derivation paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Keychain {
+    /// Receive addresses ("m/84h/1h/0h/0")
+    External,
+    /// Change addresses ("m/84h/1h/0h/1")
+    Internal,
+}
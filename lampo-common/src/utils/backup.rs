@@ -0,0 +1,69 @@
+//! Static channel backup utils.
+//!
+//! The backup contains the channel monitors indexed by their
+//! persistence key, and it is encrypted with ChaCha20-Poly1305
+//! using a key derived from the node secret.
+use std::collections::BTreeMap;
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::error;
+use crate::json;
+
+pub const NONCE_LEN: usize = 12;
+
+/// Encrypt the channel monitors and return the hex of the
+/// backup, that is the nonce followed by the cipher text.
+pub fn seal(
+    key: &[u8; 32],
+    nonce: [u8; NONCE_LEN],
+    monitors: &BTreeMap<String, Vec<u8>>,
+) -> error::Result<String> {
+    let monitors = monitors
+        .iter()
+        .map(|(key, monitor)| (key.clone(), hex::encode(monitor)))
+        .collect::<BTreeMap<_, _>>();
+    let plaintext = json::to_vec(&monitors)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+        .map_err(|err| error::anyhow!("impossible encrypt the channel backup: {err}"))?;
+    let mut backup = nonce.to_vec();
+    backup.extend(ciphertext);
+    Ok(hex::encode(backup))
+}
+
+/// Decrypt the backup produced by `seal`.
+pub fn open(key: &[u8; 32], backup: &str) -> error::Result<BTreeMap<String, Vec<u8>>> {
+    let backup = hex::decode(backup)?;
+    if backup.len() < NONCE_LEN {
+        error::bail!("channel backup too short");
+    }
+    let (nonce, ciphertext) = backup.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| error::anyhow!("impossible decrypt the channel backup, wrong node?"))?;
+    let monitors: BTreeMap<String, String> = json::from_slice(&plaintext)?;
+    monitors
+        .into_iter()
+        .map(|(key, monitor)| Ok((key, hex::decode(monitor)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{open, seal};
+
+    #[test]
+    fn seal_and_open() {
+        let mut monitors = BTreeMap::new();
+        monitors.insert("txid_0".to_owned(), vec![1, 2, 3]);
+        let backup = seal(&[7; 32], [1; 12], &monitors).unwrap();
+        assert_eq!(open(&[7; 32], &backup).unwrap(), monitors);
+        assert!(open(&[8; 32], &backup).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! BIP 329 wallet labels utils.
+//!
+//! The labels are exported as JSON Lines, one label record for each
+//! line, so they can be moved between the wallets that support BIP 329.
+use std::str::FromStr;
+
+use bitcoin::bip32::ExtendedPubKey;
+use bitcoin::{Address, OutPoint, PublicKey, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::error;
+use crate::json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+}
+
+impl LabelType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tx => "tx",
+            Self::Addr => "addr",
+            Self::Pubkey => "pubkey",
+            Self::Input => "input",
+            Self::Output => "output",
+            Self::Xpub => "xpub",
+        }
+    }
+}
+
+/// A label record of BIP 329.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    #[serde(rename = "type")]
+    pub label_type: LabelType,
+    /// The reference to the labeled object, e.g. the txid
+    /// of a transaction or the `txid:vout` of an output.
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The origin of the key of `addr`, `pubkey` and `xpub` records.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Allowed only on the `output` records.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+impl Label {
+    /// Make sure that the reference is valid for the type of the record.
+    pub fn validate(&self) -> error::Result<()> {
+        let reference = self.reference.as_str();
+        let valid = match self.label_type {
+            LabelType::Tx => Txid::from_str(reference).is_ok(),
+            LabelType::Addr => Address::from_str(reference).is_ok(),
+            LabelType::Pubkey => PublicKey::from_str(reference).is_ok(),
+            LabelType::Input | LabelType::Output => OutPoint::from_str(reference).is_ok(),
+            LabelType::Xpub => ExtendedPubKey::from_str(reference).is_ok(),
+        };
+        if !valid {
+            error::bail!(
+                "`{reference}` is not a valid reference for a `{}` label",
+                self.label_type.as_str()
+            );
+        }
+        if self.spendable.is_some() && self.label_type != LabelType::Output {
+            error::bail!("`spendable` is allowed only on the `output` labels");
+        }
+        Ok(())
+    }
+}
+
+/// Parse and validate the JSON Lines of labels, the empty lines are ignored.
+pub fn parse_jsonl(jsonl: &str) -> error::Result<Vec<Label>> {
+    jsonl
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let line_number = index + 1;
+            let label: Label = json::from_str(line)
+                .map_err(|err| error::anyhow!("invalid label at line {line_number}: {err}"))?;
+            label
+                .validate()
+                .map_err(|err| error::anyhow!("invalid label at line {line_number}: {err}"))?;
+            Ok(label)
+        })
+        .collect()
+}
+
+/// Encode the labels as JSON Lines.
+pub fn to_jsonl(labels: &[Label]) -> error::Result<String> {
+    let mut jsonl = String::new();
+    for label in labels {
+        jsonl.push_str(&json::to_string(label)?);
+        jsonl.push('\n');
+    }
+    Ok(jsonl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_jsonl, to_jsonl, LabelType};
+
+    #[test]
+    fn parse_and_encode_labels() {
+        let jsonl = r#"{"type":"tx","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd","label":"Transaction","origin":"wpkh([d34db33f/84'/0'/0'])"}
+{"type":"addr","ref":"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c","label":"Address"}
+
+{"type":"output","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1","label":"Output","spendable":false}
+"#;
+        let labels = parse_jsonl(jsonl).unwrap();
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels[2].label_type, LabelType::Output);
+        assert_eq!(labels[2].spendable, Some(false));
+        assert_eq!(parse_jsonl(&to_jsonl(&labels).unwrap()).unwrap(), labels);
+
+        let err = parse_jsonl("{\"type\":\"tx\",\"ref\":\"not a txid\"}").unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+        assert!(parse_jsonl("{\"type\":\"tx\"").is_err());
+        assert!(parse_jsonl(
+            r#"{"type":"tx","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd","spendable":true}"#
+        )
+        .is_err());
+    }
+}
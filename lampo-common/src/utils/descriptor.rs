@@ -0,0 +1,103 @@
+//! Output script descriptor utils.
+//!
+//! See BIP 380 for the checksum algorithm specification.
+use crate::error;
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn poly_mod(mut c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    c = ((c & 0x7ffffffff) << 5) ^ val;
+    if c0 & 1 > 0 {
+        c ^= 0xf5dee51989
+    };
+    if c0 & 2 > 0 {
+        c ^= 0xa9fdca3312
+    };
+    if c0 & 4 > 0 {
+        c ^= 0x1bab10e32d
+    };
+    if c0 & 8 > 0 {
+        c ^= 0x3706b1677a
+    };
+    if c0 & 16 > 0 {
+        c ^= 0x644d626ffd
+    };
+    c
+}
+
+/// Compute the checksum of a descriptor without the `#checksum` suffix.
+pub fn checksum(descriptor: &str) -> error::Result<String> {
+    let mut c = 1;
+    let mut cls = 0;
+    let mut clscount = 0;
+    for ch in descriptor.chars() {
+        let pos = INPUT_CHARSET.find(ch).ok_or(error::anyhow!(
+            "invalid character `{ch}` inside the descriptor"
+        ))? as u64;
+        c = poly_mod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = poly_mod(c, cls);
+    }
+    (0..8).for_each(|_| c = poly_mod(c, 0));
+    c ^= 1;
+
+    let checksum = (0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect();
+    Ok(checksum)
+}
+
+/// Verify the `#checksum` suffix of the descriptor and return
+/// the descriptor without it.
+///
+/// When the checksum is missing the error contains the computed
+/// one, so the user can double check the descriptor and append it.
+pub fn verify_checksum(descriptor: &str) -> error::Result<&str> {
+    let descriptor = descriptor.trim();
+    let Some((body, expected)) = descriptor.split_once('#') else {
+        let checksum = checksum(descriptor)?;
+        error::bail!(
+            "descriptor checksum missing, verify the descriptor and append `#{checksum}` to confirm it"
+        );
+    };
+    if checksum(body)? != expected {
+        error::bail!("descriptor checksum mismatch, the descriptor is not valid");
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, verify_checksum};
+
+    #[test]
+    fn compute_checksum() {
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(
+            checksum("addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)").unwrap(),
+            "02wpgw69"
+        );
+    }
+
+    #[test]
+    fn verify_descriptor_checksum() {
+        assert_eq!(
+            verify_checksum("raw(deadbeef)#89f8spxm").unwrap(),
+            "raw(deadbeef)"
+        );
+        assert!(verify_checksum("raw(deadbeef)#89f8spxn").is_err());
+        assert!(verify_checksum("raw(deedbeef)#89f8spxm").is_err());
+        assert!(verify_checksum("raw(deadbeef)").is_err());
+    }
+}
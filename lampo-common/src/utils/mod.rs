@@ -1,2 +1,6 @@
 //! Utils module implementation
+pub mod backup;
+pub mod bip329;
+pub mod descriptor;
 pub mod logger;
+pub mod network;
@@ -0,0 +1,49 @@
+//! Network utils.
+//!
+//! The hash of the genesis block identifies the network and it is the
+//! same in every version of the `bitcoin` crate, so the network can be
+//! converted between the versions used by the wallets without matching
+//! on its name.
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::{BlockHash, Network};
+
+/// The networks supported by lampo.
+pub const NETWORKS: [Network; 4] = [
+    Network::Bitcoin,
+    Network::Testnet,
+    Network::Signet,
+    Network::Regtest,
+];
+
+/// The hash of the genesis block of `network`.
+pub fn genesis_block_hash(network: Network) -> BlockHash {
+    genesis_block(network).block_hash()
+}
+
+/// The network of the genesis block `hash`, `None` when
+/// it is not the genesis block of a supported network.
+pub fn network_from_genesis_block_hash(hash: &BlockHash) -> Option<Network> {
+    NETWORKS
+        .into_iter()
+        .find(|network| genesis_block_hash(*network) == *hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+
+    use super::{genesis_block_hash, network_from_genesis_block_hash, NETWORKS};
+
+    #[test]
+    fn networks_round_trip() {
+        for network in NETWORKS {
+            let hash = genesis_block_hash(network);
+            assert_eq!(network_from_genesis_block_hash(&hash), Some(network));
+        }
+        assert_eq!(
+            network_from_genesis_block_hash(&BlockHash::all_zeros()),
+            None
+        );
+    }
+}
@@ -1,10 +1,248 @@
-use std::sync::Arc;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use crate::bitcoin::{ScriptBuf, Transaction};
+use crate::backend::Backend;
+use crate::bitcoin::absolute::LockTime;
+use crate::bitcoin::{Address, OutPoint, ScriptBuf, Sequence, Transaction, Txid};
+use crate::chan;
 use crate::conf::LampoConf;
 use crate::error;
+use crate::error::LampoErrorCode;
 use crate::keys::LampoKeys;
-use crate::model::response::{NewAddress, Utxo};
+use crate::model::response::{Balance, Coin, NewAddress, Utxo, UtxoDiscrepancy};
+use crate::types::Keychain;
+
+/// The failures of a wallet, so the caller can tell them apart
+/// without looking at the message.
+#[derive(Debug)]
+pub enum WalletError {
+    /// The wallet does not have enough funds to pay the amount and the fee.
+    InsufficientFunds(String),
+    /// The options of the transaction are not valid.
+    InvalidOptions(String),
+    /// The wallet is not in sync with the chain.
+    Sync(error::Error),
+    /// The wallet is not able to sign the transaction.
+    Signing(error::Error),
+    /// Any other failure of the wallet or of its backend.
+    Other(error::Error),
+}
+
+impl WalletError {
+    pub fn other<E: Into<error::Error>>(err: E) -> Self {
+        Self::Other(err.into())
+    }
+
+    /// The code of the RPC error that reports this failure.
+    pub fn code(&self) -> LampoErrorCode {
+        match self {
+            Self::InsufficientFunds(_) => LampoErrorCode::InsufficientFunds,
+            Self::InvalidOptions(_) => LampoErrorCode::InvalidParams,
+            Self::Sync(_) => LampoErrorCode::WalletNotSynced,
+            Self::Signing(_) | Self::Other(_) => LampoErrorCode::Generic,
+        }
+    }
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientFunds(msg) => write!(f, "insufficient funds: {msg}"),
+            Self::InvalidOptions(msg) => write!(f, "{msg}"),
+            Self::Sync(err) => write!(f, "wallet not synced: {err}"),
+            Self::Signing(err) => write!(f, "impossible sign the transaction: {err}"),
+            Self::Other(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Sync(err) | Self::Signing(err) | Self::Other(err) => Some(err.as_ref()),
+            Self::InsufficientFunds(_) | Self::InvalidOptions(_) => None,
+        }
+    }
+}
+
+/// Give back the `WalletError` wrapped inside the error,
+/// any other error is `WalletError::Other`.
+impl From<error::Error> for WalletError {
+    fn from(err: error::Error) -> Self {
+        err.downcast::<WalletError>()
+            .unwrap_or_else(WalletError::Other)
+    }
+}
+
+/// The options used to build a transaction, the
+/// default is the behaviour of the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionOptions {
+    /// Allow to spend our own unconfirmed change, but never
+    /// the unconfirmed outputs received from others.
+    pub allow_unconfirmed: bool,
+    /// The nLockTime of the transaction, it is a block height
+    /// when below 500000000, a unix timestamp otherwise.
+    pub locktime: Option<u32>,
+    /// The nSequence of every input.
+    pub sequence: Option<u32>,
+    /// Signal that the transaction can be replaced (BIP 125), when
+    /// false the inputs are final. The `sequence` wins over it.
+    pub rbf: bool,
+    /// The only outputs that the transaction can spend, all of
+    /// them are spent. When empty the coin selection chooses them.
+    pub utxos: Vec<OutPoint>,
+    /// The recipient pays the fee, so it receives the amount minus
+    /// the fee, like the `subtractfeefromamount` of bitcoin core.
+    pub subtract_fee_from_amount: bool,
+    /// The address that receives the change, by default
+    /// a fresh address of the internal keychain.
+    pub change_address: Option<Address>,
+    /// The outputs that the coin selection never spends, e.g.
+    /// the reserve kept to bump the anchor channels.
+    pub excluded_utxos: Vec<OutPoint>,
+}
+
+impl Default for TransactionOptions {
+    fn default() -> Self {
+        Self {
+            allow_unconfirmed: false,
+            locktime: None,
+            sequence: None,
+            rbf: true,
+            utxos: Vec::new(),
+            subtract_fee_from_amount: false,
+            change_address: None,
+            excluded_utxos: Vec::new(),
+        }
+    }
+}
+
+impl TransactionOptions {
+    pub fn locktime(&self) -> Option<LockTime> {
+        self.locktime.map(LockTime::from_consensus)
+    }
+
+    pub fn sequence(&self) -> Option<Sequence> {
+        self.sequence.map(Sequence)
+    }
+
+    /// The nSequence that the wallet must set on every input, `None`
+    /// leaves the choice to the wallet. Without RBF the inputs are
+    /// final, unless they must enable the locktime.
+    pub fn input_sequence(&self) -> Option<Sequence> {
+        if self.sequence.is_some() || self.rbf {
+            return self.sequence();
+        }
+        match self.locktime() {
+            Some(locktime) if locktime != LockTime::ZERO => Some(Sequence::ENABLE_LOCKTIME_NO_RBF),
+            _ => Some(Sequence::MAX),
+        }
+    }
+
+    /// Make sure that the locktime and the sequence do not contradict
+    /// each other, a locktime is not enforced when the inputs are final.
+    pub fn validate(&self) -> Result<(), WalletError> {
+        if let Some(utxo) = self
+            .utxos
+            .iter()
+            .enumerate()
+            .find_map(|(i, utxo)| self.utxos[..i].contains(utxo).then_some(utxo))
+        {
+            return Err(WalletError::InvalidOptions(format!(
+                "the output `{utxo}` is selected more than once"
+            )));
+        }
+        let (Some(locktime), Some(sequence)) = (self.locktime(), self.sequence()) else {
+            return Ok(());
+        };
+        if locktime != LockTime::ZERO && !sequence.enables_absolute_lock_time() {
+            let kind = if locktime.is_block_height() {
+                "block height"
+            } else {
+                "unix timestamp"
+            };
+            return Err(WalletError::InvalidOptions(format!(
+                "the locktime `{locktime}` ({kind}) is not enforced with the final sequence `{sequence}`"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Decode the seed entropy when `seed` is hex instead of a BIP39
+/// mnemonic, e.g. when it is assembled from shares outside lampo.
+///
+/// The entropy must be 128 or 256 bits, and it restores the same
+/// wallet of the BIP39 mnemonic with that entropy.
+pub fn hex_entropy(seed: &str) -> error::Result<Option<Vec<u8>>> {
+    let seed = seed.trim();
+    // The words of a mnemonic are separated by spaces, so they are never hex.
+    if seed.is_empty() || !seed.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let entropy = hex::decode(seed)?;
+    if entropy.len() != 16 && entropy.len() != 32 {
+        error::bail!(
+            "the seed entropy must be 128 or 256 bits, it is {} bits",
+            entropy.len() * 8
+        );
+    }
+    Ok(Some(entropy))
+}
+
+/// A transaction of the wallet, with the inputs and
+/// the outputs that belong to the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletTransaction {
+    pub tx: Transaction,
+    /// The height of the block that confirms the
+    /// transaction, `None` when it is unconfirmed.
+    pub height: Option<u32>,
+    /// For every input, if it spends an output of the wallet.
+    pub our_inputs: Vec<bool>,
+    /// For every output, if it pays the wallet.
+    pub our_outputs: Vec<bool>,
+    /// The value of our outputs spent by the transaction.
+    pub sent_sat: u64,
+}
+
+impl WalletTransaction {
+    /// The value of the outputs that pay the wallet.
+    pub fn received_sat(&self) -> u64 {
+        self.tx
+            .output
+            .iter()
+            .zip(self.our_outputs.iter())
+            .filter(|(_, ours)| **ours)
+            .map(|(output, _)| output.value)
+            .sum()
+    }
+
+    /// The fee of the transaction, known only
+    /// when all the inputs are of the wallet.
+    pub fn fee_sat(&self) -> Option<u64> {
+        if self.our_inputs.is_empty() || self.our_inputs.iter().any(|ours| !ours) {
+            return None;
+        }
+        let outputs_sat = self.tx.output.iter().map(|output| output.value).sum();
+        self.sent_sat.checked_sub(outputs_sat)
+    }
+}
+
+/// An output that is not of the wallet but that is spent together
+/// with the outputs of the wallet, e.g. the anchor of a commitment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalInput {
+    pub outpoint: OutPoint,
+    pub value_sat: u64,
+    /// The weight of the input once signed.
+    pub weight: u64,
+}
 
 /// Wallet manager trait that define a generic interface
 /// over Wallet implementation!
@@ -25,21 +263,367 @@ pub trait WalletManager: Send + Sync {
     /// return an on chain address
     fn get_onchain_address(&self) -> error::Result<NewAddress>;
 
+    /// Generate `count` on chain addresses at once, labeled
+    /// as `{label_prefix}-{n}` when a prefix is given.
+    ///
+    /// The default implementation do not store any label.
+    fn get_onchain_addresses(
+        &self,
+        count: u32,
+        label_prefix: Option<&str>,
+    ) -> error::Result<Vec<NewAddress>> {
+        let _ = label_prefix;
+        (0..count).map(|_| self.get_onchain_address()).collect()
+    }
+
+    /// Move back the next derivation index of the keychain to `index`,
+    /// so the addresses in the range can be reused.
+    ///
+    /// This must fail if an address above `index` already received funds.
+    fn reset_address_index(&self, keychain: Keychain, index: u32) -> error::Result<()>;
+
+    /// Return the next derivation index of the external
+    /// and internal keychains.
+    fn address_indices(&self) -> error::Result<(u32, u32)>;
+
     /// Get the current balance of the wallet.
     fn get_onchain_balance(&self) -> error::Result<u64>;
 
+    /// Return the balance of the external and of the internal
+    /// keychain, so the received funds are split from the change.
+    fn balance_by_keychain(&self) -> error::Result<(Balance, Balance)>;
+
     /// Create the transaction from a script and return the transaction
     /// to propagate to the network.
+    ///
+    /// Only confirmed outputs are spent, unless `options.allow_unconfirmed`
+    /// is true, in that case also our unconfirmed change can be spent.
+    ///
+    /// When `options.utxos` is not empty only those outputs are spent, and
+    /// it fails if one of them is reserved or is not an output of the wallet.
     fn create_transaction(
         &self,
         script: ScriptBuf,
         amount_sat: u64,
         fee_rate: u32,
-    ) -> error::Result<Transaction>;
+        options: TransactionOptions,
+    ) -> Result<Transaction, WalletError>;
 
     /// Return the list of transaction stored inside the wallet
     fn list_transactions(&self) -> error::Result<Vec<Utxo>>;
 
+    /// Return the unspent outputs of the wallet with the address
+    /// where they were received and its derivation path.
+    fn list_coins(&self) -> error::Result<Vec<Coin>>;
+
+    /// Return the transactions that spend or create outputs of the wallet.
+    fn transaction_history(&self) -> error::Result<Vec<WalletTransaction>>;
+
+    /// Create the transaction that spends the confirmed outputs of the
+    /// wallet, the smallest first and at most `max_inputs`, into a single
+    /// fresh address. The reserved outputs are never spent.
+    fn consolidate(
+        &self,
+        fee_rate: u32,
+        max_inputs: Option<usize>,
+    ) -> Result<Transaction, WalletError>;
+
+    /// Create the transaction that spends the `inputs`, that are not of
+    /// the wallet, together with the outputs of the wallet needed to pay
+    /// `fee_rate` sats per kw, and return it with its fee in sats. The
+    /// value of the `inputs` goes back to the wallet.
+    ///
+    /// Only the inputs of the wallet are signed, the caller signs the others.
+    fn fund_external_inputs(
+        &self,
+        inputs: &[ExternalInput],
+        fee_rate: u32,
+    ) -> Result<(Transaction, u64), WalletError> {
+        let _ = (inputs, fee_rate);
+        Err(WalletError::InvalidOptions(
+            "the wallet is not able to spend external inputs".to_owned(),
+        ))
+    }
+
+    /// Release the coins reserved by a transaction built with
+    /// `create_transaction` that will never be broadcast.
+    fn release_transaction(&self, _tx: &Transaction) -> error::Result<()> {
+        Ok(())
+    }
+
     /// Sync the wallet.
-    fn sync(&self) -> error::Result<()>;
+    fn sync(&self) -> Result<(), WalletError>;
+
+    /// Return the unix timestamp of the last successful sync.
+    fn last_sync(&self) -> Option<u64> {
+        None
+    }
+
+    /// The wallet is kept in sync by a `SyncWorker`, so the reads
+    /// can use the state of the last sync instead of doing their own.
+    fn set_background_sync(&self, _enabled: bool) {}
+
+    /// Scan again the chain, so the wallet drops the outputs
+    /// created or spent inside the blocks removed by a reorg.
+    fn rescan(&self) -> Result<(), WalletError> {
+        self.sync()
+    }
+
+    /// Compare the outputs of the wallet with the utxo set of the
+    /// `backend`, and return the ones that the backend considers spent
+    /// or missing. With `rescan` the wallet is scanned again when
+    /// some output is wrong, to fix its view of the chain.
+    fn reconcile(
+        &self,
+        backend: &dyn Backend,
+        rescan: bool,
+    ) -> Result<Vec<UtxoDiscrepancy>, WalletError> {
+        let mut discrepancies = Vec::new();
+        for utxo in self.list_transactions()? {
+            let txid = Txid::from_str(&utxo.txid).map_err(WalletError::other)?;
+            if backend.is_unspent(&txid, utxo.vout)? {
+                continue;
+            }
+            log::warn!(
+                "the output `{}:{}` of the wallet is not unspent for the backend",
+                utxo.txid,
+                utxo.vout
+            );
+            discrepancies.push(UtxoDiscrepancy {
+                txid: utxo.txid,
+                vout: utxo.vout,
+                amount_msat: utxo.amount_msat,
+                fixed: false,
+            });
+        }
+        if !rescan || discrepancies.is_empty() {
+            return Ok(discrepancies);
+        }
+        self.rescan()?;
+        let utxos = self.list_transactions()?;
+        for discrepancy in discrepancies.iter_mut() {
+            discrepancy.fixed = !utxos
+                .iter()
+                .any(|utxo| utxo.txid == discrepancy.txid && utxo.vout == discrepancy.vout);
+        }
+        Ok(discrepancies)
+    }
+}
+
+/// The result of a round of the `SyncWorker`, the unix
+/// timestamp of the sync or the reason of the failure.
+pub type SyncResult = Result<u64, String>;
+
+/// Sync the wallet on a background thread, so the RPC
+/// threads never wait a full scan of the chain.
+///
+/// The thread is stopped and joined when the worker is dropped.
+pub struct SyncWorker {
+    wallet: Arc<dyn WalletManager>,
+    shared: Arc<SyncShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// The state shared between the worker and its thread.
+struct SyncShared {
+    /// Who is waiting the result of the next rounds.
+    subscribers: Mutex<Vec<chan::Sender<SyncResult>>>,
+    /// Set when the worker is dropped, the thread exits
+    /// at the end of the current round.
+    shutdown: AtomicBool,
+}
+
+impl SyncWorker {
+    /// Spawn the thread that syncs `wallet` every `interval`, the
+    /// first round starts immediately.
+    pub fn spawn(wallet: Arc<dyn WalletManager>, interval: Duration) -> Arc<Self> {
+        let shared = Arc::new(SyncShared {
+            subscribers: Mutex::new(Vec::new()),
+            shutdown: AtomicBool::new(false),
+        });
+        wallet.set_background_sync(true);
+        let thread = {
+            let wallet = wallet.clone();
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                while !shared.shutdown.load(Ordering::SeqCst) {
+                    let result = wallet
+                        .sync()
+                        .map(|_| wallet.last_sync().unwrap_or_default())
+                        .map_err(|err| err.to_string());
+                    if let Err(err) = &result {
+                        log::warn!(target: "lampo", "background wallet sync failed: {err}");
+                    }
+                    shared.publish(result);
+                    // The drop of the worker unparks the thread, so it
+                    // does not wait the full interval before exiting.
+                    let deadline = Instant::now() + interval;
+                    while !shared.shutdown.load(Ordering::SeqCst) {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            break;
+                        }
+                        std::thread::park_timeout(deadline - now);
+                    }
+                }
+            })
+        };
+        Arc::new(Self {
+            wallet,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Receive the result of every next round.
+    pub fn subscribe(&self) -> chan::Receiver<SyncResult> {
+        let (sender, receiver) = chan::unbounded();
+        // SAFETY: the lock can not be poisoned.
+        self.shared.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Wait the end of the next round, and return the
+    /// unix timestamp of the sync.
+    pub fn wait_next(&self, timeout: Duration) -> Result<u64, WalletError> {
+        self.subscribe()
+            .recv_timeout(timeout)
+            .map_err(|err| WalletError::Sync(error::anyhow!("{err}")))?
+            .map_err(|err| WalletError::Sync(error::anyhow!(err)))
+    }
+}
+
+impl Drop for SyncWorker {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                log::warn!(target: "lampo", "the background wallet sync thread panicked");
+            }
+        }
+        // Without the worker the reads sync the wallet by themselves.
+        self.wallet.set_background_sync(false);
+    }
+}
+
+impl SyncShared {
+    /// Send the result to the subscribers, and forget
+    /// the ones that dropped the receiver.
+    fn publish(&self, result: SyncResult) {
+        // SAFETY: the lock can not be poisoned.
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| subscriber.send(result.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TransactionOptions, WalletError};
+    use crate::error;
+    use crate::error::LampoErrorCode;
+
+    #[test]
+    fn locktime_needs_a_non_final_sequence() {
+        let options = |locktime, sequence| TransactionOptions {
+            allow_unconfirmed: false,
+            locktime,
+            sequence,
+            rbf: true,
+            utxos: Vec::new(),
+            subtract_fee_from_amount: false,
+            change_address: None,
+        };
+        assert!(options(None, None).validate().is_ok());
+        assert!(options(Some(800_000), None).validate().is_ok());
+        assert!(options(Some(800_000), Some(0xFFFFFFFE)).validate().is_ok());
+        assert!(options(Some(1_700_000_000), Some(0xFFFFFFFD))
+            .validate()
+            .is_ok());
+        assert!(options(Some(0), Some(0xFFFFFFFF)).validate().is_ok());
+        assert!(options(Some(800_000), Some(0xFFFFFFFF)).validate().is_err());
+        assert!(matches!(
+            options(Some(1_700_000_000), Some(0xFFFFFFFF)).validate(),
+            Err(WalletError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn utxos_are_selected_once() {
+        use std::str::FromStr;
+
+        use crate::bitcoin::OutPoint;
+
+        let utxo = OutPoint::from_str(
+            "f3b2bd6bc2a2d50dbd1a3c41e4d6ac4b1f5bb5b7c8cd0bd6b1a0e5a4e0a7c9d1:0",
+        )
+        .unwrap();
+        let options = |utxos| TransactionOptions {
+            utxos,
+            ..Default::default()
+        };
+        assert!(options(vec![utxo]).validate().is_ok());
+        assert!(matches!(
+            options(vec![utxo, utxo]).validate(),
+            Err(WalletError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn seed_from_hex_entropy() {
+        use super::hex_entropy;
+
+        let words = "legal winner thank year wave sausage worth useful legal winner thank yellow";
+        assert_eq!(hex_entropy(words).unwrap(), None);
+        assert_eq!(
+            hex_entropy("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f").unwrap(),
+            Some(vec![0x7f; 16])
+        );
+        assert_eq!(hex_entropy(&"80".repeat(32)).unwrap(), Some(vec![0x80; 32]));
+        // 192 bits are valid for BIP39, but not as raw entropy.
+        assert!(hex_entropy(&"7f".repeat(24)).is_err());
+        assert!(hex_entropy("7f7f7").is_err());
+    }
+
+    #[test]
+    fn wallet_error_survives_anyhow() {
+        let err: error::Error = WalletError::InsufficientFunds("needed 2 sats".to_owned()).into();
+        let err = WalletError::from(err);
+        assert!(matches!(err, WalletError::InsufficientFunds(_)));
+        assert_eq!(err.code(), LampoErrorCode::InsufficientFunds);
+
+        let err = WalletError::from(error::anyhow!("bitcoin core is down"));
+        assert!(matches!(err, WalletError::Other(_)));
+        assert_eq!(err.code(), LampoErrorCode::Generic);
+    }
+
+    #[test]
+    fn inputs_are_final_without_rbf() {
+        use crate::bitcoin::Sequence;
+
+        let options = |locktime, sequence, rbf| TransactionOptions {
+            allow_unconfirmed: false,
+            locktime,
+            sequence,
+            rbf,
+            utxos: Vec::new(),
+            subtract_fee_from_amount: false,
+            change_address: None,
+        };
+        assert!(TransactionOptions::default().rbf);
+        assert_eq!(options(None, None, true).input_sequence(), None);
+        assert_eq!(
+            options(None, None, false).input_sequence(),
+            Some(Sequence::MAX)
+        );
+        // The locktime is still enforced, without signaling RBF.
+        assert_eq!(
+            options(Some(800_000), None, false).input_sequence(),
+            Some(Sequence::ENABLE_LOCKTIME_NO_RBF)
+        );
+        assert_eq!(
+            options(None, Some(0xFFFFFFFD), false).input_sequence(),
+            Some(Sequence(0xFFFFFFFD))
+        );
+    }
 }
@@ -1,12 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Not;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use bdk::bitcoin::blockdata::constants::genesis_block;
+use bdk::bitcoin::hashes::Hash as _;
 use bdk::bitcoin::Amount;
 use bdk::keys::bip39::Language;
 use bdk::keys::bip39::Mnemonic;
 use bdk::keys::bip39::WordCount;
 use bdk::keys::DerivableKey;
+use bdk::keys::DescriptorSecretKey;
 use bdk::keys::ExtendedKey;
 use bdk::keys::GeneratableKey;
 use bdk::keys::GeneratedKey;
@@ -20,18 +25,39 @@ use crate::bitcoin::PrivateKey;
 
 use lampo_common::bitcoin;
 use lampo_common::bitcoin::consensus::Decodable;
+use lampo_common::bitcoin::hashes::Hash;
 use lampo_common::conf::{LampoConf, Network};
 use lampo_common::error;
 use lampo_common::json;
 use lampo_common::json::Deserialize;
 use lampo_common::keys::LampoKeys;
-use lampo_common::model::response::{NewAddress, Utxo};
-use lampo_common::wallet::WalletManager;
+use lampo_common::model::response::{Balance, Coin, NewAddress, Utxo};
+use lampo_common::types::Keychain;
+use lampo_common::utils::{descriptor, network};
+use lampo_common::wallet::{
+    hex_entropy, ExternalInput, TransactionOptions, WalletError, WalletManager, WalletTransaction,
+};
+
+/// The network of bdk with the same genesis block of `network`.
+fn bdk_network(network: Network) -> error::Result<bdk::bitcoin::Network> {
+    let genesis = network::genesis_block_hash(network).to_byte_array();
+    [
+        bdk::bitcoin::Network::Bitcoin,
+        bdk::bitcoin::Network::Testnet,
+        bdk::bitcoin::Network::Signet,
+        bdk::bitcoin::Network::Regtest,
+    ]
+    .into_iter()
+    .find(|bdk_network| genesis_block(*bdk_network).block_hash().to_byte_array() == genesis)
+    .ok_or(error::anyhow!("network `{network}` not supported"))
+}
 
 pub struct CoreWalletManager {
     rpc: Client,
     keymanager: Arc<LampoKeys>,
     network: Network,
+    /// Unix timestamp of the last sync, zero if never synced.
+    last_sync: AtomicU64,
 }
 
 impl CoreWalletManager {
@@ -40,23 +66,21 @@ impl CoreWalletManager {
         conf: Arc<LampoConf>,
         mnemonic_words: &str,
     ) -> error::Result<(bdk::Wallet, LampoKeys)> {
-        // Parse a mnemonic
-        let mnemonic = Mnemonic::parse(mnemonic_words).map_err(|err| error::anyhow!("{err}"))?;
+        // Parse a mnemonic, or the hex of its entropy
+        let mnemonic = match hex_entropy(mnemonic_words)? {
+            Some(entropy) => Mnemonic::from_entropy(&entropy),
+            None => Mnemonic::parse(mnemonic_words),
+        }
+        .map_err(|err| error::anyhow!("{err}"))?;
         // Generate the extended key
         let xkey: ExtendedKey = mnemonic.into_extended_key()?;
-        let network = match conf.network.to_string().as_str() {
-            "bitcoin" => bdk::bitcoin::Network::Bitcoin,
-            "testnet" => bdk::bitcoin::Network::Testnet,
-            "signet" => bdk::bitcoin::Network::Signet,
-            "regtest" => bdk::bitcoin::Network::Regtest,
-            _ => unreachable!(),
-        };
+        let network = bdk_network(conf.network)?;
         // Get xprv from the extended key
         let xprv = xkey
             .into_xprv(network)
             .ok_or(error::anyhow!("impossible cast the private key"))?;
 
-        let ldk_keys = LampoKeys::new(xprv.private_key.secret_bytes());
+        let ldk_keys = LampoKeys::from_secret(xprv.private_key.secret_bytes())?;
         // Create a BDK wallet structure using BIP 84 descriptor ("m/84h/1h/0h/0" and "m/84h/1h/0h/1")
         let wallet = bdk::Wallet::new(
             Bip84(xprv, KeychainKind::External),
@@ -74,18 +98,13 @@ impl CoreWalletManager {
     ) -> error::Result<(bdk::Wallet, LampoKeys)> {
         use bdk::bitcoin::bip32::Xpriv;
 
+        LampoKeys::validate_secret(&xprv.inner.secret_bytes())?;
         let ldk_keys = if let Some(channel_keys) = channel_keys {
             LampoKeys::with_channel_keys(xprv.inner.secret_bytes(), channel_keys)
         } else {
             LampoKeys::new(xprv.inner.secret_bytes())
         };
-        let network = match xprv.network.to_string().as_str() {
-            "bitcoin" => bdk::bitcoin::Network::Bitcoin,
-            "testnet" => bdk::bitcoin::Network::Testnet,
-            "signet" => bdk::bitcoin::Network::Signet,
-            "regtest" => bdk::bitcoin::Network::Regtest,
-            _ => unreachable!(),
-        };
+        let network = bdk_network(xprv.network)?;
         let key = Xpriv::new_master(network, &xprv.inner.secret_bytes())?;
         let key = ExtendedKey::from(key);
         let wallet = bdk::Wallet::new(Bip84(key, KeychainKind::External), None, (), network)
@@ -93,6 +112,37 @@ impl CoreWalletManager {
         Ok((wallet, ldk_keys))
     }
 
+    /// Restore the wallet from a private descriptor, the descriptor
+    /// must end with the `#checksum` suffix defined in BIP 380.
+    pub fn restore_from_descriptor(conf: Arc<LampoConf>, descriptor: &str) -> error::Result<Self> {
+        let descriptor = descriptor::verify_checksum(descriptor)?;
+        let network = bdk_network(conf.network)?;
+        let wallet = bdk::Wallet::new(descriptor, None, (), network)
+            .map_err(|err| error::anyhow!(err.to_string()))?;
+        let signers = wallet.get_signers(KeychainKind::External);
+        let xprv = signers
+            .as_key_map(wallet.secp_ctx())
+            .into_values()
+            .find_map(|key| match key {
+                DescriptorSecretKey::XPrv(xkey) => Some(xkey.xkey),
+                _ => None,
+            })
+            .ok_or(error::anyhow!(
+                "the descriptor must contain an extended private key"
+            ))?;
+        let keymanager = LampoKeys::from_secret(xprv.private_key.secret_bytes())?;
+
+        let rpc = Self::build_bitcoin_rpc(conf.clone(), None)?;
+        let wallet_name = Self::configure_bitcoin_wallet(&rpc, conf.clone(), wallet)?;
+        let rpc = Self::build_bitcoin_rpc(conf.clone(), Some(&wallet_name))?;
+        Ok(Self {
+            rpc,
+            keymanager: Arc::new(keymanager),
+            network: conf.network,
+            last_sync: AtomicU64::new(0),
+        })
+    }
+
     fn configure_bitcoin_wallet(
         rpc: &Client,
         conf: Arc<LampoConf>,
@@ -169,19 +219,158 @@ impl CoreWalletManager {
         if let Some(wallet_name) = wallet {
             url = format!("{url}/wallet/{wallet_name}");
         }
-        let rpc = Client::new(
-            &url,
-            Auth::UserPass(
-                conf.core_user
-                    .clone()
-                    .ok_or(error::anyhow!("bitcoin core user not specified"))?,
-                conf.core_pass
-                    .clone()
-                    .ok_or(error::anyhow!("bitcoin core password not specified"))?,
-            ),
-        )?;
+        let (user, pass) = conf.core_auth()?.credentials()?;
+        let rpc = Client::new(&url, Auth::UserPass(user, pass))?;
         Ok(rpc)
     }
+
+    /// Return the derivation path of one of our addresses,
+    /// `None` if the address is not derived by the wallet.
+    fn derivation_path(&self, address: &str) -> error::Result<Option<String>> {
+        let info: json::Value = self.rpc.call("getaddressinfo", &[address.into()])?;
+        Ok(info
+            .get("hdkeypath")
+            .and_then(|path| path.as_str())
+            .map(str::to_owned))
+    }
+
+    /// Return true when the `script` pays an address of the wallet.
+    fn is_mine(&self, script: &bitcoin::Script) -> error::Result<bool> {
+        let Ok(address) = bitcoin::Address::from_script(script, self.network) else {
+            return Ok(false);
+        };
+        let info: json::Value = self
+            .rpc
+            .call("getaddressinfo", &[address.to_string().into()])?;
+        Ok(info
+            .get("ismine")
+            .and_then(|mine| mine.as_bool())
+            .unwrap_or_default())
+    }
+
+    /// Return the keychain and the derivation index of one of our
+    /// addresses, `None` if the address is not derived by the wallet.
+    fn derivation_index(&self, address: &str) -> error::Result<Option<(Keychain, u32)>> {
+        Ok(self
+            .derivation_path(address)?
+            .and_then(|path| keychain_index(&path)))
+    }
+
+    /// Return the active segwit descriptor of the `keychain`, with
+    /// the private keys when `private` is true.
+    fn active_descriptor(&self, keychain: Keychain, private: bool) -> error::Result<json::Value> {
+        let internal = keychain == Keychain::Internal;
+        let descriptors: json::Value = self.rpc.call("listdescriptors", &[private.into()])?;
+        descriptors["descriptors"]
+            .as_array()
+            .and_then(|descriptors| {
+                descriptors.iter().find(|desc| {
+                    desc["active"].as_bool() == Some(true)
+                        && desc["internal"].as_bool() == Some(internal)
+                        && desc["desc"]
+                            .as_str()
+                            .map(|desc| desc.starts_with("wpkh("))
+                            .unwrap_or_default()
+                })
+            })
+            .cloned()
+            .ok_or(error::anyhow!(
+                "active descriptor for the {:?} keychain not found",
+                keychain
+            ))
+    }
+
+    /// Return the highest index of the keychain that received some funds.
+    fn highest_funded_index(&self, keychain: Keychain) -> error::Result<Option<u32>> {
+        // `listreceivedbyaddress` does not report the change addresses and
+        // `listunspent` forgets the spent change, so the outputs of every
+        // wallet transaction are scanned. The inputs are followed too,
+        // because `listtransactions` hides the transactions that pay only
+        // our change.
+        let listed: Vec<json::Value> = self.rpc.call(
+            "listtransactions",
+            &["*".into(), 100_000.into(), 0.into(), true.into()],
+        )?;
+        let unspent: Vec<json::Value> = self.rpc.call("listunspent", &[0.into()])?;
+        let mut queue = listed
+            .iter()
+            .chain(unspent.iter())
+            .filter_map(|entry| entry.get("txid").and_then(|txid| txid.as_str()))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        let mut visited = HashSet::new();
+        let mut mine = HashMap::new();
+        let mut highest = None;
+        while let Some(txid) = queue.pop() {
+            if !visited.insert(txid.clone()) {
+                continue;
+            }
+            // The inputs that spend the outputs of somebody else
+            // are not wallet transactions.
+            let Some((tx, _)) = self.wallet_transaction(&txid)? else {
+                continue;
+            };
+            queue.extend(
+                tx.input
+                    .iter()
+                    .filter(|input| !input.previous_output.is_null())
+                    .map(|input| input.previous_output.txid.to_string()),
+            );
+            for output in tx.output.iter() {
+                let Ok(address) =
+                    bitcoin::Address::from_script(&output.script_pubkey, self.network)
+                else {
+                    continue;
+                };
+                let address = address.to_string();
+                if !mine.contains_key(&address) {
+                    let index = self.derivation_index(&address)?;
+                    mine.insert(address.clone(), index);
+                }
+                if let Some((kind, index)) = mine[&address] {
+                    if kind == keychain {
+                        highest = highest.max(Some(index));
+                    }
+                }
+            }
+        }
+        Ok(highest)
+    }
+
+    /// Return the wallet transaction with `txid` and its block height,
+    /// `None` when the transaction is not inside the wallet.
+    fn wallet_transaction(
+        &self,
+        txid: &str,
+    ) -> error::Result<Option<(bitcoin::Transaction, Option<u32>)>> {
+        let info: json::Value = match self.rpc.call("gettransaction", &[txid.into(), true.into()]) {
+            Ok(info) => info,
+            // RPC_INVALID_ADDRESS_OR_KEY, the transaction is not inside the wallet.
+            Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(err)))
+                if err.code == -5 =>
+            {
+                return Ok(None)
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let hex = info["hex"]
+            .as_str()
+            .ok_or(error::anyhow!("the transaction `{txid}` has no hex"))?;
+        let mut reader = HexIterator::new(hex)?;
+        let tx: bitcoin::Transaction = Decodable::consensus_decode(&mut reader)?;
+        let height = info
+            .get("blockheight")
+            .and_then(|height| height.as_u64())
+            .map(|height| height as u32);
+        Ok(Some((tx, height)))
+    }
+
+    /// Return the outputs locked by the coin selection of a
+    /// transaction that is not broadcast yet.
+    fn locked_outputs(&self) -> error::Result<Vec<LockedOutput>> {
+        let locked: Vec<LockedOutput> = self.rpc.call("listlockunspent", &[])?;
+        Ok(locked)
+    }
 }
 
 #[macro_export]
@@ -199,36 +388,76 @@ struct Tx {
     hex: Option<String>,
 }
 
-impl WalletManager for CoreWalletManager {
-    fn new(conf: Arc<LampoConf>) -> error::Result<(Self, String)>
-    where
-        Self: Sized,
-    {
-        let mnemonic: GeneratedKey<_, bdk::miniscript::Tap> =
-            Mnemonic::generate((WordCount::Words12, Language::English))
-                .map_err(|err| error::anyhow!("{:?}", err))?;
+/// The transaction returned by `fundrawtransaction` with its fee in BTC.
+#[derive(Debug, Deserialize)]
+struct FundedTx {
+    hex: Option<String>,
+    fee: f64,
+}
 
-        let (wallet, keymanager) =
-            CoreWalletManager::build_wallet(conf.clone(), &mnemonic.to_string())?;
-        let rpc = Self::build_bitcoin_rpc(conf.clone(), None)?;
-        let wallet_name = Self::configure_bitcoin_wallet(&rpc, conf.clone(), wallet)?;
-        let rpc = Self::build_bitcoin_rpc(conf.clone(), Some(&wallet_name))?;
-        Ok((
-            Self {
-                rpc,
-                keymanager: keymanager.into(),
-                network: conf.network,
-            },
-            mnemonic.to_string(),
-        ))
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+struct LockedOutput {
+    txid: String,
+    vout: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct TxOut {
+    value: f64,
+    confirmations: u32,
+    #[serde(rename = "scriptPubKey")]
+    script_pub_key: ScriptPubKey,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScriptPubKey {
+    address: Option<String>,
+}
+
+/// Return the keychain and the address index of a derivation path
+/// like `m/84h/1h/0h/0/5`, where they are the last two steps.
+fn keychain_index(path: &str) -> Option<(Keychain, u32)> {
+    let mut steps = path.rsplit('/');
+    let index = steps.next()?.parse::<u32>().ok()?;
+    let keychain = match steps.next()? {
+        "0" => Keychain::External,
+        "1" => Keychain::Internal,
+        _ => return None,
+    };
+    Some((keychain, index))
+}
+
+/// Tell apart the coin selection of bitcoin core that
+/// has not enough funds from the other failures.
+fn fund_error(err: bitcoincore_rpc::Error) -> WalletError {
+    match err {
+        bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(ref rpc))
+            if rpc.message.contains("Insufficient funds")
+                // The outputs selected by the user are not enough.
+                || rpc.message.contains("does not cover the transaction target") =>
+        {
+            WalletError::InsufficientFunds(rpc.message.clone())
+        }
+        // The amount minus the fee is dust, or it does not pay the fee.
+        bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(ref rpc))
+            if rpc.message.contains("amount is too small") =>
+        {
+            WalletError::InvalidOptions(rpc.message.clone())
+        }
+        err => WalletError::other(err),
     }
+}
 
-    fn create_transaction(
+impl CoreWalletManager {
+    /// Fund the transaction that pays `amount_sat` to `script`, and
+    /// return it unsigned with the locktime and the sequences of `options`.
+    fn fund_transaction(
         &self,
         script: bitcoin::ScriptBuf,
         amount_sat: u64,
         fee_rate: u32,
-    ) -> error::Result<bitcoin::Transaction> {
+        options: TransactionOptions,
+    ) -> error::Result<Option<String>> {
         let addr = bitcoin_bech32::WitnessProgram::from_scriptpubkey(
             script.as_bytes(),
             match self.network {
@@ -242,45 +471,259 @@ impl WalletManager for CoreWalletManager {
         .to_address();
         let mut map = HashMap::new();
         map.insert(addr, Amount::from_sat(amount_sat).to_btc());
-        let options = json::json!({
+        // A fresh address of the change keychain for every transaction, the
+        // index is stored inside the bitcoin core wallet so it survives restarts.
+        let change_address: String = match &options.change_address {
+            Some(address) => address.to_string(),
+            None => self.rpc.call("getrawchangeaddress", &["bech32".into()])?,
+        };
+        self.check_selected_utxos(&options)?;
+        let inputs = options
+            .utxos
+            .iter()
+            .map(|utxo| json::json!({ "txid": utxo.txid.to_string(), "vout": utxo.vout }))
+            .collect::<Vec<_>>();
+        let fund_options = json::json!({
             // LDK gives us feerates in satoshis per KW but Bitcoin Core here expects fees
             // denominated in satoshis per vB. First we need to multiply by 4 to convert weight
             // units to virtual bytes, then divide by 1000 to convert KvB to vB.
             "fee_rate": fee_rate as f64 / 250.0,
-            // While users could "cancel" a channel open by RBF-bumping and paying back to
-            // themselves, we don't allow it here as its easy to have users accidentally RBF bump
-            // and pay to the channel funding address, which results in loss of funds. Real
-            // LDK-based applications should enable RBF bumping and RBF bump either to a local
-            // change address or to a new channel output negotiated with the same node.
-            "replaceable": false,
-            "include_unsafe": true,
+            "replaceable": options.rbf,
+            // The unconfirmed outputs received from others are unsafe, so they
+            // are never spent, while our unconfirmed change is spent only when
+            // the coin selection can use the outputs with zero confirmations.
+            "include_unsafe": false,
+            "minconf": if options.allow_unconfirmed { 0 } else { 1 },
             "includeWatching": true,
-            "add_inputs": true,
+            // The outputs selected by the user are the only ones spent.
+            "add_inputs": inputs.is_empty(),
+            // The recipient is the only output before the funding.
+            "subtractFeeFromOutputs": if options.subtract_fee_from_amount { vec![0] } else { vec![] },
+            "changeAddress": change_address,
+            // The inputs are locked until the transaction is broadcast or
+            // released, so two funding transactions never spend the same coins.
+            "lockUnspents": true,
         });
 
         let hex: String = self.rpc.call(
             "createrawtransaction",
-            &[json::json!([]), json::json!(&map), json::json!(0)],
+            &[json::json!(inputs), json::json!(&map), json::json!(0)],
         )?;
 
-        let tx: Tx = self.rpc.call(
+        // The excluded outputs are locked while the coin selection runs,
+        // the ones that were already locked stay locked after.
+        let locked = self.locked_outputs()?;
+        let excluded = options
+            .excluded_utxos
+            .iter()
+            .map(|utxo| LockedOutput {
+                txid: utxo.txid.to_string(),
+                vout: utxo.vout,
+            })
+            .filter(|utxo| !locked.contains(utxo))
+            .map(|utxo| json::json!({ "txid": utxo.txid, "vout": utxo.vout }))
+            .collect::<Vec<_>>();
+        if !excluded.is_empty() {
+            let _: bool = self
+                .rpc
+                .call("lockunspent", &[false.into(), json::json!(excluded)])?;
+        }
+        let tx = self.rpc.call::<Tx>(
             "fundrawtransaction",
-            &[json::json!(hex), json::json!(options)],
-        )?;
+            &[json::json!(hex), json::json!(fund_options)],
+        );
+        if !excluded.is_empty() {
+            let _: bool = self
+                .rpc
+                .call("lockunspent", &[true.into(), json::json!(excluded)])?;
+        }
+        let tx = tx.map_err(fund_error)?;
+        let mut hex = tx.hex;
+        if options.locktime.is_some() || options.input_sequence().is_some() {
+            // The inputs are chosen by the coin selection, so the
+            // locktime and the sequences are set before signing.
+            let mut tx: bitcoin::Transaction =
+                bitcoin::consensus::deserialize(&hex!(hex.as_deref().unwrap_or_default()))?;
+            if let Some(locktime) = options.locktime() {
+                tx.lock_time = locktime;
+            }
+            if let Some(sequence) = options.input_sequence() {
+                tx.input
+                    .iter_mut()
+                    .for_each(|input| input.sequence = sequence);
+            }
+            hex = Some(bitcoin::consensus::encode::serialize_hex(&tx));
+        }
+        Ok(hex)
+    }
 
-        let hex: Tx = self
+    /// Make sure that the outputs selected in `options` can be
+    /// spent, so they are not reserved and they are in the wallet.
+    fn check_selected_utxos(&self, options: &TransactionOptions) -> error::Result<()> {
+        if options.utxos.is_empty() {
+            return Ok(());
+        }
+        let locked = self.locked_outputs()?;
+        let minconf = if options.allow_unconfirmed { 0 } else { 1 };
+        let unspent = self
             .rpc
-            .call("signrawtransactionwithwallet", &[json::json!(tx.hex)])?;
-        let hex = hex.hex.unwrap();
+            .list_unspent(Some(minconf), None, None, Some(false), None)?
+            .into_iter()
+            .filter(|utxo| utxo.spendable)
+            .map(|utxo| (utxo.txid.to_string(), utxo.vout))
+            .collect::<Vec<_>>();
+        for utxo in options.utxos.iter() {
+            let (txid, vout) = (utxo.txid.to_string(), utxo.vout);
+            if locked.contains(&LockedOutput {
+                txid: txid.clone(),
+                vout,
+            }) {
+                return Err(WalletError::InvalidOptions(format!(
+                    "the output `{utxo}` is reserved"
+                ))
+                .into());
+            }
+            if !unspent.contains(&(txid, vout)) {
+                return Err(WalletError::InvalidOptions(format!(
+                    "the output `{utxo}` is not a spendable output of the wallet"
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sign with the wallet keys the funded transaction `hex`.
+    fn sign_transaction(&self, hex: Option<String>) -> error::Result<bitcoin::Transaction> {
+        let tx: Tx = self
+            .rpc
+            .call("signrawtransactionwithwallet", &[json::json!(hex)])?;
+        let hex = tx.hex.ok_or(error::anyhow!(
+            "bitcoin core did not return the signed transaction"
+        ))?;
         let mut reader = HexIterator::new(&hex)?;
         let object = Decodable::consensus_decode(&mut reader)?;
         Ok(object)
     }
+}
+
+impl WalletManager for CoreWalletManager {
+    fn new(conf: Arc<LampoConf>) -> error::Result<(Self, String)>
+    where
+        Self: Sized,
+    {
+        let mnemonic: GeneratedKey<_, bdk::miniscript::Tap> =
+            Mnemonic::generate((WordCount::Words12, Language::English))
+                .map_err(|err| error::anyhow!("{:?}", err))?;
+
+        let (wallet, keymanager) =
+            CoreWalletManager::build_wallet(conf.clone(), &mnemonic.to_string())?;
+        let rpc = Self::build_bitcoin_rpc(conf.clone(), None)?;
+        let wallet_name = Self::configure_bitcoin_wallet(&rpc, conf.clone(), wallet)?;
+        let rpc = Self::build_bitcoin_rpc(conf.clone(), Some(&wallet_name))?;
+        Ok((
+            Self {
+                rpc,
+                keymanager: keymanager.into(),
+                network: conf.network,
+                last_sync: AtomicU64::new(0),
+            },
+            mnemonic.to_string(),
+        ))
+    }
+
+    fn create_transaction(
+        &self,
+        script: bitcoin::ScriptBuf,
+        amount_sat: u64,
+        fee_rate: u32,
+        options: TransactionOptions,
+    ) -> Result<bitcoin::Transaction, WalletError> {
+        options.validate()?;
+        let hex = self.fund_transaction(script, amount_sat, fee_rate, options)?;
+        self.sign_transaction(hex).map_err(WalletError::Signing)
+    }
 
     fn get_onchain_address(&self) -> error::Result<NewAddress> {
         let addr = self.rpc.call("getnewaddress", &["lampo-addr".into()])?;
         log::debug!(target: "core-wallet", "addr generated: {addr}" );
-        Ok(NewAddress { address: addr })
+        Ok(NewAddress {
+            address: addr,
+            label: None,
+        })
+    }
+
+    fn get_onchain_addresses(
+        &self,
+        count: u32,
+        label_prefix: Option<&str>,
+    ) -> error::Result<Vec<NewAddress>> {
+        let mut addresses = Vec::with_capacity(count as usize);
+        for n in 0..count {
+            let label = label_prefix.map(|prefix| format!("{prefix}-{n}"));
+            let addr: String = self.rpc.call(
+                "getnewaddress",
+                &[label.as_deref().unwrap_or("lampo-addr").into()],
+            )?;
+            addresses.push(NewAddress {
+                address: addr,
+                label,
+            });
+        }
+        log::debug!(target: "core-wallet", "{count} addresses generated");
+        Ok(addresses)
+    }
+
+    fn reset_address_index(&self, keychain: Keychain, index: u32) -> error::Result<()> {
+        if let Some(funded) = self.highest_funded_index(keychain)? {
+            if funded >= index {
+                error::bail!(
+                    "impossible reset the {:?} keychain to index `{index}`, the address at index `{funded}` already received funds",
+                    keychain
+                );
+            }
+        }
+        let internal = keychain == Keychain::Internal;
+        let descriptor = self.active_descriptor(keychain, true)?;
+        let range_end = descriptor["range"][1]
+            .as_u64()
+            .unwrap_or_default()
+            .max(index as u64);
+        // Re-importing the same descriptor allow us to override the `next_index`
+        // without touch the keys.
+        let options = json::json!([{
+            "desc": descriptor["desc"],
+            "active": true,
+            "internal": internal,
+            "timestamp": "now",
+            "range": [0, range_end],
+            "next_index": index,
+        }]);
+        let result: Vec<json::Value> = self.rpc.call("importdescriptors", &[options])?;
+        if let Some(failure) = result
+            .iter()
+            .find(|result| result["success"].as_bool() != Some(true))
+        {
+            error::bail!("impossible reset the address index: {failure}");
+        }
+        log::info!(target: "core-wallet", "{:?} keychain reset to index `{index}`", keychain);
+        Ok(())
+    }
+
+    fn address_indices(&self) -> error::Result<(u32, u32)> {
+        let next_index = |keychain| -> error::Result<u32> {
+            let descriptor = self.active_descriptor(keychain, false)?;
+            // Older versions of bitcoin core call it `next`.
+            let next = descriptor["next_index"]
+                .as_u64()
+                .or(descriptor["next"].as_u64())
+                .unwrap_or_default();
+            Ok(next as u32)
+        };
+        Ok((
+            next_index(Keychain::External)?,
+            next_index(Keychain::Internal)?,
+        ))
     }
 
     fn get_onchain_balance(&self) -> error::Result<u64> {
@@ -288,12 +731,44 @@ impl WalletManager for CoreWalletManager {
         Ok(balance.to_sat() * 1000)
     }
 
+    fn balance_by_keychain(&self) -> error::Result<(Balance, Balance)> {
+        let (mut external, mut internal) = (Balance::default(), Balance::default());
+        // The addresses of the change keychain, asked once for each address.
+        let mut is_change: HashMap<String, bool> = HashMap::new();
+        for utxo in self
+            .rpc
+            .list_unspent(Some(0), None, None, Some(true), None)?
+        {
+            let Some(address) = utxo.address else {
+                continue;
+            };
+            let address = address.assume_checked().to_string();
+            let change = match is_change.get(&address) {
+                Some(change) => *change,
+                None => {
+                    let info: json::Value =
+                        self.rpc.call("getaddressinfo", &[address.clone().into()])?;
+                    let change = info["ischange"].as_bool().unwrap_or_default();
+                    is_change.insert(address, change);
+                    change
+                }
+            };
+            let balance = if change { &mut internal } else { &mut external };
+            if utxo.confirmations > 0 {
+                balance.confirmed_sat += utxo.amount.to_sat();
+            } else {
+                balance.unconfirmed_sat += utxo.amount.to_sat();
+            }
+        }
+        Ok((external, internal))
+    }
+
     fn ldk_keys(&self) -> Arc<LampoKeys> {
         self.keymanager.clone()
     }
 
     fn list_transactions(&self) -> error::Result<Vec<Utxo>> {
-        let unspend = self
+        let mut unspend = self
             .rpc
             .list_unspent(None, None, None, Some(true), None)?
             .iter()
@@ -305,28 +780,309 @@ impl WalletManager for CoreWalletManager {
                 amount_msat: utxo.amount.to_sat() * 1000,
             })
             .collect::<Vec<_>>();
+        // `listunspent` hides the locked outputs, but they are
+        // still ours, so they are reported as reserved.
+        for locked in self.locked_outputs()? {
+            let out: Option<TxOut> = self.rpc.call(
+                "gettxout",
+                &[
+                    json::json!(locked.txid),
+                    json::json!(locked.vout),
+                    true.into(),
+                ],
+            )?;
+            let Some(out) = out else {
+                continue;
+            };
+            unspend.push(Utxo {
+                txid: locked.txid,
+                vout: locked.vout,
+                reserved: true,
+                confirmed: out.confirmations,
+                amount_msat: Amount::from_btc(out.value)?.to_sat() * 1000,
+            });
+        }
         Ok(unspend)
     }
 
+    fn list_coins(&self) -> error::Result<Vec<Coin>> {
+        let mut coins = self
+            .rpc
+            .list_unspent(None, None, None, Some(true), None)?
+            .into_iter()
+            .map(|utxo| Coin {
+                txid: utxo.txid.to_string(),
+                vout: utxo.vout,
+                reserved: utxo.spendable.not(),
+                confirmed: utxo.confirmations,
+                amount_msat: utxo.amount.to_sat() * 1000,
+                address: utxo
+                    .address
+                    .map(|address| address.assume_checked().to_string()),
+                derivation_path: None,
+                keychain: None,
+            })
+            .collect::<Vec<_>>();
+        // `listunspent` hides the locked outputs, see `list_transactions`.
+        for locked in self.locked_outputs()? {
+            let out: Option<TxOut> = self.rpc.call(
+                "gettxout",
+                &[
+                    json::json!(locked.txid),
+                    json::json!(locked.vout),
+                    true.into(),
+                ],
+            )?;
+            let Some(out) = out else {
+                continue;
+            };
+            coins.push(Coin {
+                txid: locked.txid,
+                vout: locked.vout,
+                reserved: true,
+                confirmed: out.confirmations,
+                amount_msat: Amount::from_btc(out.value)?.to_sat() * 1000,
+                address: out.script_pub_key.address,
+                derivation_path: None,
+                keychain: None,
+            });
+        }
+        for coin in coins.iter_mut() {
+            let Some(address) = coin.address.as_deref() else {
+                continue;
+            };
+            coin.derivation_path = self.derivation_path(address)?;
+            coin.keychain = coin
+                .derivation_path
+                .as_deref()
+                .and_then(keychain_index)
+                .map(|(keychain, _)| keychain);
+        }
+        Ok(coins)
+    }
+
+    fn transaction_history(&self) -> error::Result<Vec<WalletTransaction>> {
+        // `listtransactions` hides the transactions that pay only our change,
+        // so the transactions of the unspent outputs are added too.
+        let listed: Vec<json::Value> = self.rpc.call(
+            "listtransactions",
+            &["*".into(), 100_000.into(), 0.into(), true.into()],
+        )?;
+        let unspent: Vec<json::Value> = self.rpc.call("listunspent", &[0.into()])?;
+        let mut txids = Vec::new();
+        for entry in listed.iter().chain(unspent.iter()) {
+            let Some(txid) = entry.get("txid").and_then(|txid| txid.as_str()) else {
+                continue;
+            };
+            if !txids.contains(&txid) {
+                txids.push(txid);
+            }
+        }
+
+        let mut txs = Vec::new();
+        for txid in txids {
+            let tx = self.wallet_transaction(txid)?.ok_or(error::anyhow!(
+                "the transaction `{txid}` is not inside the wallet"
+            ))?;
+            txs.push(tx);
+        }
+
+        let mut mine = HashMap::new();
+        let mut our_txos = HashMap::new();
+        let mut history = Vec::new();
+        for (tx, height) in txs.iter() {
+            let mut our_outputs = Vec::new();
+            for (vout, output) in tx.output.iter().enumerate() {
+                let is_mine = match mine.get(&output.script_pubkey) {
+                    Some(is_mine) => *is_mine,
+                    None => {
+                        let is_mine = self.is_mine(&output.script_pubkey)?;
+                        mine.insert(output.script_pubkey.clone(), is_mine);
+                        is_mine
+                    }
+                };
+                if is_mine {
+                    let outpoint = bitcoin::OutPoint::new(tx.txid(), vout as u32);
+                    our_txos.insert(outpoint, output.value);
+                }
+                our_outputs.push(is_mine);
+            }
+            history.push(WalletTransaction {
+                tx: tx.clone(),
+                height: *height,
+                our_inputs: Vec::new(),
+                our_outputs,
+                sent_sat: 0,
+            });
+        }
+        // Our outputs are always created by one of our transactions.
+        for wallet_tx in history.iter_mut() {
+            for input in wallet_tx.tx.input.iter() {
+                let value = our_txos.get(&input.previous_output);
+                wallet_tx.our_inputs.push(value.is_some());
+                wallet_tx.sent_sat += value.copied().unwrap_or_default();
+            }
+        }
+        Ok(history)
+    }
+
+    fn consolidate(
+        &self,
+        fee_rate: u32,
+        max_inputs: Option<usize>,
+    ) -> Result<bitcoin::Transaction, WalletError> {
+        // The locked outputs are not listed, and the unconfirmed
+        // ones received from others are unsafe.
+        let mut utxos = self
+            .rpc
+            .list_unspent(Some(1), None, None, Some(false), None)
+            .map_err(WalletError::other)?
+            .into_iter()
+            .filter(|utxo| utxo.spendable)
+            .collect::<Vec<_>>();
+        utxos.sort_by_key(|utxo| utxo.amount);
+        if let Some(max_inputs) = max_inputs {
+            utxos.truncate(max_inputs);
+        }
+        if utxos.is_empty() {
+            return Err(WalletError::InsufficientFunds(
+                "there are no confirmed outputs to consolidate".to_owned(),
+            ));
+        }
+        let inputs = utxos
+            .iter()
+            .map(|utxo| json::json!({ "txid": utxo.txid.to_string(), "vout": utxo.vout }))
+            .collect::<Vec<_>>();
+        let amount_sat = utxos.iter().map(|utxo| utxo.amount.to_sat()).sum::<u64>();
+        let address = self.get_onchain_address()?.address;
+        let mut map = HashMap::new();
+        map.insert(address, Amount::from_sat(amount_sat).to_btc());
+        let hex: String = self
+            .rpc
+            .call(
+                "createrawtransaction",
+                &[json::json!(inputs), json::json!(&map), json::json!(0)],
+            )
+            .map_err(WalletError::other)?;
+        let fund_options = json::json!({
+            // See `create_transaction` for the conversion.
+            "fee_rate": fee_rate as f64 / 250.0,
+            "replaceable": false,
+            // Only the chosen inputs are spent, and the fee is paid by
+            // the only output, so there is no change.
+            "add_inputs": false,
+            "subtractFeeFromOutputs": [0],
+        });
+        let tx: Tx = self
+            .rpc
+            .call(
+                "fundrawtransaction",
+                &[json::json!(hex), json::json!(fund_options)],
+            )
+            .map_err(fund_error)?;
+        self.sign_transaction(tx.hex).map_err(WalletError::Signing)
+    }
+
+    fn fund_external_inputs(
+        &self,
+        inputs: &[ExternalInput],
+        fee_rate: u32,
+    ) -> Result<(bitcoin::Transaction, u64), WalletError> {
+        let outpoints = inputs
+            .iter()
+            .map(|input| {
+                json::json!({ "txid": input.outpoint.txid.to_string(), "vout": input.outpoint.vout })
+            })
+            .collect::<Vec<_>>();
+        // Bitcoin core can not solve the external inputs, so it
+        // needs their weight to estimate the fee.
+        let weights = inputs
+            .iter()
+            .map(|input| {
+                json::json!({
+                    "txid": input.outpoint.txid.to_string(),
+                    "vout": input.outpoint.vout,
+                    "weight": input.weight,
+                })
+            })
+            .collect::<Vec<_>>();
+        let amount_sat = inputs.iter().map(|input| input.value_sat).sum::<u64>();
+        let address: String = self
+            .rpc
+            .call("getrawchangeaddress", &["bech32".into()])
+            .map_err(WalletError::other)?;
+        let mut map = HashMap::new();
+        map.insert(address, Amount::from_sat(amount_sat).to_btc());
+        let hex: String = self
+            .rpc
+            .call(
+                "createrawtransaction",
+                &[json::json!(outpoints), json::json!(&map), json::json!(0)],
+            )
+            .map_err(WalletError::other)?;
+        let fund_options = json::json!({
+            // See `create_transaction` for the conversion.
+            "fee_rate": fee_rate as f64 / 250.0,
+            // A child with an higher fee can replace this one.
+            "replaceable": true,
+            "include_unsafe": false,
+            "minconf": 1,
+            "add_inputs": true,
+            "input_weights": weights,
+            "lockUnspents": true,
+        });
+        let tx: FundedTx = self
+            .rpc
+            .call(
+                "fundrawtransaction",
+                &[json::json!(hex), json::json!(fund_options)],
+            )
+            .map_err(fund_error)?;
+        let fee_sat = Amount::from_btc(tx.fee)
+            .map_err(WalletError::other)?
+            .to_sat();
+        // The external inputs are left unsigned.
+        let tx = self
+            .sign_transaction(tx.hex)
+            .map_err(WalletError::Signing)?;
+        Ok((tx, fee_sat))
+    }
+
+    fn release_transaction(&self, tx: &bitcoin::Transaction) -> error::Result<()> {
+        let locked = self.locked_outputs()?;
+        let inputs = tx
+            .input
+            .iter()
+            .map(|input| LockedOutput {
+                txid: input.previous_output.txid.to_string(),
+                vout: input.previous_output.vout,
+            })
+            .filter(|input| locked.contains(input))
+            .map(|input| json::json!({ "txid": input.txid, "vout": input.vout }))
+            .collect::<Vec<_>>();
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        let _: bool = self
+            .rpc
+            .call("lockunspent", &[true.into(), json::json!(inputs)])?;
+        log::info!(target: "core-wallet", "released {} inputs of transaction `{}`", inputs.len(), tx.txid());
+        Ok(())
+    }
+
     fn restore(conf: Arc<LampoConf>, mnemonic_words: &str) -> error::Result<Self>
     where
         Self: Sized,
     {
         let (wallet, keymanager) = CoreWalletManager::build_wallet(conf.clone(), mnemonic_words)?;
 
+        let (user, pass) = conf.core_auth()?.credentials()?;
         let rpc = Client::new(
             conf.core_url
                 .clone()
                 .ok_or(error::anyhow!("bitcoin core url not specified"))?
                 .as_str(),
-            Auth::UserPass(
-                conf.core_user
-                    .clone()
-                    .ok_or(error::anyhow!("bitcoin core user not specified"))?,
-                conf.core_pass
-                    .clone()
-                    .ok_or(error::anyhow!("bitcoin core password not specified"))?,
-            ),
+            Auth::UserPass(user, pass),
         )?;
 
         Self::configure_bitcoin_wallet(&rpc, conf.clone(), wallet)?;
@@ -334,12 +1090,45 @@ impl WalletManager for CoreWalletManager {
             rpc,
             keymanager: keymanager.into(),
             network: conf.network,
+            last_sync: AtomicU64::new(0),
         })
     }
 
-    fn sync(&self) -> error::Result<()> {
+    fn sync(&self) -> Result<(), WalletError> {
+        // bitcoind keeps the wallet in sync, we need only to check
+        // that it is not rescanning the chain.
+        let info: json::Value = self
+            .rpc
+            .call("getwalletinfo", &[])
+            .map_err(|err| WalletError::Sync(err.into()))?;
+        if info
+            .get("scanning")
+            .is_some_and(|scanning| scanning.is_object())
+        {
+            return Err(WalletError::Sync(error::anyhow!(
+                "bitcoin core is rescanning the wallet"
+            )));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(WalletError::other)?
+            .as_secs();
+        self.last_sync.store(now, Ordering::SeqCst);
         Ok(())
     }
+
+    fn last_sync(&self) -> Option<u64> {
+        let last_sync = self.last_sync.load(Ordering::SeqCst);
+        (last_sync > 0).then_some(last_sync)
+    }
+
+    fn rescan(&self) -> Result<(), WalletError> {
+        let _: json::Value = self
+            .rpc
+            .call("rescanblockchain", &[])
+            .map_err(|err| WalletError::Sync(err.into()))?;
+        self.sync()
+    }
 }
 
 #[cfg(debug_assertions)]
@@ -357,6 +1146,7 @@ impl TryFrom<(PrivateKey, Option<String>, Arc<LampoConf>)> for CoreWalletManager
             keymanager: Arc::new(keymanager),
             rpc,
             network: conf.network,
+            last_sync: AtomicU64::new(0),
         })
     }
 }
@@ -0,0 +1,42 @@
+//! Access control lists of the RPC methods.
+//!
+//! The caller is identified by the `auth` token inside the request,
+//! and every token is allowed to call only its set of methods.
+use std::collections::{HashMap, HashSet};
+
+/// The error code returned to the callers that can not call a method.
+pub const UNAUTHORIZED: i32 = -32003;
+
+/// The method name that allows all the methods.
+pub const ALL_METHODS: &str = "*";
+
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    identities: HashMap<String, HashSet<String>>,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow `identity` to call `methods`, `*` allows all of them.
+    pub fn allow<I, S>(mut self, identity: &str, methods: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.identities
+            .entry(identity.to_owned())
+            .or_default()
+            .extend(methods.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn is_allowed(&self, identity: Option<&str>, method: &str) -> bool {
+        let Some(methods) = identity.and_then(|identity| self.identities.get(identity)) else {
+            return false;
+        };
+        methods.contains(ALL_METHODS) || methods.contains(method)
+    }
+}
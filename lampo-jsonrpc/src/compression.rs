@@ -0,0 +1,38 @@
+//! Optional gzip compression of the JSON RPC 2.0 responses.
+//!
+//! The compression is negotiated by the client with the `compress`
+//! field inside the request, and the server compresses the response
+//! only when it is bigger than `COMPRESSION_THRESHOLD`, so the
+//! client should always check the first bytes before decoding.
+use std::io::{self, Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// Size in bytes below which the response is sent uncompressed.
+pub const COMPRESSION_THRESHOLD: usize = 16 * 1024;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+pub fn compress(buff: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(buff)?;
+    encoder.finish()
+}
+
+/// Decode the response buffer, if the buffer is not compressed
+/// it is returned as it is.
+pub fn decompress(buff: &[u8]) -> io::Result<Vec<u8>> {
+    if !is_compressed(buff) {
+        return Ok(buff.to_vec());
+    }
+    let mut decoder = GzDecoder::new(buff);
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+pub fn is_compressed(buff: &[u8]) -> bool {
+    buff.starts_with(&GZIP_MAGIC)
+}
@@ -40,11 +40,19 @@ impl From<RpcError> for Error {
 
 impl From<anyhow::Error> for Error {
     fn from(e: anyhow::Error) -> Error {
-        Error::Rpc(RpcError {
-            code: -1,
-            message: format!("{e}"),
-            data: None,
-        })
+        // Keep the code of the errors that are already JSON RPC errors.
+        let e = match e.downcast::<RpcError>() {
+            Ok(rpc) => return Error::Rpc(rpc),
+            Err(e) => e,
+        };
+        match e.downcast::<Error>() {
+            Ok(err) => err,
+            Err(e) => Error::Rpc(RpcError {
+                code: -1,
+                message: format!("{e}"),
+                data: None,
+            }),
+        }
     }
 }
 
@@ -82,10 +90,24 @@ pub struct RpcError {
     pub data: Option<serde_json::Value>,
 }
 
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for RpcError {}
+
 impl From<Error> for RpcError {
     fn from(value: Error) -> Self {
         match value {
             Error::Rpc(rpc) => rpc.clone(),
+            // The request can not be decoded.
+            Error::Json(_) => RpcError {
+                code: -32602,
+                message: format!("{value}"),
+                data: None,
+            },
             _ => RpcError {
                 code: -1,
                 message: format!("{value}"),
@@ -34,6 +34,18 @@ pub struct Request<T: Serialize> {
     pub id: Option<Id>,
     /// jsonrpc field, MUST be "2.0"
     pub jsonrpc: String,
+    /// Lampo extension: the client is able to read a gzip
+    /// compressed response.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compress: bool,
+    /// Lampo extension: the server keeps the connection open after
+    /// the response, that is prefixed by its length as big endian u32.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keep_alive: bool,
+    /// Lampo extension: the token that identifies the caller
+    /// when the server has an access control list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<String>,
 }
 
 impl<T: Serialize> Request<T> {
@@ -43,8 +55,29 @@ impl<T: Serialize> Request<T> {
             params: args,
             id: Some("lampo/jsonrpc/1".into()),
             jsonrpc: "2.0".to_owned(),
+            compress: false,
+            keep_alive: false,
+            auth: None,
         }
     }
+
+    /// Ask the server to compress the response when it is big enough.
+    pub fn with_compression(mut self) -> Self {
+        self.compress = true;
+        self
+    }
+
+    /// Ask the server to keep the connection open for the next requests.
+    pub fn with_keep_alive(mut self) -> Self {
+        self.keep_alive = true;
+        self
+    }
+
+    /// Identify the caller with the `token`.
+    pub fn with_auth(mut self, token: &str) -> Self {
+        self.auth = Some(token.to_owned());
+        self
+    }
 }
 
 #[allow(clippy::derive_partial_eq_without_eq)]
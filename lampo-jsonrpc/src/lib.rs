@@ -1,24 +1,27 @@
 //! Full feature async JSON RPC 2.0 Server/client with a
 //! minimal dependencies footprint.
-use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::io::ErrorKind;
 use std::io::{Read, Write};
 use std::os::fd::AsRawFd;
 use std::os::unix::net::UnixListener;
 use std::os::unix::net::UnixStream;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use std::thread::JoinHandle;
 
 // FIXME: use mio for a better platform support.
 use popol::{Event, Sources, Timeout};
 use serde_json::Value;
 
+pub mod acl;
 pub mod command;
+pub mod compression;
 pub mod errors;
 pub mod json_rpc2;
 
+use acl::Acl;
 use command::Context;
 
 use crate::errors::Error;
@@ -34,42 +37,90 @@ pub struct JSONRPCv2<T: Send + Sync + 'static> {
     socket_path: String,
     sources: Sources<RPCEvent>,
     open_streams: HashMap<i32, UnixStream>,
-    response_queue: HashMap<i32, Response<Value>>,
+    response_queue: HashMap<i32, Vec<u8>>,
+    /// The connections that the client asked to keep open.
+    keep_alive_streams: HashSet<i32>,
     socket: UnixListener,
     handler: Arc<Handler<T>>,
 }
 
+/// The callback of a RPC method.
+type Callback<T> = Arc<dyn Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static>;
+
 pub struct Handler<T: Send + Sync + 'static> {
-    stop: Cell<bool>,
-    rpc_method:
-        RefCell<HashMap<String, Arc<dyn Fn(&T, &Value) -> Result<Value, errors::Error> + 'static>>>,
+    stop: AtomicBool,
+    rpc_method: RwLock<HashMap<String, Callback<T>>>,
+    /// The methods that every caller can call, all of
+    /// them for everybody when missing.
+    acl: RwLock<Option<Acl>>,
+    /// The methods whose params are secrets, so they are never logged.
+    sensitive: RwLock<HashSet<String>>,
     ctx: Arc<dyn Context<Ctx = T>>,
 }
 
-unsafe impl<T: Send + Sync> Sync for Handler<T> {}
-unsafe impl<T: Send + Sync> Send for Handler<T> {}
-
 impl<T: Send + Sync + 'static> Handler<T> {
     pub fn new(ctx: Arc<dyn Context<Ctx = T>>) -> Self {
         Handler::<T> {
-            stop: Cell::new(false),
-            rpc_method: RefCell::new(HashMap::new()),
+            stop: AtomicBool::new(false),
+            rpc_method: RwLock::new(HashMap::new()),
+            acl: RwLock::new(None),
+            sensitive: RwLock::new(HashSet::new()),
             ctx,
         }
     }
 
     pub fn add_method<F>(&self, method: &str, callback: F)
     where
-        F: Fn(&T, &Value) -> Result<Value, errors::Error> + 'static,
+        F: Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static,
     {
+        // SAFETY: the lock can not be poisoned.
         self.rpc_method
-            .borrow_mut()
+            .write()
+            .unwrap()
             .insert(method.to_owned(), Arc::new(callback));
     }
 
+    /// Add a method whose params are never logged, e.g. because
+    /// they contain a private key.
+    pub fn add_sensitive_method<F>(&self, method: &str, callback: F)
+    where
+        F: Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static,
+    {
+        // SAFETY: the lock can not be poisoned.
+        self.sensitive.write().unwrap().insert(method.to_owned());
+        self.add_method(method, callback);
+    }
+
+    /// The params of `method` must not be logged.
+    pub fn is_sensitive(&self, method: &str) -> bool {
+        // SAFETY: the lock can not be poisoned.
+        self.sensitive.read().unwrap().contains(method)
+    }
+
+    /// Restrict the methods that every caller can call.
+    pub fn set_acl(&self, acl: Acl) {
+        // SAFETY: the lock can not be poisoned.
+        *self.acl.write().unwrap() = Some(acl);
+    }
+
     pub fn run_callback(&self, req: &Request<Value>) -> Option<Result<Value, errors::Error>> {
-        let binding = self.rpc_method.borrow();
-        let Some(callback) = binding.get(&req.method) else {
+        // SAFETY: the lock can not be poisoned.
+        if let Some(acl) = self.acl.read().unwrap().as_ref() {
+            if !acl.is_allowed(req.auth.as_deref(), &req.method) {
+                log::warn!("unauthorized call of the method `{}`", req.method);
+                return Some(Err(errors::RpcError {
+                    message: "unauthorized".to_owned(),
+                    code: acl::UNAUTHORIZED,
+                    data: None,
+                }
+                .into()));
+            }
+        }
+        // The lock is not held while the callback runs, so a
+        // callback can call another RPC method.
+        // SAFETY: the lock can not be poisoned.
+        let callback = self.rpc_method.read().unwrap().get(&req.method).cloned();
+        let Some(callback) = callback else {
             return Some(Err(errors::RpcError {
                 message: format!("method `{}` not found", req.method),
                 code: -1,
@@ -82,7 +133,8 @@ impl<T: Send + Sync + 'static> Handler<T> {
     }
 
     pub fn has_rpc(&self, method: &str) -> bool {
-        self.rpc_method.borrow().contains_key(method)
+        // SAFETY: the lock can not be poisoned.
+        self.rpc_method.read().unwrap().contains_key(method)
     }
 
     fn ctx(&self) -> &T {
@@ -90,7 +142,7 @@ impl<T: Send + Sync + 'static> Handler<T> {
     }
 
     pub fn stop(&self) {
-        self.stop.set(true);
+        self.stop.store(true, Ordering::SeqCst);
     }
 }
 
@@ -105,12 +157,13 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
             socket_path: path.to_owned(),
             open_streams: HashMap::new(),
             response_queue: HashMap::new(),
+            keep_alive_streams: HashSet::new(),
         })
     }
 
     pub fn add_rpc<F>(&self, name: &str, callback: F) -> Result<(), ()>
     where
-        F: Fn(&T, &Value) -> Result<Value, errors::Error> + 'static,
+        F: Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static,
     {
         if self.handler.has_rpc(name) {
             return Err(());
@@ -119,6 +172,18 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
         Ok(())
     }
 
+    /// Add a RPC method whose params are never logged.
+    pub fn add_sensitive_rpc<F>(&self, name: &str, callback: F) -> Result<(), ()>
+    where
+        F: Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static,
+    {
+        if self.handler.has_rpc(name) {
+            return Err(());
+        }
+        self.handler.add_sensitive_method(name, callback);
+        Ok(())
+    }
+
     #[allow(dead_code)]
     fn ctx(&self) -> &T {
         self.handler.ctx()
@@ -140,14 +205,19 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
                 Ok(count) => {
                     buff.truncate(count);
                     if count > 0 {
-                        log::info!(target: "jsonrpc", "buffer read {}", String::from_utf8(buff.to_vec()).unwrap());
-                        // Put this inside the unfinish queue
+                        // The body is logged only after it is parsed, so the
+                        // params of the sensitive methods are never logged.
                         let Ok(requ) = serde_json::from_slice::<Request<Value>>(&buff) else {
-                            log::warn!(target: "jsonrpc", "looks like that the json is not fully read ` {}`", String::from_utf8(buff.to_vec()).unwrap());
+                            log::warn!(target: "jsonrpc", "looks like that the json is not fully read, {count} bytes read");
                             // Usually this mean that we was too fast in reading and the sender too low
                             continue;
                         };
-                        log::trace!(target: "jsonrpc", "request {:?}", requ);
+                        if self.handler.is_sensitive(&requ.method) {
+                            log::info!(target: "jsonrpc", "buffer read for `{}` with redacted params", requ.method);
+                        } else {
+                            log::info!(target: "jsonrpc", "buffer read {}", String::from_utf8_lossy(&buff));
+                            log::trace!(target: "jsonrpc", "request {:?}", requ);
+                        }
                         let Some(resp) = self.handler.run_callback(&requ) else {
                             log::error!(target: "jsonrpc", "`{}` not found!", requ.method);
                             return Ok(());
@@ -167,7 +237,7 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
                                 jsonrpc: requ.jsonrpc.clone(),
                             },
                         };
-                        break response;
+                        break (response, requ.compress, requ.keep_alive);
                     } else {
                         log::info!("Reading is not finished, so keep reading");
                         event.source.unset(popol::interest::READ);
@@ -189,8 +259,25 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
             }
         };
 
+        let (resp, compress, keep_alive) = resp;
         log::trace!(target: "jsonrpc", "send response: `{:?}`", resp);
-        self.response_queue.insert(fd, resp);
+        // SAFETY: the resp should be a valid json.
+        let mut buff = serde_json::to_vec(&resp).unwrap();
+        if compress && buff.len() >= compression::COMPRESSION_THRESHOLD {
+            log::debug!(target: "jsonrpc", "compressing response of {} bytes", buff.len());
+            buff = compression::compress(&buff)?;
+        }
+        if keep_alive {
+            // The connection is not closed, so the client needs
+            // to know where the response ends.
+            let mut framed = (buff.len() as u32).to_be_bytes().to_vec();
+            framed.append(&mut buff);
+            buff = framed;
+            self.keep_alive_streams.insert(fd);
+        } else {
+            self.keep_alive_streams.remove(&fd);
+        }
+        self.response_queue.insert(fd, buff);
         event.source.set(popol::interest::WRITE);
         Ok(())
     }
@@ -201,7 +288,7 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
             .register(RPCEvent::Accept, &self.socket, popol::interest::READ);
         log::info!(target: "jsonrpc", "starting server on {}", self.socket_path);
         let mut events = vec![];
-        while !self.handler.stop.get() {
+        while !self.handler.stop.load(Ordering::SeqCst) {
             // Blocking while we are waiting new events!
             self.sources.poll(&mut events, Timeout::Never)?;
             for mut event in events.drain(..) {
@@ -235,13 +322,11 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
                     RPCEvent::Connect if event.is_writable() => {
                         let fd = event.as_raw_fd();
                         // SAFETY: we must have the response for this fd.
-                        let resp = self.response_queue.remove(&fd).unwrap();
+                        let buff = self.response_queue.remove(&fd).unwrap();
                         // SAFETY: we must have a stream for this fd.
                         let mut stream = self.open_streams.remove(&event.as_raw_fd()).unwrap();
-                        // SAFETY: the resp should be a valid json.
-                        let buff = serde_json::to_string(&resp).unwrap();
-                        log::debug!("writing the response `{buff}`");
-                        if let Err(err) = stream.write_all(buff.as_bytes()) {
+                        log::debug!("writing the response of {} bytes", buff.len());
+                        if let Err(err) = stream.write_all(&buff) {
                             if err.kind() != ErrorKind::WouldBlock {
                                 return Err(err);
                             }
@@ -256,6 +341,11 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
                                 log::trace!("writing ended");
                                 event.source.unset(popol::interest::WRITE);
                                 event.source.set(popol::interest::READ);
+                                if self.keep_alive_streams.contains(&fd) {
+                                    // wait the next request on the same connection.
+                                    self.open_streams.insert(fd, stream);
+                                    continue;
+                                }
                                 self.sources.unregister(&event.key);
                                 continue;
                             }
@@ -278,6 +368,12 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
                     RPCEvent::Connect => {
                         if event.is_hangup() || event.is_error() {
                             log::error!(target: "jsonrpc", "an error occurs: {:?}", event);
+                            // The client closed a connection that was kept alive.
+                            let fd = event.as_raw_fd();
+                            if self.keep_alive_streams.remove(&fd) {
+                                self.open_streams.remove(&fd);
+                                self.sources.unregister(&event.key);
+                            }
                             continue;
                         }
 
@@ -312,7 +408,8 @@ impl<T: Send + Sync + 'static> Drop for JSONRPCv2<T> {
 #[cfg(test)]
 mod tests {
     use std::{
-        io::Write, os::unix::net::UnixStream, path::Path, str::FromStr, sync::Arc, time::Duration,
+        io::Read, io::Write, os::unix::net::UnixStream, path::Path, str::FromStr, sync::Arc,
+        time::Duration,
     };
 
     use lampo_common::logger;
@@ -320,9 +417,12 @@ mod tests {
     use serde_json::Value;
 
     use crate::{
+        acl::{Acl, ALL_METHODS, UNAUTHORIZED},
         command::Context,
+        compression::{self, COMPRESSION_THRESHOLD},
+        errors,
         json_rpc2::{Id, Request, Response},
-        JSONRPCv2,
+        Handler, JSONRPCv2,
     };
 
     struct DummyCtx;
@@ -357,6 +457,9 @@ mod tests {
             jsonrpc: String::from_str("2.0").unwrap(),
             method: "foo".to_owned(),
             params: serde_json::Value::Array([].to_vec()),
+            compress: false,
+            keep_alive: false,
+            auth: None,
         };
         let client_worker = std::thread::spawn(move || {
             let buff = serde_json::to_string(&request).unwrap();
@@ -383,6 +486,9 @@ mod tests {
                 jsonrpc: String::from_str("2.0").unwrap(),
                 method: "secon".to_owned(),
                 params: serde_json::Value::Array([].to_vec()),
+                compress: false,
+                keep_alive: false,
+                auth: None,
             };
 
             let buff = serde_json::to_string(&request).unwrap();
@@ -406,4 +512,103 @@ mod tests {
         assert_eq!(Id::Str("1".to_owned()), resp.id);
         handler.stop();
     }
+
+    #[test]
+    #[timeout(9000)]
+    fn concurrent_methods() {
+        let handler = Arc::new(Handler::new(Arc::new(DummyCtx)));
+        let workers = (0..8)
+            .map(|i| {
+                let handler = handler.clone();
+                std::thread::spawn(move || {
+                    let method = format!("method-{i}");
+                    handler.add_method(&method, move |_: &DummyCtx, _| Ok(serde_json::json!(i)));
+                    let request = Request::<Value>::new(&method, serde_json::json!({}));
+                    handler.run_callback(&request).unwrap().unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        for (i, worker) in workers.into_iter().enumerate() {
+            assert_eq!(worker.join().unwrap(), serde_json::json!(i));
+        }
+        assert!((0..8).all(|i| handler.has_rpc(&format!("method-{i}"))));
+    }
+
+    #[test]
+    #[timeout(9000)]
+    fn compressed_response() {
+        let path = "/tmp/tmp-compress.sock";
+        let _ = std::fs::remove_file(path);
+        let server = JSONRPCv2::new(Arc::new(DummyCtx), path).unwrap();
+        let big = "lampo".repeat(COMPRESSION_THRESHOLD);
+        let expected = serde_json::json!({ "big": big });
+        let result = expected.clone();
+        let _ = server.add_rpc("big", move |_: &DummyCtx, _| Ok(result.clone()));
+
+        let handler = server.handler();
+        let _worker = server.spawn();
+        let request = Request::<Value>::new("big", serde_json::json!({})).with_compression();
+        let buff = serde_json::to_string(&request).unwrap();
+        let mut stream = UnixStream::connect(Path::new(path)).unwrap();
+        stream.write_all(buff.as_bytes()).unwrap();
+        stream.flush().unwrap();
+
+        let mut buff = Vec::new();
+        stream.read_to_end(&mut buff).unwrap();
+        assert!(compression::is_compressed(&buff));
+        let buff = compression::decompress(&buff).unwrap();
+        let resp: Response<Value> = serde_json::from_slice(&buff).unwrap();
+        assert_eq!(resp.result, Some(expected));
+        handler.stop();
+    }
+
+    #[test]
+    fn anyhow_error_keeps_the_rpc_code() {
+        let err = anyhow::Error::new(errors::RpcError {
+            code: 1002,
+            message: "no route".to_owned(),
+            data: Some(serde_json::json!({ "amount_msat": 1000 })),
+        });
+        let errors::Error::Rpc(rpc) = errors::Error::from(err) else {
+            panic!("the error must be an rpc error");
+        };
+        assert_eq!(rpc.code, 1002);
+        assert_eq!(rpc.message, "no route");
+
+        let errors::Error::Rpc(rpc) = errors::Error::from(anyhow::anyhow!("generic")) else {
+            panic!("the error must be an rpc error");
+        };
+        assert_eq!(rpc.code, -1);
+    }
+
+    #[test]
+    fn acl_restricts_the_methods() {
+        let handler = Handler::new(Arc::new(DummyCtx));
+        handler.add_method("getinfo", |_: &DummyCtx, _| Ok(serde_json::json!({})));
+        handler.add_method("pay", |_: &DummyCtx, _| Ok(serde_json::json!({})));
+        let request = Request::<Value>::new("pay", serde_json::json!({}));
+        assert!(handler.run_callback(&request).unwrap().is_ok());
+
+        handler.set_acl(
+            Acl::new()
+                .allow("reader", ["getinfo", "channels"])
+                .allow("admin", [ALL_METHODS]),
+        );
+        let unauthorized = |request: &Request<Value>| match handler.run_callback(request) {
+            Some(Err(errors::Error::Rpc(err))) => err.code == UNAUTHORIZED,
+            _ => false,
+        };
+        let getinfo = Request::<Value>::new("getinfo", serde_json::json!({}));
+        assert!(handler
+            .run_callback(&getinfo.clone().with_auth("reader"))
+            .unwrap()
+            .is_ok());
+        assert!(unauthorized(&request.clone().with_auth("reader")));
+        assert!(unauthorized(&request));
+        assert!(unauthorized(&getinfo.with_auth("stranger")));
+        assert!(handler
+            .run_callback(&request.with_auth("admin"))
+            .unwrap()
+            .is_ok());
+    }
 }
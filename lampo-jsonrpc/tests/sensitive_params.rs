@@ -0,0 +1,95 @@
+//! The params of the sensitive methods never reach the log.
+//!
+//! This lives in its own test binary, because it installs
+//! the global logger that captures the log lines.
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::Value;
+
+use lampo_jsonrpc::command::Context;
+use lampo_jsonrpc::json_rpc2::{Request, Response};
+use lampo_jsonrpc::JSONRPCv2;
+
+const WIF: &str = "cQJMpPcRADTJxCTAhqsFNKv3GwjPEGj7uUBsA8fvqZGjqFQzxQwK";
+
+struct DummyCtx;
+
+impl Context for DummyCtx {
+    type Ctx = DummyCtx;
+
+    fn ctx(&self) -> &Self::Ctx {
+        self
+    }
+}
+
+/// Keep every log line in memory.
+struct Capture(Mutex<Vec<String>>);
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        // SAFETY: the lock can not be poisoned.
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+fn call(path: &str, method: &str) -> Response<Value> {
+    let request = Request::<Value>::new(method, serde_json::json!({ "wif": WIF }));
+    let mut stream = UnixStream::connect(Path::new(path)).unwrap();
+    stream
+        .write_all(serde_json::to_string(&request).unwrap().as_bytes())
+        .unwrap();
+    stream.flush().unwrap();
+    let mut buff = Vec::new();
+    stream.read_to_end(&mut buff).unwrap();
+    serde_json::from_slice(&buff).unwrap()
+}
+
+#[test]
+fn sensitive_params_are_not_logged() {
+    let capture: &'static Capture = Box::leak(Box::new(Capture(Mutex::new(Vec::new()))));
+    log::set_logger(capture).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    let path = "/tmp/tmp-sensitive.sock";
+    let _ = std::fs::remove_file(path);
+    let server = JSONRPCv2::new(Arc::new(DummyCtx), path).unwrap();
+    server
+        .add_sensitive_rpc("sweepprivatekey", |_: &DummyCtx, _| {
+            Ok(serde_json::json!({}))
+        })
+        .unwrap();
+    server
+        .add_rpc("echo", |_: &DummyCtx, request| Ok(request.clone()))
+        .unwrap();
+    let handler = server.handler();
+    let _worker = server.spawn();
+
+    assert!(call(path, "sweepprivatekey").result.is_some());
+    {
+        // SAFETY: the lock can not be poisoned.
+        let lines = capture.0.lock().unwrap();
+        assert!(lines.iter().any(|line| line.contains("sweepprivatekey")));
+        assert!(lines.iter().all(|line| !line.contains(WIF)), "{lines:?}");
+    }
+
+    // The params of the other methods are still logged.
+    assert!(call(path, "echo").result.is_some());
+    // SAFETY: the lock can not be poisoned.
+    assert!(capture
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|line| line.contains(WIF)));
+    handler.stop();
+}
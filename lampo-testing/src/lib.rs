@@ -2,6 +2,7 @@
 pub mod prelude {
     pub use clightning_testing::prelude::*;
     pub use clightning_testing::*;
+    pub use lampo_bitcoind::faucet;
     pub use lampod;
     pub use lampod::async_run;
 }
@@ -15,6 +16,9 @@ use clightning_testing::prelude::*;
 use lampo_common::json;
 use lampo_common::model::response;
 use lampo_common::model::response::NewAddress;
+use lampod::jsonrpc::channels::json_add_tower;
+use lampod::jsonrpc::channels::json_bump_channel_close;
+use lampod::jsonrpc::channels::json_channel_funding_tx;
 use lampod::jsonrpc::channels::json_close_channel;
 use lampod::jsonrpc::inventory::json_network_channels;
 use lampod::jsonrpc::offchain::json_keysend;
@@ -23,20 +27,69 @@ use tempfile::TempDir;
 use lampo_bitcoind::BitcoinCore;
 use lampo_common::conf::LampoConf;
 use lampo_common::error;
+use lampo_common::error::LampoErrorCode;
 use lampo_core_wallet::CoreWalletManager;
+use lampo_jsonrpc::acl::Acl;
 use lampo_jsonrpc::JSONRPCv2;
 use lampod::actions::handler::LampoHandler;
 use lampod::chain::WalletManager;
+use lampod::jsonrpc::channels::json_dump_channel;
+use lampod::jsonrpc::channels::json_estimate_close_all;
+use lampod::jsonrpc::channels::json_export_channel_backup;
+use lampod::jsonrpc::channels::json_import_channel_backup;
+use lampod::jsonrpc::channels::json_list_channel_fees;
 use lampod::jsonrpc::channels::json_list_channels;
+use lampod::jsonrpc::channels::json_list_closed_channels;
+use lampod::jsonrpc::channels::json_list_towers;
+use lampod::jsonrpc::channels::json_rebroadcast_commitment;
+use lampod::jsonrpc::channels::json_set_channel_fee;
+use lampod::jsonrpc::channels::json_set_dust_exposure;
+use lampod::jsonrpc::channels::json_update_channel_config;
+use lampod::jsonrpc::channels::json_verify_channel_funding;
 use lampod::jsonrpc::inventory::get_info;
+use lampod::jsonrpc::inventory::json_health;
+use lampod::jsonrpc::inventory::json_set_log_level;
+use lampod::jsonrpc::offchain::json_create_invoice;
 use lampod::jsonrpc::offchain::json_decode_invoice;
+use lampod::jsonrpc::offchain::json_get_route;
 use lampod::jsonrpc::offchain::json_invoice;
+use lampod::jsonrpc::offchain::json_invoice_status;
+use lampod::jsonrpc::offchain::json_last_forward_error;
+use lampod::jsonrpc::offchain::json_lnurl_pay;
 use lampod::jsonrpc::offchain::json_offer;
 use lampod::jsonrpc::offchain::json_pay;
+use lampod::jsonrpc::offchain::json_payment_failures;
+use lampod::jsonrpc::offchain::json_probes;
+use lampod::jsonrpc::offchain::json_reissue_invoice;
+use lampod::jsonrpc::offchain::json_reset_scorer;
+use lampod::jsonrpc::offchain::json_settle_invoice;
+use lampod::jsonrpc::offchain::json_wait_send_pay;
+use lampod::jsonrpc::onchain::json_address_indices;
+use lampod::jsonrpc::onchain::json_consolidate;
+use lampod::jsonrpc::onchain::json_create_timelock_output;
+use lampod::jsonrpc::onchain::json_export_labels;
 use lampod::jsonrpc::onchain::json_funds;
+use lampod::jsonrpc::onchain::json_import_labels;
+use lampod::jsonrpc::onchain::json_list_coins;
+use lampod::jsonrpc::onchain::json_mempool_info;
 use lampod::jsonrpc::onchain::json_new_addr;
+use lampod::jsonrpc::onchain::json_new_addrs;
+use lampod::jsonrpc::onchain::json_reconcile_wallet;
+use lampod::jsonrpc::onchain::json_reset_address_index;
+use lampod::jsonrpc::onchain::json_simulate_reorg;
+use lampod::jsonrpc::onchain::json_sweep_private_key;
+use lampod::jsonrpc::onchain::json_sync_wallet;
+use lampod::jsonrpc::onchain::json_tx_confirmation_eta;
+use lampod::jsonrpc::onchain::json_tx_history;
+use lampod::jsonrpc::onchain::json_withdraw;
+use lampod::jsonrpc::open_channel::json_cancel_open;
+use lampod::jsonrpc::open_channel::json_min_channel_capacity;
 use lampod::jsonrpc::open_channel::json_open_channel;
 use lampod::jsonrpc::peer_control::json_connect;
+use lampod::jsonrpc::peer_control::json_forget_peer;
+use lampod::jsonrpc::peer_control::json_gossip_sync;
+use lampod::jsonrpc::peer_control::json_list_addresses;
+use lampod::jsonrpc::peer_control::json_list_stored_peers;
 use lampod::jsonrpc::CommandHandler;
 use lampod::LampoDaemon;
 
@@ -67,13 +120,51 @@ pub struct LampoTesting {
     pub wallet: Arc<dyn WalletManager>,
     pub mnemonic: String,
     pub btc: Arc<BtcNode>,
+    /// The bitcoind backend of the node, used by the faucet.
+    pub backend: Arc<BitcoinCore>,
     pub info: response::GetInfo,
 }
 
 impl LampoTesting {
     pub fn new(btc: Arc<BtcNode>) -> error::Result<Self> {
+        Self::with_conf(btc, |_| {})
+    }
+
+    /// Build a lampo node allowing the caller to change the
+    /// configuration before the daemon is started.
+    pub fn with_conf<F>(btc: Arc<BtcNode>, configure: F) -> error::Result<Self>
+    where
+        F: FnOnce(&mut LampoConf),
+    {
+        Self::build(btc, tempfile::tempdir()?, configure, None)
+    }
+
+    /// Build a lampo node with a fresh data directory from
+    /// the mnemonic of another node.
+    pub fn restore(btc: Arc<BtcNode>, mnemonic: &str) -> error::Result<Self> {
+        Self::build(btc, tempfile::tempdir()?, |_| {}, Some(mnemonic))
+    }
+
+    /// Start a new daemon on a copy of the data directory of this
+    /// node, like the node does after a restart.
+    ///
+    /// This node keeps running, so only the returned one should
+    /// be used after.
+    pub fn restart(&self) -> error::Result<Self> {
         let dir = tempfile::tempdir()?;
+        copy_dir(self.root_path.path(), dir.path())?;
+        Self::build(self.btc.clone(), dir, |_| {}, Some(&self.mnemonic))
+    }
 
+    fn build<F>(
+        btc: Arc<BtcNode>,
+        dir: TempDir,
+        configure: F,
+        mnemonic: Option<&str>,
+    ) -> error::Result<Self>
+    where
+        F: FnOnce(&mut LampoConf),
+    {
         // SAFETY: this should be safe because if the system has no
         // ports it is a bug
         let port = port::random_free_port().unwrap();
@@ -92,36 +183,159 @@ impl LampoTesting {
             .ldk_conf
             .channel_handshake_limits
             .force_announced_channel_preference = false;
-        let (wallet, mnemonic) = CoreWalletManager::new(Arc::new(lampo_conf.clone()))?;
+        configure(&mut lampo_conf);
+        let (wallet, mnemonic) = match mnemonic {
+            Some(mnemonic) => (
+                CoreWalletManager::restore(Arc::new(lampo_conf.clone()), mnemonic)?,
+                mnemonic.to_owned(),
+            ),
+            None => CoreWalletManager::new(Arc::new(lampo_conf.clone()))?,
+        };
         let wallet = Arc::new(wallet);
         let mut lampo = LampoDaemon::new(lampo_conf.clone(), wallet.clone());
         let node = BitcoinCore::new(
             &format!("127.0.0.1:{}", btc.port),
-            &btc.user,
-            &btc.pass,
+            lampo_conf.core_auth()?,
             Arc::new(false),
             Some(1),
         )?;
-        lampo.init(Arc::new(node))?;
+        let backend = Arc::new(node);
+        lampo.init(backend.clone())?;
 
         // Configuring the JSON RPC over unix
         let lampo = Arc::new(lampo);
         let socket_path = format!("{}/lampod.socket", lampo.root_path());
         let server = JSONRPCv2::new(lampo.clone(), &socket_path)?;
+        if !lampo.conf().rpc_acl.is_empty() {
+            let acl = lampo
+                .conf()
+                .rpc_acl
+                .iter()
+                .fold(Acl::new(), |acl, (token, methods)| {
+                    acl.allow(token, methods)
+                });
+            server.handler().set_acl(acl);
+        }
         server.add_rpc("getinfo", get_info).unwrap();
+        server.add_rpc("health", json_health).unwrap();
+        server.add_rpc("setloglevel", json_set_log_level).unwrap();
         server.add_rpc("connect", json_connect).unwrap();
+        server
+            .add_rpc("storedpeers", json_list_stored_peers)
+            .unwrap();
+        server.add_rpc("forgetpeer", json_forget_peer).unwrap();
+        server
+            .add_rpc("listaddresses", json_list_addresses)
+            .unwrap();
+        server.add_rpc("gossipsync", json_gossip_sync).unwrap();
         server.add_rpc("fundchannel", json_open_channel).unwrap();
+        server.add_rpc("cancelopen", json_cancel_open).unwrap();
+        server
+            .add_rpc("minchannelcapacity", json_min_channel_capacity)
+            .unwrap();
         server.add_rpc("newaddr", json_new_addr).unwrap();
+        server.add_rpc("newaddrs", json_new_addrs).unwrap();
+        server
+            .add_rpc("resetaddrindex", json_reset_address_index)
+            .unwrap();
+        server
+            .add_rpc("addressindices", json_address_indices)
+            .unwrap();
         server.add_rpc("channels", json_list_channels).unwrap();
+        server
+            .add_rpc("listchannelfees", json_list_channel_fees)
+            .unwrap();
+        server
+            .add_rpc("setchannelfee", json_set_channel_fee)
+            .unwrap();
+        server
+            .add_rpc("setdustexposure", json_set_dust_exposure)
+            .unwrap();
+        server
+            .add_rpc("updatechannelconfig", json_update_channel_config)
+            .unwrap();
+        server
+            .add_rpc("closedchannels", json_list_closed_channels)
+            .unwrap();
+        server.add_rpc("dumpchannel", json_dump_channel).unwrap();
+        server
+            .add_rpc("channelfundingtx", json_channel_funding_tx)
+            .unwrap();
+        server
+            .add_rpc("verifychannelfunding", json_verify_channel_funding)
+            .unwrap();
+        server
+            .add_rpc("rebroadcastcommitment", json_rebroadcast_commitment)
+            .unwrap();
+        server
+            .add_rpc("bumpchannelclose", json_bump_channel_close)
+            .unwrap();
+        server
+            .add_rpc("estimatecloseall", json_estimate_close_all)
+            .unwrap();
+        server
+            .add_rpc("exportchannelbackup", json_export_channel_backup)
+            .unwrap();
+        server
+            .add_rpc("importchannelbackup", json_import_channel_backup)
+            .unwrap();
+        server.add_rpc("listtowers", json_list_towers).unwrap();
+        server.add_rpc("addtower", json_add_tower).unwrap();
         server.add_rpc("funds", json_funds).unwrap();
+        server.add_rpc("listcoins", json_list_coins).unwrap();
+        server.add_rpc("txhistory", json_tx_history).unwrap();
+        server.add_rpc("exportlabels", json_export_labels).unwrap();
+        server.add_rpc("importlabels", json_import_labels).unwrap();
+        server.add_rpc("consolidate", json_consolidate).unwrap();
+        server.add_rpc("withdraw", json_withdraw).unwrap();
+        server.add_rpc("syncwallet", json_sync_wallet).unwrap();
+        server
+            .add_rpc("reconcilewallet", json_reconcile_wallet)
+            .unwrap();
+        server
+            .add_rpc("txconfirmationeta", json_tx_confirmation_eta)
+            .unwrap();
+        server.add_rpc("mempoolinfo", json_mempool_info).unwrap();
+        server
+            .add_rpc("simulatereorg", json_simulate_reorg)
+            .unwrap();
+        server
+            .add_sensitive_rpc("sweepprivatekey", json_sweep_private_key)
+            .unwrap();
+        server
+            .add_rpc("createtimelockoutput", json_create_timelock_output)
+            .unwrap();
         server.add_rpc("invoice", json_invoice).unwrap();
+        server
+            .add_rpc("createinvoice", json_create_invoice)
+            .unwrap();
+        server
+            .add_rpc("settleinvoice", json_settle_invoice)
+            .unwrap();
+        server
+            .add_rpc("invoicestatus", json_invoice_status)
+            .unwrap();
+        server
+            .add_rpc("reissueinvoice", json_reissue_invoice)
+            .unwrap();
         server.add_rpc("offer", json_offer).unwrap();
         server
             .add_rpc("decode_invoice", json_decode_invoice)
             .unwrap();
 
         server.add_rpc("pay", json_pay).unwrap();
+        server.add_rpc("lnurlpay", json_lnurl_pay).unwrap();
+        server.add_rpc("waitsendpay", json_wait_send_pay).unwrap();
+        server
+            .add_rpc("paymentfailures", json_payment_failures)
+            .unwrap();
+        server
+            .add_rpc("lastforwarderror", json_last_forward_error)
+            .unwrap();
         server.add_rpc("keysend", json_keysend).unwrap();
+        server.add_rpc("getroute", json_get_route).unwrap();
+        server.add_rpc("probes", json_probes).unwrap();
+        server.add_rpc("resetscorer", json_reset_scorer).unwrap();
         server.add_rpc("close", json_close_channel).unwrap();
         server
             .add_rpc("networkchannels", json_network_channels)
@@ -144,6 +358,7 @@ impl LampoTesting {
             port: port.into(),
             wallet,
             btc,
+            backend,
             root_path: Arc::new(dir),
             info,
         })
@@ -173,6 +388,14 @@ impl LampoTesting {
         Ok(address)
     }
 
+    /// Return the `LampoErrorCode` of an error returned by a `call`.
+    pub fn error_code(err: &error::Error) -> Option<LampoErrorCode> {
+        match err.downcast_ref::<lampo_jsonrpc::errors::Error>()? {
+            lampo_jsonrpc::errors::Error::Rpc(rpc) => Some(LampoErrorCode::from_code(rpc.code)),
+            _ => None,
+        }
+    }
+
     pub fn lampod(&self) -> Arc<LampoHandler> {
         self.inner.clone()
     }
@@ -181,3 +404,20 @@ impl LampoTesting {
         self.root_path.clone()
     }
 }
+
+/// Copy the files of the directory `from` inside `to`, the
+/// sockets of a running node are skipped.
+fn copy_dir(from: &std::path::Path, to: &std::path::Path) -> error::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let dest = to.join(entry.file_name());
+        if file_type.is_dir() {
+            std::fs::create_dir_all(&dest)?;
+            copy_dir(&entry.path(), &dest)?;
+        } else if file_type.is_file() {
+            std::fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
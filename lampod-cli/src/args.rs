@@ -31,7 +31,11 @@ Options
     --core-url         Set the url of the bitcoin core backend
     --core-user        Set the username of the bitcoin core backend
     --core-pass        Set the password of the bitcoin core backend
-    --restore-wallet   Restore a wallet from a mnemonic 
+    --core-cookie      Set the path of the cookie file of the bitcoin core backend
+    --restore-wallet   Restore a wallet from a mnemonic, or from the hex of its entropy
+    --restore-descriptor
+                       Restore a wallet from a private descriptor with the `#checksum` suffix
+    --skip-sync-check  Start without waiting the backend to be in sync with the network
 "#,
 };
 
@@ -41,11 +45,14 @@ pub struct LampoCliArgs {
     pub network: Option<String>,
     pub client: Option<String>,
     pub restore_wallet: bool,
+    pub restore_descriptor: Option<String>,
+    pub skip_sync_check: bool,
     pub log_level: Option<String>,
     pub log_file: Option<String>,
     pub bitcoind_url: Option<String>,
     pub bitcoind_user: Option<String>,
     pub bitcoind_pass: Option<String>,
+    pub bitcoind_cookie: Option<String>,
 }
 
 impl TryInto<LampoConf> for LampoCliArgs {
@@ -84,6 +91,9 @@ impl TryInto<LampoConf> for LampoCliArgs {
         if self.bitcoind_pass.is_some() {
             conf.core_pass = self.bitcoind_pass;
         }
+        if self.bitcoind_cookie.is_some() {
+            conf.core_cookie = self.bitcoind_cookie;
+        }
         if self.log_file.is_some() {
             conf.log_file = self.log_file;
         }
@@ -105,7 +115,10 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
     let mut bitcoind_url: Option<String> = None;
     let mut bitcoind_user: Option<String> = None;
     let mut bitcoind_pass: Option<String> = None;
+    let mut bitcoind_cookie: Option<String> = None;
     let mut restore_wallet = false;
+    let mut restore_descriptor: Option<String> = None;
+    let mut skip_sync_check = false;
 
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
@@ -142,9 +155,20 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
                 let var: String = parser.value()?.parse()?;
                 bitcoind_pass = Some(var);
             }
+            Long("core-cookie") => {
+                let var: String = parser.value()?.parse()?;
+                bitcoind_cookie = Some(var);
+            }
             Long("restore-wallet") => {
                 restore_wallet = true;
             }
+            Long("restore-descriptor") => {
+                let var: String = parser.value()?.parse()?;
+                restore_descriptor = Some(var);
+            }
+            Long("skip-sync-check") => {
+                skip_sync_check = true;
+            }
             Long("help") => {
                 let _ = print_help();
                 std::process::exit(0);
@@ -158,10 +182,13 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
         network,
         client,
         restore_wallet,
+        restore_descriptor,
+        skip_sync_check,
         log_file,
         bitcoind_url,
         bitcoind_pass,
         bitcoind_user,
+        bitcoind_cookie,
         // Default log level is info if it is not specified
         // in the command line
         log_level: level,
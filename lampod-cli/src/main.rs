@@ -7,6 +7,7 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
@@ -20,22 +21,74 @@ use lampo_common::conf::LampoConf;
 use lampo_common::error;
 use lampo_common::logger;
 use lampo_core_wallet::CoreWalletManager;
+use lampo_jsonrpc::acl::Acl;
 use lampo_jsonrpc::Handler;
 use lampo_jsonrpc::JSONRPCv2;
+use lampod::chain::sync_check::wait_backend_sync;
 use lampod::chain::WalletManager;
+use lampod::jsonrpc::channels::json_add_tower;
+use lampod::jsonrpc::channels::json_bump_channel_close;
+use lampod::jsonrpc::channels::json_channel_funding_tx;
 use lampod::jsonrpc::channels::json_close_channel;
+use lampod::jsonrpc::channels::json_dump_channel;
+use lampod::jsonrpc::channels::json_estimate_close_all;
+use lampod::jsonrpc::channels::json_export_channel_backup;
+use lampod::jsonrpc::channels::json_import_channel_backup;
+use lampod::jsonrpc::channels::json_list_channel_fees;
 use lampod::jsonrpc::channels::json_list_channels;
+use lampod::jsonrpc::channels::json_list_closed_channels;
+use lampod::jsonrpc::channels::json_list_towers;
+use lampod::jsonrpc::channels::json_rebroadcast_commitment;
+use lampod::jsonrpc::channels::json_set_channel_fee;
+use lampod::jsonrpc::channels::json_set_dust_exposure;
+use lampod::jsonrpc::channels::json_update_channel_config;
+use lampod::jsonrpc::channels::json_verify_channel_funding;
 use lampod::jsonrpc::inventory::get_info;
+use lampod::jsonrpc::inventory::json_health;
+use lampod::jsonrpc::inventory::json_set_log_level;
+use lampod::jsonrpc::offchain::json_create_invoice;
 use lampod::jsonrpc::offchain::json_decode_invoice;
+use lampod::jsonrpc::offchain::json_get_route;
 use lampod::jsonrpc::offchain::json_invoice;
+use lampod::jsonrpc::offchain::json_invoice_status;
 use lampod::jsonrpc::offchain::json_keysend;
+use lampod::jsonrpc::offchain::json_last_forward_error;
+use lampod::jsonrpc::offchain::json_lnurl_pay;
 use lampod::jsonrpc::offchain::json_offer;
 use lampod::jsonrpc::offchain::json_pay;
+use lampod::jsonrpc::offchain::json_payment_failures;
+use lampod::jsonrpc::offchain::json_probes;
+use lampod::jsonrpc::offchain::json_reissue_invoice;
+use lampod::jsonrpc::offchain::json_reset_scorer;
+use lampod::jsonrpc::offchain::json_settle_invoice;
+use lampod::jsonrpc::offchain::json_wait_send_pay;
+use lampod::jsonrpc::onchain::json_address_indices;
+use lampod::jsonrpc::onchain::json_consolidate;
+use lampod::jsonrpc::onchain::json_create_timelock_output;
 use lampod::jsonrpc::onchain::json_estimate_fees;
+use lampod::jsonrpc::onchain::json_export_labels;
 use lampod::jsonrpc::onchain::json_funds;
+use lampod::jsonrpc::onchain::json_import_labels;
+use lampod::jsonrpc::onchain::json_list_coins;
+use lampod::jsonrpc::onchain::json_mempool_info;
 use lampod::jsonrpc::onchain::json_new_addr;
+use lampod::jsonrpc::onchain::json_new_addrs;
+use lampod::jsonrpc::onchain::json_reconcile_wallet;
+use lampod::jsonrpc::onchain::json_reset_address_index;
+use lampod::jsonrpc::onchain::json_simulate_reorg;
+use lampod::jsonrpc::onchain::json_sweep_private_key;
+use lampod::jsonrpc::onchain::json_sync_wallet;
+use lampod::jsonrpc::onchain::json_tx_confirmation_eta;
+use lampod::jsonrpc::onchain::json_tx_history;
+use lampod::jsonrpc::onchain::json_withdraw;
+use lampod::jsonrpc::open_channel::json_cancel_open;
+use lampod::jsonrpc::open_channel::json_min_channel_capacity;
 use lampod::jsonrpc::open_channel::json_open_channel;
 use lampod::jsonrpc::peer_control::json_connect;
+use lampod::jsonrpc::peer_control::json_forget_peer;
+use lampod::jsonrpc::peer_control::json_gossip_sync;
+use lampod::jsonrpc::peer_control::json_list_addresses;
+use lampod::jsonrpc::peer_control::json_list_stored_peers;
 use lampod::jsonrpc::CommandHandler;
 use lampod::LampoDaemon;
 
@@ -77,6 +130,8 @@ fn load_words_from_file<P: AsRef<Path>>(path: P) -> error::Result<String> {
 /// Return the root directory.
 fn run(args: LampoCliArgs) -> error::Result<()> {
     let restore_wallet = args.restore_wallet;
+    let restore_descriptor = args.restore_descriptor.clone();
+    let skip_sync_check = args.skip_sync_check;
 
     // After this point the configuration is ready!
     let mut lampo_conf: LampoConf = args.try_into()?;
@@ -105,20 +160,27 @@ fn run(args: LampoCliArgs) -> error::Result<()> {
                 .core_url
                 .clone()
                 .ok_or(error::anyhow!("Miss the bitcoin url"))?,
-            &lampo_conf
-                .core_user
-                .clone()
-                .ok_or(error::anyhow!("Miss the bitcoin user for auth"))?,
-            &lampo_conf
-                .core_pass
-                .clone()
-                .ok_or(error::anyhow!("Miss the bitcoin password for auth"))?,
+            lampo_conf.core_auth()?,
             Arc::new(false),
             Some(60),
         )?),
         _ => error::bail!("client {:?} not supported", client),
     };
 
+    // The RPCs are served only when the backend is at the tip, otherwise
+    // the wallet and the channels see an old chain.
+    if skip_sync_check {
+        log::warn!(target: "lampod-cli", "skipping the sync check of the backend");
+    } else {
+        let timeout = lampo_conf.startup_sync_timeout_secs;
+        wait_backend_sync(
+            client.as_ref(),
+            lampo_conf.startup_sync_max_blocks_behind,
+            (timeout > 0).then(|| Duration::from_secs(timeout)),
+            Duration::from_secs(10),
+        )?;
+    }
+
     if let Some(ref _private_key) = lampo_conf.private_key {
         error::bail!("Option to force a private key not available at the moment")
     }
@@ -132,7 +194,32 @@ fn run(args: LampoCliArgs) -> error::Result<()> {
     //   2.1: The user keep specify --restore-wallet, so lampo should return an error an tell the user that there is already a wallet
     //   2.2: The user do not specify the --restore-wallet, so lampo load from disk the file, if there is no file it return an error
     // FIXME: there is a problem of code duplication here, we should move this code in utils functions.
-    let wallet = if restore_wallet {
+    let descriptor_path = format!("{}/descriptor.dat", words_path);
+    let wallet = if let Some(descriptor) = restore_descriptor {
+        let wallet = match client.kind() {
+            lampo_common::backend::BackendKind::Core => CoreWalletManager::restore_from_descriptor(
+                Arc::new(lampo_conf.clone()),
+                &descriptor,
+            )?,
+            lampo_common::backend::BackendKind::Nakamoto => {
+                error::bail!("wallet is not implemented for nakamoto")
+            }
+        };
+        write_words_to_file(&descriptor_path, descriptor)?;
+        wallet
+    } else if Path::new(&descriptor_path).exists() {
+        log::warn!("Loading from existing descriptor");
+        let descriptor = load_words_from_file(&descriptor_path)?;
+        match client.kind() {
+            lampo_common::backend::BackendKind::Core => CoreWalletManager::restore_from_descriptor(
+                Arc::new(lampo_conf.clone()),
+                &descriptor,
+            )?,
+            lampo_common::backend::BackendKind::Nakamoto => {
+                error::bail!("wallet is not implemented for nakamoto")
+            }
+        }
+    } else if restore_wallet {
         if Path::new(&format!("{}/wallet.dat", words_path)).exists() {
             // Load the mnemonic from the file
             let mnemonic = load_words_from_file(format!("{}/wallet.dat", words_path))?;
@@ -150,7 +237,7 @@ fn run(args: LampoCliArgs) -> error::Result<()> {
             let mnemonic: String = term::input(
                 "BIP 39 Mnemonic",
                 None,
-                Some("To restore the wallet, lampo needs the BIP39 mnemonic with words separated by spaces."),
+                Some("To restore the wallet, lampo needs the BIP39 mnemonic with words separated by spaces, or the hex of its 128 or 256 bits entropy."),
             )?;
             // FIXME: make some sanity check about the mnemonic string
             let wallet = match client.kind() {
@@ -217,10 +304,11 @@ fn run(args: LampoCliArgs) -> error::Result<()> {
     let (jsorpc_worker, handler) = run_jsonrpc(lampod.clone()).unwrap();
     rpc_handler.set_handler(handler.clone());
 
+    let daemon = lampod.clone();
     ctrlc::set_handler(move || {
-        use std::time::Duration;
         log::info!("Shutdown...");
         handler.stop();
+        daemon.shutdown();
         std::thread::sleep(Duration::from_secs(5));
         std::process::exit(0);
     })?;
@@ -241,19 +329,135 @@ fn run_jsonrpc(
     // that it is running.
     let _ = std::fs::remove_file(socket_path.clone());
     env::set_var("LAMPO_UNIX", socket_path.clone());
-    let server = JSONRPCv2::new(lampod, &socket_path)?;
+    let server = JSONRPCv2::new(lampod.clone(), &socket_path)?;
+    if !lampod.conf().rpc_acl.is_empty() {
+        let acl = lampod
+            .conf()
+            .rpc_acl
+            .iter()
+            .fold(Acl::new(), |acl, (token, methods)| {
+                acl.allow(token, methods)
+            });
+        server.handler().set_acl(acl);
+    }
     server.add_rpc("getinfo", get_info).unwrap();
+    server.add_rpc("health", json_health).unwrap();
+    server.add_rpc("setloglevel", json_set_log_level).unwrap();
     server.add_rpc("connect", json_connect).unwrap();
+    server
+        .add_rpc("storedpeers", json_list_stored_peers)
+        .unwrap();
+    server.add_rpc("forgetpeer", json_forget_peer).unwrap();
+    server
+        .add_rpc("listaddresses", json_list_addresses)
+        .unwrap();
+    server.add_rpc("gossipsync", json_gossip_sync).unwrap();
     server.add_rpc("fundchannel", json_open_channel).unwrap();
+    server.add_rpc("cancelopen", json_cancel_open).unwrap();
+    server
+        .add_rpc("minchannelcapacity", json_min_channel_capacity)
+        .unwrap();
     server.add_rpc("newaddr", json_new_addr).unwrap();
+    server.add_rpc("newaddrs", json_new_addrs).unwrap();
+    server
+        .add_rpc("resetaddrindex", json_reset_address_index)
+        .unwrap();
+    server
+        .add_rpc("addressindices", json_address_indices)
+        .unwrap();
     server.add_rpc("channels", json_list_channels).unwrap();
+    server
+        .add_rpc("listchannelfees", json_list_channel_fees)
+        .unwrap();
+    server
+        .add_rpc("setchannelfee", json_set_channel_fee)
+        .unwrap();
+    server
+        .add_rpc("setdustexposure", json_set_dust_exposure)
+        .unwrap();
+    server
+        .add_rpc("updatechannelconfig", json_update_channel_config)
+        .unwrap();
+    server
+        .add_rpc("closedchannels", json_list_closed_channels)
+        .unwrap();
+    server.add_rpc("dumpchannel", json_dump_channel).unwrap();
+    server
+        .add_rpc("channelfundingtx", json_channel_funding_tx)
+        .unwrap();
+    server
+        .add_rpc("verifychannelfunding", json_verify_channel_funding)
+        .unwrap();
+    server
+        .add_rpc("rebroadcastcommitment", json_rebroadcast_commitment)
+        .unwrap();
+    server
+        .add_rpc("bumpchannelclose", json_bump_channel_close)
+        .unwrap();
+    server
+        .add_rpc("estimatecloseall", json_estimate_close_all)
+        .unwrap();
+    server
+        .add_rpc("exportchannelbackup", json_export_channel_backup)
+        .unwrap();
+    server
+        .add_rpc("importchannelbackup", json_import_channel_backup)
+        .unwrap();
+    server.add_rpc("listtowers", json_list_towers).unwrap();
+    server.add_rpc("addtower", json_add_tower).unwrap();
     server.add_rpc("funds", json_funds).unwrap();
+    server.add_rpc("listcoins", json_list_coins).unwrap();
+    server.add_rpc("txhistory", json_tx_history).unwrap();
+    server.add_rpc("exportlabels", json_export_labels).unwrap();
+    server.add_rpc("importlabels", json_import_labels).unwrap();
+    server.add_rpc("consolidate", json_consolidate).unwrap();
+    server.add_rpc("withdraw", json_withdraw).unwrap();
+    server.add_rpc("syncwallet", json_sync_wallet).unwrap();
+    server
+        .add_rpc("reconcilewallet", json_reconcile_wallet)
+        .unwrap();
     server.add_rpc("invoice", json_invoice).unwrap();
+    server
+        .add_rpc("createinvoice", json_create_invoice)
+        .unwrap();
+    server
+        .add_rpc("settleinvoice", json_settle_invoice)
+        .unwrap();
+    server
+        .add_rpc("invoicestatus", json_invoice_status)
+        .unwrap();
+    server
+        .add_rpc("reissueinvoice", json_reissue_invoice)
+        .unwrap();
     server.add_rpc("offer", json_offer).unwrap();
     server.add_rpc("decode", json_decode_invoice).unwrap();
     server.add_rpc("pay", json_pay).unwrap();
+    server.add_rpc("lnurlpay", json_lnurl_pay).unwrap();
+    server.add_rpc("waitsendpay", json_wait_send_pay).unwrap();
+    server
+        .add_rpc("paymentfailures", json_payment_failures)
+        .unwrap();
+    server
+        .add_rpc("lastforwarderror", json_last_forward_error)
+        .unwrap();
     server.add_rpc("keysend", json_keysend).unwrap();
+    server.add_rpc("getroute", json_get_route).unwrap();
+    server.add_rpc("probes", json_probes).unwrap();
+    server.add_rpc("resetscorer", json_reset_scorer).unwrap();
     server.add_rpc("fees", json_estimate_fees).unwrap();
+    server
+        .add_rpc("txconfirmationeta", json_tx_confirmation_eta)
+        .unwrap();
+    server.add_rpc("mempoolinfo", json_mempool_info).unwrap();
+    server
+        .add_rpc("simulatereorg", json_simulate_reorg)
+        .unwrap();
+    server
+        .add_sensitive_rpc("sweepprivatekey", json_sweep_private_key)
+        .unwrap();
+    server
+        .add_rpc("createtimelockoutput", json_create_timelock_output)
+        .unwrap();
     server.add_rpc("close", json_close_channel).unwrap();
     let handler = server.handler();
     Ok((server.spawn(), handler))
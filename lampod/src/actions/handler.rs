@@ -1,7 +1,10 @@
 //! Handler module implementation that
 use std::cell::RefCell;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use lampo_common::bitcoin::Address;
 use lampo_common::chan;
 use lampo_common::error;
 use lampo_common::error::Ok;
@@ -10,11 +13,25 @@ use lampo_common::event::{Emitter, Event, Subscriber};
 use lampo_common::handler::Handler as EventHandler;
 use lampo_common::json;
 use lampo_common::ldk;
+use lampo_common::ldk::chain::chaininterface::BroadcasterInterface;
+use lampo_common::ldk::chain::transaction::OutPoint;
+use lampo_common::ldk::events::bump_transaction::BumpTransactionEvent;
+use lampo_common::ldk::events::{ClosureReason, HTLCDestination, PathFailure};
+use lampo_common::ldk::ln::PaymentHash;
+use lampo_common::ldk::routing::gossip::NetworkUpdate;
+use lampo_common::ldk::routing::router::Path;
+use lampo_common::ldk::sign::SpendableOutputDescriptor;
+use lampo_common::model::response::ForwardError;
+use lampo_common::model::response::InvoiceState;
+use lampo_common::model::response::PaymentFailure;
 use lampo_common::model::response::PaymentHop;
 use lampo_common::model::response::PaymentState;
-use lampo_common::types::ChannelState;
+use lampo_common::model::response::{CloseType, ClosedChannel};
+use lampo_common::types::{ChannelId, ChannelState};
 use lampo_jsonrpc::json_rpc2::Request;
 
+use crate::chain::bump::AnchorClose;
+use crate::chain::sweep::create_spending_transaction;
 use crate::chain::{LampoChainManager, WalletManager};
 use crate::command::Command;
 use crate::handler::external_handler::ExternalHandler;
@@ -78,6 +95,36 @@ impl LampoHandler {
         let result = receiver.recv()?;
         Ok(json::from_value::<R>(result)?)
     }
+
+    /// Refresh the static channel backup on disk, a failure is only
+    /// logged because it must not stop the processing of the events.
+    fn write_channel_backup(&self) {
+        if let Err(err) = self.channel_manager.write_channel_backup() {
+            log::warn!("impossible write the channel backup: {err}");
+        }
+    }
+
+    /// Sweep the outputs that LDK give back to us into the
+    /// wallet, paying the configured sweep feerate.
+    // FIXME: the descriptors are lost if the broadcast fails,
+    // they should be persisted and swept again later.
+    fn sweep_spendable_outputs(&self, outputs: &[SpendableOutputDescriptor]) -> error::Result<()> {
+        let conf = &self.channel_manager.conf;
+        let feerate = self.chain_manager.sweep_feerate(conf);
+        log::info!(
+            "sweeping {} spendable outputs with feerate `{feerate}` sats per kw",
+            outputs.len()
+        );
+        let address = self.wallet_manager.get_onchain_address()?;
+        let script = Address::from_str(&address.address)?
+            .require_network(conf.network)?
+            .script_pubkey();
+        let keys = self.wallet_manager.ldk_keys();
+        let tx = create_spending_transaction(&keys.keys_manager, outputs, script, feerate)?;
+        log::info!("broadcasting sweep transaction `{}`", tx.txid());
+        self.chain_manager.broadcast_transactions(&[&tx]);
+        Ok(())
+    }
 }
 
 impl EventHandler for LampoHandler {
@@ -128,10 +175,12 @@ impl Handler for LampoHandler {
                 temporary_channel_id,
                 counterparty_node_id,
                 funding_satoshis,
-                push_msat,
                 channel_type,
+                ..
             } => {
-                Err(error::anyhow!("Request for open a channel received, unfortunatly we do not support this feature yet."))
+                log::info!("request to open a channel of {funding_satoshis} sats from `{counterparty_node_id}`, and channel type {channel_type}");
+                self.channel_manager
+                    .accept_inbound_channel(&temporary_channel_id, &counterparty_node_id)
             }
             ldk::events::Event::ChannelReady {
                 channel_id,
@@ -140,18 +189,25 @@ impl Handler for LampoHandler {
                 channel_type,
             } => {
                 log::info!("channel ready with node `{counterparty_node_id}`, and channel type {channel_type}");
+                if channel_type.supports_anchors_zero_fee_htlc_tx() {
+                    if let Err(err) = self.channel_manager.anchor_reserve() {
+                        log::warn!("the anchor reserve can not be maintained: {err}");
+                    }
+                }
                 self.emit(Event::Lightning(LightningEvent::ChannelReady {
                     counterparty_node_id,
                     channel_id,
                     channel_type,
                 }));
+                self.write_channel_backup();
                 Ok(())
-            },
+            }
             ldk::events::Event::ChannelClosed {
                 channel_id,
                 user_channel_id,
                 reason,
                 counterparty_node_id,
+                channel_capacity_sats,
                 channel_funding_txo,
                 ..
             } => {
@@ -160,8 +216,52 @@ impl Handler for LampoHandler {
                 }
                 let node_id = counterparty_node_id.map(|id| id.to_string());
                 let txo = channel_funding_txo.map(|txo| txo.to_string());
-                self.emit(Event::Lightning(LightningEvent::CloseChannelEvent { channel_id: channel_id.to_string(), message: reason.to_string(), counterparty_node_id : node_id, funding_utxo : txo}));
+                let close_type = match &reason {
+                    ClosureReason::LegacyCooperativeClosure
+                    | ClosureReason::CounterpartyInitiatedCooperativeClosure
+                    | ClosureReason::LocallyInitiatedCooperativeClosure => CloseType::Cooperative,
+                    // The peer force closed with a revoked commitment.
+                    _ if channel_funding_txo.is_some_and(|txo| {
+                        self.channel_manager
+                            .is_breached(&txo.into_bitcoin_outpoint())
+                    }) =>
+                    {
+                        CloseType::Breach
+                    }
+                    _ => CloseType::Force,
+                };
+                let closed = ClosedChannel {
+                    channel_id: channel_id.to_string(),
+                    peer_id: node_id.clone(),
+                    capacity_sat: channel_capacity_sats,
+                    close_type,
+                    reason: reason.to_string(),
+                    funding_utxo: txo.clone(),
+                    closing_txid: None,
+                    closing_height: None,
+                };
+                // A channel without a funding transaction never
+                // existed on chain, e.g. a dry run.
+                if closed.funding_utxo.is_some() {
+                    self.channel_manager.store_closed_channel(&closed)?;
+                }
+                // The open was aborted before the funding was broadcast,
+                // so the caller waiting for the funding is woken up.
+                if self.channel_manager.take_pending_open(&channel_id) {
+                    self.emit(Event::Lightning(LightningEvent::ChannelEvent {
+                        channel_id,
+                        state: ChannelState::OpeningError,
+                        message: format!("Channel Opening Error: channel closed before the funding was broadcast: {reason}"),
+                    }));
+                }
+                self.emit(Event::Lightning(LightningEvent::CloseChannelEvent {
+                    channel_id: channel_id.to_string(),
+                    message: reason.to_string(),
+                    counterparty_node_id: node_id,
+                    funding_utxo: txo,
+                }));
                 log::info!("channel `{user_channel_id}` closed with reason: `{reason}`");
+                self.write_channel_backup();
                 Ok(())
             }
             ldk::events::Event::FundingGenerationReady {
@@ -169,6 +269,7 @@ impl Handler for LampoHandler {
                 counterparty_node_id,
                 channel_value_satoshis,
                 output_script,
+                user_channel_id,
                 ..
             } => {
                 self.emit(Event::Lightning(LightningEvent::FundingChannelStart {
@@ -179,48 +280,138 @@ impl Handler for LampoHandler {
 
                 log::info!("propagate funding transaction for open a channel with `{counterparty_node_id}`");
                 // FIXME: estimate the fee rate with a callback
-                let fee = self.chain_manager.backend.fee_rate_estimation(6).map_err(|err| {
-                    let msg = format!("Channel Opening Error: {err}");
-                    self.emit(Event::Lightning(LightningEvent::ChannelEvent { state: ChannelState::OpeningError, message : msg}));
-                    err
-                })?;
+                let fee = match self.channel_manager.take_funding_feerate(user_channel_id) {
+                    Some(fee) => fee,
+                    None => self
+                        .chain_manager
+                        .backend
+                        .fee_rate_estimation(6)
+                        .map_err(|err| {
+                            let msg = format!("Channel Opening Error: {err}");
+                            self.emit(Event::Lightning(LightningEvent::ChannelEvent {
+                                channel_id: temporary_channel_id,
+                                state: ChannelState::OpeningError,
+                                message: msg,
+                            }));
+                            err
+                        })?,
+                };
+                let fee = self.chain_manager.feerate_floor.apply(fee);
                 log::info!("fee estimated {:?} sats", fee);
-                let transaction = self.wallet_manager.create_transaction(
-                    output_script,
+                let options = self.channel_manager.take_funding_options(user_channel_id);
+                let transaction = match self.wallet_manager.create_transaction(
+                    output_script.clone(),
                     channel_value_satoshis,
                     fee,
-                )?;
+                    options,
+                ) {
+                    Ok(transaction) => transaction,
+                    Err(err) => {
+                        let msg = format!("Channel Opening Error: impossible create the funding transaction: {err}");
+                        self.channel_manager
+                            .take_pending_open(&temporary_channel_id);
+                        self.channel_manager
+                            .set_funding_failure(user_channel_id, err.code());
+                        self.emit(Event::Lightning(LightningEvent::ChannelEvent {
+                            channel_id: temporary_channel_id,
+                            state: ChannelState::OpeningError,
+                            message: msg,
+                        }));
+                        self.channel_manager
+                            .manager()
+                            .force_close_without_broadcasting_txn(
+                                &temporary_channel_id,
+                                &counterparty_node_id,
+                            )
+                            .map_err(|err| error::anyhow!("{:?}", err))?;
+                        return Err(err.into());
+                    }
+                };
                 log::info!("funding transaction created `{}`", transaction.txid());
                 log::info!(
                     "transaction hex `{}`",
                     lampo_common::bitcoin::consensus::encode::serialize_hex(&transaction)
                 );
+                let dry_run = self.channel_manager.take_dry_run(user_channel_id);
+                if dry_run {
+                    // Released before the end of the funding, so the
+                    // caller of the dry run finds the coins spendable.
+                    self.wallet_manager.release_transaction(&transaction)?;
+                }
                 self.emit(Event::Lightning(LightningEvent::FundingChannelEnd {
                     counterparty_node_id,
                     temporary_channel_id,
                     channel_value_satoshis,
                     funding_transaction: transaction.clone(),
                 }));
-                self.channel_manager
+                if dry_run {
+                    log::info!("dry run, dropping the channel with `{counterparty_node_id}` without funding it");
+                    self.channel_manager
+                        .manager()
+                        .force_close_without_broadcasting_txn(
+                            &temporary_channel_id,
+                            &counterparty_node_id,
+                        )
+                        .map_err(|err| error::anyhow!("{:?}", err))?;
+                    return Ok(());
+                }
+                if let Err(err) = self
+                    .channel_manager
                     .manager()
                     .funding_transaction_generated(
                         &temporary_channel_id,
                         &counterparty_node_id,
-                        transaction,
+                        transaction.clone(),
                     )
-                    .map_err(|err| error::anyhow!("{:?}", err))?;
+                {
+                    self.wallet_manager.release_transaction(&transaction)?;
+                    error::bail!("{:?}", err);
+                }
+                // The channel is now identified by its funding output.
+                if let Some(index) = transaction
+                    .output
+                    .iter()
+                    .position(|output| output.script_pubkey == output_script)
+                {
+                    let funding_txo = OutPoint {
+                        txid: transaction.txid(),
+                        index: index as u16,
+                    };
+                    self.channel_manager.fund_pending_open(
+                        &temporary_channel_id,
+                        ChannelId::v1_from_funding_outpoint(funding_txo),
+                    );
+                }
                 Ok(())
             }
+            ldk::events::Event::DiscardFunding {
+                channel_id,
+                transaction,
+            } => {
+                log::info!("funding transaction `{}` of channel `{channel_id}` discarded, releasing its coins", transaction.txid());
+                self.wallet_manager.release_transaction(&transaction)
+            }
             ldk::events::Event::ChannelPending {
+                channel_id,
                 counterparty_node_id,
                 funding_txo,
+                former_temporary_channel_id,
                 ..
             } => {
+                self.channel_manager.take_pending_open(&channel_id);
+                if let Some(temporary_channel_id) = former_temporary_channel_id {
+                    self.channel_manager
+                        .take_pending_open(&temporary_channel_id);
+                }
                 log::info!(
                     "channel pending with node `{}` with funding `{funding_txo}`",
                     counterparty_node_id.to_string()
                 );
-                self.emit(Event::Lightning(LightningEvent::ChannelPending { counterparty_node_id, funding_transaction: funding_txo }));
+                self.emit(Event::Lightning(LightningEvent::ChannelPending {
+                    counterparty_node_id,
+                    funding_transaction: funding_txo,
+                }));
+                self.write_channel_backup();
                 Ok(())
             }
             ldk::events::Event::PendingHTLCsForwardable { time_forwardable } => {
@@ -240,17 +431,49 @@ impl Handler for LampoHandler {
                 via_user_channel_id,
                 claim_deadline,
             } => {
+                // LDK can not limit the size of a single inbound HTLC, so
+                // the payments with an HTLC above the cap are failed back.
+                if let Some(htlc_msat) = self.channel_manager.oversized_htlc(&payment_hash) {
+                    log::warn!(
+                        "failing back payment `{payment_hash}`, it has an HTLC of {htlc_msat} msat above the `max-inbound-htlc-msat`"
+                    );
+                    self.channel_manager
+                        .manager()
+                        .fail_htlc_backwards(&payment_hash);
+                    return Ok(());
+                }
                 let preimage = match purpose {
-                    ldk::events::PaymentPurpose::Bolt11InvoicePayment  {
+                    ldk::events::PaymentPurpose::Bolt11InvoicePayment {
+                        payment_preimage, ..
+                    } => payment_preimage,
+                    ldk::events::PaymentPurpose::Bolt12OfferPayment {
+                        payment_preimage, ..
+                    } => payment_preimage,
+                    ldk::events::PaymentPurpose::Bolt12RefundPayment {
                         payment_preimage, ..
                     } => payment_preimage,
-                    ldk::events::PaymentPurpose::Bolt12OfferPayment { payment_preimage, .. } => payment_preimage,
-                    ldk::events::PaymentPurpose::Bolt12RefundPayment { payment_preimage, .. } => payment_preimage,
                     ldk::events::PaymentPurpose::SpontaneousPayment(preimage) => Some(preimage),
                 };
-                self.channel_manager
-                    .manager()
-                    .claim_funds(preimage.unwrap());
+                let Some(preimage) = preimage else {
+                    // The invoice was created for a payment hash without
+                    // the preimage, so the payment is held until the
+                    // preimage is provided.
+                    log::info!("payment `{payment_hash}` held, waiting for the preimage");
+                    self.channel_manager.hold_payment(payment_hash);
+                    self.channel_manager.update_invoice(
+                        payment_hash,
+                        InvoiceState::Held,
+                        amount_msat,
+                        None,
+                    );
+                    self.emit(Event::Lightning(LightningEvent::PaymentEvent {
+                        state: PaymentState::Held,
+                        payment_hash: Some(payment_hash.to_string()),
+                        path: vec![],
+                    }));
+                    return Ok(());
+                };
+                self.channel_manager.manager().claim_funds(preimage);
                 Ok(())
             }
             ldk::events::Event::PaymentClaimed {
@@ -266,25 +489,346 @@ impl Handler for LampoHandler {
                         payment_secret,
                         ..
                     } => (payment_preimage, Some(payment_secret)),
-                    ldk::events::PaymentPurpose::Bolt12OfferPayment { payment_preimage, payment_secret, .. } => (payment_preimage, Some(payment_secret)),
-                    ldk::events::PaymentPurpose::Bolt12RefundPayment { payment_preimage, payment_secret, .. } => (payment_preimage, Some(payment_secret)),
-                    ldk::events::PaymentPurpose::SpontaneousPayment(preimage) => (Some(preimage), None),
+                    ldk::events::PaymentPurpose::Bolt12OfferPayment {
+                        payment_preimage,
+                        payment_secret,
+                        ..
+                    } => (payment_preimage, Some(payment_secret)),
+                    ldk::events::PaymentPurpose::Bolt12RefundPayment {
+                        payment_preimage,
+                        payment_secret,
+                        ..
+                    } => (payment_preimage, Some(payment_secret)),
+                    ldk::events::PaymentPurpose::SpontaneousPayment(preimage) => {
+                        (Some(preimage), None)
+                    }
                 };
+                self.channel_manager.update_invoice(
+                    payment_hash,
+                    InvoiceState::Paid,
+                    amount_msat,
+                    payment_preimage,
+                );
                 log::warn!("please note the payments are not make persistent for the moment");
                 // FIXME: make peristent these information
                 Ok(())
             }
-            ldk::events::Event::PaymentSent { .. } => {
+            ldk::events::Event::PaymentSent {
+                payment_hash,
+                payment_preimage,
+                ..
+            } => {
                 log::info!("payment sent: `{:?}`", event);
+                self.channel_manager.update_payment(
+                    payment_hash,
+                    PaymentState::Success,
+                    None,
+                    Some(payment_preimage),
+                );
+                Ok(())
+            }
+            ldk::events::Event::PaymentFailed {
+                payment_hash,
+                reason,
+                ..
+            } => {
+                log::warn!("payment `{payment_hash}` failed: {:?}", reason);
+                self.channel_manager.update_payment(
+                    payment_hash,
+                    PaymentState::Failure,
+                    None,
+                    None,
+                );
+                self.emit(Event::Lightning(LightningEvent::PaymentEvent {
+                    state: PaymentState::Failure,
+                    payment_hash: Some(payment_hash.to_string()),
+                    path: vec![],
+                }));
                 Ok(())
-            },
-            ldk::events::Event::PaymentPathSuccessful { payment_hash, path, .. } => {
-                let path = path.hops.iter().map(|hop| PaymentHop::from(hop.clone())).collect::<Vec<PaymentHop>>();
-                let hop = LightningEvent::PaymentEvent { state: PaymentState::Success, payment_hash: payment_hash.map(|hash| hash.to_string()), path };
+            }
+            ldk::events::Event::PaymentPathFailed {
+                payment_hash,
+                payment_failed_permanently,
+                failure,
+                path,
+                short_channel_id,
+                ..
+            } => {
+                let failure = payment_failure(
+                    payment_hash,
+                    payment_failed_permanently,
+                    &failure,
+                    &path,
+                    short_channel_id,
+                );
+                log::warn!("payment `{payment_hash}` path failed: {:?}", failure);
+                self.channel_manager
+                    .record_payment_failure(payment_hash, failure);
+                Ok(())
+            }
+            // The scorer learns from the probes before we get the event.
+            ldk::events::Event::ProbeSuccessful { path, .. } => {
+                log::debug!("probe through `{:?}` succeeded", path);
+                self.channel_manager.record_probe(&path, true, None);
+                Ok(())
+            }
+            ldk::events::Event::ProbeFailed {
+                path,
+                short_channel_id,
+                ..
+            } => {
+                log::debug!(
+                    "probe through `{:?}` failed at `{:?}`",
+                    path,
+                    short_channel_id
+                );
+                self.channel_manager
+                    .record_probe(&path, false, short_channel_id);
+                Ok(())
+            }
+            ldk::events::Event::PaymentPathSuccessful {
+                payment_hash, path, ..
+            } => {
+                let path = path
+                    .hops
+                    .iter()
+                    .map(|hop| PaymentHop::from(hop.clone()))
+                    .collect::<Vec<PaymentHop>>();
+                if let Some(payment_hash) = payment_hash {
+                    self.channel_manager
+                        .record_payment_part(payment_hash, path.clone());
+                    self.channel_manager.update_payment(
+                        payment_hash,
+                        PaymentState::Success,
+                        Some(path.clone()),
+                        None,
+                    );
+                }
+                let hop = LightningEvent::PaymentEvent {
+                    state: PaymentState::Success,
+                    payment_hash: payment_hash.map(|hash| hash.to_string()),
+                    path,
+                };
                 self.emit(Event::Lightning(hop));
                 Ok(())
-            },
+            }
+            ldk::events::Event::HTLCHandlingFailed {
+                prev_channel_id,
+                failed_next_destination,
+            } => {
+                let Some(error) =
+                    forward_error(&prev_channel_id.to_string(), &failed_next_destination)
+                else {
+                    log::debug!(
+                        "failed to receive an HTLC from channel `{prev_channel_id}`: {:?}",
+                        failed_next_destination
+                    );
+                    return Ok(());
+                };
+                log::warn!(
+                    "failed to forward an HTLC from channel `{prev_channel_id}`: {:?}",
+                    error
+                );
+                self.channel_manager.record_forward_error(error);
+                Ok(())
+            }
+            ldk::events::Event::SpendableOutputs { outputs, .. } => {
+                self.sweep_spendable_outputs(&outputs)
+            }
+            ldk::events::Event::BumpTransaction(BumpTransactionEvent::ChannelClose {
+                channel_id,
+                package_target_feerate_sat_per_1000_weight,
+                commitment_tx,
+                commitment_tx_fee_satoshis,
+                anchor_descriptor,
+                ..
+            }) => {
+                let close = AnchorClose {
+                    commitment_tx,
+                    commitment_tx_fee_sat: commitment_tx_fee_satoshis,
+                    anchor_descriptor,
+                };
+                self.chain_manager
+                    .commitment_bumper
+                    .record(&channel_id.to_string(), close.clone());
+                let feerate = package_target_feerate_sat_per_1000_weight;
+                if close.commitment_feerate() >= feerate as u64 {
+                    log::info!("the commitment of channel `{channel_id}` pays enough, broadcasting it without a child");
+                    self.chain_manager
+                        .broadcast_transactions(&[&close.commitment_tx]);
+                    return Ok(());
+                }
+                self.chain_manager.bump_commitment(&close, feerate)?;
+                Ok(())
+            }
             _ => Err(error::anyhow!("unexpected ldk event: {:?}", event)),
         }
     }
 }
+
+/// Decode the failure of a payment path, LDK does not give back the raw
+/// onion failure code so it is guessed from the network update.
+fn payment_failure(
+    payment_hash: PaymentHash,
+    permanent: bool,
+    failure: &PathFailure,
+    path: &Path,
+    short_channel_id: Option<u64>,
+) -> PaymentFailure {
+    // The node that forwards through the channel at `index` of the path.
+    let forwarding_node = |index: usize| {
+        index
+            .checked_sub(1)
+            .map(|index| path.hops[index].pubkey.to_string())
+    };
+    let channel_index = |scid: u64| {
+        path.hops
+            .iter()
+            .position(|hop| hop.short_channel_id == scid)
+    };
+    let (failure, erring_node, erring_channel) = match failure {
+        PathFailure::InitialSend { err } => {
+            log::debug!("payment `{payment_hash}` not sent: {:?}", err);
+            ("initial_send_failure".to_owned(), None, short_channel_id)
+        }
+        PathFailure::OnPath {
+            network_update: Some(NetworkUpdate::ChannelUpdateMessage { msg }),
+        } => {
+            let update = &msg.contents;
+            let index = channel_index(update.short_channel_id);
+            // The fee and the cltv delta that we gave to the node.
+            let paid = index
+                .and_then(|index| index.checked_sub(1))
+                .map(|index| &path.hops[index]);
+            let amount_msat: u64 = index
+                .map(|index| path.hops[index..].iter().map(|hop| hop.fee_msat).sum())
+                .unwrap_or_default();
+            let required_fee_msat = update.fee_base_msat as u64
+                + amount_msat * update.fee_proportional_millionths as u64 / 1_000_000;
+            let failure = if update.flags & 2 == 2 {
+                "channel_disabled"
+            } else if paid.is_some_and(|hop| hop.fee_msat < required_fee_msat) {
+                "fee_insufficient"
+            } else if paid
+                .is_some_and(|hop| hop.cltv_expiry_delta < update.cltv_expiry_delta as u32)
+            {
+                "incorrect_cltv_expiry"
+            } else if amount_msat < update.htlc_minimum_msat {
+                "amount_below_minimum"
+            } else {
+                "temporary_channel_failure"
+            };
+            (
+                failure.to_owned(),
+                index.and_then(forwarding_node),
+                Some(update.short_channel_id),
+            )
+        }
+        PathFailure::OnPath {
+            network_update:
+                Some(NetworkUpdate::ChannelFailure {
+                    short_channel_id,
+                    is_permanent,
+                }),
+        } => {
+            let failure = if *is_permanent {
+                "permanent_channel_failure"
+            } else {
+                "temporary_channel_failure"
+            };
+            (
+                failure.to_owned(),
+                channel_index(*short_channel_id).and_then(forwarding_node),
+                Some(*short_channel_id),
+            )
+        }
+        PathFailure::OnPath {
+            network_update:
+                Some(NetworkUpdate::NodeFailure {
+                    node_id,
+                    is_permanent,
+                }),
+        } => {
+            let failure = if *is_permanent {
+                "permanent_node_failure"
+            } else {
+                "temporary_node_failure"
+            };
+            (failure.to_owned(), Some(node_id.to_string()), None)
+        }
+        // The destination refused the payment.
+        PathFailure::OnPath {
+            network_update: None,
+        } if permanent => (
+            "incorrect_or_unknown_payment_details".to_owned(),
+            path.hops.last().map(|hop| hop.pubkey.to_string()),
+            None,
+        ),
+        PathFailure::OnPath {
+            network_update: None,
+        } => ("unknown_failure".to_owned(), None, short_channel_id),
+    };
+    PaymentFailure {
+        payment_hash: payment_hash.to_string(),
+        failure,
+        erring_node,
+        erring_channel,
+        permanent,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    }
+}
+
+/// Decode the error that we sent back upstream for an HTLC incoming from
+/// `incoming_channel_id` that we failed to forward, LDK does not give back
+/// the onion failure code so it is guessed from the destination that failed.
+///
+/// Return `None` when we were the destination of the HTLC.
+fn forward_error(incoming_channel_id: &str, destination: &HTLCDestination) -> Option<ForwardError> {
+    let (failure_code, failure, outgoing_channel_id, outgoing_node_id, requested_forward_scid) =
+        match destination {
+            HTLCDestination::NextHopChannel {
+                node_id,
+                channel_id,
+            } => (
+                0x1007,
+                "temporary_channel_failure",
+                Some(channel_id.to_string()),
+                node_id.map(|node_id| node_id.to_string()),
+                None,
+            ),
+            HTLCDestination::UnknownNextHop {
+                requested_forward_scid,
+            } => (
+                0x400a,
+                "unknown_next_peer",
+                None,
+                None,
+                Some(*requested_forward_scid),
+            ),
+            HTLCDestination::InvalidForward {
+                requested_forward_scid,
+            } => (
+                0x4016,
+                "invalid_onion_payload",
+                None,
+                None,
+                Some(*requested_forward_scid),
+            ),
+            HTLCDestination::FailedPayment { .. } => return None,
+        };
+    Some(ForwardError {
+        incoming_channel_id: incoming_channel_id.to_owned(),
+        failure_code,
+        failure: failure.to_owned(),
+        outgoing_channel_id,
+        outgoing_node_id,
+        requested_forward_scid,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    })
+}
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -5,6 +6,8 @@ use lampo_common::backend::Backend;
 use lampo_common::bitcoin;
 use lampo_common::bitcoin::blockdata::constants::ChainHash;
 use lampo_common::bitcoin::Transaction;
+use lampo_common::conf::LampoConf;
+use lampo_common::error;
 use lampo_common::ldk;
 use lampo_common::ldk::chain::chaininterface::{
     BroadcasterInterface, ConfirmationTarget, FeeEstimator,
@@ -13,10 +16,35 @@ use lampo_common::ldk::chain::Filter;
 use lampo_common::ldk::routing::utxo::UtxoLookup;
 use lampo_common::wallet::WalletManager;
 
+use super::bump::{AnchorClose, CommitmentBumper};
+use super::feerate::FeerateFloor;
+use super::rebroadcast::Rebroadcaster;
+use super::sweep;
+
+/// The feerate floor that LDK applies to every fee estimation.
+pub(crate) const FEERATE_FLOOR_SATS_PER_KW: u32 = 253;
+
+thread_local! {
+    /// Feerate returned for the commitment transactions to the thread
+    /// that is creating a channel with a custom feerate, so the other
+    /// opens and the fee updates keep the estimated one.
+    static COMMITMENT_FEERATE: Cell<Option<u32>> = Cell::new(None);
+}
+
 #[derive(Clone)]
 pub struct LampoChainManager {
     pub backend: Arc<dyn Backend>,
     pub wallet_manager: Arc<dyn WalletManager>,
+    /// The transactions that we broadcast and that are not
+    /// confirmed yet.
+    pub rebroadcaster: Arc<Rebroadcaster>,
+    /// The lowest feerate that we use, from the
+    /// min relay fee of the backend.
+    pub feerate_floor: Arc<FeerateFloor>,
+    /// The commitments of the anchor channels to bump.
+    pub commitment_bumper: Arc<CommitmentBumper>,
+    /// The backend deletes the old blocks.
+    pub backend_pruned: bool,
 }
 
 /// Personal Lampo implementation
@@ -25,11 +53,60 @@ impl LampoChainManager {
     /// Backend.
     pub fn new(client: Arc<dyn Backend>, wallet_manager: Arc<dyn WalletManager>) -> Self {
         LampoChainManager {
+            rebroadcaster: Arc::new(Rebroadcaster::new(client.clone())),
+            feerate_floor: Arc::new(FeerateFloor::new(client.clone())),
+            commitment_bumper: Arc::new(CommitmentBumper::new(wallet_manager.clone())),
             backend: client,
             wallet_manager,
+            backend_pruned: false,
         }
     }
 
+    /// Run `f` while the commitment feerate estimation returns
+    /// `feerate` on the current thread, LDK read it when the
+    /// channel is created.
+    // FIXME: LDK will update the fee of the channel to the
+    // estimated one at the next timer tick.
+    pub fn with_commitment_feerate<R>(&self, feerate: u32, f: impl FnOnce() -> R) -> R {
+        COMMITMENT_FEERATE.with(|commitment| commitment.set(Some(feerate)));
+        let result = f();
+        COMMITMENT_FEERATE.with(|commitment| commitment.set(None));
+        result
+    }
+
+    /// Resolve the feerate used to sweep our outputs, from the
+    /// estimation at the configured confirmation target or from the
+    /// override in the configuration.
+    pub fn sweep_feerate(&self, conf: &LampoConf) -> u32 {
+        let estimate = self
+            .backend
+            .fee_rate_estimation(conf.sweep_confirmation_target.into())
+            .map_err(|err| log::warn!("impossible estimate the sweep feerate: {err}"))
+            .ok();
+        self.feerate_floor
+            .apply(sweep::resolve_sweep_feerate(conf, estimate))
+    }
+
+    /// Broadcast the commitment of an anchor channel with a child that
+    /// brings the package to `feerate` sats per kw, and return the
+    /// child with its fee in sats.
+    pub fn bump_commitment(
+        &self,
+        close: &AnchorClose,
+        feerate: u32,
+    ) -> error::Result<(Transaction, u64)> {
+        // The wallet needs the commitment inside the mempool to spend its anchor.
+        self.broadcast_transactions(&[&close.commitment_tx]);
+        let (child, fee_sat) = self.commitment_bumper.create_child(close, feerate)?;
+        log::info!(
+            "broadcasting the child `{}` of commitment `{}` with feerate `{feerate}` sats per kw",
+            child.txid(),
+            close.commitment_tx.txid()
+        );
+        self.broadcast_transactions(&[&child]);
+        Ok((child, fee_sat))
+    }
+
     pub fn is_lightway(&self) -> bool {
         self.backend.is_lightway()
     }
@@ -74,33 +151,39 @@ impl LampoChainManager {
 impl FeeEstimator for LampoChainManager {
     fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
         //FIXME: use cache to avoid return default value (that is 0) on u32
-        match confirmation_target {
+        let feerate = match confirmation_target {
             ConfirmationTarget::OnChainSweep => {
                 self.backend.fee_rate_estimation(1).unwrap_or_default()
             }
-            ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee
-            | ConfirmationTarget::AnchorChannelFee
-            | ConfirmationTarget::NonAnchorChannelFee => {
-                self.backend.fee_rate_estimation(6).unwrap_or_default()
+            ConfirmationTarget::AnchorChannelFee | ConfirmationTarget::NonAnchorChannelFee => {
+                match COMMITMENT_FEERATE.with(Cell::get) {
+                    Some(feerate) => feerate,
+                    None => self.backend.fee_rate_estimation(6).unwrap_or_default(),
+                }
             }
-            ConfirmationTarget::MinAllowedAnchorChannelRemoteFee => {
-                self.backend.minimum_mempool_fee().unwrap()
+            ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee => {
+                self.backend.fee_rate_estimation(6).unwrap_or_default()
             }
+            ConfirmationTarget::MinAllowedAnchorChannelRemoteFee => self.feerate_floor.get(),
             ConfirmationTarget::ChannelCloseMinimum => {
                 self.backend.fee_rate_estimation(100).unwrap_or_default()
             }
             ConfirmationTarget::OutputSpendingFee => {
                 self.backend.fee_rate_estimation(12).unwrap_or_default()
             }
-        }
+        };
+        // Not logged, LDK asks the estimations at every timer tick.
+        feerate.max(self.feerate_floor.get())
     }
 }
 
 /// Brodcaster Interface implementation for Lampo.
 impl BroadcasterInterface for LampoChainManager {
-    fn broadcast_transactions(&self, tx: &[&Transaction]) {
-        // FIXME: change the brodcasting
-        self.backend.brodcast_tx(tx.first().unwrap());
+    fn broadcast_transactions(&self, txs: &[&Transaction]) {
+        for tx in txs {
+            self.backend.brodcast_tx(tx);
+            self.rebroadcaster.track(tx);
+        }
     }
 }
 
@@ -0,0 +1,125 @@
+//! Child pays for parent of the commitment transactions
+//! of the anchor channels.
+//!
+//! The commitment of an anchor channel can pay a feerate lower than
+//! the one needed to confirm, so a child that spends our anchor output
+//! together with the outputs of the wallet pays the fee of both.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use lampo_common::bitcoin::secp256k1::Secp256k1;
+use lampo_common::bitcoin::Transaction;
+use lampo_common::error;
+use lampo_common::ldk::events::bump_transaction::AnchorDescriptor;
+use lampo_common::ldk::ln::chan_utils::ANCHOR_INPUT_WITNESS_WEIGHT;
+use lampo_common::ldk::sign::ecdsa::EcdsaChannelSigner;
+use lampo_common::ldk::sign::InMemorySigner;
+use lampo_common::wallet::{ExternalInput, WalletManager};
+
+/// The weight of the input that spends the anchor output, the
+/// outpoint, the empty script sig, the sequence and the witness.
+const ANCHOR_INPUT_WEIGHT: u64 = 4 * (32 + 4 + 1 + 4) + ANCHOR_INPUT_WITNESS_WEIGHT;
+
+/// How many times the child is funded again when the wallet
+/// inputs make it heavier than expected.
+const MAX_FUNDING_ROUNDS: usize = 3;
+
+/// The commitment of a force closed anchor channel.
+#[derive(Clone, Debug)]
+pub struct AnchorClose {
+    pub commitment_tx: Transaction,
+    pub commitment_tx_fee_sat: u64,
+    pub anchor_descriptor: AnchorDescriptor,
+}
+
+impl AnchorClose {
+    /// The feerate in sats per kw of the commitment alone.
+    pub fn commitment_feerate(&self) -> u64 {
+        self.commitment_tx_fee_sat * 1000 / self.commitment_tx.weight().to_wu()
+    }
+
+    /// The feerate in sats per kw of the commitment
+    /// with a child that pays `child_fee_sat`.
+    pub fn package_feerate(&self, child: &Transaction, child_fee_sat: u64) -> u64 {
+        (self.commitment_tx_fee_sat + child_fee_sat) * 1000
+            / (self.commitment_tx.weight().to_wu() + child.weight().to_wu())
+    }
+}
+
+/// The commitments of the anchor channels that LDK asked to bump,
+/// indexed by the channel id.
+pub struct CommitmentBumper {
+    wallet: Arc<dyn WalletManager>,
+    closes: Mutex<HashMap<String, AnchorClose>>,
+}
+
+impl CommitmentBumper {
+    pub fn new(wallet: Arc<dyn WalletManager>) -> Self {
+        Self {
+            wallet,
+            closes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Store the latest commitment of the channel.
+    pub fn record(&self, channel_id: &str, close: AnchorClose) {
+        // SAFETY: the lock can not be poisoned.
+        self.closes
+            .lock()
+            .unwrap()
+            .insert(channel_id.to_owned(), close);
+    }
+
+    pub fn anchor_close(&self, channel_id: &str) -> Option<AnchorClose> {
+        // SAFETY: the lock can not be poisoned.
+        self.closes.lock().unwrap().get(channel_id).cloned()
+    }
+
+    /// Create the child that brings the package of the commitment and the
+    /// child to `feerate` sats per kw, and return it with its fee in sats.
+    pub fn create_child(
+        &self,
+        close: &AnchorClose,
+        feerate: u32,
+    ) -> error::Result<(Transaction, u64)> {
+        let anchor = ExternalInput {
+            outpoint: close.anchor_descriptor.outpoint,
+            value_sat: close.anchor_descriptor.previous_utxo().value,
+            weight: ANCHOR_INPUT_WEIGHT,
+        };
+        let parent_weight = close.commitment_tx.weight().to_wu();
+        let mut child_feerate = feerate;
+        for _ in 0..MAX_FUNDING_ROUNDS {
+            let (mut child, fee_sat) = self
+                .wallet
+                .fund_external_inputs(&[anchor.clone()], child_feerate)?;
+            // The anchor input is not signed yet.
+            let child_weight = child.weight().to_wu() + ANCHOR_INPUT_WITNESS_WEIGHT;
+            let target_fee_sat = feerate as u64 * (parent_weight + child_weight) / 1000;
+            if close.commitment_tx_fee_sat + fee_sat >= target_fee_sat {
+                self.sign_anchor(close, &mut child)?;
+                return Ok((child, fee_sat));
+            }
+            self.wallet.release_transaction(&child)?;
+            let missing_fee_sat = target_fee_sat - close.commitment_tx_fee_sat;
+            child_feerate = (missing_fee_sat * 1000).div_ceil(child_weight) as u32;
+        }
+        error::bail!("impossible fund the child of the commitment at feerate `{feerate}`")
+    }
+
+    fn sign_anchor(&self, close: &AnchorClose, child: &mut Transaction) -> error::Result<()> {
+        let descriptor = &close.anchor_descriptor;
+        let index = child
+            .input
+            .iter()
+            .position(|input| input.previous_output == descriptor.outpoint)
+            .ok_or(error::anyhow!("the child does not spend the anchor output"))?;
+        let keys = self.wallet.ldk_keys();
+        let signer: InMemorySigner = descriptor.derive_channel_signer(&keys.keys_manager);
+        let signature = signer
+            .sign_holder_anchor_input(child, index, &Secp256k1::new())
+            .map_err(|_| error::anyhow!("impossible sign the anchor input"))?;
+        child.input[index].witness = descriptor.tx_input_witness(&signature);
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! Estimate of the blocks that a transaction inside the
+//! mempool waits before being confirmed.
+
+/// The virtual size of the transactions that fit inside a block.
+pub const BLOCK_VSIZE: u64 = 1_000_000;
+
+/// The position of a transaction inside the mempool, where the
+/// transactions are ordered by feerate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolPosition {
+    /// How many transactions pay a better feerate.
+    pub txs_ahead: usize,
+    /// The virtual size of the transactions that pay a better feerate.
+    pub vsize_ahead: u64,
+}
+
+/// Find the position of a transaction that pays `fee_rate` inside
+/// the `(fee_rate, vsize)` entries of the mempool.
+pub fn mempool_position(fee_rate: u32, histogram: &[(u32, u64)]) -> MempoolPosition {
+    histogram
+        .iter()
+        .filter(|(entry_fee_rate, _)| *entry_fee_rate > fee_rate)
+        .fold(
+            MempoolPosition {
+                txs_ahead: 0,
+                vsize_ahead: 0,
+            },
+            |position, (_, vsize)| MempoolPosition {
+                txs_ahead: position.txs_ahead + 1,
+                vsize_ahead: position.vsize_ahead + vsize,
+            },
+        )
+}
+
+/// How many blocks the transaction of `vsize` waits at the `position`,
+/// assuming that the miners fill the blocks with the best feerates and
+/// that no better transaction enters the mempool.
+pub fn eta_blocks(position: &MempoolPosition, vsize: u64) -> u32 {
+    (position.vsize_ahead + vsize).div_ceil(BLOCK_VSIZE).max(1) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{eta_blocks, mempool_position, BLOCK_VSIZE};
+
+    #[test]
+    fn low_fee_waits_more_blocks() {
+        // Three blocks of transactions at 5000 sats per kw, and
+        // some small transaction at 1000 sats per kw.
+        let mut histogram = vec![(5_000, BLOCK_VSIZE / 10); 30];
+        histogram.extend([(1_000, 200); 10]);
+
+        let high = mempool_position(10_000, &histogram);
+        assert_eq!(high.txs_ahead, 0);
+        assert_eq!(eta_blocks(&high, 200), 1);
+
+        let low = mempool_position(2_000, &histogram);
+        assert_eq!(low.txs_ahead, 30);
+        assert_eq!(low.vsize_ahead, 3 * BLOCK_VSIZE);
+        assert_eq!(eta_blocks(&low, 200), 4);
+    }
+}
@@ -0,0 +1,97 @@
+//! Feerate floor derived from the min relay fee of the backend,
+//! a transaction below it is not relayed by the network.
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use lampo_common::backend::Backend;
+use lampo_common::error;
+
+use super::FEERATE_FLOOR_SATS_PER_KW;
+
+pub struct FeerateFloor {
+    backend: Arc<dyn Backend>,
+    /// The floor in sats per kw.
+    floor: AtomicU32,
+}
+
+impl FeerateFloor {
+    pub fn new(backend: Arc<dyn Backend>) -> Self {
+        Self {
+            backend,
+            floor: AtomicU32::new(FEERATE_FLOOR_SATS_PER_KW),
+        }
+    }
+
+    /// Ask the min relay fee to the backend and use it as floor,
+    /// but never below the LDK floor.
+    pub fn refresh(&self) -> error::Result<u32> {
+        let floor = self
+            .backend
+            .minimum_mempool_fee()?
+            .max(FEERATE_FLOOR_SATS_PER_KW);
+        if self.floor.swap(floor, Ordering::SeqCst) != floor {
+            log::info!(target: "lampo", "feerate floor set to {floor} sats per kw by the min relay fee of the backend");
+        }
+        Ok(floor)
+    }
+
+    /// The floor in sats per kw.
+    pub fn get(&self) -> u32 {
+        self.floor.load(Ordering::SeqCst)
+    }
+
+    /// Raise `feerate` to the floor when it is below it.
+    pub fn apply(&self, feerate: u32) -> u32 {
+        let floor = self.get();
+        if feerate < floor {
+            log::info!(target: "lampo", "feerate of {feerate} sats per kw is below the min relay fee, using {floor} sats per kw");
+            return floor;
+        }
+        feerate
+    }
+
+    /// Spawn a thread that refreshes the floor every `interval`.
+    pub fn spawn(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            if let Err(err) = self.refresh() {
+                log::warn!(target: "lampo", "impossible to get the min relay fee of the backend: {err}");
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::FeerateFloor;
+    use crate::chain::mock::MockBackend;
+    use crate::chain::FEERATE_FLOOR_SATS_PER_KW;
+
+    /// 1 sat/vB in sats per kw.
+    const SAT_PER_VB: u32 = 250;
+
+    #[test]
+    fn feerate_is_bumped_to_the_min_relay_fee() {
+        let backend = Arc::new(MockBackend::default());
+        *backend.min_relay_feerate.lock().unwrap() = 2 * SAT_PER_VB;
+        let floor = FeerateFloor::new(backend.clone());
+        assert_eq!(floor.get(), FEERATE_FLOOR_SATS_PER_KW);
+
+        assert_eq!(floor.refresh().unwrap(), 2 * SAT_PER_VB);
+        assert_eq!(floor.apply(SAT_PER_VB), 2 * SAT_PER_VB);
+        assert_eq!(floor.apply(5 * SAT_PER_VB), 5 * SAT_PER_VB);
+    }
+
+    #[test]
+    fn floor_is_never_below_the_ldk_floor() {
+        let backend = Arc::new(MockBackend::default());
+        *backend.min_relay_feerate.lock().unwrap() = 0;
+        let floor = FeerateFloor::new(backend);
+        assert_eq!(floor.refresh().unwrap(), FEERATE_FLOOR_SATS_PER_KW);
+        assert_eq!(floor.apply(0), FEERATE_FLOOR_SATS_PER_KW);
+    }
+}
@@ -0,0 +1,90 @@
+//! Verify that the funding output of a channel on chain
+//! carries the capacity that the channel claims.
+use std::fmt;
+
+use lampo_common::bitcoin::Transaction;
+
+/// The mismatch between the funding transaction on chain and the channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FundingDiscrepancy {
+    /// The funding transaction has no output at the funding index.
+    MissingOutput { vout: u16 },
+    /// The value of the funding output is not the channel capacity.
+    Value { output_sat: u64, capacity_sat: u64 },
+}
+
+impl fmt::Display for FundingDiscrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingOutput { vout } => {
+                write!(f, "the funding transaction has no output {vout}")
+            }
+            Self::Value {
+                output_sat,
+                capacity_sat,
+            } => write!(
+                f,
+                "the funding output has {output_sat} sats but the channel capacity is {capacity_sat} sats"
+            ),
+        }
+    }
+}
+
+/// Check that the output `vout` of the funding `tx` is worth `capacity_sat`,
+/// and return the value of the output.
+pub fn verify_funding_output(
+    tx: &Transaction,
+    vout: u16,
+    capacity_sat: u64,
+) -> Result<u64, FundingDiscrepancy> {
+    let output = tx
+        .output
+        .get(vout as usize)
+        .ok_or(FundingDiscrepancy::MissingOutput { vout })?;
+    if output.value != capacity_sat {
+        return Err(FundingDiscrepancy::Value {
+            output_sat: output.value,
+            capacity_sat,
+        });
+    }
+    Ok(output.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use lampo_common::bitcoin::absolute::LockTime;
+    use lampo_common::bitcoin::{ScriptBuf, Transaction, TxOut};
+
+    use super::{verify_funding_output, FundingDiscrepancy};
+
+    #[test]
+    fn funding_output_mismatch() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: ScriptBuf::new(),
+                },
+                TxOut {
+                    value: 500_000,
+                    script_pubkey: ScriptBuf::new(),
+                },
+            ],
+        };
+        assert_eq!(verify_funding_output(&tx, 1, 500_000), Ok(500_000));
+        assert_eq!(
+            verify_funding_output(&tx, 0, 500_000),
+            Err(FundingDiscrepancy::Value {
+                output_sat: 10_000,
+                capacity_sat: 500_000,
+            })
+        );
+        assert_eq!(
+            verify_funding_output(&tx, 2, 500_000),
+            Err(FundingDiscrepancy::MissingOutput { vout: 2 })
+        );
+    }
+}
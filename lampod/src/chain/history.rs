@@ -0,0 +1,135 @@
+//! Classify the transactions of the wallet, so the history
+//! tells apart the payments from the moves between our outputs.
+use std::collections::HashSet;
+
+use lampo_common::bitcoin::OutPoint;
+use lampo_common::error;
+use lampo_common::model::response::{HistoryTx, TxHistory};
+use lampo_common::types::TxKind;
+use lampo_common::wallet::{WalletManager, WalletTransaction};
+
+/// How `wallet_tx` moves the funds, `funding_points` are the
+/// funding outputs of our channels, open or closed.
+pub fn classify(wallet_tx: &WalletTransaction, funding_points: &HashSet<OutPoint>) -> TxKind {
+    let tx = &wallet_tx.tx;
+    if tx
+        .input
+        .iter()
+        .any(|input| funding_points.contains(&input.previous_output))
+    {
+        return TxKind::ChannelClose;
+    }
+    let txid = tx.txid();
+    if (0..tx.output.len() as u32).any(|vout| funding_points.contains(&OutPoint::new(txid, vout))) {
+        return TxKind::ChannelFunding;
+    }
+    let all_ours = |ours: &[bool]| !ours.is_empty() && ours.iter().all(|ours| *ours);
+    if all_ours(&wallet_tx.our_inputs) && all_ours(&wallet_tx.our_outputs) {
+        return TxKind::SelfTransfer;
+    }
+    if wallet_tx.sent_sat > 0 {
+        TxKind::Send
+    } else {
+        TxKind::Receive
+    }
+}
+
+/// The transactions of the `wallet`, only the ones of `kind` when it is specified.
+pub fn tx_history(
+    wallet: &dyn WalletManager,
+    funding_points: &HashSet<OutPoint>,
+    kind: Option<TxKind>,
+) -> error::Result<TxHistory> {
+    let transactions = wallet
+        .transaction_history()?
+        .into_iter()
+        .map(|wallet_tx| HistoryTx {
+            txid: wallet_tx.tx.txid().to_string(),
+            kind: classify(&wallet_tx, funding_points),
+            height: wallet_tx.height,
+            sent_sat: wallet_tx.sent_sat,
+            received_sat: wallet_tx.received_sat(),
+            fee_sat: wallet_tx.fee_sat(),
+        })
+        .filter(|tx| kind.map_or(true, |kind| tx.kind == kind))
+        .collect();
+    Ok(TxHistory { transactions })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use lampo_common::bitcoin::absolute::LockTime;
+    use lampo_common::bitcoin::hashes::Hash;
+    use lampo_common::bitcoin::{
+        OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+    };
+    use lampo_common::types::TxKind;
+    use lampo_common::wallet::WalletTransaction;
+
+    use super::classify;
+
+    fn tx(inputs: u8, outputs: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: (0..inputs)
+                .map(|n| TxIn {
+                    previous_output: OutPoint::new(Txid::from_byte_array([n; 32]), 0),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: (0..outputs)
+                .map(|value| TxOut {
+                    value: 10_000 + value,
+                    script_pubkey: ScriptBuf::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn consolidation_is_a_self_transfer() {
+        // Three of our outputs spent into a single output of the wallet.
+        let consolidation = WalletTransaction {
+            tx: tx(3, 1),
+            height: Some(101),
+            our_inputs: vec![true; 3],
+            our_outputs: vec![true],
+            sent_sat: 31_000,
+        };
+        let funding_points = HashSet::new();
+        assert_eq!(
+            classify(&consolidation, &funding_points),
+            TxKind::SelfTransfer
+        );
+        assert_eq!(consolidation.fee_sat(), Some(21_000));
+
+        // The same inputs that pay also somebody else.
+        let send = WalletTransaction {
+            tx: tx(3, 2),
+            our_outputs: vec![true, false],
+            ..consolidation.clone()
+        };
+        assert_eq!(classify(&send, &funding_points), TxKind::Send);
+
+        let receive = WalletTransaction {
+            tx: tx(1, 2),
+            height: None,
+            our_inputs: vec![false],
+            our_outputs: vec![false, true],
+            sent_sat: 0,
+        };
+        assert_eq!(classify(&receive, &funding_points), TxKind::Receive);
+        assert_eq!(receive.fee_sat(), None);
+
+        // The output of the consolidation funds a channel.
+        let funding = HashSet::from([OutPoint::new(consolidation.tx.txid(), 0)]);
+        assert_eq!(classify(&consolidation, &funding), TxKind::ChannelFunding);
+        let close = HashSet::from([consolidation.tx.input[1].previous_output]);
+        assert_eq!(classify(&consolidation, &close), TxKind::ChannelClose);
+    }
+}
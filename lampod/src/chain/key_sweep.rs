@@ -0,0 +1,221 @@
+//! Sweep of the outputs locked by an external private key,
+//! e.g. the key of a paper wallet.
+//!
+//! The key is used only to sign the sweep, it is never stored.
+use lampo_common::bitcoin::blockdata::script::{Builder, PushBytesBuf};
+use lampo_common::bitcoin::ecdsa::Signature;
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::bitcoin::secp256k1::{Message, Secp256k1, Signing};
+use lampo_common::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use lampo_common::bitcoin::{
+    absolute::LockTime, OutPoint, PrivateKey, PublicKey, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Witness,
+};
+use lampo_common::error;
+
+/// The scripts that an output locked by the key can have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyScript {
+    P2pkh,
+    P2wpkh,
+    P2shP2wpkh,
+}
+
+impl KeyScript {
+    /// The scripts of the key, the segwit ones only for compressed keys.
+    fn of(public_key: &PublicKey) -> Vec<Self> {
+        if public_key.compressed {
+            vec![Self::P2wpkh, Self::P2shP2wpkh, Self::P2pkh]
+        } else {
+            vec![Self::P2pkh]
+        }
+    }
+
+    fn script_pubkey(&self, public_key: &PublicKey) -> ScriptBuf {
+        match self {
+            Self::P2pkh => ScriptBuf::new_p2pkh(&public_key.pubkey_hash()),
+            // SAFETY: the segwit scripts are used only with the compressed keys.
+            Self::P2wpkh => ScriptBuf::new_v0_p2wpkh(&public_key.wpubkey_hash().unwrap()),
+            Self::P2shP2wpkh => {
+                ScriptBuf::new_p2sh(&Self::P2wpkh.script_pubkey(public_key).script_hash())
+            }
+        }
+    }
+}
+
+/// The scripts of the outputs that the key can spend.
+pub fn key_scripts<C: Signing>(secp: &Secp256k1<C>, key: &PrivateKey) -> Vec<ScriptBuf> {
+    let public_key = key.public_key(secp);
+    KeyScript::of(&public_key)
+        .iter()
+        .map(|script| script.script_pubkey(&public_key))
+        .collect()
+}
+
+/// Create the transaction that spends all the `utxos` of the key
+/// to `destination` paying `feerate` sats per kw, and return it
+/// with its fee in sats.
+pub fn create_sweep_transaction<C: Signing>(
+    secp: &Secp256k1<C>,
+    key: &PrivateKey,
+    utxos: &[(OutPoint, TxOut)],
+    destination: ScriptBuf,
+    feerate: u32,
+) -> error::Result<(Transaction, u64)> {
+    if utxos.is_empty() {
+        error::bail!("there are no outputs to sweep");
+    }
+    let public_key = key.public_key(secp);
+    let scripts = KeyScript::of(&public_key)
+        .into_iter()
+        .map(|script| (script.script_pubkey(&public_key), script))
+        .collect::<Vec<_>>();
+    let kinds = utxos
+        .iter()
+        .map(|(outpoint, output)| {
+            scripts
+                .iter()
+                .find(|(script, _)| *script == output.script_pubkey)
+                .map(|(_, kind)| *kind)
+                .ok_or_else(|| error::anyhow!("the output `{outpoint}` is not locked by the key"))
+        })
+        .collect::<error::Result<Vec<_>>>()?;
+    let amount = utxos.iter().map(|(_, output)| output.value).sum::<u64>();
+    let mut tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: utxos
+            .iter()
+            .map(|(outpoint, _)| TxIn {
+                previous_output: *outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: amount,
+            script_pubkey: destination,
+        }],
+    };
+    // Signed once to know the weight, the signatures can
+    // be one byte longer when they are signed again.
+    sign(secp, key, &mut tx, utxos, &kinds)?;
+    let weight = tx.weight().to_wu() + 4 * tx.input.len() as u64;
+    let fee = (feerate as u64 * weight).div_ceil(1000);
+    let dust = tx.output[0].script_pubkey.dust_value().to_sat();
+    if amount < fee + dust {
+        error::bail!(
+            "the {amount} sats of the key minus the fee of {fee} sats are below the dust limit of {dust} sats"
+        );
+    }
+    tx.output[0].value = amount - fee;
+    sign(secp, key, &mut tx, utxos, &kinds)?;
+    Ok((tx, fee))
+}
+
+fn sign<C: Signing>(
+    secp: &Secp256k1<C>,
+    key: &PrivateKey,
+    tx: &mut Transaction,
+    utxos: &[(OutPoint, TxOut)],
+    kinds: &[KeyScript],
+) -> error::Result<()> {
+    let public_key = key.public_key(secp);
+    let unsigned = tx.clone();
+    let mut cache = SighashCache::new(&unsigned);
+    for (index, ((_, output), kind)) in utxos.iter().zip(kinds).enumerate() {
+        let sighash = match kind {
+            KeyScript::P2pkh => cache
+                .legacy_signature_hash(
+                    index,
+                    &output.script_pubkey,
+                    EcdsaSighashType::All.to_u32(),
+                )?
+                .to_byte_array(),
+            KeyScript::P2wpkh | KeyScript::P2shP2wpkh => cache
+                .segwit_signature_hash(
+                    index,
+                    &KeyScript::P2pkh.script_pubkey(&public_key),
+                    output.value,
+                    EcdsaSighashType::All,
+                )?
+                .to_byte_array(),
+        };
+        let signature =
+            Signature::sighash_all(secp.sign_ecdsa(&Message::from_slice(&sighash)?, &key.inner))
+                .to_vec();
+        let input = &mut tx.input[index];
+        match kind {
+            KeyScript::P2pkh => {
+                input.script_sig = Builder::new()
+                    .push_slice(PushBytesBuf::try_from(signature)?)
+                    .push_key(&public_key)
+                    .into_script();
+            }
+            KeyScript::P2wpkh | KeyScript::P2shP2wpkh => {
+                if *kind == KeyScript::P2shP2wpkh {
+                    let redeem_script = KeyScript::P2wpkh.script_pubkey(&public_key);
+                    input.script_sig = Builder::new()
+                        .push_slice(PushBytesBuf::try_from(redeem_script.into_bytes())?)
+                        .into_script();
+                }
+                input.witness = Witness::from_slice(&[signature, public_key.to_bytes()]);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lampo_common::bitcoin::hashes::Hash;
+    use lampo_common::bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use lampo_common::bitcoin::{Network, OutPoint, PrivateKey, TxOut, Txid};
+
+    use super::{create_sweep_transaction, key_scripts};
+
+    #[test]
+    fn sweep_pays_the_feerate() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::new(
+            SecretKey::from_slice(&[0x11; 32]).unwrap(),
+            Network::Regtest,
+        );
+        let scripts = key_scripts(&secp, &key);
+        assert_eq!(scripts.len(), 3);
+        let utxos = scripts
+            .iter()
+            .enumerate()
+            .map(|(vout, script)| {
+                (
+                    OutPoint::new(Txid::all_zeros(), vout as u32),
+                    TxOut {
+                        value: 50_000,
+                        script_pubkey: script.clone(),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let destination = scripts[0].clone();
+        let (tx, fee) = create_sweep_transaction(&secp, &key, &utxos, destination, 2_000).unwrap();
+        assert_eq!(tx.input.len(), 3);
+        assert_eq!(tx.output[0].value, 150_000 - fee);
+        // The legacy input has only the script sig.
+        assert!(tx.input[2].witness.is_empty());
+        assert!(!tx.input[0].witness.is_empty());
+        assert!(!tx.input[1].script_sig.is_empty());
+        let paid = fee * 1000 / tx.weight().to_wu();
+        assert!(paid >= 2_000, "paid {paid}");
+
+        // The dust can not pay the fee.
+        let utxos = vec![(
+            OutPoint::new(Txid::all_zeros(), 0),
+            TxOut {
+                value: 500,
+                script_pubkey: scripts[0].clone(),
+            },
+        )];
+        assert!(create_sweep_transaction(&secp, &key, &utxos, scripts[0].clone(), 2_000).is_err());
+    }
+}
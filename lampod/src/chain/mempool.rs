@@ -0,0 +1,78 @@
+//! The mempool info of the backend, with the same
+//! shape for all the backends.
+use lampo_common::backend::Backend;
+use lampo_common::error;
+use lampo_common::model::response::{FeeHistogramBucket, MempoolInfo};
+
+/// The min feerates in sats per vbyte of the fee histogram buckets.
+const BUCKETS_SAT_PER_VB: [u32; 18] = [
+    0, 1, 2, 3, 4, 5, 6, 8, 10, 12, 15, 20, 30, 50, 75, 100, 200, 500,
+];
+
+/// Group the `(fee_rate, vsize)` entries of the mempool
+/// in buckets, starting from the best feerate.
+pub fn fee_histogram(entries: &[(u32, u64)]) -> Vec<FeeHistogramBucket> {
+    let mut buckets = BUCKETS_SAT_PER_VB
+        .iter()
+        .rev()
+        .map(|sat_per_vb| FeeHistogramBucket {
+            // 250 vbytes per kw.
+            fee_rate: sat_per_vb * 250,
+            count: 0,
+            vsize: 0,
+        })
+        .collect::<Vec<_>>();
+    for (fee_rate, vsize) in entries {
+        // The last bucket starts from zero, so every entry has one.
+        if let Some(bucket) = buckets
+            .iter_mut()
+            .find(|bucket| bucket.fee_rate <= *fee_rate)
+        {
+            bucket.count += 1;
+            bucket.vsize += vsize;
+        }
+    }
+    buckets.retain(|bucket| bucket.count > 0);
+    buckets
+}
+
+pub fn mempool_info(backend: &dyn Backend) -> error::Result<MempoolInfo> {
+    let stats = backend.mempool_stats()?;
+    Ok(MempoolInfo {
+        size: stats.size,
+        bytes: stats.bytes,
+        min_relay_fee: stats.min_relay_fee,
+        fee_histogram: fee_histogram(&backend.fee_histogram()?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use lampo_common::backend::MempoolStats;
+
+    use super::mempool_info;
+    use crate::chain::mock::MockBackend;
+
+    #[test]
+    fn mempool_info_of_the_backend() {
+        let backend = MockBackend::default();
+        *backend.mempool_stats.lock().unwrap() = MempoolStats {
+            size: 4,
+            bytes: 1_000,
+            min_relay_fee: 253,
+        };
+        *backend.fee_histogram.lock().unwrap() =
+            vec![(300, 250), (2_600, 250), (2_500, 400), (100, 100)];
+
+        let info = mempool_info(&backend).unwrap();
+        assert_eq!(info.min_relay_fee, 253);
+        assert_eq!(info.size, 4);
+        assert_eq!(info.bytes, 1_000);
+        let buckets = info
+            .fee_histogram
+            .iter()
+            .map(|bucket| (bucket.fee_rate, bucket.count, bucket.vsize))
+            .collect::<Vec<_>>();
+        assert_eq!(buckets, vec![(2_500, 2, 650), (250, 1, 250), (0, 1, 100)]);
+    }
+}
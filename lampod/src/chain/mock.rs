@@ -0,0 +1,154 @@
+//! Backend used by the unit tests of the chain module.
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use lampo_common::backend::{
+    get_block_or_archived, AsyncBlockSourceResult, Backend, BackendKind, Block, BlockArchive,
+    BlockData, BlockHash, BlockHeaderData, BroadcastStatus, MempoolStats, OutPoint, Script,
+    ScriptBuf, SyncProgress, TxOut, TxResult, TxStatus, UtxoResult, WatchedOutput,
+};
+use lampo_common::bitcoin::{Transaction, Txid};
+use lampo_common::error;
+
+/// Backend that returns the statuses in order, and
+/// records the transactions broadcast.
+#[derive(Default)]
+pub struct MockBackend {
+    pub statuses: Mutex<Vec<BroadcastStatus>>,
+    pub broadcast: Mutex<Vec<Txid>>,
+    /// The min relay fee in sats per kw.
+    pub min_relay_feerate: Mutex<u32>,
+    /// The sync progresses returned in order.
+    pub sync_progress: Mutex<Vec<SyncProgress>>,
+    pub mempool_stats: Mutex<MempoolStats>,
+    /// The `(fee_rate, vsize)` of the transactions inside the mempool.
+    pub fee_histogram: Mutex<Vec<(u32, u64)>>,
+    pub pruned: bool,
+    /// The blocks that the backend did not prune.
+    pub blocks: Mutex<Vec<Block>>,
+    pub block_archive: Mutex<Option<Arc<dyn BlockArchive>>>,
+}
+
+impl Backend for MockBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Core
+    }
+
+    fn fee_rate_estimation(&self, _: u64) -> error::Result<u32> {
+        unimplemented!()
+    }
+
+    fn minimum_mempool_fee(&self) -> error::Result<u32> {
+        Ok(*self.min_relay_feerate.lock().unwrap())
+    }
+
+    fn brodcast_tx(&self, tx: &Transaction) {
+        self.broadcast.lock().unwrap().push(tx.txid());
+    }
+
+    fn is_lightway(&self) -> bool {
+        false
+    }
+
+    fn watch_utxo(&self, _: &Txid, _: &Script) {
+        unimplemented!()
+    }
+
+    fn register_output(&self, _: WatchedOutput) -> Option<(usize, Transaction)> {
+        unimplemented!()
+    }
+
+    fn get_header<'a>(
+        &'a self,
+        _: &'a BlockHash,
+        _: Option<u32>,
+    ) -> AsyncBlockSourceResult<'a, BlockHeaderData> {
+        unimplemented!()
+    }
+
+    fn get_block<'a>(&'a self, header_hash: &'a BlockHash) -> error::Result<BlockData> {
+        let archive = self.block_archive.lock().unwrap().clone();
+        get_block_or_archived(header_hash, archive.as_ref(), || {
+            self.blocks
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|block| block.block_hash() == *header_hash)
+                .cloned()
+                .map(BlockData::FullBlock)
+                .ok_or(error::anyhow!("block `{header_hash}` not found"))
+        })
+    }
+
+    fn get_best_block(&self) -> error::Result<(BlockHash, Option<u32>)> {
+        unimplemented!()
+    }
+
+    fn is_pruned(&self) -> error::Result<bool> {
+        Ok(self.pruned)
+    }
+
+    fn set_block_archive(&self, archive: Arc<dyn BlockArchive>) {
+        *self.block_archive.lock().unwrap() = Some(archive);
+    }
+
+    fn sync_progress(&self) -> error::Result<SyncProgress> {
+        let mut progress = self.sync_progress.lock().unwrap();
+        if progress.is_empty() {
+            error::bail!("no sync progress");
+        }
+        Ok(progress.remove(0))
+    }
+
+    fn get_utxo(&self, _: &BlockHash, _: u64) -> UtxoResult {
+        unimplemented!()
+    }
+
+    fn get_utxo_by_txid(&self, _: &Txid, _: &Script) -> error::Result<TxResult> {
+        unimplemented!()
+    }
+
+    fn manage_transactions(&self, _: &mut Vec<Txid>) -> error::Result<()> {
+        unimplemented!()
+    }
+
+    fn listen(self: Arc<Self>) -> error::Result<JoinHandle<()>> {
+        unimplemented!()
+    }
+
+    fn get_transaction(&self, _: &Txid) -> error::Result<TxResult> {
+        unimplemented!()
+    }
+
+    fn process_transactions(&self) -> error::Result<()> {
+        unimplemented!()
+    }
+
+    fn broadcast_status(&self, _: &Transaction) -> error::Result<BroadcastStatus> {
+        Ok(self.statuses.lock().unwrap().remove(0))
+    }
+
+    fn is_unspent(&self, _: &Txid, _: u32) -> error::Result<bool> {
+        unimplemented!()
+    }
+
+    fn tx_status(&self, _: &Txid) -> error::Result<TxStatus> {
+        unimplemented!()
+    }
+
+    fn fee_histogram(&self) -> error::Result<Vec<(u32, u64)>> {
+        Ok(self.fee_histogram.lock().unwrap().clone())
+    }
+
+    fn mempool_stats(&self) -> error::Result<MempoolStats> {
+        Ok(*self.mempool_stats.lock().unwrap())
+    }
+
+    fn invalidate_blocks(&self, _: u32) -> error::Result<Vec<Block>> {
+        unimplemented!()
+    }
+
+    fn scan_utxos(&self, _: &[ScriptBuf]) -> error::Result<Vec<(OutPoint, TxOut)>> {
+        unimplemented!()
+    }
+}
@@ -1,7 +1,22 @@
 //! Chain module implementation that contains all the code related to the blockchain communication.
 mod blockchain;
+pub mod bump;
+pub mod eta;
+pub mod feerate;
+pub mod funding_check;
+pub mod history;
+pub mod key_sweep;
+pub mod mempool;
+#[cfg(test)]
+mod mock;
+pub mod pruned;
+pub mod rebroadcast;
+pub mod sweep;
+pub mod sync_check;
+pub mod timelock;
 
 pub use lampo_common::bitcoin::Network;
 pub use lampo_common::wallet::WalletManager;
 
 pub use blockchain::LampoChainManager;
+pub(crate) use blockchain::FEERATE_FLOOR_SATS_PER_KW;
@@ -0,0 +1,146 @@
+//! Support of the pruned backends.
+//!
+//! A pruned bitcoind deletes the old blocks, so the blocks needed
+//! to rescan the chain (e.g. after a restart) are fetched from
+//! an esplora server.
+use std::sync::Arc;
+use std::time::Duration;
+
+use lampo_common::backend::{deserialize, Backend, Block, BlockArchive, BlockHash};
+use lampo_common::conf::Network;
+use lampo_common::error;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Archive of the blocks served by an esplora server.
+pub struct EsploraArchive {
+    url: String,
+    user_agent: String,
+}
+
+impl EsploraArchive {
+    pub fn new(url: &str, user_agent: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            user_agent: user_agent.to_owned(),
+        }
+    }
+
+    /// The archive of the public esplora server of `network`, if any.
+    pub fn for_network(network: Network, user_agent: &str) -> Option<Self> {
+        let url = match network {
+            Network::Bitcoin => "https://mempool.space/api",
+            Network::Testnet => "https://mempool.space/testnet/api",
+            Network::Signet => "https://mempool.space/signet/api",
+            _ => return None,
+        };
+        Some(Self::new(url, user_agent))
+    }
+}
+
+impl BlockArchive for EsploraArchive {
+    fn get_archived_block(&self, header_hash: &BlockHash) -> error::Result<Block> {
+        let url = format!("{}/block/{header_hash}/raw", self.url);
+        let response = minreq::get(&url)
+            .with_header("User-Agent", &self.user_agent)
+            .with_timeout(HTTP_TIMEOUT.as_secs())
+            .send()
+            .map_err(|err| error::anyhow!("impossible reach the esplora server `{url}`: {err}"))?;
+        if !(200..300).contains(&response.status_code) {
+            error::bail!(
+                "the esplora server answered with status `{}` for the block `{header_hash}`",
+                response.status_code
+            );
+        }
+        Ok(deserialize(response.as_bytes())?)
+    }
+}
+
+/// Fetch the blocks pruned by the `backend` from the `archive`,
+/// and return true when the backend is pruned.
+pub fn enable_block_archive(
+    backend: &dyn Backend,
+    archive: Option<Arc<dyn BlockArchive>>,
+) -> error::Result<bool> {
+    if !backend.is_pruned()? {
+        return Ok(false);
+    }
+    match archive {
+        Some(archive) => {
+            log::warn!(target: "lampod", "the backend is pruned, the old blocks are fetched from esplora and the deep reorgs may not be handled");
+            backend.set_block_archive(archive);
+        }
+        None => {
+            log::warn!(target: "lampod", "the backend is pruned and there is no esplora server for the network, the old blocks can not be fetched without `esplora-url`");
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use lampo_common::backend::{Backend, Block, BlockArchive, BlockData, BlockHash};
+    use lampo_common::bitcoin::blockdata::constants::genesis_block;
+    use lampo_common::bitcoin::Network;
+    use lampo_common::error;
+
+    use super::enable_block_archive;
+    use crate::chain::mock::MockBackend;
+
+    #[derive(Default)]
+    struct StubArchive {
+        blocks: HashMap<BlockHash, Block>,
+        requests: Mutex<usize>,
+    }
+
+    impl BlockArchive for StubArchive {
+        fn get_archived_block(&self, header_hash: &BlockHash) -> error::Result<Block> {
+            *self.requests.lock().unwrap() += 1;
+            self.blocks
+                .get(header_hash)
+                .cloned()
+                .ok_or(error::anyhow!("unknown block `{header_hash}`"))
+        }
+    }
+
+    #[test]
+    fn pruned_blocks_from_the_archive() {
+        let recent = genesis_block(Network::Regtest);
+        let historical = genesis_block(Network::Testnet);
+        let archive = Arc::new(StubArchive {
+            blocks: HashMap::from([(historical.block_hash(), historical.clone())]),
+            ..Default::default()
+        });
+
+        let backend = MockBackend {
+            pruned: true,
+            blocks: Mutex::new(vec![recent.clone()]),
+            ..Default::default()
+        };
+        assert!(enable_block_archive(&backend, Some(archive.clone())).unwrap());
+        let BlockData::FullBlock(block) = backend.get_block(&recent.block_hash()).unwrap() else {
+            panic!("the backend must return the full block");
+        };
+        assert_eq!(block, recent);
+        assert_eq!(*archive.requests.lock().unwrap(), 0);
+
+        let BlockData::FullBlock(block) = backend.get_block(&historical.block_hash()).unwrap()
+        else {
+            panic!("the archive must return the full block");
+        };
+        assert_eq!(block, historical);
+        assert_eq!(*archive.requests.lock().unwrap(), 1);
+
+        // The archive is not used when the backend has all the blocks.
+        let backend = MockBackend {
+            blocks: Mutex::new(vec![recent]),
+            ..Default::default()
+        };
+        assert!(!enable_block_archive(&backend, Some(archive.clone())).unwrap());
+        assert!(backend.get_block(&historical.block_hash()).is_err());
+        assert_eq!(*archive.requests.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,140 @@
+//! Rebroadcast of the transactions that we broadcast, until
+//! they or a conflicting transaction are confirmed.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use lampo_common::backend::{Backend, BroadcastStatus};
+use lampo_common::bitcoin::{Transaction, Txid};
+
+pub struct Rebroadcaster {
+    backend: Arc<dyn Backend>,
+    /// The pending transactions with the number of
+    /// times that we broadcast them again.
+    pending: Mutex<HashMap<Txid, (Transaction, u64)>>,
+}
+
+impl Rebroadcaster {
+    pub fn new(backend: Arc<dyn Backend>) -> Self {
+        Self {
+            backend,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keep track of `tx` until it is confirmed.
+    pub fn track(&self, tx: &Transaction) {
+        // SAFETY: the lock can not be poisoned.
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(tx.txid())
+            .or_insert_with(|| (tx.clone(), 0));
+    }
+
+    /// Return the transactions that are not confirmed yet.
+    pub fn pending(&self) -> Vec<Txid> {
+        self.pending.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Broadcast again the pending transactions that are out of the
+    /// mempool, and forget the ones that are confirmed or conflicted.
+    pub fn rebroadcast(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(
+            |txid, (tx, attempts)| match self.backend.broadcast_status(tx) {
+                Ok(BroadcastStatus::Mempool) => true,
+                Ok(BroadcastStatus::Evicted) => {
+                    *attempts += 1;
+                    log::info!(target: "lampo", "rebroadcasting transaction `{txid}`, attempt {attempts}");
+                    self.backend.brodcast_tx(tx);
+                    true
+                }
+                Ok(BroadcastStatus::Confirmed) => {
+                    log::debug!(target: "lampo", "transaction `{txid}` confirmed, stop to rebroadcast it");
+                    false
+                }
+                Ok(BroadcastStatus::Conflicted) => {
+                    log::warn!(target: "lampo", "a transaction conflicting with `{txid}` was confirmed, stop to rebroadcast it");
+                    false
+                }
+                Err(err) => {
+                    log::warn!(target: "lampo", "impossible get the status of transaction `{txid}`: {err}");
+                    true
+                }
+            },
+        );
+    }
+
+    /// Spawn a thread that looks at the pending transactions
+    /// every `interval`.
+    pub fn spawn(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            self.rebroadcast();
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use lampo_common::backend::BroadcastStatus;
+    use lampo_common::bitcoin::absolute::LockTime;
+    use lampo_common::bitcoin::{Transaction, TxOut};
+
+    use super::Rebroadcaster;
+    use crate::chain::mock::MockBackend;
+
+    fn transaction(value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value,
+                script_pubkey: Default::default(),
+            }],
+        }
+    }
+
+    #[test]
+    fn rebroadcast_evicted_transaction_until_confirmed() {
+        let backend = Arc::new(MockBackend::default());
+        *backend.statuses.lock().unwrap() = vec![
+            BroadcastStatus::Mempool,
+            BroadcastStatus::Evicted,
+            BroadcastStatus::Evicted,
+            BroadcastStatus::Confirmed,
+        ];
+        let rebroadcaster = Rebroadcaster::new(backend.clone());
+        let tx = transaction(1000);
+        rebroadcaster.track(&tx);
+
+        rebroadcaster.rebroadcast();
+        assert!(backend.broadcast.lock().unwrap().is_empty());
+
+        rebroadcaster.rebroadcast();
+        rebroadcaster.rebroadcast();
+        assert_eq!(*backend.broadcast.lock().unwrap(), vec![tx.txid(); 2]);
+        assert_eq!(rebroadcaster.pending(), vec![tx.txid()]);
+
+        rebroadcaster.rebroadcast();
+        assert!(rebroadcaster.pending().is_empty());
+        assert_eq!(backend.broadcast.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn forget_conflicted_transaction() {
+        let backend = Arc::new(MockBackend::default());
+        *backend.statuses.lock().unwrap() = vec![BroadcastStatus::Conflicted];
+        let rebroadcaster = Rebroadcaster::new(backend.clone());
+        rebroadcaster.track(&transaction(1000));
+
+        rebroadcaster.rebroadcast();
+        assert!(rebroadcaster.pending().is_empty());
+        assert!(backend.broadcast.lock().unwrap().is_empty());
+    }
+}
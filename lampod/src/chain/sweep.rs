@@ -0,0 +1,114 @@
+//! Sweep of the outputs that LDK give back to us when
+//! a channel is closed.
+use lampo_common::bitcoin::secp256k1::Secp256k1;
+use lampo_common::bitcoin::{ScriptBuf, Transaction};
+use lampo_common::conf::LampoConf;
+use lampo_common::error;
+use lampo_common::keys::LampoKeysManager;
+use lampo_common::ldk::sign::{OutputSpender, SpendableOutputDescriptor};
+
+use super::FEERATE_FLOOR_SATS_PER_KW;
+
+/// Resolve the sweep feerate in sats per kw, the override inside the
+/// configuration wins over the `estimate`, and the result is always
+/// between the LDK floor and the `max-feerate-per-kw`.
+pub fn resolve_sweep_feerate(conf: &LampoConf, estimate: Option<u32>) -> u32 {
+    conf.sweep_feerate
+        .or(estimate.filter(|feerate| *feerate > 0))
+        .unwrap_or(FEERATE_FLOOR_SATS_PER_KW)
+        .clamp(
+            FEERATE_FLOOR_SATS_PER_KW,
+            conf.max_feerate_per_kw.max(FEERATE_FLOOR_SATS_PER_KW),
+        )
+}
+
+/// Create the transaction that spends all the `descriptors` to
+/// `change_script` paying `feerate` sats per kw.
+pub fn create_spending_transaction(
+    keys: &LampoKeysManager,
+    descriptors: &[SpendableOutputDescriptor],
+    change_script: ScriptBuf,
+    feerate: u32,
+) -> error::Result<Transaction> {
+    let descriptors = descriptors.iter().collect::<Vec<_>>();
+    keys.spend_spendable_outputs(
+        &descriptors,
+        vec![],
+        change_script,
+        feerate,
+        None,
+        &Secp256k1::new(),
+    )
+    .map_err(|_| {
+        error::anyhow!(
+            "impossible create the transaction that spends {} outputs",
+            descriptors.len()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use lampo_common::bitcoin::hashes::Hash;
+    use lampo_common::bitcoin::{TxOut, Txid};
+    use lampo_common::conf::LampoConf;
+    use lampo_common::keys::LampoKeysManager;
+    use lampo_common::ldk::chain::transaction::OutPoint;
+    use lampo_common::ldk::sign::{SignerProvider, SpendableOutputDescriptor};
+
+    use super::{create_spending_transaction, resolve_sweep_feerate};
+    use crate::chain::FEERATE_FLOOR_SATS_PER_KW;
+
+    #[test]
+    fn sweep_feerate_within_bounds() {
+        let mut conf = LampoConf::default();
+        assert_eq!(
+            resolve_sweep_feerate(&conf, None),
+            FEERATE_FLOOR_SATS_PER_KW
+        );
+        assert_eq!(
+            resolve_sweep_feerate(&conf, Some(0)),
+            FEERATE_FLOOR_SATS_PER_KW
+        );
+        assert_eq!(
+            resolve_sweep_feerate(&conf, Some(100)),
+            FEERATE_FLOOR_SATS_PER_KW
+        );
+        assert_eq!(resolve_sweep_feerate(&conf, Some(5_000)), 5_000);
+        assert_eq!(
+            resolve_sweep_feerate(&conf, Some(1_000_000)),
+            conf.max_feerate_per_kw
+        );
+
+        conf.sweep_feerate = Some(2_000);
+        assert_eq!(resolve_sweep_feerate(&conf, Some(5_000)), 2_000);
+    }
+
+    #[test]
+    fn spending_transaction_pays_the_sweep_feerate() {
+        let mut conf = LampoConf::default();
+        conf.sweep_feerate = Some(2_000);
+        let feerate = resolve_sweep_feerate(&conf, Some(5_000));
+
+        let keys = LampoKeysManager::new(&[42; 32], 0, 0);
+        let script = keys.get_destination_script([0; 32]).unwrap();
+        let amount = 100_000;
+        let descriptor = SpendableOutputDescriptor::StaticOutput {
+            outpoint: OutPoint {
+                txid: Txid::all_zeros(),
+                index: 0,
+            },
+            output: TxOut {
+                value: amount,
+                script_pubkey: script.clone(),
+            },
+            channel_keys_id: None,
+        };
+        let tx = create_spending_transaction(&keys, &[descriptor], script, feerate).unwrap();
+
+        let fee = amount - tx.output.iter().map(|out| out.value).sum::<u64>();
+        let paid = fee * 1000 / tx.weight().to_wu();
+        assert!(paid >= feerate as u64, "paid {paid}, expected {feerate}");
+        assert!(paid <= conf.max_feerate_per_kw as u64, "paid {paid}");
+    }
+}
@@ -0,0 +1,89 @@
+//! Startup check that waits the backend to reach the tip of the
+//! network, so the RPCs do not fail while it is still syncing.
+use std::time::{Duration, Instant};
+
+use lampo_common::backend::{Backend, SyncProgress};
+use lampo_common::error;
+
+/// Poll the backend every `interval` until it is at most `max_blocks_behind`
+/// the network tip, and fail when it is not in sync before `timeout`.
+pub fn wait_backend_sync(
+    backend: &dyn Backend,
+    max_blocks_behind: u32,
+    timeout: Option<Duration>,
+    interval: Duration,
+) -> error::Result<SyncProgress> {
+    let start = Instant::now();
+    loop {
+        match backend.sync_progress() {
+            Ok(progress) if progress.is_synced(max_blocks_behind) => {
+                log::info!(target: "lampo", "backend in sync at block {}", progress.blocks);
+                return Ok(progress);
+            }
+            Ok(progress) => log::info!(
+                target: "lampo",
+                "waiting the backend to sync, block {} of {} ({:.2}%)",
+                progress.blocks,
+                progress.headers,
+                progress.verification_progress * 100.0
+            ),
+            // bitcoind answers with an error while it is loading the chain.
+            Err(err) => {
+                log::warn!(target: "lampo", "impossible to get the sync progress of the backend: {err}")
+            }
+        }
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            error::bail!(
+                "the backend is not in sync after {} seconds, use `--skip-sync-check` to start anyway",
+                start.elapsed().as_secs()
+            );
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use lampo_common::backend::SyncProgress;
+
+    use super::wait_backend_sync;
+    use crate::chain::mock::MockBackend;
+
+    fn progress(blocks: u32, headers: u32, verification_progress: f64) -> SyncProgress {
+        SyncProgress {
+            blocks,
+            headers,
+            verification_progress,
+        }
+    }
+
+    #[test]
+    fn startup_waits_the_backend_sync() {
+        let backend = MockBackend::default();
+        *backend.sync_progress.lock().unwrap() = vec![
+            progress(100, 800_000, 0.01),
+            progress(799_990, 800_000, 0.9999),
+            progress(799_999, 800_000, 0.99),
+            progress(799_999, 800_000, 0.99995),
+        ];
+        let synced = wait_backend_sync(&backend, 2, None, Duration::from_millis(1)).unwrap();
+        assert_eq!(synced, progress(799_999, 800_000, 0.99995));
+        // Every progress was polled before starting.
+        assert!(backend.sync_progress.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn startup_fails_when_the_backend_never_syncs() {
+        let backend = MockBackend::default();
+        *backend.sync_progress.lock().unwrap() = vec![progress(100, 800_000, 0.01); 1000];
+        let result = wait_backend_sync(
+            &backend,
+            2,
+            Some(Duration::from_millis(20)),
+            Duration::from_millis(5),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! Experimental timelocked outputs, e.g. for vaulting.
+//!
+//! The output is a P2WSH of `<timelock> OP_CSV/OP_CLTV OP_DROP <pubkey> OP_CHECKSIG`,
+//! so only the key can spend it and only after the timelock.
+use lampo_common::bitcoin::absolute::LockTime;
+use lampo_common::bitcoin::blockdata::opcodes::all::{OP_CHECKSIG, OP_CLTV, OP_CSV, OP_DROP};
+use lampo_common::bitcoin::blockdata::script::Builder;
+use lampo_common::bitcoin::{PublicKey, ScriptBuf};
+use lampo_common::error;
+
+/// The timelock of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timelock {
+    /// The blocks after the confirmation of the output, BIP 112.
+    Relative(u16),
+    /// The height or the time from which the output can be spent, BIP 65.
+    Absolute(LockTime),
+}
+
+impl Timelock {
+    pub fn new(locktime_or_blocks: u32, absolute: bool) -> error::Result<Self> {
+        if locktime_or_blocks == 0 {
+            error::bail!("the timelock must be greater than zero");
+        }
+        if absolute {
+            return Ok(Self::Absolute(LockTime::from_consensus(locktime_or_blocks)));
+        }
+        // BIP 68 encodes the relative timelock on 16 bits.
+        let blocks = u16::try_from(locktime_or_blocks).map_err(|_| {
+            error::anyhow!(
+                "a relative timelock can be at most {} blocks, not {locktime_or_blocks}",
+                u16::MAX
+            )
+        })?;
+        Ok(Self::Relative(blocks))
+    }
+
+    /// The witness script that locks the output to `pubkey` until the timelock.
+    pub fn redeem_script(&self, pubkey: &PublicKey) -> ScriptBuf {
+        let builder = match self {
+            Self::Relative(blocks) => Builder::new().push_int(*blocks as i64).push_opcode(OP_CSV),
+            Self::Absolute(locktime) => Builder::new()
+                .push_int(locktime.to_consensus_u32() as i64)
+                .push_opcode(OP_CLTV),
+        };
+        builder
+            .push_opcode(OP_DROP)
+            .push_key(pubkey)
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use lampo_common::bitcoin::PublicKey;
+
+    use super::Timelock;
+
+    #[test]
+    fn timelock_scripts() {
+        let pubkey = PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let csv = Timelock::new(144, false).unwrap();
+        assert_eq!(csv, Timelock::Relative(144));
+        assert_eq!(
+            csv.redeem_script(&pubkey).to_asm_string(),
+            "OP_PUSHBYTES_2 9000 OP_CSV OP_DROP OP_PUSHBYTES_33 0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798 OP_CHECKSIG"
+        );
+        let cltv = Timelock::new(800_000, true).unwrap();
+        assert!(cltv
+            .redeem_script(&pubkey)
+            .to_asm_string()
+            .contains("OP_CLTV"));
+        // BIP 68 has only 16 bits for the blocks.
+        assert!(Timelock::new(70_000, false).is_err());
+        assert!(Timelock::new(0, true).is_err());
+    }
+}
@@ -20,6 +20,13 @@ use crate::{handler::external_handler::ExternalHandler, LampoDaemon};
 
 #[macro_export]
 macro_rules! rpc_error {
+    ($code:path, $($msg:tt)+) => {{
+        Error::Rpc(RpcError {
+            code: i32::from($code),
+            message: format!($($msg)+),
+            data: None,
+        })
+    }};
     ($($msg:tt)*) => {{
         Error::Rpc(RpcError {
             code: -1,
@@ -29,6 +36,27 @@ macro_rules! rpc_error {
     }};
 }
 
+/// Build an `error::Error` that keeps its `LampoErrorCode`
+/// until the JSON RPC response, with an optional `data`
+/// to give some context to the machines.
+#[macro_export]
+macro_rules! lampo_error {
+    ($code:expr, data: $data:expr, $($msg:tt)+) => {{
+        lampo_common::error::Error::new(lampo_jsonrpc::errors::RpcError {
+            code: i32::from($code),
+            message: format!($($msg)+),
+            data: Some($data),
+        })
+    }};
+    ($code:expr, $($msg:tt)+) => {{
+        lampo_common::error::Error::new(lampo_jsonrpc::errors::RpcError {
+            code: i32::from($code),
+            message: format!($($msg)+),
+            data: None,
+        })
+    }};
+}
+
 /// JSON RPC 2.0 Command handler!
 pub struct CommandHandler {
     pub handler: RefCell<Option<Arc<Handler<LampoDaemon>>>>,
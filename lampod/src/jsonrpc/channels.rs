@@ -1,13 +1,19 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use lampo_common::conf::LampoConf;
+use lampo_common::error::LampoErrorCode;
 use lampo_common::event::ln::LightningEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
 use lampo_common::json;
 use lampo_common::model::request;
 use lampo_common::model::response;
+use lampo_common::types::NodeId;
 use lampo_jsonrpc::errors::Error;
 use lampo_jsonrpc::errors::RpcError;
 
-use crate::ln::events::ChannelEvents;
+use crate::ln::events::{ChannelEvents, PeerEvents};
 
 use crate::rpc_error;
 use crate::LampoDaemon;
@@ -18,6 +24,131 @@ pub fn json_list_channels(ctx: &LampoDaemon, request: &json::Value) -> Result<js
     Ok(json::to_value(resp)?)
 }
 
+pub fn json_list_closed_channels(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `closedchannels` with request {:?}", request);
+    let resp = ctx.channel_manager().list_closed_channels()?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_dump_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `dumpchannel` with request {:?}", request);
+    let request: request::DumpChannel = json::from_value(request.clone())?;
+    let resp = ctx.channel_manager().dump_channel(&request.channel_id)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_channel_funding_tx(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `channelfundingtx` with request {:?}", request);
+    let request: request::ChannelFundingTx = json::from_value(request.clone())?;
+    let resp = ctx
+        .channel_manager()
+        .channel_funding_tx(&request.channel_id)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_verify_channel_funding(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `verifychannelfunding` with request {:?}", request);
+    let request: request::VerifyChannelFunding = json::from_value(request.clone())?;
+    let resp = ctx
+        .channel_manager()
+        .verify_channel_funding(&request.channel_id)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_rebroadcast_commitment(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!(
+        "call for `rebroadcastcommitment` with request {:?}",
+        request
+    );
+    if !ctx.conf().allow_unsafe_rpc {
+        return Err(rpc_error!(
+            "`rebroadcastcommitment` force closes the channel and it is disabled, set `allow-unsafe-rpc=true` to enable it"
+        ));
+    }
+    let request: request::RebroadcastCommitment = json::from_value(request.clone())?;
+    let resp = ctx
+        .channel_manager()
+        .rebroadcast_commitment(&request.channel_id)?;
+    Ok(json::to_value(resp)?)
+}
+
+/// Bump the commitment of a force closed anchor channel with
+/// a child that spends our anchor output.
+pub fn json_bump_channel_close(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `bumpchannelclose` with request {:?}", request);
+    let request: request::BumpChannelClose = json::from_value(request.clone())?;
+    let resp = ctx
+        .channel_manager()
+        .bump_channel_close(&request.channel_id, request.fee_rate)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_list_channel_fees(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `listchannelfees` with request {:?}", request);
+    let request: request::ListChannelFees = json::from_value(request.clone())?;
+    let resp = ctx
+        .channel_manager()
+        .list_channel_fees(request.channel_id.as_deref())?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_set_channel_fee(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `setchannelfee` with request {:?}", request);
+    let request: request::SetChannelFee = json::from_value(request.clone())?;
+    let resp = ctx.channel_manager().set_channel_fee(&request)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_set_dust_exposure(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `setdustexposure` with request {:?}", request);
+    let request: request::SetDustExposure = json::from_value(request.clone())?;
+    let resp = ctx.channel_manager().set_dust_exposure(&request)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_update_channel_config(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `updatechannelconfig` with request {:?}", request);
+    let request: request::UpdateChannelConfig = json::from_value(request.clone())?;
+    let resp = ctx.channel_manager().update_channel_config(&request)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_estimate_close_all(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `estimatecloseall` with request {:?}", request);
+    let resp = ctx.channel_manager().estimate_close_all();
+    Ok(json::to_value(resp)?)
+}
+
 pub fn json_close_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `closechannel` with request {:?}", request);
     let mut request: request::CloseChannel = json::from_value(request.clone())?;
@@ -34,7 +165,10 @@ pub fn json_close_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<js
         // check the channel_id if it is not none, if it is return an error
         // and if it is not none then we need to have the channel_id that needs to be shut
         if request.channel_id.is_none() {
-            return Err(rpc_error!("Channels > 1, provide `channel_id`"));
+            return Err(rpc_error!(
+                LampoErrorCode::InvalidParams,
+                "Channels > 1, provide `channel_id`"
+            ));
         } else {
             request
         }
@@ -46,7 +180,10 @@ pub fn json_close_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<js
         request
     } else {
         // No channels with the given peer.
-        return Err(rpc_error!("No channels with associated peer"));
+        return Err(rpc_error!(
+            LampoErrorCode::ChannelNotFound,
+            "No channels with associated peer"
+        ));
     };
     ctx.channel_manager().close_channel(res)?;
 
@@ -82,3 +219,69 @@ pub fn json_close_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<js
         "funding_utxo" : funding_utxo,
     }))
 }
+
+pub fn json_export_channel_backup(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `exportchannelbackup` with request {:?}", request);
+    let backup = ctx.channel_manager().export_channel_backup()?;
+    Ok(json::to_value(response::ChannelBackup { backup })?)
+}
+
+pub fn json_import_channel_backup(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `importchannelbackup` with request {:?}", request);
+    let request: request::ImportChannelBackup = json::from_value(request.clone())?;
+    let mut recovered = ctx
+        .channel_manager()
+        .import_channel_backup(&request.backup)?;
+    // Reconnect with the peers that we know, so they receive our
+    // `channel_reestablish` and force close the lost channels.
+    let stored = ctx.peer_manager().stored_peers()?;
+    for channel in recovered.iter_mut() {
+        let Some(peer) = stored
+            .peers
+            .iter()
+            .find(|peer| Some(&peer.node_id) == channel.peer_id.as_ref())
+        else {
+            continue;
+        };
+        let (Ok(node_id), Some(Ok(addr))) = (
+            NodeId::from_str(&peer.node_id),
+            peer.addresses.last().map(|addr| SocketAddr::from_str(addr)),
+        ) else {
+            continue;
+        };
+        match ctx.rt.block_on(ctx.peer_manager().connect(node_id, addr)) {
+            Ok(()) => channel.reconnected = true,
+            Err(err) => log::warn!("impossible reconnect with `{node_id}`: {err}"),
+        }
+    }
+    Ok(json::to_value(response::ImportChannelBackup { recovered })?)
+}
+
+pub fn json_list_towers(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listtowers` with request {:?}", request);
+    let towers = ctx.channel_manager().towers().list_towers();
+    Ok(json::to_value(response::Towers { towers })?)
+}
+
+/// Register a watchtower, that receives the monitors of the
+/// channels from now on.
+pub fn json_add_tower(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `addtower` with request {:?}", request);
+    let request: request::AddTower = json::from_value(request.clone())?;
+    let uri = LampoConf::parse_watchtower(&request.uri)
+        .map_err(|err| rpc_error!(LampoErrorCode::InvalidParams, "{err}"))?;
+    if !ctx.channel_manager().add_tower(&uri) {
+        return Err(rpc_error!(
+            LampoErrorCode::InvalidParams,
+            "watchtower `{uri}` is already registered"
+        ));
+    }
+    let towers = ctx.channel_manager().towers().list_towers();
+    Ok(json::to_value(response::Towers { towers })?)
+}
@@ -1,6 +1,10 @@
 //! Inventory method implementation
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use lampo_common::json;
-use lampo_common::model::response::{NetworkChannel, NetworkChannels};
+use lampo_common::logger;
+use lampo_common::model::request;
+use lampo_common::model::response::{self, NetworkChannel, NetworkChannels};
 use lampo_jsonrpc::errors::Error;
 
 use crate::LampoDaemon;
@@ -11,6 +15,23 @@ pub fn get_info(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value,
     Ok(result)
 }
 
+pub fn json_health(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("calling `health` with request `{:?}`", request);
+    let wallet_last_sync = ctx.wallet_manager().last_sync();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let wallet_synced = wallet_last_sync.is_some_and(|last_sync| {
+        now.saturating_sub(last_sync) <= ctx.conf().wallet_sync_staleness_secs
+    });
+    Ok(json::to_value(response::Health {
+        wallet_last_sync,
+        wallet_synced,
+        inflight_payments: ctx.channel_manager().inflight_payments(),
+    })?)
+}
+
 // FIXME: check the request
 pub fn json_network_channels(ctx: &LampoDaemon, _: &json::Value) -> Result<json::Value, Error> {
     let network_graph = ctx.channel_manager().graph();
@@ -27,3 +48,14 @@ pub fn json_network_channels(ctx: &LampoDaemon, _: &json::Value) -> Result<json:
         channels: network_channels,
     })?)
 }
+
+pub fn json_set_log_level(_: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("calling `setloglevel` with request `{:?}`", request);
+    let request: request::SetLogLevel = json::from_value(request.clone())?;
+    let previous = logger::set_level(request.target.as_deref(), &request.level)?;
+    Ok(json::to_value(response::SetLogLevel {
+        target: request.target,
+        level: request.level,
+        previous_level: previous.to_string(),
+    })?)
+}
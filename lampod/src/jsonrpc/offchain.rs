@@ -1,29 +1,62 @@
 //! Offchain RPC methods
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::chan;
 use lampo_common::conf::Network;
+use lampo_common::error::LampoErrorCode;
 use lampo_common::event::ln::LightningEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
 use lampo_common::ldk;
+use lampo_common::ldk::ln::{PaymentHash, PaymentPreimage};
 use lampo_common::ldk::offers::offer;
 use lampo_common::ldk::offers::offer::Amount;
+use lampo_common::model::request::CreateInvoice;
 use lampo_common::model::request::GenerateInvoice;
 use lampo_common::model::request::GenerateOffer;
+use lampo_common::model::request::GetRoute;
+use lampo_common::model::request::InvoiceStatus;
 use lampo_common::model::request::KeySend;
+use lampo_common::model::request::LastForwardError;
+use lampo_common::model::request::LnurlPay;
 use lampo_common::model::request::Pay;
+use lampo_common::model::request::PaymentFailures;
+use lampo_common::model::request::ReissueInvoice;
+use lampo_common::model::request::SettleInvoice;
+use lampo_common::model::request::WaitSendPay;
 use lampo_common::model::response;
 use lampo_common::model::response::PayResult;
+use lampo_common::model::response::PaymentState;
 use lampo_common::model::response::{Invoice, InvoiceInfo};
 use lampo_common::{json, model::request::DecodeInvoice};
-use lampo_jsonrpc::errors::{Error, RpcError};
+use lampo_jsonrpc::errors::Error;
 
+use crate::lampo_error;
+use crate::ln::lnurl;
 use crate::LampoDaemon;
 
 pub fn json_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `invoice` with request `{:?}`", request);
     let request: GenerateInvoice = json::from_value(request.clone())?;
+    if request.ignore_min_amount && !ctx.conf().allow_unsafe_rpc {
+        return Err(lampo_error!(
+            LampoErrorCode::InvalidParams,
+            "`ignore_min_amount` is disabled, set `allow-unsafe-rpc=true` to enable it"
+        )
+        .into());
+    }
+    let min_invoice_msat = ctx.conf().min_invoice_msat.unwrap_or_default();
+    if let Some(amount_msat) = request.amount_msat {
+        if amount_msat < min_invoice_msat && !request.ignore_min_amount {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "the invoice amount {amount_msat} msat is below the minimum of {min_invoice_msat} msat"
+            )
+            .into());
+        }
+    }
     let invoice = ctx.offchain_manager().generate_invoice(
         request.amount_msat,
         &request.description,
@@ -35,6 +68,88 @@ pub fn json_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Va
     Ok(json::to_value(&invoice)?)
 }
 
+pub fn json_create_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `createinvoice` with request `{:?}`", request);
+    let request: CreateInvoice = json::from_value(request.clone())?;
+    let invoice = ctx.offchain_manager().create_invoice_for_hash(
+        PaymentHash(request.payment_hash()?),
+        request.amount_msat,
+        &request.description,
+        request.expiry.unwrap_or(10000),
+    )?;
+    let invoice = Invoice {
+        bolt11: invoice.to_string(),
+    };
+    Ok(json::to_value(&invoice)?)
+}
+
+pub fn json_settle_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `settleinvoice` with request `{:?}`", request);
+    let request: SettleInvoice = json::from_value(request.clone())?;
+    let payment_hash = ctx
+        .offchain_manager()
+        .settle_invoice(PaymentPreimage(request.preimage()?))?;
+    Ok(json::to_value(response::SettleInvoice {
+        payment_hash: payment_hash.to_string(),
+    })?)
+}
+
+pub fn json_invoice_status(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `invoicestatus` with request `{:?}`", request);
+    let request: InvoiceStatus = json::from_value(request.clone())?;
+    let status = ctx
+        .channel_manager()
+        .invoice_status(&PaymentHash(request.payment_hash()?));
+    Ok(json::to_value(status)?)
+}
+
+pub fn json_reissue_invoice(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `reissueinvoice` with request `{:?}`", request);
+    let request: ReissueInvoice = json::from_value(request.clone())?;
+    let payment_hash = match (request.payment_hash()?, &request.bolt11) {
+        (Some(payment_hash), None) => PaymentHash(payment_hash),
+        (None, Some(bolt11)) => {
+            let invoice = ctx.offchain_manager().decode_invoice(bolt11)?;
+            PaymentHash(invoice.payment_hash().to_byte_array())
+        }
+        _ => {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "one between `payment_hash` and `bolt11` must be provided"
+            )
+            .into())
+        }
+    };
+    let Some(original) = ctx.channel_manager().issued_invoice(&payment_hash) else {
+        return Err(lampo_error!(
+            LampoErrorCode::InvoiceNotFound,
+            "no invoice generated by lampo with payment hash `{payment_hash}`"
+        )
+        .into());
+    };
+    let status = ctx.channel_manager().invoice_status(&payment_hash);
+    if matches!(
+        status.state,
+        response::InvoiceState::Held | response::InvoiceState::Paid
+    ) {
+        return Err(lampo_error!(
+            LampoErrorCode::InvalidParams,
+            "the invoice with payment hash `{payment_hash}` was already paid"
+        )
+        .into());
+    }
+    let invoice = ctx
+        .offchain_manager()
+        .reissue_invoice(&original, request.expiry)?;
+    let invoice = Invoice {
+        bolt11: invoice.to_string(),
+    };
+    Ok(json::to_value(&invoice)?)
+}
+
 pub fn json_offer(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `offer` with request `{:?}`", request);
     let request: GenerateOffer = json::from_value(request.clone())?;
@@ -120,46 +235,264 @@ pub fn json_pay(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value,
     log::info!("call for `pay` with request `{:?}`", request);
     let request: Pay = json::from_value(request.clone())?;
     let events = ctx.handler().events();
-    if let Ok(_) = offer::Offer::from_str(&request.invoice_str) {
+    let payment_hash = if let Ok(_) = offer::Offer::from_str(&request.invoice_str) {
         ctx.offchain_manager()
             .pay_offer(&request.invoice_str, request.amount)?;
+        None
+    } else if request.use_channel.is_some() || !request.exclude_channels.is_empty() {
+        Some(ctx.offchain_manager().pay_invoice_with_first_hop(
+            &request.invoice_str,
+            request.amount,
+            request.use_channel.as_deref(),
+            &request.exclude_channels,
+            &request.route_hints,
+            request.replace_route_hints,
+        )?)
     } else {
+        Some(ctx.offchain_manager().pay_invoice(
+            &request.invoice_str,
+            request.amount,
+            &request.route_hints,
+            request.replace_route_hints,
+        )?)
+    };
+    wait_payment(ctx, events, payment_hash, Duration::from_secs(30))
+}
+
+/// Pay a LNURL or a lightning address, after asking to the
+/// service the invoice for `amount_msat`.
+pub fn json_lnurl_pay(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `lnurlpay` with request `{:?}`", request);
+    let request: LnurlPay = json::from_value(request.clone())?;
+    let network = ctx.conf().network;
+    let url = lnurl::decode(&request.lnurl, network)?;
+    let pay_request = lnurl::fetch_pay_request(&url)?;
+    let (invoice, pay_response) = lnurl::fetch_invoice(
+        &pay_request,
+        request.amount_msat,
+        request.comment.as_deref(),
+        network,
+    )?;
+    log::info!(
+        "paying the invoice of {} msat returned by the LNURL service `{url}`",
+        request.amount_msat
+    );
+    let events = ctx.handler().events();
+    let payment_hash =
         ctx.offchain_manager()
-            .pay_invoice(&request.invoice_str, request.amount)?;
+            .pay_invoice(&invoice.to_string(), None, &[], false)?;
+    let result = wait_pay_result(ctx, events, Some(payment_hash), Duration::from_secs(30))?;
+    Ok(json::to_value(response::LnurlPay {
+        invoice: invoice.to_string(),
+        amount_msat: request.amount_msat,
+        description: pay_request.description(),
+        payment_hash: payment_hash.to_string(),
+        state: result.state,
+        payment_preimage: result.payment_preimage,
+        success_action: pay_response.success_action,
+    })?)
+}
+
+pub fn json_wait_send_pay(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `waitsendpay` with request `{:?}`", request);
+    let request: WaitSendPay = json::from_value(request.clone())?;
+    let payment_hash = PaymentHash(request.payment_hash()?);
+    // Subscribe before looking at the status, so the
+    // result of the payment can not be lost.
+    let events = ctx.handler().events();
+    let Some(payment) = ctx.channel_manager().payment_status(&payment_hash) else {
+        return Err(lampo_error!(
+            LampoErrorCode::InvoiceNotFound,
+            "payment `{payment_hash}` was not sent by this node"
+        )
+        .into());
+    };
+    if !matches!(payment.state, response::PaymentState::Pending) {
+        return Ok(json::to_value(payment)?);
     }
-    // FIXME: this will loop when the Payment event is not generated
+    let timeout = Duration::from_secs(request.timeout_secs.unwrap_or(60));
+    wait_payment(ctx, events, Some(payment_hash), timeout)
+}
+
+pub fn json_payment_failures(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `paymentfailures` with request `{:?}`", request);
+    let request: PaymentFailures = json::from_value(request.clone())?;
+    let payment_hash = request
+        .payment_hash()
+        .map_err(|err| lampo_error!(LampoErrorCode::InvalidParams, "{err}"))?
+        .map(PaymentHash);
+    let failures = ctx
+        .channel_manager()
+        .payment_failures(payment_hash.as_ref(), request.clear);
+    Ok(json::to_value(response::PaymentFailures { failures })?)
+}
+
+pub fn json_last_forward_error(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `lastforwarderror` with request `{:?}`", request);
+    let request: LastForwardError = json::from_value(request.clone())?;
+    let errors = ctx
+        .channel_manager()
+        .forward_errors(request.channel_id.as_deref());
+    Ok(json::to_value(response::ForwardErrors { errors })?)
+}
+
+/// Wait the event that resolves the payment with `payment_hash`, or
+/// the first payment event when the hash is not known (e.g. offers).
+fn wait_payment(
+    ctx: &LampoDaemon,
+    events: chan::Receiver<Event>,
+    payment_hash: Option<PaymentHash>,
+    timeout: Duration,
+) -> Result<json::Value, Error> {
+    let result = wait_pay_result(ctx, events, payment_hash, timeout)?;
+    Ok(json::to_value(result)?)
+}
+
+fn wait_pay_result(
+    ctx: &LampoDaemon,
+    events: chan::Receiver<Event>,
+    payment_hash: Option<PaymentHash>,
+    timeout: Duration,
+) -> Result<PayResult, Error> {
+    let deadline = Instant::now() + timeout;
     loop {
         let event = events
-            .recv_timeout(Duration::from_secs(30))
-            // FIXME: this should be avoided, the `?` should be used here
-            .map_err(|err| {
-                Error::Rpc(RpcError {
-                    code: -1,
-                    message: format!("{err}"),
-                    data: None,
-                })
-            })?;
-
-        if let Event::Lightning(LightningEvent::PaymentEvent {
-            payment_hash,
+            .recv_deadline(deadline)
+            .map_err(|err| lampo_error!(LampoErrorCode::Generic, "{err}"))?;
+
+        let Event::Lightning(LightningEvent::PaymentEvent {
+            payment_hash: event_hash,
             path,
             state,
         }) = event
-        {
-            return Ok(json::to_value(PayResult {
+        else {
+            continue;
+        };
+        let Some(payment_hash) = payment_hash else {
+            return Ok(PayResult {
                 state,
                 path,
-                payment_hash,
-            })?);
+                payment_hash: event_hash,
+                payment_preimage: None,
+            });
+        };
+        if event_hash != Some(payment_hash.to_string()) {
+            continue;
         }
+        let payment_preimage = ctx
+            .channel_manager()
+            .payment_status(&payment_hash)
+            .and_then(|payment| payment.payment_preimage);
+        return Ok(PayResult {
+            state,
+            path,
+            payment_hash: event_hash,
+            payment_preimage,
+        });
     }
 }
 
 pub fn json_keysend(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::debug!("call for `keysend` with request `{:?}`", request);
     let request: KeySend = json::from_value(request.clone())?;
-    ctx.offchain_manager()
-        .keysend(request.destination, request.amount_msat)?;
-    // FIXME: return a better response
-    Ok(json::json!({}))
+    let events = ctx.handler().events();
+    let payment_hash = ctx.offchain_manager().keysend(
+        request.destination,
+        request.amount_msat,
+        &request.route_hints,
+        request.max_parts,
+    )?;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    // Every part that reaches the destination gives a success event,
+    // so we wait until the parts deliver the whole amount.
+    let status = loop {
+        let event = events
+            .recv_deadline(deadline)
+            .map_err(|err| lampo_error!(LampoErrorCode::Generic, "{err}"))?;
+        let Event::Lightning(LightningEvent::PaymentEvent {
+            payment_hash: event_hash,
+            state,
+            ..
+        }) = event
+        else {
+            continue;
+        };
+        if event_hash != Some(payment_hash.to_string()) {
+            continue;
+        }
+        let delivered_msat = ctx
+            .channel_manager()
+            .payment_parts(&payment_hash)
+            .iter()
+            .filter_map(|path| path.last().map(|hop| hop.hop_fee_msat))
+            .sum::<u64>();
+        match state {
+            PaymentState::Success if delivered_msat < request.amount_msat => continue,
+            state => break state,
+        }
+    };
+    if let PaymentState::Failure = status {
+        let failed_parts = ctx
+            .channel_manager()
+            .payment_failures(Some(&payment_hash), false)
+            .len();
+        // A destination that does not accept the multi part keysend
+        // fails back all the parts, so the payment fails as a whole.
+        if request.max_parts != Some(1) && failed_parts > 1 {
+            return Err(lampo_error!(
+                LampoErrorCode::Generic,
+                "keysend `{payment_hash}` failed with {failed_parts} failed parts, the destination may not accept multi part keysend, retry with `max_parts` 1"
+            )
+            .into());
+        }
+        return Err(
+            lampo_error!(LampoErrorCode::Generic, "keysend `{payment_hash}` failed").into(),
+        );
+    }
+    let parts = ctx.channel_manager().payment_parts(&payment_hash);
+    let payment_preimage = ctx
+        .channel_manager()
+        .payment_status(&payment_hash)
+        .and_then(|payment| payment.payment_preimage);
+    Ok(json::to_value(response::KeySendInfo {
+        payment_preimage,
+        payment_hash: payment_hash.to_string(),
+        parts: parts.len(),
+        amount_msat: request.amount_msat,
+        amount_sent_msat: Some(
+            parts
+                .iter()
+                .flatten()
+                .map(|hop| hop.hop_fee_msat)
+                .sum::<u64>(),
+        ),
+        status,
+    })?)
+}
+
+pub fn json_get_route(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `getroute` with request `{:?}`", request);
+    let request: GetRoute = json::from_value(request.clone())?;
+    let route = ctx
+        .offchain_manager()
+        .get_route(request.node_id()?, request.amount_msat)?;
+    Ok(json::to_value(route)?)
+}
+
+pub fn json_probes(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `probes` with request `{:?}`", request);
+    let probes = ctx.channel_manager().probes();
+    Ok(json::to_value(response::Probes { probes })?)
+}
+
+pub fn json_reset_scorer(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `resetscorer` with request `{:?}`", request);
+    ctx.channel_manager().reset_scorer()?;
+    Ok(json::to_value(response::ResetScorer { reset: true })?)
 }
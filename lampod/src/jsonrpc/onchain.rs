@@ -1,25 +1,555 @@
 //! On Chain RPC methods
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::time::Duration;
+
+use lampo_common::backend::{BlockData, TxStatus};
+use lampo_common::bitcoin::absolute::Height;
+use lampo_common::bitcoin::secp256k1::Secp256k1;
+use lampo_common::bitcoin::{Address, OutPoint, PrivateKey, PublicKey, Txid};
+use lampo_common::conf::Network;
+use lampo_common::error::LampoErrorCode;
+use lampo_common::event::onchain::OnChainEvent;
+use lampo_common::event::Event;
+use lampo_common::handler::Handler;
 use lampo_common::json;
+use lampo_common::ldk::chain::chaininterface::BroadcasterInterface;
+use lampo_common::ldk::chain::Confirm;
+use lampo_common::model::request;
+use lampo_common::model::response;
+use lampo_common::utils::bip329;
+use lampo_common::wallet::TransactionOptions;
 use lampo_jsonrpc::errors::Error;
+use lampo_jsonrpc::errors::RpcError;
 
+use crate::chain::timelock::Timelock;
+use crate::chain::{eta, history, key_sweep, mempool};
+use crate::lampo_error;
+use crate::rpc_error;
 use crate::LampoDaemon;
 
+/// Max number of addresses that `newaddrs` is able to generate with
+/// a single call, so the keychain can not be moved too far away from
+/// the last used address.
+const MAX_NEW_ADDRESSES: u32 = 1000;
+
+/// How long `syncwallet` waits the next round of the background sync.
+const SYNC_WORKER_TIMEOUT: Duration = Duration::from_secs(300);
+
 pub fn json_new_addr(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `new_addr` with request {:?}", request);
     let resp = ctx.wallet_manager().get_onchain_address()?;
     Ok(json::to_value(resp)?)
 }
 
+pub fn json_new_addrs(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `new_addrs` with request {:?}", request);
+    let request: request::NewAddresses = json::from_value(request.clone())?;
+    if request.count == 0 || request.count > MAX_NEW_ADDRESSES {
+        return Err(rpc_error!(
+            LampoErrorCode::InvalidParams,
+            "`count` must be between 1 and {MAX_NEW_ADDRESSES}, received `{}`",
+            request.count
+        ));
+    }
+    let addresses = ctx
+        .wallet_manager()
+        .get_onchain_addresses(request.count, request.label_prefix.as_deref())?;
+    Ok(json::to_value(response::NewAddresses { addresses })?)
+}
+
+pub fn json_reset_address_index(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `resetaddrindex` with request {:?}", request);
+    let request: request::ResetAddressIndex = json::from_value(request.clone())?;
+    ctx.wallet_manager()
+        .reset_address_index(request.keychain, request.index)?;
+    Ok(json::to_value(response::ResetAddressIndex {
+        keychain: request.keychain,
+        index: request.index,
+    })?)
+}
+
+pub fn json_address_indices(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `addressindices` with request {:?}", request);
+    let (external, internal) = ctx.wallet_manager().address_indices()?;
+    Ok(json::to_value(response::AddressIndices {
+        external,
+        internal,
+    })?)
+}
+
 pub fn json_funds(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `funds` with request `{:?}`", request);
     let txs = ctx.wallet_manager().list_transactions()?;
+    let (external_balance, internal_balance) = ctx.wallet_manager().balance_by_keychain()?;
     Ok(json::json!({
         "transactions": txs,
+        "onchain_fee_reserve_sat": ctx.conf().onchain_fee_reserve_sat,
+        "external_balance": external_balance,
+        "internal_balance": internal_balance,
+        "channels": ctx.channel_manager().channel_balances(),
     }))
 }
 
+pub fn json_list_coins(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listcoins` with request `{:?}`", request);
+    let coins = ctx.wallet_manager().list_coins()?;
+    Ok(json::to_value(response::Coins { coins })?)
+}
+
+pub fn json_tx_history(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `txhistory` with request `{:?}`", request);
+    let request: request::TxHistory = json::from_value(request.clone())?;
+    let funding_points = ctx.channel_manager().funding_outpoints()?;
+    let history =
+        history::tx_history(ctx.wallet_manager().as_ref(), &funding_points, request.kind)?;
+    Ok(json::to_value(history)?)
+}
+
+pub fn json_consolidate(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `consolidate` with request `{:?}`", request);
+    let request: request::Consolidate = json::from_value(request.clone())?;
+    if request.max_inputs == Some(0) {
+        return Err(rpc_error!(
+            LampoErrorCode::InvalidParams,
+            "`max_inputs` must be greater than zero"
+        ));
+    }
+    let fee_rate = ctx.channel_manager().clamp_feerate(request.fee_rate);
+    let tx = ctx
+        .wallet_manager()
+        .consolidate(fee_rate, request.max_inputs)
+        .map_err(|err| lampo_error!(err.code(), "{err}"))?;
+    log::info!(
+        "broadcasting consolidation `{}` of {} outputs with feerate `{fee_rate}` sats per kw",
+        tx.txid(),
+        tx.input.len()
+    );
+    ctx.onchain_manager().broadcast_transactions(&[&tx]);
+    Ok(json::to_value(response::Consolidate {
+        txid: tx.txid().to_string(),
+        inputs: tx.input.len(),
+    })?)
+}
+
+pub fn json_withdraw(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `withdraw` with request `{:?}`", request);
+    let request: request::Withdraw = json::from_value(request.clone())?;
+    let script = Address::from_str(&request.address)
+        .and_then(|address| address.require_network(ctx.conf().network))
+        .map_err(|err| {
+            rpc_error!(
+                LampoErrorCode::InvalidParams,
+                "invalid address `{}`: {err}",
+                request.address
+            )
+        })?
+        .script_pubkey();
+    let utxos = request
+        .utxos
+        .unwrap_or_default()
+        .iter()
+        .map(|utxo| {
+            OutPoint::from_str(utxo).map_err(|err| {
+                rpc_error!(
+                    LampoErrorCode::InvalidParams,
+                    "invalid output `{utxo}`, expected `txid:vout`: {err}"
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let change_address = request
+        .change_address
+        .as_ref()
+        .map(|address| {
+            Address::from_str(address)
+                .and_then(|address| address.require_network(ctx.conf().network))
+                .map_err(|err| {
+                    rpc_error!(
+                        LampoErrorCode::InvalidParams,
+                        "invalid change address `{address}`: {err}"
+                    )
+                })
+        })
+        .transpose()?;
+    let channel_manager = ctx.channel_manager();
+    channel_manager.ensure_wallet_synced()?;
+    let fee_rate = match request.fee_rate {
+        Some(fee_rate) => channel_manager.clamp_feerate(fee_rate),
+        None => {
+            let onchain = ctx.onchain_manager();
+            onchain
+                .feerate_floor
+                .apply(onchain.backend.fee_rate_estimation(6)?)
+        }
+    };
+    let mut options = TransactionOptions {
+        utxos,
+        subtract_fee_from_amount: request.subtract_fee_from_amount,
+        change_address,
+        ..Default::default()
+    };
+    channel_manager.exclude_anchor_reserve(&mut options)?;
+    let tx = ctx
+        .wallet_manager()
+        .create_transaction(script, request.amount_sat, fee_rate, options)
+        .map_err(|err| lampo_error!(err.code(), "{err}"))?;
+    log::info!(
+        "broadcasting withdraw `{}` of {} sats to `{}` with feerate `{fee_rate}` sats per kw",
+        tx.txid(),
+        request.amount_sat,
+        request.address
+    );
+    ctx.onchain_manager().broadcast_transactions(&[&tx]);
+    Ok(json::to_value(response::Withdraw {
+        txid: tx.txid().to_string(),
+        tx_hex: lampo_common::bitcoin::consensus::encode::serialize_hex(&tx),
+    })?)
+}
+
 pub fn json_estimate_fees(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `estimate_fees` with request `{:?}`", request);
     let response = ctx.onchain_manager().estimated_fees();
     Ok(json::to_value(response)?)
 }
+
+/// Sync the wallet, when the background sync is enabled
+/// wait the end of its next round instead.
+pub fn json_sync_wallet(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `syncwallet` with request {:?}", request);
+    let wallet = ctx.wallet_manager();
+    let last_sync = match ctx.sync_worker() {
+        Some(worker) => worker.wait_next(SYNC_WORKER_TIMEOUT),
+        None => wallet
+            .sync()
+            .map(|_| wallet.last_sync().unwrap_or_default()),
+    }
+    .map_err(|err| lampo_error!(err.code(), "{err}"))?;
+    Ok(json::to_value(response::WalletSync { last_sync })?)
+}
+
+/// Check the outputs of the wallet against the backend, and
+/// optionally rescan the chain to fix the discrepancies.
+pub fn json_reconcile_wallet(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `reconcilewallet` with request {:?}", request);
+    let request: request::ReconcileWallet = json::from_value(request.clone())?;
+    let backend = ctx.onchain_manager().backend.clone();
+    let discrepancies = ctx
+        .wallet_manager()
+        .reconcile(backend.as_ref(), request.rescan)
+        .map_err(|err| lampo_error!(err.code(), "{err}"))?;
+    let rescanned = request.rescan && !discrepancies.is_empty();
+    Ok(json::to_value(response::ReconcileWallet {
+        discrepancies,
+        rescanned,
+    })?)
+}
+
+/// Estimate how many blocks a transaction waits
+/// before being confirmed.
+pub fn json_tx_confirmation_eta(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `txconfirmationeta` with request {:?}", request);
+    let request: request::TxConfirmationEta = json::from_value(request.clone())?;
+    let txid = Txid::from_str(&request.txid)
+        .map_err(|err| lampo_error!(LampoErrorCode::InvalidParams, "invalid txid: {err}"))?;
+    let backend = ctx.onchain_manager().backend.clone();
+    let mut eta = response::TxConfirmationEta {
+        txid: request.txid,
+        status: response::TxConfirmationStatus::Unknown,
+        confirmations: None,
+        fee_rate: None,
+        eta_blocks: None,
+        txs_ahead: None,
+        vsize_ahead: None,
+    };
+    match backend.tx_status(&txid)? {
+        TxStatus::Confirmed { confirmations } => {
+            eta.status = response::TxConfirmationStatus::Confirmed;
+            eta.confirmations = Some(confirmations);
+        }
+        TxStatus::Mempool { fee_rate, vsize } => {
+            let position = eta::mempool_position(fee_rate, &backend.fee_histogram()?);
+            eta.status = response::TxConfirmationStatus::Mempool;
+            eta.fee_rate = Some(fee_rate);
+            eta.eta_blocks = Some(eta::eta_blocks(&position, vsize));
+            eta.txs_ahead = Some(position.txs_ahead);
+            eta.vsize_ahead = Some(position.vsize_ahead);
+        }
+        TxStatus::Unknown => {}
+    }
+    Ok(json::to_value(eta)?)
+}
+
+/// Export the labels of the wallet as BIP 329 JSON Lines.
+pub fn json_export_labels(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `exportlabels` with request {:?}", request);
+    let labels = ctx.label_store().labels();
+    Ok(json::to_value(response::ExportLabels {
+        labels: bip329::to_jsonl(&labels)?,
+        count: labels.len(),
+    })?)
+}
+
+/// Import the labels from BIP 329 JSON Lines, the labels of
+/// the same objects are overridden.
+pub fn json_import_labels(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `importlabels` with request {:?}", request);
+    let request: request::ImportLabels = json::from_value(request.clone())?;
+    let labels = bip329::parse_jsonl(&request.labels)
+        .map_err(|err| lampo_error!(LampoErrorCode::InvalidParams, "{err}"))?;
+    let imported = labels.len();
+    ctx.label_store().import(labels, request.replace)?;
+    Ok(json::to_value(response::ImportLabels { imported })?)
+}
+
+/// Return the mempool info of the backend.
+pub fn json_mempool_info(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `mempoolinfo` with request {:?}", request);
+    let backend = ctx.onchain_manager().backend.clone();
+    let info = mempool::mempool_info(backend.as_ref())?;
+    Ok(json::to_value(info)?)
+}
+
+/// Drop the last blocks of the backend, and unconfirm the transactions
+/// of the wallet and of the channels inside them. Only on regtest.
+pub fn json_simulate_reorg(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `simulatereorg` with request {:?}", request);
+    let request: request::SimulateReorg = json::from_value(request.clone())?;
+    if ctx.conf().network != Network::Regtest {
+        return Err(rpc_error!(
+            LampoErrorCode::InvalidParams,
+            "`simulatereorg` is available only on regtest"
+        ));
+    }
+    if request.blocks == 0 {
+        return Err(rpc_error!(
+            LampoErrorCode::InvalidParams,
+            "`blocks` must be at least 1"
+        ));
+    }
+    let channel_manager = ctx.channel_manager();
+    // The transactions that we watch, before the reorg drops them.
+    let mut watched = ctx
+        .wallet_manager()
+        .list_transactions()?
+        .into_iter()
+        .map(|utxo| utxo.txid)
+        .collect::<HashSet<_>>();
+    watched.extend(
+        channel_manager
+            .manager()
+            .get_relevant_txids()
+            .into_iter()
+            .chain(channel_manager.chain_monitor().get_relevant_txids())
+            .map(|(txid, _, _)| txid.to_string()),
+    );
+    let backend = ctx.onchain_manager().backend.clone();
+    let blocks = backend.invalidate_blocks(request.blocks)?;
+    let mut affected_txids = blocks
+        .iter()
+        .flat_map(|block| block.txdata.iter())
+        .map(|tx| tx.txid())
+        .filter(|txid| watched.contains(&txid.to_string()))
+        .collect::<Vec<_>>();
+
+    let (tip_hash, height) = backend.get_best_block()?;
+    let height = height.ok_or_else(|| {
+        lampo_error!(
+            LampoErrorCode::Generic,
+            "the backend does not know the height of the tip `{tip_hash}`"
+        )
+    })?;
+    let BlockData::FullBlock(tip) = backend.get_block(&tip_hash)? else {
+        return Err(lampo_error!(
+            LampoErrorCode::Generic,
+            "the tip `{tip_hash}` has no header"
+        )
+        .into());
+    };
+    // The channels see the transactions unconfirmed before the new tip,
+    // like LDK expects when the blocks are disconnected.
+    let handler = ctx.handler();
+    for txid in affected_txids.iter() {
+        handler.emit(Event::OnChain(OnChainEvent::UnconfirmedTransaction(*txid)));
+    }
+    let tip_height = Height::from_consensus(height)
+        .map_err(|err| lampo_error!(LampoErrorCode::Generic, "{err}"))?;
+    handler.emit(Event::OnChain(OnChainEvent::NewBestBlock((
+        tip.header, tip_height,
+    ))));
+    let txids = affected_txids
+        .iter()
+        .map(|txid| txid.to_string())
+        .collect::<Vec<_>>();
+    // The backend reports them again when they are confirmed.
+    backend.manage_transactions(&mut affected_txids)?;
+    ctx.wallet_manager()
+        .sync()
+        .map_err(|err| lampo_error!(err.code(), "{err}"))?;
+    Ok(json::to_value(response::SimulateReorg {
+        invalidated_blocks: blocks
+            .iter()
+            .map(|block| block.block_hash().to_string())
+            .collect(),
+        height,
+        affected_txids: txids,
+    })?)
+}
+
+/// Experimental: pay `amount_sat` to a P2WSH output that only the
+/// `destination_pubkey` can spend, and only after the timelock.
+pub fn json_create_timelock_output(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `createtimelockoutput` with request {:?}", request);
+    if !ctx.conf().allow_unsafe_rpc {
+        return Err(rpc_error!(
+            "`createtimelockoutput` is experimental and it is disabled, set `allow-unsafe-rpc=true` to enable it"
+        ));
+    }
+    let request: request::CreateTimelockOutput = json::from_value(request.clone())?;
+    let pubkey = PublicKey::from_str(&request.destination_pubkey).map_err(|err| {
+        rpc_error!(
+            LampoErrorCode::InvalidParams,
+            "invalid destination pubkey `{}`: {err}",
+            request.destination_pubkey
+        )
+    })?;
+    if !pubkey.compressed {
+        return Err(rpc_error!(
+            LampoErrorCode::InvalidParams,
+            "the destination pubkey must be compressed"
+        ));
+    }
+    let timelock = Timelock::new(request.locktime_or_blocks, request.absolute)
+        .map_err(|err| rpc_error!(LampoErrorCode::InvalidParams, "{err}"))?;
+    let redeem_script = timelock.redeem_script(&pubkey);
+    let address = Address::p2wsh(&redeem_script, ctx.conf().network);
+    let script = address.script_pubkey();
+
+    let channel_manager = ctx.channel_manager();
+    channel_manager.ensure_wallet_synced()?;
+    let fee_rate = match request.fee_rate {
+        Some(fee_rate) => channel_manager.clamp_feerate(fee_rate),
+        None => {
+            let onchain = ctx.onchain_manager();
+            onchain
+                .feerate_floor
+                .apply(onchain.backend.fee_rate_estimation(6)?)
+        }
+    };
+    let mut options = TransactionOptions::default();
+    channel_manager.exclude_anchor_reserve(&mut options)?;
+    let tx = ctx
+        .wallet_manager()
+        .create_transaction(script.clone(), request.amount_sat, fee_rate, options)
+        .map_err(|err| lampo_error!(err.code(), "{err}"))?;
+    // SAFETY: the wallet always pays the requested script.
+    let vout = tx
+        .output
+        .iter()
+        .position(|output| output.script_pubkey == script)
+        .unwrap() as u32;
+    log::warn!(
+        "broadcasting `{}` that locks {} sats with {:?} to `{address}`",
+        tx.txid(),
+        request.amount_sat,
+        timelock
+    );
+    ctx.onchain_manager().broadcast_transactions(&[&tx]);
+    Ok(json::to_value(response::CreateTimelockOutput {
+        txid: tx.txid().to_string(),
+        tx_hex: lampo_common::bitcoin::consensus::encode::serialize_hex(&tx),
+        vout,
+        address: address.to_string(),
+        redeem_script: format!("{:x}", redeem_script),
+        amount_sat: request.amount_sat,
+    })?)
+}
+
+/// Sweep all the confirmed outputs of an external private key to the
+/// wallet, or to `destination`. The key is never stored.
+pub fn json_sweep_private_key(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    // The request has the private key, so only the method is logged,
+    // the server registers it as sensitive to not log the params.
+    log::info!("call for `sweepprivatekey`");
+    if !ctx.conf().allow_unsafe_rpc {
+        return Err(rpc_error!(
+            "`sweepprivatekey` handles an external private key and it is disabled, set `allow-unsafe-rpc=true` to enable it"
+        ));
+    }
+    let request: request::SweepPrivateKey = json::from_value(request.clone())?;
+    let network = ctx.conf().network;
+    let key = PrivateKey::from_wif(&request.wif)
+        .map_err(|err| rpc_error!(LampoErrorCode::InvalidParams, "invalid wif: {err}"))?;
+    // The wif of the test networks is the same.
+    if (key.network == Network::Bitcoin) != (network == Network::Bitcoin) {
+        return Err(rpc_error!(
+            LampoErrorCode::InvalidParams,
+            "the key is for `{}` while the node is on `{network}`",
+            key.network
+        ));
+    }
+    let destination = match request.destination {
+        Some(destination) => destination,
+        None => ctx.wallet_manager().get_onchain_address()?.address,
+    };
+    let script = Address::from_str(&destination)
+        .and_then(|address| address.require_network(network))
+        .map_err(|err| {
+            rpc_error!(
+                LampoErrorCode::InvalidParams,
+                "invalid destination `{destination}`: {err}"
+            )
+        })?
+        .script_pubkey();
+    let onchain = ctx.onchain_manager();
+    let fee_rate = match request.fee_rate {
+        Some(fee_rate) => ctx.channel_manager().clamp_feerate(fee_rate),
+        None => onchain
+            .feerate_floor
+            .apply(onchain.backend.fee_rate_estimation(6)?),
+    };
+    let secp = Secp256k1::new();
+    let utxos = onchain
+        .backend
+        .scan_utxos(&key_sweep::key_scripts(&secp, &key))?;
+    if utxos.is_empty() {
+        return Err(rpc_error!(
+            LampoErrorCode::InsufficientFunds,
+            "the key has no confirmed outputs to sweep"
+        ));
+    }
+    let (tx, fee_sat) = key_sweep::create_sweep_transaction(&secp, &key, &utxos, script, fee_rate)
+        .map_err(|err| lampo_error!(LampoErrorCode::InsufficientFunds, "{err}"))?;
+    log::info!(
+        "broadcasting sweep `{}` of {} outputs with feerate `{fee_rate}` sats per kw",
+        tx.txid(),
+        utxos.len()
+    );
+    onchain.broadcast_transactions(&[&tx]);
+    Ok(json::to_value(response::SweepPrivateKey {
+        txid: tx.txid().to_string(),
+        tx_hex: lampo_common::bitcoin::consensus::encode::serialize_hex(&tx),
+        inputs: utxos.len(),
+        amount_sat: tx.output[0].value,
+        fee_sat,
+        destination,
+    })?)
+}
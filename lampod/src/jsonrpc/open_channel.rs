@@ -1,15 +1,34 @@
 //! Open Channel RPC Method implementation
+use std::time::{Duration, Instant};
 
+use lampo_common::bitcoin::Txid;
+use lampo_common::error;
+use lampo_common::error::LampoErrorCode;
+use lampo_common::event::ln::LightningEvent;
+use lampo_common::event::Event;
+use lampo_common::handler::Handler;
 use lampo_common::json;
+use lampo_common::ldk::ln::PaymentHash;
 use lampo_common::model::request;
+use lampo_common::model::response::PaymentState;
+use lampo_common::types::NodeId;
 use lampo_jsonrpc::errors::Error;
+use lampo_jsonrpc::errors::RpcError;
 
+use crate::lampo_error;
 use crate::ln::events::ChannelEvents;
+use crate::rpc_error;
 use crate::LampoDaemon;
 
 pub fn json_open_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `openchannel` with request {:?}", request);
     let request: request::OpenChannel = json::from_value(request.clone())?;
+    if request.dry_run && request.then_keysend_msat.is_some() {
+        return Err(rpc_error!(
+            LampoErrorCode::InvalidParams,
+            "`then_keysend_msat` can not be used with a dry run"
+        ));
+    }
 
     // LDK's `create_channel()` doesn't check if you are currently connected
     // to the given peer so we need to check ourselves
@@ -29,6 +48,115 @@ pub fn json_open_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<jso
     // - When there is an error how we return back to the user?
     // - In this case there is some feedback that ldk need to give us
     // before return the message, so we should design a solution for this.
-    let resp = ctx.channel_manager().open_channel(request)?;
+    let node_id = request.node_id()?;
+    let then_keysend_msat = request.then_keysend_msat;
+    let timeout = Duration::from_secs(request.then_keysend_timeout_secs.unwrap_or(3600));
+    let events = ctx.handler().events();
+    let mut resp = ctx.channel_manager().open_channel(request)?;
+    if let (Some(amount_msat), Some(txid)) = (then_keysend_msat, resp.txid) {
+        let deadline = Instant::now() + timeout;
+        let channel_id = wait_usable_channel(ctx, &events, node_id, txid, deadline)?;
+        // The keysend goes through the new channel only.
+        let payment_hash = ctx
+            .offchain_manager()
+            .keysend(node_id, amount_msat, &[], Some(1))?;
+        let state = wait_keysend(&events, &payment_hash, deadline)?;
+        resp.channel_id = Some(channel_id);
+        resp.keysend_payment_hash = Some(payment_hash.to_string());
+        resp.keysend_state = Some(state);
+    }
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_cancel_open(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `cancelopen` with request {:?}", request);
+    let request: request::CancelOpen = json::from_value(request.clone())?;
+    let resp = ctx.channel_manager().cancel_open(&request.channel_id)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_min_channel_capacity(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `minchannelcapacity` with request {:?}", request);
+    let request: request::MinChannelCapacity = json::from_value(request.clone())?;
+    let node_id = request.node_id()?;
+    // The features of the peer are known only while it is connected.
+    let peer_anchors = node_id
+        .and_then(|node_id| ctx.peer_manager().manager().peer_by_node_id(&node_id))
+        .map(|peer| peer.init_features.supports_anchors_zero_fee_htlc_tx());
+    let resp = ctx
+        .channel_manager()
+        .min_channel_capacity(node_id, peer_anchors);
     Ok(json::to_value(resp)?)
 }
+
+/// Wait until the channel funded by `txid` is usable, that is
+/// right after the open for a 0-conf channel, and return its id.
+fn wait_usable_channel(
+    ctx: &LampoDaemon,
+    events: &lampo_common::chan::Receiver<Event>,
+    node_id: NodeId,
+    txid: Txid,
+    deadline: Instant,
+) -> error::Result<String> {
+    let usable_channel = || {
+        ctx.channel_manager()
+            .manager()
+            .list_usable_channels()
+            .into_iter()
+            .find(|channel| {
+                channel.counterparty.node_id == node_id
+                    && channel.funding_txo.map(|txo| txo.txid) == Some(txid)
+            })
+            .map(|channel| channel.channel_id.to_string())
+    };
+    loop {
+        if let Some(channel_id) = usable_channel() {
+            return Ok(channel_id);
+        }
+        // A channel becomes usable only after a `ChannelReady` event.
+        loop {
+            let event = events.recv_deadline(deadline).map_err(|_| {
+                lampo_error!(
+                    LampoErrorCode::Generic,
+                    "the channel funded by `{txid}` is not usable yet, the keysend was not sent"
+                )
+            })?;
+            if let Event::Lightning(LightningEvent::ChannelReady { .. }) = event {
+                break;
+            }
+        }
+    }
+}
+
+/// Wait until the keysend with `payment_hash` succeeds or fails.
+fn wait_keysend(
+    events: &lampo_common::chan::Receiver<Event>,
+    payment_hash: &PaymentHash,
+    deadline: Instant,
+) -> error::Result<PaymentState> {
+    loop {
+        let event = events.recv_deadline(deadline).map_err(|_| {
+            lampo_error!(
+                LampoErrorCode::Generic,
+                "keysend `{payment_hash}` is still pending"
+            )
+        })?;
+        let Event::Lightning(LightningEvent::PaymentEvent {
+            payment_hash: event_hash,
+            state,
+            ..
+        }) = event
+        else {
+            continue;
+        };
+        if event_hash != Some(payment_hash.to_string()) {
+            continue;
+        }
+        if let PaymentState::Success | PaymentState::Failure = state {
+            return Ok(state);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 //! Peer Control JSON RPC Interface!
 use lampo_common::json;
+use lampo_common::model::request;
 use lampo_common::model::Connect;
 use lampo_jsonrpc::errors::Error;
 
@@ -14,3 +15,34 @@ pub fn json_connect(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Va
     ctx.rt.block_on(ctx.peer_manager().connect(node_id, host))?;
     Ok(request.clone())
 }
+
+pub fn json_list_stored_peers(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `storedpeers` with request `{:?}`", request);
+    let peers = ctx.peer_manager().stored_peers()?;
+    Ok(json::to_value(peers)?)
+}
+
+pub fn json_forget_peer(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `forgetpeer` with request `{:?}`", request);
+    let input: request::ForgetPeer = json::from_value(request.clone())?;
+    ctx.peer_manager().forget_peer(input.node_id()?)?;
+    Ok(request.clone())
+}
+
+pub fn json_list_addresses(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listaddresses` with request `{:?}`", request);
+    let addresses = ctx.peer_manager().list_addresses()?;
+    Ok(json::to_value(addresses)?)
+}
+
+pub fn json_gossip_sync(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `gossipsync` with request `{:?}`", request);
+    let input: request::GossipSync = json::from_value(request.clone())?;
+    let sync = ctx
+        .rt
+        .block_on(ctx.peer_manager().gossip_sync(input.node_id()?))?;
+    Ok(json::to_value(sync)?)
+}
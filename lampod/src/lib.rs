@@ -22,26 +22,30 @@ pub mod persistence;
 use std::cell::Cell;
 use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use tokio::runtime::Runtime;
 
-use lampo_common::backend::Backend;
+use lampo_common::backend::{Backend, BlockArchive};
 use lampo_common::bitcoin::absolute::Height;
-use lampo_common::conf::LampoConf;
+use lampo_common::conf::{LampoConf, ANCHORS_FEATURE_BIT, SCID_PRIVACY_FEATURE_BIT};
 use lampo_common::error;
 use lampo_common::json;
 use lampo_common::ldk::events::Event;
 use lampo_common::ldk::processor::{BackgroundProcessor, GossipSync};
 use lampo_common::ldk::routing::gossip::P2PGossipSync;
+use lampo_common::ldk::util::config::MaxDustHTLCExposure;
 use lampo_common::utils;
-use lampo_common::wallet::WalletManager;
+use lampo_common::wallet::{SyncWorker, WalletManager};
 
 use crate::actions::handler::LampoHandler;
 use crate::actions::Handler;
+use crate::chain::pruned::{enable_block_archive, EsploraArchive};
 use crate::chain::LampoChainManager;
 use crate::handler::external_handler::ExternalHandler;
 use crate::ln::OffchainManager;
 use crate::ln::{LampoChannelManager, LampoInventoryManager, LampoPeerManager};
+use crate::persistence::labels::LabelStore;
 use crate::persistence::LampoPersistence;
 use crate::utils::logger::LampoLogger;
 
@@ -62,9 +66,11 @@ pub struct LampoDaemon {
     channel_manager: Option<Arc<LampoChannelManager>>,
     inventory_manager: Option<Arc<LampoInventoryManager>>,
     wallet_manager: Arc<dyn WalletManager>,
+    sync_worker: Option<Arc<SyncWorker>>,
     offchain_manager: Option<Arc<OffchainManager>>,
     logger: Arc<LampoLogger>,
     persister: Arc<LampoPersistence>,
+    label_store: Option<Arc<LabelStore>>,
     handler: Option<Arc<LampoHandler>>,
     process: Cell<Option<BackgroundProcessor>>,
 
@@ -78,9 +84,15 @@ unsafe impl Sync for LampoDaemon {}
 impl LampoDaemon {
     pub fn new(config: LampoConf, wallet_manager: Arc<dyn WalletManager>) -> Self {
         let root_path = config.path();
-        //FIXME: sync some where else
-        let wallet = wallet_manager.clone();
-        let _ = std::thread::spawn(move || wallet.sync().unwrap());
+        let sync_worker = if config.wallet_sync_interval_secs > 0 {
+            let interval = Duration::from_secs(config.wallet_sync_interval_secs);
+            Some(SyncWorker::spawn(wallet_manager.clone(), interval))
+        } else {
+            //FIXME: sync some where else
+            let wallet = wallet_manager.clone();
+            let _ = std::thread::spawn(move || wallet.sync().unwrap());
+            None
+        };
         LampoDaemon {
             conf: config,
             logger: Arc::new(LampoLogger {}),
@@ -90,7 +102,9 @@ impl LampoDaemon {
             channel_manager: None,
             inventory_manager: None,
             wallet_manager,
+            sync_worker,
             offchain_manager: None,
+            label_store: None,
             handler: None,
             process: Cell::new(None),
             rt: Runtime::new().unwrap(),
@@ -107,7 +121,22 @@ impl LampoDaemon {
 
     pub fn init_onchaind(&mut self, client: Arc<dyn Backend>) -> error::Result<()> {
         log::debug!(target: "lampod", "init onchaind ..");
-        let onchain_manager = LampoChainManager::new(client, self.wallet_manager.clone());
+        let mut onchain_manager = LampoChainManager::new(client, self.wallet_manager.clone());
+        let user_agent = &self.conf.esplora_user_agent;
+        let archive = match self.conf.esplora_url.as_ref() {
+            Some(url) => Some(EsploraArchive::new(url, user_agent)),
+            None => EsploraArchive::for_network(self.conf.network, user_agent),
+        }
+        .map(|archive| Arc::new(archive) as Arc<dyn BlockArchive>);
+        match enable_block_archive(onchain_manager.backend.as_ref(), archive) {
+            Ok(pruned) => onchain_manager.backend_pruned = pruned,
+            Err(err) => {
+                log::warn!(target: "lampod", "impossible to know if the backend is pruned: {err}")
+            }
+        }
+        if let Err(err) = onchain_manager.feerate_floor.refresh() {
+            log::warn!(target: "lampod", "impossible to get the min relay fee of the backend: {err}");
+        }
         self.onchain_manager = Some(Arc::new(onchain_manager));
         Ok(())
     }
@@ -118,6 +147,39 @@ impl LampoDaemon {
 
     pub fn init_channeld(&mut self) -> error::Result<()> {
         log::debug!(target: "lampod", "init channeld ...");
+        if let Some(script) = self.conf.upfront_shutdown_script()? {
+            log::info!(target: "lampod", "cooperative closes pay to `{}`", script);
+            self.conf
+                .ldk_conf
+                .channel_handshake_config
+                .commit_upfront_shutdown_pubkey = true;
+            self.wallet_manager
+                .ldk_keys()
+                .keys_manager
+                .set_shutdown_script(script);
+        }
+        self.conf.check_peer_minimum_depth()?;
+        if let Some(depth) = self.conf.minimum_depth {
+            self.conf.ldk_conf.channel_handshake_config.minimum_depth = depth;
+        }
+        if let Some(limit) = self.conf.max_dust_htlc_exposure_msat {
+            self.conf.ldk_conf.channel_config.max_dust_htlc_exposure =
+                MaxDustHTLCExposure::FixedLimitMsat(limit);
+        }
+        if self.conf.feature_disabled(ANCHORS_FEATURE_BIT) {
+            self.conf
+                .ldk_conf
+                .channel_handshake_config
+                .negotiate_anchors_zero_fee_htlc_tx = false;
+        }
+        if self.conf.feature_disabled(SCID_PRIVACY_FEATURE_BIT) {
+            self.conf
+                .ldk_conf
+                .channel_handshake_config
+                .negotiate_scid_privacy = false;
+        }
+        // We accept the inbound channels to apply the minimum depth of the peer.
+        self.conf.ldk_conf.manually_accept_inbound_channels = true;
         let mut manager = LampoChannelManager::new(
             &self.conf,
             self.logger.clone(),
@@ -167,7 +229,8 @@ impl LampoDaemon {
 
     pub fn init_peer_manager(&mut self) -> error::Result<()> {
         log::debug!(target: "lampo", "init peer manager ...");
-        let mut peer_manager = LampoPeerManager::new(&self.conf, self.logger.clone());
+        let mut peer_manager =
+            LampoPeerManager::new(&self.conf, self.logger.clone(), self.persister.clone());
         peer_manager.init(
             self.onchain_manager(),
             self.wallet_manager.clone(),
@@ -199,6 +262,22 @@ impl LampoDaemon {
         self.wallet_manager.clone()
     }
 
+    /// The worker that syncs the wallet in background, when enabled.
+    pub fn sync_worker(&self) -> Option<Arc<SyncWorker>> {
+        self.sync_worker.clone()
+    }
+
+    fn init_label_store(&mut self) -> error::Result<()> {
+        log::debug!(target: "lampod", "init label store ...");
+        self.label_store = Some(Arc::new(LabelStore::new(&self.conf.path())?));
+        Ok(())
+    }
+
+    /// The BIP 329 labels of the wallet.
+    pub fn label_store(&self) -> Arc<LabelStore> {
+        self.label_store.clone().unwrap()
+    }
+
     pub fn init_event_handler(&mut self) -> error::Result<()> {
         log::debug!(target: "lampod", "init inventory manager ...");
         let handler = LampoHandler::new(self);
@@ -221,6 +300,7 @@ impl LampoDaemon {
         self.init_offchain_manager()?;
         self.init_peer_manager()?;
         self.init_inventory_manager()?;
+        self.init_label_store()?;
         self.init_event_handler()?;
         client.set_handler(self.handler());
         self.channel_manager().set_handler(self.handler());
@@ -241,6 +321,23 @@ impl LampoDaemon {
         Ok(())
     }
 
+    /// Release the resources that outlive the process, like
+    /// the Tor hidden service, and write the network graph
+    /// and the payment scorer.
+    pub fn shutdown(&self) {
+        if let Some(peer_manager) = &self.peer_manager {
+            peer_manager.shutdown();
+        }
+        if let Some(channel_manager) = &self.channel_manager {
+            if let Err(err) = channel_manager.persist_graph() {
+                log::error!(target: "lampod", "impossible to write the network graph: {err}");
+            }
+            if let Err(err) = channel_manager.persist_scorer() {
+                log::error!(target: "lampod", "impossible to write the payment scorer: {err}");
+            }
+        }
+    }
+
     pub fn listen(self: Arc<Self>) -> error::Result<JoinHandle<std::io::Result<()>>> {
         log::info!(target: "lampod", "Starting lightning node version `{}`", env!("CARGO_PKG_VERSION"));
         let gossip_sync = Arc::new(P2PGossipSync::new(
@@ -259,8 +356,58 @@ impl LampoDaemon {
 
         log::info!(target: "lampo", "Stating onchaind");
         let _ = self.onchain_manager().backend.clone().listen();
+        let rebroadcast_interval = self.conf.rebroadcast_interval_secs;
+        if rebroadcast_interval > 0 {
+            log::info!(target: "lampo", "Starting transactions rebroadcast");
+            let _ = self
+                .onchain_manager()
+                .rebroadcaster
+                .clone()
+                .spawn(Duration::from_secs(rebroadcast_interval));
+        }
+        let feerate_floor_interval = self.conf.feerate_floor_refresh_secs;
+        if feerate_floor_interval > 0 {
+            let _ = self
+                .onchain_manager()
+                .feerate_floor
+                .clone()
+                .spawn(Duration::from_secs(feerate_floor_interval));
+        }
+        let graph_persist_interval = self.conf.graph_persist_interval_secs;
+        if graph_persist_interval > 0 {
+            let channel_manager = self.channel_manager();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(Duration::from_secs(graph_persist_interval));
+                if let Err(err) = channel_manager.persist_graph() {
+                    log::warn!(target: "lampo", "impossible to write the network graph: {err}");
+                }
+            });
+        }
+        let open_timeout = self.conf.channel_open_timeout_secs;
+        if open_timeout > 0 {
+            let channel_manager = self.channel_manager();
+            let timeout = Duration::from_secs(open_timeout);
+            // Checked often enough to abandon an open close to its timeout.
+            let interval = Duration::from_secs(open_timeout.clamp(1, 30));
+            std::thread::spawn(move || loop {
+                std::thread::sleep(interval);
+                if let Err(err) = channel_manager.abandon_stale_opens(timeout) {
+                    log::warn!(target: "lampo", "impossible to abandon the stale channel opens: {err}");
+                }
+            });
+        }
+        let probe_interval = self.conf.probe_interval_secs;
+        if probe_interval > 0 {
+            let offchain_manager = self.offchain_manager();
+            let amount_msat = self.conf.probe_amount_msat;
+            std::thread::spawn(move || loop {
+                std::thread::sleep(Duration::from_secs(probe_interval));
+                let probes = offchain_manager.probe_channels(amount_msat);
+                log::debug!(target: "lampo", "sent {probes} probes of {amount_msat} msat");
+            });
+        }
         log::info!(target: "lampo", "Starting peer manager");
-        let _ = self.peer_manager().run();
+        self.peer_manager().run()?;
         log::info!(target: "lampo", "Starting channel manager");
         let _ = self.channel_manager().listen();
 
@@ -1,40 +1,72 @@
 //! Channel Manager Implementation
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Cursor};
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use lampo_common::backend::{TxResult, TxStatus};
 use lampo_common::bitcoin::absolute::Height;
-use lampo_common::bitcoin::{BlockHash, Transaction};
-use lampo_common::conf::LampoConf;
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+use lampo_common::conf::{LampoConf, UserConfig};
 use lampo_common::error;
+use lampo_common::error::LampoErrorCode;
+use lampo_common::event::ln::LightningEvent;
 use lampo_common::event::onchain::OnChainEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
+use lampo_common::json;
 use lampo_common::keys::LampoKeysManager;
+use lampo_common::ldk::chain::chaininterface::{
+    BroadcasterInterface, ConfirmationTarget, FeeEstimator,
+};
 use lampo_common::ldk::chain::chainmonitor::ChainMonitor;
-use lampo_common::ldk::chain::channelmonitor::ChannelMonitor;
+use lampo_common::ldk::chain::channelmonitor::{Balance, ChannelMonitor};
+use lampo_common::ldk::chain::transaction::OutPoint as LdkOutPoint;
 use lampo_common::ldk::chain::{BestBlock, Confirm, Filter, Watch};
+use lampo_common::ldk::invoice::Bolt11Invoice;
 use lampo_common::ldk::ln::channelmanager::{
-    ChainParameters, ChannelManager, ChannelManagerReadArgs,
+    ChainParameters, ChannelDetails, ChannelManager, ChannelManagerReadArgs, RecentPaymentDetails,
 };
-use lampo_common::ldk::persister::fs_store::FilesystemStore;
+use lampo_common::ldk::ln::{PaymentHash, PaymentPreimage};
 use lampo_common::ldk::routing::gossip::{NetworkGraph, ReadOnlyNetworkGraph};
-use lampo_common::ldk::routing::router::DefaultRouter;
-use lampo_common::ldk::routing::scoring::{
-    ProbabilisticScorer, ProbabilisticScoringDecayParameters, ProbabilisticScoringFeeParameters,
+use lampo_common::ldk::routing::router::Path as RoutePath;
+use lampo_common::ldk::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringFeeParameters};
+use lampo_common::ldk::sign::{EntropySource, InMemorySigner};
+use lampo_common::ldk::util::config::{ChannelConfigUpdate, MaxDustHTLCExposure};
+use lampo_common::ldk::util::errors::APIError;
+use lampo_common::ldk::util::persist::{
+    read_channel_monitors, KVStore, CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+    CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE, NETWORK_GRAPH_PERSISTENCE_KEY,
+    NETWORK_GRAPH_PERSISTENCE_PRIMARY_NAMESPACE, NETWORK_GRAPH_PERSISTENCE_SECONDARY_NAMESPACE,
+    SCORER_PERSISTENCE_KEY, SCORER_PERSISTENCE_PRIMARY_NAMESPACE,
+    SCORER_PERSISTENCE_SECONDARY_NAMESPACE,
 };
-use lampo_common::ldk::sign::InMemorySigner;
-use lampo_common::ldk::util::persist::read_channel_monitors;
-use lampo_common::ldk::util::ser::ReadableArgs;
+use lampo_common::ldk::util::ser::{ReadableArgs, Writeable};
 use lampo_common::model::request;
-use lampo_common::model::response::{self, Channel, Channels};
+use lampo_common::model::response::{
+    self, BumpChannelClose, Channel, ChannelBalance, ChannelDump, ChannelFee, ChannelFees,
+    ChannelFundingTx, Channels, CloseEstimate, CloseType, ClosedChannel, ClosedChannels,
+    DustExposure, DustExposures, EstimateCloseAll, ForwardError, InvoiceState, InvoiceStatus,
+    MinChannelCapacity, PayResult, PaymentFailure, PaymentHop, PaymentState, PendingHtlc,
+    ProbeResult, RebroadcastCommitment, RecoveredChannel, VerifyChannelFunding,
+};
+use lampo_common::types::{ChannelId, ChannelState, NodeId};
+use lampo_common::utils::backup;
+use lampo_common::wallet::{TransactionOptions, WalletError};
 
+use super::LampoRouter;
 use crate::actions::handler::LampoHandler;
-use crate::chain::{LampoChainManager, WalletManager};
+use crate::chain::funding_check::verify_funding_output;
+use crate::chain::{LampoChainManager, WalletManager, FEERATE_FLOOR_SATS_PER_KW};
+use crate::lampo_error;
 use crate::ln::events::{ChangeStateChannelEvent, ChannelEvents};
+use crate::persistence::tower::{TowerClient, TowerPersister};
 use crate::persistence::LampoPersistence;
 use crate::utils::logger::LampoLogger;
 
@@ -44,7 +76,7 @@ pub type LampoChainMonitor = ChainMonitor<
     Arc<LampoChainManager>,
     Arc<LampoChainManager>,
     Arc<LampoLogger>,
-    Arc<FilesystemStore>,
+    Arc<TowerPersister>,
 >;
 
 pub type LampoArcChannelManager<M, T, F, L> = ChannelManager<
@@ -58,28 +90,95 @@ pub type LampoArcChannelManager<M, T, F, L> = ChannelManager<
     Arc<L>,
 >;
 
+/// The namespace inside the persister where the closed
+/// channels history is stored.
+const CLOSED_CHANNELS_NAMESPACE: &str = "closed_channels";
+
+/// The file where the static channel backup is written
+/// at every change of the channels state.
+const CHANNEL_BACKUP_FILE: &str = "channel.backup";
+
+/// Upper bound of the weight of a cooperative close transaction, that
+/// spends the 2-of-2 funding output to two P2WSH outputs.
+const CLOSING_TX_WEIGHT: u64 = 770;
+
+/// Weight of a commitment transaction without HTLCs, with and
+/// without the anchor outputs (the same values of LDK).
+const COMMITMENT_TX_BASE_WEIGHT: u64 = 724;
+const COMMITMENT_TX_BASE_ANCHOR_WEIGHT: u64 = 1124;
+
+/// The value of an anchor output of the commitment transaction.
+const ANCHOR_OUTPUT_VALUE_SAT: u64 = 330;
+
+/// Weight of the CPFP child that spends our anchor output with
+/// one P2WPKH input of the wallet and one change output.
+const ANCHOR_CHILD_WEIGHT: u64 = 718;
+
+/// The dust limit of our commitment transactions, that is the
+/// smallest dust limit accepted by LDK (BOLT 3 for P2WSH outputs).
+const CHANNEL_DUST_LIMIT_SAT: u64 = 354;
+
+/// The smallest channel reserve accepted by LDK.
+const MIN_CHANNEL_RESERVE_SAT: u64 = 1000;
+
+/// The reserve suggested by BOLT 2, 1% of the channel capacity,
+/// used when we do not know the one required by the peer.
+const DEFAULT_RESERVE_PROPORTIONAL_MILLIONTHS: u64 = 10_000;
+
 type LampoChannel =
     LampoArcChannelManager<LampoChainMonitor, LampoChainManager, LampoChainManager, LampoLogger>;
 
 pub type LampoGraph = NetworkGraph<Arc<LampoLogger>>;
 pub type LampoScorer = ProbabilisticScorer<Arc<LampoGraph>, Arc<LampoLogger>>;
-pub type LampoRouter = DefaultRouter<
-    Arc<LampoGraph>,
-    Arc<LampoLogger>,
-    Arc<LampoKeysManager>,
-    Arc<Mutex<LampoScorer>>,
-    ProbabilisticScoringFeeParameters,
-    LampoScorer,
->;
 
 pub struct LampoChannelManager {
     monitor: Option<Arc<LampoChainMonitor>>,
     wallet_manager: Arc<dyn WalletManager>,
     persister: Arc<LampoPersistence>,
+    /// The watchtowers that receive the channel monitors.
+    towers: Arc<TowerClient>,
     graph: Option<Arc<LampoGraph>>,
     score: Option<Arc<Mutex<LampoScorer>>>,
     handler: RefCell<Option<Arc<LampoHandler>>>,
     router: Option<Arc<LampoRouter>>,
+    /// The `user_channel_id` of the channels opened as dry run.
+    dry_run_channels: Mutex<HashSet<u128>>,
+    /// The channels that we are opening and whose funding transaction
+    /// is not broadcast yet, indexed by their temporary id and by the id
+    /// of their funding, with the temporary id and the time when the
+    /// open started.
+    pending_opens: Mutex<HashMap<ChannelId, (ChannelId, Instant)>>,
+    /// The feerate of the funding transaction chosen by the user,
+    /// indexed by `user_channel_id`.
+    funding_feerates: Mutex<HashMap<u128, u32>>,
+    /// The options used to build the funding transaction,
+    /// indexed by `user_channel_id`.
+    funding_options: Mutex<HashMap<u128, TransactionOptions>>,
+    /// The code of the wallet failure that aborted the funding
+    /// transaction, indexed by `user_channel_id`.
+    funding_failures: Mutex<HashMap<u128, LampoErrorCode>>,
+    /// The payments claimable that are waiting the preimage.
+    held_payments: Mutex<HashSet<PaymentHash>>,
+    /// The status of the invoices generated by lampo.
+    // FIXME: make them persistent.
+    invoices: Mutex<HashMap<PaymentHash, InvoiceStatus>>,
+    /// The invoices generated by lampo, used to reissue them.
+    // FIXME: make them persistent.
+    issued_invoices: Mutex<HashMap<PaymentHash, Bolt11Invoice>>,
+    /// The status of the payments sent by lampo.
+    // FIXME: make them persistent.
+    payments: Mutex<HashMap<PaymentHash, PayResult>>,
+    /// The failed attempts of the payments sent by lampo.
+    payment_failures: Mutex<HashMap<PaymentHash, Vec<PaymentFailure>>>,
+    /// The paths that delivered a part of the payments sent by lampo.
+    payment_parts: Mutex<HashMap<PaymentHash, Vec<Vec<PaymentHop>>>>,
+    /// The error of the last failed forward, by the incoming channel.
+    forward_errors: Mutex<HashMap<String, ForwardError>>,
+    /// The latest result of the probes, by the channels of their path.
+    probes: Mutex<HashMap<Vec<u64>, ProbeResult>>,
+    /// The id of the closed channels by the txid of their funding,
+    /// loaded from the persister the first time it is used.
+    closed_by_funding: Mutex<Option<HashMap<Txid, Vec<String>>>>,
 
     pub(crate) onchain: Arc<LampoChainManager>,
     pub(crate) conf: LampoConf,
@@ -103,6 +202,8 @@ impl LampoChannelManager {
         wallet_manager: Arc<dyn WalletManager>,
         persister: Arc<LampoPersistence>,
     ) -> Self {
+        let towers = Arc::new(TowerClient::new(&conf.watchtowers));
+        towers.clone().listen();
         LampoChannelManager {
             conf: conf.to_owned(),
             monitor: None,
@@ -111,11 +212,773 @@ impl LampoChannelManager {
             wallet_manager,
             logger,
             persister,
+            towers,
             handler: RefCell::new(None),
             graph: None,
             score: None,
             router: None,
+            dry_run_channels: Mutex::new(HashSet::new()),
+            pending_opens: Mutex::new(HashMap::new()),
+            funding_feerates: Mutex::new(HashMap::new()),
+            funding_options: Mutex::new(HashMap::new()),
+            funding_failures: Mutex::new(HashMap::new()),
+            held_payments: Mutex::new(HashSet::new()),
+            invoices: Mutex::new(HashMap::new()),
+            issued_invoices: Mutex::new(HashMap::new()),
+            payments: Mutex::new(HashMap::new()),
+            payment_failures: Mutex::new(HashMap::new()),
+            payment_parts: Mutex::new(HashMap::new()),
+            forward_errors: Mutex::new(HashMap::new()),
+            probes: Mutex::new(HashMap::new()),
+            closed_by_funding: Mutex::new(None),
+        }
+    }
+
+    /// Accept an inbound channel from `counterparty_node_id` with the
+    /// minimum depth configured for the peer, a depth of zero accepts
+    /// it as zero conf channel.
+    pub fn accept_inbound_channel(
+        &self,
+        temporary_channel_id: &ChannelId,
+        counterparty_node_id: &NodeId,
+    ) -> error::Result<()> {
+        if let Some(limit) = self.conf.max_channels_per_peer {
+            let channels = self
+                .manager()
+                .list_channels_with_counterparty(counterparty_node_id)
+                .len();
+            if channels >= limit {
+                log::warn!(
+                    "rejecting the channel from `{counterparty_node_id}`, it has already {channels} channels of the max {limit}"
+                );
+                return self
+                    .manager()
+                    .force_close_without_broadcasting_txn(
+                        temporary_channel_id,
+                        counterparty_node_id,
+                    )
+                    .map_err(|err| error::anyhow!("{:?}", err));
+            }
+        }
+        let user_channel_id = self.new_user_channel_id();
+        let depth = self.conf.minimum_depth(counterparty_node_id);
+        let result = if depth == 0 {
+            log::info!(
+                "accepting zero conf channel from the trusted peer `{counterparty_node_id}`"
+            );
+            self.manager()
+                .accept_inbound_channel_from_trusted_peer_0conf(
+                    temporary_channel_id,
+                    counterparty_node_id,
+                    user_channel_id,
+                )
+        } else {
+            // LDK applies the global minimum depth, the conf
+            // rejects the other depths of the peers.
+            self.manager().accept_inbound_channel(
+                temporary_channel_id,
+                counterparty_node_id,
+                user_channel_id,
+            )
+        };
+        result.map_err(|err| error::anyhow!("{:?}", err))
+    }
+
+    /// A random `user_channel_id`, so it does not collide with the
+    /// ones of the channels persisted before a restart.
+    fn new_user_channel_id(&self) -> u128 {
+        let keys = self.wallet_manager.ldk_keys().keys_manager.clone();
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&keys.get_secure_random_bytes()[..16]);
+        u128::from_be_bytes(bytes)
+    }
+
+    /// Forget the channel open, because its funding transaction
+    /// was broadcast or the channel was closed.
+    pub fn take_pending_open(&self, channel_id: &ChannelId) -> bool {
+        // SAFETY: the lock can not be poisoned.
+        let mut pending_opens = self.pending_opens.lock().unwrap();
+        let Some((temporary_channel_id, _)) = pending_opens.get(channel_id).copied() else {
+            return false;
+        };
+        pending_opens.retain(|_, (temporary, _)| *temporary != temporary_channel_id);
+        true
+    }
+
+    /// Track the pending open also with the id that the channel
+    /// takes once its funding transaction is generated.
+    pub fn fund_pending_open(&self, temporary_channel_id: &ChannelId, channel_id: ChannelId) {
+        // SAFETY: the lock can not be poisoned.
+        let mut pending_opens = self.pending_opens.lock().unwrap();
+        if let Some(open) = pending_opens.get(temporary_channel_id).copied() {
+            pending_opens.insert(channel_id, open);
+        }
+    }
+
+    /// Abandon the channel opens that are pending since more than
+    /// `timeout`, e.g. because the peer never sent `funding_signed`.
+    /// The coins reserved by the funding transaction are released
+    /// with the `DiscardFunding` event.
+    pub fn abandon_stale_opens(&self, timeout: Duration) -> error::Result<()> {
+        let mut stale: HashMap<ChannelId, Vec<ChannelId>> = HashMap::new();
+        // SAFETY: the lock can not be poisoned.
+        for (channel_id, (temporary_channel_id, started)) in
+            self.pending_opens.lock().unwrap().iter()
+        {
+            if started.elapsed() >= timeout {
+                stale
+                    .entry(*temporary_channel_id)
+                    .or_default()
+                    .push(*channel_id);
+            }
+        }
+        for (temporary_channel_id, channel_ids) in stale {
+            if !self.take_pending_open(&temporary_channel_id) {
+                continue;
+            }
+            let Some(channel) = self
+                .manager()
+                .list_channels()
+                .into_iter()
+                .find(|channel| channel_ids.contains(&channel.channel_id))
+            else {
+                continue;
+            };
+            // A channel that is funded on chain is not an open anymore.
+            if channel.is_channel_ready || channel.confirmations.unwrap_or_default() > 0 {
+                log::debug!(
+                    "channel `{}` is already funded, it is not abandoned",
+                    channel.channel_id
+                );
+                continue;
+            }
+            let peer_id = channel.counterparty.node_id;
+            let message = format!(
+                "Channel Opening Error: open of channel `{}` with `{peer_id}` abandoned after {} seconds",
+                channel.channel_id,
+                timeout.as_secs()
+            );
+            log::warn!("{message}");
+            self.handler()
+                .emit(Event::Lightning(LightningEvent::ChannelEvent {
+                    channel_id: channel.channel_id,
+                    state: ChannelState::OpeningError,
+                    message,
+                }));
+            self.manager()
+                .close_channel(&channel.channel_id, &peer_id)
+                .map_err(|err| error::anyhow!("{:?}", err))?;
+        }
+        Ok(())
+    }
+
+    /// Abort the open of a channel whose funding transaction is not
+    /// broadcast yet, LDK gives back the funding transaction with a
+    /// `DiscardFunding` event and its coins are released.
+    ///
+    /// The open is forgotten when the `ChannelClosed` event is handled.
+    pub fn cancel_open(&self, channel_id: &str) -> error::Result<response::CancelOpen> {
+        let channel = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .find(|channel| channel.channel_id.to_string() == channel_id)
+            .ok_or(lampo_error!(
+                LampoErrorCode::ChannelNotFound,
+                "channel `{channel_id}` not found"
+            ))?;
+        // SAFETY: the lock can not be poisoned.
+        if !self
+            .pending_opens
+            .lock()
+            .unwrap()
+            .contains_key(&channel.channel_id)
+        {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "the funding transaction of channel `{channel_id}` was already broadcast"
+            ));
+        }
+        let peer_id = channel.counterparty.node_id;
+        self.manager()
+            .close_channel(&channel.channel_id, &peer_id)
+            .map_err(|err| match err {
+                APIError::ChannelUnavailable { err } => {
+                    lampo_error!(LampoErrorCode::ChannelNotFound, "{err}")
+                }
+                _ => error::anyhow!("{:?}", err),
+            })?;
+        log::info!("open of channel `{channel_id}` with `{peer_id}` cancelled");
+        Ok(response::CancelOpen {
+            channel_id: channel_id.to_owned(),
+            peer_id: peer_id.to_string(),
+        })
+    }
+
+    /// Return true if the channel was opened as dry run, and
+    /// forget about it.
+    pub fn take_dry_run(&self, user_channel_id: u128) -> bool {
+        // SAFETY: the lock can not be poisoned.
+        self.dry_run_channels
+            .lock()
+            .unwrap()
+            .remove(&user_channel_id)
+    }
+
+    /// Return the funding feerate chosen when the channel was opened.
+    pub fn take_funding_feerate(&self, user_channel_id: u128) -> Option<u32> {
+        // SAFETY: the lock can not be poisoned.
+        self.funding_feerates
+            .lock()
+            .unwrap()
+            .remove(&user_channel_id)
+    }
+
+    /// Return the options used to build the funding
+    /// transaction of the channel, and forget about them.
+    pub fn take_funding_options(&self, user_channel_id: u128) -> TransactionOptions {
+        // SAFETY: the lock can not be poisoned.
+        self.funding_options
+            .lock()
+            .unwrap()
+            .remove(&user_channel_id)
+            .unwrap_or_default()
+    }
+
+    /// Remember why the wallet was not able to build the funding
+    /// transaction, so `open_channel` reports it with its code.
+    pub fn set_funding_failure(&self, user_channel_id: u128, code: LampoErrorCode) {
+        // SAFETY: the lock can not be poisoned.
+        self.funding_failures
+            .lock()
+            .unwrap()
+            .insert(user_channel_id, code);
+    }
+
+    fn take_funding_failure(&self, user_channel_id: u128) -> Option<LampoErrorCode> {
+        // SAFETY: the lock can not be poisoned.
+        self.funding_failures
+            .lock()
+            .unwrap()
+            .remove(&user_channel_id)
+    }
+
+    /// The value that a UTXO of the anchor reserve must have to pay
+    /// a CPFP of the commitment transaction at `max-feerate-per-kw`.
+    pub fn anchor_reserve_sat(&self) -> u64 {
+        self.conf.max_feerate_per_kw as u64
+            * (COMMITMENT_TX_BASE_ANCHOR_WEIGHT + ANCHOR_CHILD_WEIGHT)
+            / 1000
+    }
+
+    /// Return the `anchor-reserve-utxos` UTXOs that are kept to bump the
+    /// commitment transactions while anchor channels are open, the smallest
+    /// confirmed ones that pay a worst-case CPFP.
+    pub fn anchor_reserve(&self) -> error::Result<Vec<OutPoint>> {
+        let reserve_utxos = self.conf.anchor_reserve_utxos;
+        let anchor_channels = self
+            .manager()
+            .list_channels()
+            .iter()
+            .filter(|channel| {
+                channel
+                    .channel_type
+                    .as_ref()
+                    .is_some_and(|features| features.supports_anchors_zero_fee_htlc_tx())
+            })
+            .count();
+        if anchor_channels == 0 || reserve_utxos == 0 {
+            return Ok(Vec::new());
+        }
+        let reserve_sat = self.anchor_reserve_sat();
+        let mut candidates = self
+            .wallet_manager
+            .list_transactions()?
+            .into_iter()
+            .filter(|utxo| !utxo.reserved && utxo.confirmed > 0)
+            .filter(|utxo| utxo.amount_msat / 1000 >= reserve_sat)
+            .collect::<Vec<_>>();
+        if (candidates.len() as u64) < reserve_utxos {
+            log::warn!(
+                "{anchor_channels} anchor channels are open but the wallet has only {} UTXOs of at least {reserve_sat} sats, \
+                 {reserve_utxos} must be kept to bump the commitment transactions",
+                candidates.len()
+            );
+            return Err(lampo_error!(
+                LampoErrorCode::InsufficientFunds,
+                data: json::json!({
+                    "reserve_utxos": candidates.len(),
+                    "anchor_reserve_utxos": reserve_utxos,
+                    "anchor_reserve_sat": reserve_sat,
+                    "anchor_channels": anchor_channels,
+                }),
+                "the wallet has {} UTXOs of at least {reserve_sat} sats, less than the {reserve_utxos} UTXOs reserved for the anchor channels",
+                candidates.len()
+            ));
+        }
+        candidates.sort_by_key(|utxo| utxo.amount_msat);
+        candidates
+            .into_iter()
+            .take(reserve_utxos as usize)
+            .map(|utxo| {
+                OutPoint::from_str(&format!("{}:{}", utxo.txid, utxo.vout))
+                    .map_err(error::Error::from)
+            })
+            .collect()
+    }
+
+    /// Keep the coin selection of `options` away from the anchor
+    /// reserve, and refuse to spend the outputs of the reserve.
+    pub fn exclude_anchor_reserve(&self, options: &mut TransactionOptions) -> error::Result<()> {
+        let reserve = self.anchor_reserve()?;
+        if let Some(utxo) = options.utxos.iter().find(|utxo| reserve.contains(utxo)) {
+            return Err(lampo_error!(
+                LampoErrorCode::InsufficientFunds,
+                data: json::json!({
+                    "utxo": utxo.to_string(),
+                    "anchor_reserve_sat": self.anchor_reserve_sat(),
+                }),
+                "the output `{utxo}` is reserved to bump the commitment transactions of the anchor channels"
+            ));
+        }
+        options.excluded_utxos.extend(reserve);
+        Ok(())
+    }
+
+    /// Make sure that the wallet was synced inside the staleness
+    /// window, otherwise try to sync it now.
+    pub fn ensure_wallet_synced(&self) -> error::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let fresh = self.wallet_manager.last_sync().is_some_and(|last_sync| {
+            now.saturating_sub(last_sync) <= self.conf.wallet_sync_staleness_secs
+        });
+        if fresh {
+            return Ok(());
+        }
+        self.wallet_manager.sync().map_err(|err| {
+            lampo_error!(
+                LampoErrorCode::WalletNotSynced,
+                data: json::json!({ "last_sync": self.wallet_manager.last_sync() }),
+                "wallet not synced, try again: {err}"
+            )
+        })
+    }
+
+    /// Build the LDK configuration of the channel with the HTLC
+    /// minimum and the in flight limit chosen by the user.
+    fn channel_config(&self, open_channel: &request::OpenChannel) -> error::Result<UserConfig> {
+        let mut config = self.conf.ldk_conf;
+        let capacity_msat = open_channel.amount * 1000;
+        let min = open_channel.htlc_minimum_msat;
+        let max = open_channel.max_htlc_value_in_flight_msat;
+        if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                return Err(lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "`htlc_minimum_msat` {min} is bigger than `max_htlc_value_in_flight_msat` {max}"
+                ));
+            }
+        }
+        if let Some(min) = min {
+            if min > capacity_msat {
+                return Err(lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "`htlc_minimum_msat` {min} is bigger than the channel capacity"
+                ));
+            }
+            config.channel_handshake_config.our_htlc_minimum_msat = min;
         }
+        if let Some(max) = max {
+            if max > capacity_msat {
+                return Err(lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "`max_htlc_value_in_flight_msat` {max} is bigger than the channel capacity"
+                ));
+            }
+        }
+        // LDK rejects the channels without capacity by itself.
+        if let Some(max) = max.filter(|_| capacity_msat > 0) {
+            // LDK accept the limit as percentage of the channel, between 1% and 100%,
+            // and it rejects every HTLC that brings the value in flight above it.
+            let percent = (max * 100 / capacity_msat).clamp(1, 100) as u8;
+            if capacity_msat * percent as u64 / 100 > max {
+                return Err(lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "the in flight limit of {max} msat is below 1% of the channel capacity"
+                ));
+            }
+            config
+                .channel_handshake_config
+                .max_inbound_htlc_value_in_flight_percent_of_channel = percent;
+        }
+        Ok(config)
+    }
+
+    /// The capacity of a channel funded with `percent` of the spendable
+    /// balance, that is the balance without the on chain fee reserve and
+    /// the fee of a funding transaction that spends every UTXO.
+    fn funding_amount(&self, percent: u64, funding_feerate: Option<u32>) -> error::Result<u64> {
+        let feerate = match funding_feerate {
+            Some(feerate) => self.clamp_feerate(feerate),
+            None => self
+                .onchain
+                .feerate_floor
+                .apply(self.onchain.backend.fee_rate_estimation(6)?),
+        };
+        let utxos = self
+            .wallet_manager
+            .list_transactions()?
+            .into_iter()
+            .filter(|utxo| !utxo.reserved)
+            .collect::<Vec<_>>();
+        let balance_sat = utxos
+            .iter()
+            .map(|utxo| utxo.amount_msat / 1000)
+            .sum::<u64>();
+        // A P2WPKH input weights 272 WU, the P2WSH funding output 172 WU,
+        // the change output 124 WU and the rest of the transaction 42 WU.
+        let weight = 42 + 172 + 124 + 272 * utxos.len() as u64;
+        let fee_sat = weight * feerate as u64 / 1000;
+        let spendable_sat = balance_sat
+            .saturating_sub(self.conf.onchain_fee_reserve_sat)
+            .saturating_sub(fee_sat);
+        let amount = spendable_sat * percent / 100;
+        if amount == 0 {
+            return Err(lampo_error!(
+                LampoErrorCode::InsufficientFunds,
+                "there is no spendable balance to fund the channel (balance {balance_sat} sats, reserve {} sats, fee {fee_sat} sats)",
+                self.conf.onchain_fee_reserve_sat
+            ));
+        }
+        log::info!(
+            "funding the channel with {percent}% of the spendable balance of {spendable_sat} sats: {amount} sats"
+        );
+        Ok(amount)
+    }
+
+    /// Clamp the feerate chosen by the user to the configured bounds,
+    /// the min relay fee of the backend wins over the maximum.
+    pub(crate) fn clamp_feerate(&self, feerate: u32) -> u32 {
+        let feerate = feerate.clamp(
+            FEERATE_FLOOR_SATS_PER_KW,
+            self.conf.max_feerate_per_kw.max(FEERATE_FLOOR_SATS_PER_KW),
+        );
+        self.onchain.feerate_floor.apply(feerate)
+    }
+
+    /// The biggest HTLC of the claimable payment with `payment_hash` when
+    /// it is above `max-inbound-htlc-msat`, so the payment must be failed.
+    pub fn oversized_htlc(&self, payment_hash: &PaymentHash) -> Option<u64> {
+        let cap = self.conf.max_inbound_htlc_msat?;
+        self.manager()
+            .list_channels()
+            .iter()
+            .flat_map(|channel| channel.pending_inbound_htlcs.iter())
+            .filter(|htlc| htlc.payment_hash == *payment_hash)
+            .map(|htlc| htlc.amount_msat)
+            .filter(|amount_msat| *amount_msat > cap)
+            .max()
+    }
+
+    pub fn hold_payment(&self, payment_hash: PaymentHash) {
+        // SAFETY: the lock can not be poisoned.
+        self.held_payments.lock().unwrap().insert(payment_hash);
+    }
+
+    /// Return true if the payment was held, and forget about it.
+    pub fn take_held_payment(&self, payment_hash: &PaymentHash) -> bool {
+        // SAFETY: the lock can not be poisoned.
+        self.held_payments.lock().unwrap().remove(payment_hash)
+    }
+
+    /// Start to track the status of an invoice generated by us.
+    pub fn register_invoice(&self, invoice: &Bolt11Invoice) {
+        let payment_hash = PaymentHash(invoice.payment_hash().to_byte_array());
+        let status = InvoiceStatus {
+            payment_hash: payment_hash.to_string(),
+            state: InvoiceState::Unpaid,
+            amount_msat: invoice.amount_milli_satoshis(),
+            expires_at: invoice.expires_at().map(|expiry| expiry.as_secs()),
+            preimage: None,
+        };
+        // SAFETY: the lock can not be poisoned.
+        self.invoices.lock().unwrap().insert(payment_hash, status);
+        // SAFETY: the lock can not be poisoned.
+        self.issued_invoices
+            .lock()
+            .unwrap()
+            .insert(payment_hash, invoice.clone());
+    }
+
+    /// The invoice with `payment_hash` generated by lampo.
+    pub fn issued_invoice(&self, payment_hash: &PaymentHash) -> Option<Bolt11Invoice> {
+        // SAFETY: the lock can not be poisoned.
+        self.issued_invoices
+            .lock()
+            .unwrap()
+            .get(payment_hash)
+            .cloned()
+    }
+
+    /// Move the invoice with `payment_hash` to `state`, the payments
+    /// that we do not know (e.g. keysend) are tracked from now on.
+    pub fn update_invoice(
+        &self,
+        payment_hash: PaymentHash,
+        state: InvoiceState,
+        amount_msat: u64,
+        preimage: Option<PaymentPreimage>,
+    ) {
+        // SAFETY: the lock can not be poisoned.
+        let mut invoices = self.invoices.lock().unwrap();
+        let status = invoices
+            .entry(payment_hash)
+            .or_insert_with(|| InvoiceStatus {
+                payment_hash: payment_hash.to_string(),
+                state: InvoiceState::Unknown,
+                amount_msat: None,
+                expires_at: None,
+                preimage: None,
+            });
+        status.state = state;
+        status.amount_msat = Some(amount_msat);
+        status.preimage = preimage.map(|preimage| preimage.to_string());
+    }
+
+    /// Return the status of the invoice with `payment_hash`.
+    pub fn invoice_status(&self, payment_hash: &PaymentHash) -> InvoiceStatus {
+        // SAFETY: the lock can not be poisoned.
+        let Some(mut status) = self.invoices.lock().unwrap().get(payment_hash).cloned() else {
+            return InvoiceStatus {
+                payment_hash: payment_hash.to_string(),
+                state: InvoiceState::Unknown,
+                amount_msat: None,
+                expires_at: None,
+                preimage: None,
+            };
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if status.state == InvoiceState::Unpaid
+            && status
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= now)
+        {
+            status.state = InvoiceState::Expired;
+        }
+        status
+    }
+
+    /// Start to track the status of a payment sent by us, a payment
+    /// that failed before is tracked again as pending.
+    pub fn track_payment(&self, payment_hash: PaymentHash) {
+        // SAFETY: the lock can not be poisoned.
+        let mut payments = self.payments.lock().unwrap();
+        let payment = payments.entry(payment_hash).or_insert_with(|| PayResult {
+            path: vec![],
+            payment_hash: Some(payment_hash.to_string()),
+            state: PaymentState::Pending,
+            payment_preimage: None,
+        });
+        if let PaymentState::Failure = payment.state {
+            payment.state = PaymentState::Pending;
+        }
+    }
+
+    /// Move the payment with `payment_hash` to `state`, the `path`
+    /// and the `preimage` are updated only when they are known.
+    pub fn update_payment(
+        &self,
+        payment_hash: PaymentHash,
+        state: PaymentState,
+        path: Option<Vec<PaymentHop>>,
+        preimage: Option<PaymentPreimage>,
+    ) {
+        // SAFETY: the lock can not be poisoned.
+        let mut payments = self.payments.lock().unwrap();
+        let payment = payments.entry(payment_hash).or_insert_with(|| PayResult {
+            path: vec![],
+            payment_hash: Some(payment_hash.to_string()),
+            state: PaymentState::Pending,
+            payment_preimage: None,
+        });
+        payment.state = state;
+        if let Some(path) = path {
+            payment.path = path;
+        }
+        if let Some(preimage) = preimage {
+            payment.payment_preimage = Some(preimage.to_string());
+        }
+    }
+
+    /// Return the status of the payment with `payment_hash`,
+    /// if it was sent by us.
+    pub fn payment_status(&self, payment_hash: &PaymentHash) -> Option<PayResult> {
+        // SAFETY: the lock can not be poisoned.
+        self.payments.lock().unwrap().get(payment_hash).cloned()
+    }
+
+    /// How many outbound payments are in flight, the probes
+    /// are included because LDK tracks them as payments.
+    pub fn inflight_payments(&self) -> usize {
+        self.manager()
+            .list_recent_payments()
+            .iter()
+            .filter(|payment| {
+                matches!(
+                    payment,
+                    RecentPaymentDetails::AwaitingInvoice { .. }
+                        | RecentPaymentDetails::Pending { .. }
+                )
+            })
+            .count()
+    }
+
+    /// Store the path of a part of the payment `payment_hash` that
+    /// reached the destination.
+    pub fn record_payment_part(&self, payment_hash: PaymentHash, path: Vec<PaymentHop>) {
+        // SAFETY: the lock can not be poisoned.
+        self.payment_parts
+            .lock()
+            .unwrap()
+            .entry(payment_hash)
+            .or_default()
+            .push(path);
+    }
+
+    /// The paths of the parts of the payment `payment_hash` that
+    /// reached the destination so far.
+    pub fn payment_parts(&self, payment_hash: &PaymentHash) -> Vec<Vec<PaymentHop>> {
+        // SAFETY: the lock can not be poisoned.
+        self.payment_parts
+            .lock()
+            .unwrap()
+            .get(payment_hash)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Store the failure of an attempt to send the payment `payment_hash`.
+    pub fn record_payment_failure(&self, payment_hash: PaymentHash, failure: PaymentFailure) {
+        // SAFETY: the lock can not be poisoned.
+        self.payment_failures
+            .lock()
+            .unwrap()
+            .entry(payment_hash)
+            .or_default()
+            .push(failure);
+    }
+
+    /// Return the failures of the payment `payment_hash`, or of all
+    /// the payments, forgetting them when `clear` is true.
+    pub fn payment_failures(
+        &self,
+        payment_hash: Option<&PaymentHash>,
+        clear: bool,
+    ) -> Vec<PaymentFailure> {
+        // SAFETY: the lock can not be poisoned.
+        let mut stored = self.payment_failures.lock().unwrap();
+        let mut failures: Vec<PaymentFailure> = match (payment_hash, clear) {
+            (Some(payment_hash), true) => stored.remove(payment_hash).unwrap_or_default(),
+            (Some(payment_hash), false) => stored.get(payment_hash).cloned().unwrap_or_default(),
+            (None, true) => stored.drain().flat_map(|(_, failures)| failures).collect(),
+            (None, false) => stored.values().flatten().cloned().collect(),
+        };
+        failures.sort_by_key(|failure| failure.timestamp);
+        failures
+    }
+
+    /// Store the error of a failed forward, it replaces the
+    /// previous error of the same incoming channel.
+    pub fn record_forward_error(&self, error: ForwardError) {
+        // SAFETY: the lock can not be poisoned.
+        self.forward_errors
+            .lock()
+            .unwrap()
+            .insert(error.incoming_channel_id.clone(), error);
+    }
+
+    /// Return the error of the last failed forward of the
+    /// incoming `channel_id`, or of all the incoming channels.
+    pub fn forward_errors(&self, channel_id: Option<&str>) -> Vec<ForwardError> {
+        // SAFETY: the lock can not be poisoned.
+        let stored = self.forward_errors.lock().unwrap();
+        let mut errors = match channel_id {
+            Some(channel_id) => stored.get(channel_id).cloned().into_iter().collect(),
+            None => stored.values().cloned().collect::<Vec<_>>(),
+        };
+        errors.sort_by_key(|error| error.timestamp);
+        errors
+    }
+
+    /// Store the result of a probe through `path`, it replaces
+    /// the previous result of the same path.
+    pub fn record_probe(&self, path: &RoutePath, success: bool, failed_channel: Option<u64>) {
+        let short_channel_ids = path
+            .hops
+            .iter()
+            .map(|hop| hop.short_channel_id)
+            .collect::<Vec<_>>();
+        let result = ProbeResult {
+            destination: path
+                .hops
+                .last()
+                .map(|hop| hop.pubkey.to_string())
+                .unwrap_or_default(),
+            short_channel_ids: short_channel_ids.clone(),
+            amount_msat: path.final_value_msat(),
+            success,
+            failed_channel,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        // SAFETY: the lock can not be poisoned.
+        self.probes
+            .lock()
+            .unwrap()
+            .insert(short_channel_ids, result);
+    }
+
+    /// Return the latest result of every probed path.
+    pub fn probes(&self) -> Vec<ProbeResult> {
+        // SAFETY: the lock can not be poisoned.
+        let mut probes = self
+            .probes
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        probes.sort_by_key(|probe| probe.timestamp);
+        probes
+    }
+
+    /// Calculate the fee of a transaction that spends the wallet utxos.
+    fn transaction_fee(&self, tx: &Transaction) -> error::Result<u64> {
+        let utxos = self.wallet_manager.list_transactions()?;
+        let mut inputs_amount = 0;
+        for input in tx.input.iter() {
+            let utxo = utxos
+                .iter()
+                .find(|utxo| {
+                    utxo.txid == input.previous_output.txid.to_string()
+                        && utxo.vout == input.previous_output.vout
+                })
+                .ok_or(error::anyhow!(
+                    "input `{}` not found inside the wallet",
+                    input.previous_output
+                ))?;
+            inputs_amount += utxo.amount_msat / 1000;
+        }
+        let outputs_amount: u64 = tx.output.iter().map(|out| out.value).sum();
+        inputs_amount.checked_sub(outputs_amount).ok_or(error::anyhow!(
+            "the outputs of {outputs_amount} sats of transaction `{}` are above its inputs of {inputs_amount} sats",
+            tx.txid()
+        ))
     }
 
     pub fn set_handler(&self, handler: Arc<LampoHandler>) {
@@ -159,6 +1022,9 @@ impl LampoChannelManager {
                             &[(idx as usize, &tx)],
                             height.to_consensus_u32(),
                         );
+                        if let Err(err) = self.confirm_closing_tx(&tx, height.to_consensus_u32()) {
+                            log::error!(target: "channel_manager", "error while updating the closed channels: {err}");
+                        }
                     }
                     OnChainEvent::UnconfirmedTransaction(txid) => {
                         log::info!(target: "channel_manager", "transaction with txid `{txid}` is still unconfirmed");
@@ -180,10 +1046,41 @@ impl LampoChannelManager {
             self.onchain.clone(),
             self.logger.clone(),
             self.onchain.clone(),
-            self.persister.clone(),
+            Arc::new(TowerPersister::new(
+                self.persister.clone(),
+                self.towers.clone(),
+            )),
         )
     }
 
+    pub fn towers(&self) -> Arc<TowerClient> {
+        self.towers.clone()
+    }
+
+    /// Register the watchtower and send it the monitors of the
+    /// channels, return false when the tower is already known.
+    pub fn add_tower(&self, uri: &str) -> bool {
+        if !self.towers.add_tower(uri) {
+            return false;
+        }
+        let monitors = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .filter_map(|channel| {
+                let funding_txo = channel.funding_txo?;
+                let monitor = self.chain_monitor().get_monitor(funding_txo).ok()?;
+                Some((
+                    format!("{}:{}", funding_txo.txid, funding_txo.index),
+                    monitor.get_latest_update_id(),
+                    monitor.encode(),
+                ))
+            })
+            .collect();
+        self.towers.backup_to(uri, monitors);
+        true
+    }
+
     pub fn chain_monitor(&self) -> Arc<LampoChainMonitor> {
         self.monitor.clone().unwrap()
     }
@@ -208,11 +1105,891 @@ impl LampoChannelManager {
                 public: channel.is_public,
                 available_balance_for_send_msat: channel.outbound_capacity_msat,
                 available_balance_for_recv_msat: channel.inbound_capacity_msat,
+                inbound_capacity_msat: channel.inbound_capacity_msat,
+                outbound_capacity_msat: channel.outbound_capacity_msat,
+                next_outbound_htlc_limit_msat: channel.next_outbound_htlc_limit_msat,
+                our_amount_msat: self.our_amount_msat(&channel),
+                spendable_msat: channel.next_outbound_htlc_limit_msat,
+                reserve_sat: channel.unspendable_punishment_reserve,
+                counterparty_reserve_sat: channel.counterparty.unspendable_punishment_reserve,
+                feerate_sat_per_kw: channel.feerate_sat_per_1000_weight,
+                inbound_htlc_minimum_msat: channel.inbound_htlc_minimum_msat,
+                inbound_htlc_maximum_msat: channel.inbound_htlc_maximum_msat,
             })
             .collect();
         Channels { channels }
     }
 
+    /// Our balance inside every channel, with the part that we can send.
+    pub fn channel_balances(&self) -> Vec<ChannelBalance> {
+        self.list_channels()
+            .channels
+            .into_iter()
+            .map(|channel| ChannelBalance {
+                channel_id: channel.channel_id,
+                peer_id: channel.peer_id,
+                our_amount_msat: channel.our_amount_msat,
+                spendable_msat: channel.spendable_msat,
+                reserve_sat: channel.reserve_sat,
+            })
+            .collect()
+    }
+
+    /// Our balance that the channel monitor can claim on chain, LDK
+    /// does not report it inside the details of the channel.
+    fn our_amount_msat(&self, channel: &ChannelDetails) -> Option<u64> {
+        let monitor = self
+            .chain_monitor()
+            .get_monitor(channel.funding_txo?)
+            .ok()?;
+        let amount_sat = monitor
+            .get_claimable_balances()
+            .iter()
+            .map(|balance| match balance {
+                Balance::ClaimableOnChannelClose {
+                    amount_satoshis, ..
+                } => *amount_satoshis,
+                _ => 0,
+            })
+            .sum::<u64>();
+        Some(amount_sat * 1000)
+    }
+
+    /// The forwarding fees and the HTLC limits of every channel, or only
+    /// of `channel_id`, the default channel config is reported for the
+    /// channels that do not have their own yet.
+    pub fn list_channel_fees(&self, channel_id: Option<&str>) -> error::Result<ChannelFees> {
+        let channels = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .filter(|channel| {
+                channel_id.map_or(true, |channel_id| {
+                    channel.channel_id.to_string() == channel_id
+                })
+            })
+            .map(|channel| {
+                let config = channel.config.unwrap_or(self.conf.ldk_conf.channel_config);
+                ChannelFee {
+                    channel_id: channel.channel_id.to_string(),
+                    short_channel_id: channel.short_channel_id,
+                    peer_id: channel.counterparty.node_id.to_string(),
+                    base_fee_msat: config.forwarding_fee_base_msat,
+                    fee_ppm: config.forwarding_fee_proportional_millionths,
+                    cltv_delta: config.cltv_expiry_delta,
+                    htlc_min_msat: channel.counterparty.outbound_htlc_minimum_msat,
+                    htlc_max_msat: channel.counterparty.outbound_htlc_maximum_msat,
+                }
+            })
+            .collect::<Vec<_>>();
+        if let (Some(channel_id), true) = (channel_id, channels.is_empty()) {
+            return Err(lampo_error!(
+                LampoErrorCode::ChannelNotFound,
+                "channel `{channel_id}` not found"
+            ));
+        }
+        Ok(ChannelFees { channels })
+    }
+
+    /// Change the fee policy of a channel, LDK broadcasts
+    /// the channel update when the channel is public.
+    pub fn set_channel_fee(&self, fee: &request::SetChannelFee) -> error::Result<ChannelFee> {
+        let channel_id = &fee.channel_id;
+        let channel = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .find(|channel| channel.channel_id.to_string() == *channel_id)
+            .ok_or(lampo_error!(
+                LampoErrorCode::ChannelNotFound,
+                "channel `{channel_id}` not found"
+            ))?;
+        let update = ChannelConfigUpdate {
+            forwarding_fee_base_msat: fee.base_fee_msat,
+            forwarding_fee_proportional_millionths: fee.fee_ppm,
+            cltv_expiry_delta: fee.cltv_delta,
+            ..Default::default()
+        };
+        self.manager()
+            .update_partial_channel_config(
+                &channel.counterparty.node_id,
+                &[channel.channel_id],
+                &update,
+            )
+            .map_err(|err| match err {
+                APIError::APIMisuseError { err } => {
+                    lampo_error!(LampoErrorCode::InvalidParams, "{err}")
+                }
+                _ => error::anyhow!("{:?}", err),
+            })?;
+        let mut fees = self.list_channel_fees(Some(channel_id))?;
+        let fee = fees.channels.remove(0);
+        log::info!(
+            "fees of channel `{channel_id}` set to {} msat + {} ppm with cltv delta {}",
+            fee.base_fee_msat,
+            fee.fee_ppm,
+            fee.cltv_delta
+        );
+        Ok(fee)
+    }
+
+    /// Query the max dust HTLC exposure of a channel, or of all the channels
+    /// when `channel_id` is missing, and change it when a limit is given.
+    ///
+    /// The limit can not be above the capacity of the channel, because the
+    /// dust HTLCs are burned to fees when the channel is force closed.
+    pub fn set_dust_exposure(
+        &self,
+        request: &request::SetDustExposure,
+    ) -> error::Result<DustExposures> {
+        let channel_id = request.channel_id.as_deref();
+        let channels = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .filter(|channel| {
+                channel_id.map_or(true, |channel_id| {
+                    channel.channel_id.to_string() == channel_id
+                })
+            })
+            .collect::<Vec<_>>();
+        if let (Some(channel_id), true) = (channel_id, channels.is_empty()) {
+            return Err(lampo_error!(
+                LampoErrorCode::ChannelNotFound,
+                "channel `{channel_id}` not found"
+            ));
+        }
+        if let Some(limit) = request.max_dust_htlc_exposure_msat {
+            // Check all the channels before changing any of them.
+            for channel in channels.iter() {
+                let capacity_msat = channel.channel_value_satoshis * 1000;
+                if limit > capacity_msat {
+                    return Err(lampo_error!(
+                        LampoErrorCode::InvalidParams,
+                        "max dust HTLC exposure of {limit} msat is above the capacity of {capacity_msat} msat of channel `{}`",
+                        channel.channel_id
+                    ));
+                }
+            }
+            let update = ChannelConfigUpdate {
+                max_dust_htlc_exposure_msat: Some(MaxDustHTLCExposure::FixedLimitMsat(limit)),
+                ..Default::default()
+            };
+            for channel in channels.iter() {
+                self.manager()
+                    .update_partial_channel_config(
+                        &channel.counterparty.node_id,
+                        &[channel.channel_id],
+                        &update,
+                    )
+                    .map_err(|err| match err {
+                        APIError::APIMisuseError { err } => {
+                            lampo_error!(LampoErrorCode::InvalidParams, "{err}")
+                        }
+                        _ => error::anyhow!("{:?}", err),
+                    })?;
+                log::info!(
+                    "max dust HTLC exposure of channel `{}` set to {limit} msat",
+                    channel.channel_id
+                );
+            }
+        }
+        // Read back the configs, so we return what LDK applied.
+        let channels = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .filter(|channel| {
+                channels
+                    .iter()
+                    .any(|requested| requested.channel_id == channel.channel_id)
+            })
+            .map(|channel| {
+                let config = channel.config.unwrap_or(self.conf.ldk_conf.channel_config);
+                let (max_dust_htlc_exposure_msat, feerate_multiplier) = match config
+                    .max_dust_htlc_exposure
+                {
+                    MaxDustHTLCExposure::FixedLimitMsat(limit) => (Some(limit), None),
+                    MaxDustHTLCExposure::FeeRateMultiplier(multiplier) => (None, Some(multiplier)),
+                };
+                DustExposure {
+                    channel_id: channel.channel_id.to_string(),
+                    peer_id: channel.counterparty.node_id.to_string(),
+                    max_dust_htlc_exposure_msat,
+                    feerate_multiplier,
+                }
+            })
+            .collect();
+        Ok(DustExposures { channels })
+    }
+
+    /// Apply the values of the LDK channel config of a channel that are
+    /// given, LDK broadcasts the channel update when the channel is public.
+    pub fn update_channel_config(
+        &self,
+        request: &request::UpdateChannelConfig,
+    ) -> error::Result<response::UpdateChannelConfig> {
+        let channel_id = &request.channel_id;
+        let find_channel = || {
+            self.manager()
+                .list_channels()
+                .into_iter()
+                .find(|channel| channel.channel_id.to_string() == *channel_id)
+                .ok_or(lampo_error!(
+                    LampoErrorCode::ChannelNotFound,
+                    "channel `{channel_id}` not found"
+                ))
+        };
+        let channel = find_channel()?;
+        if let Some(limit) = request.max_dust_htlc_exposure_msat {
+            let capacity_msat = channel.channel_value_satoshis * 1000;
+            if limit > capacity_msat {
+                return Err(lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "max dust HTLC exposure of {limit} msat is above the capacity of {capacity_msat} msat"
+                ));
+            }
+        }
+        let update = ChannelConfigUpdate {
+            forwarding_fee_base_msat: request.base_fee_msat,
+            forwarding_fee_proportional_millionths: request.fee_ppm,
+            cltv_expiry_delta: request.cltv_delta,
+            max_dust_htlc_exposure_msat: request
+                .max_dust_htlc_exposure_msat
+                .map(MaxDustHTLCExposure::FixedLimitMsat),
+            force_close_avoidance_max_fee_satoshis: request.force_close_avoidance_max_fee_sat,
+        };
+        self.manager()
+            .update_partial_channel_config(
+                &channel.counterparty.node_id,
+                &[channel.channel_id],
+                &update,
+            )
+            .map_err(|err| match err {
+                APIError::APIMisuseError { err } => {
+                    lampo_error!(LampoErrorCode::InvalidParams, "{err}")
+                }
+                _ => error::anyhow!("{:?}", err),
+            })?;
+        // Read back the config, so we return what LDK applied.
+        let channel = find_channel()?;
+        let config = channel.config.unwrap_or(self.conf.ldk_conf.channel_config);
+        let (max_dust_htlc_exposure_msat, feerate_multiplier) = match config.max_dust_htlc_exposure
+        {
+            MaxDustHTLCExposure::FixedLimitMsat(limit) => (Some(limit), None),
+            MaxDustHTLCExposure::FeeRateMultiplier(multiplier) => (None, Some(multiplier)),
+        };
+        log::info!(
+            "config of channel `{channel_id}` set to {} msat + {} ppm with cltv delta {}",
+            config.forwarding_fee_base_msat,
+            config.forwarding_fee_proportional_millionths,
+            config.cltv_expiry_delta
+        );
+        Ok(response::UpdateChannelConfig {
+            channel_id: channel.channel_id.to_string(),
+            peer_id: channel.counterparty.node_id.to_string(),
+            base_fee_msat: config.forwarding_fee_base_msat,
+            fee_ppm: config.forwarding_fee_proportional_millionths,
+            cltv_delta: config.cltv_expiry_delta,
+            max_dust_htlc_exposure_msat,
+            feerate_multiplier,
+            force_close_avoidance_max_fee_sat: config.force_close_avoidance_max_fee_satoshis,
+        })
+    }
+
+    /// Dump the state of the channel with `channel_id` from
+    /// the channel manager and the channel monitor.
+    pub fn dump_channel(&self, channel_id: &str) -> error::Result<ChannelDump> {
+        let channel = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .find(|channel| channel.channel_id.to_string() == channel_id)
+            .ok_or(lampo_error!(
+                LampoErrorCode::ChannelNotFound,
+                "channel `{channel_id}` not found"
+            ))?;
+        let latest_monitor_update_id = channel.funding_txo.and_then(|funding_txo| {
+            self.chain_monitor()
+                .get_monitor(funding_txo)
+                .ok()
+                .map(|monitor| monitor.get_latest_update_id())
+        });
+        let pending_inbound_htlcs = channel
+            .pending_inbound_htlcs
+            .iter()
+            .map(|htlc| PendingHtlc {
+                htlc_id: Some(htlc.htlc_id),
+                payment_hash: htlc.payment_hash.to_string(),
+                amount_msat: htlc.amount_msat,
+                cltv_expiry: htlc.cltv_expiry,
+                state: htlc.state.as_ref().map(|state| format!("{state:?}")),
+                is_dust: htlc.is_dust,
+            })
+            .collect();
+        let pending_outbound_htlcs = channel
+            .pending_outbound_htlcs
+            .iter()
+            .map(|htlc| PendingHtlc {
+                htlc_id: htlc.htlc_id,
+                payment_hash: htlc.payment_hash.to_string(),
+                amount_msat: htlc.amount_msat,
+                cltv_expiry: htlc.cltv_expiry,
+                state: htlc.state.as_ref().map(|state| format!("{state:?}")),
+                is_dust: htlc.is_dust,
+            })
+            .collect();
+        Ok(ChannelDump {
+            channel_id: channel.channel_id.to_string(),
+            peer_id: channel.counterparty.node_id.to_string(),
+            short_channel_id: channel.short_channel_id,
+            funding_txid: channel.funding_txo.map(|txo| txo.txid.to_string()),
+            funding_vout: channel.funding_txo.map(|txo| txo.index),
+            confirmations: channel.confirmations,
+            is_outbound: channel.is_outbound,
+            is_channel_ready: channel.is_channel_ready,
+            is_usable: channel.is_usable,
+            is_public: channel.is_public,
+            shutdown_state: channel
+                .channel_shutdown_state
+                .map(|state| format!("{state:?}")),
+            latest_monitor_update_id,
+            feerate_sat_per_kw: channel.feerate_sat_per_1000_weight,
+            pending_inbound_htlcs,
+            pending_outbound_htlcs,
+        })
+    }
+
+    /// The funding transaction of the channel, from the
+    /// channel details and from the backend.
+    pub fn channel_funding_tx(&self, channel_id: &str) -> error::Result<ChannelFundingTx> {
+        let channel = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .find(|channel| channel.channel_id.to_string() == channel_id)
+            .ok_or(lampo_error!(
+                LampoErrorCode::ChannelNotFound,
+                "channel `{channel_id}` not found"
+            ))?;
+        let funding_txo = channel.funding_txo.ok_or(lampo_error!(
+            LampoErrorCode::InvalidParams,
+            "channel `{channel_id}` is not funded yet"
+        ))?;
+        // The backend may know only the transactions of our wallet.
+        let tx_hex = match self.onchain.backend.get_transaction(&funding_txo.txid) {
+            Ok(TxResult::Confirmed((tx, ..))) | Ok(TxResult::Unconfirmed(tx)) => {
+                Some(lampo_common::bitcoin::consensus::encode::serialize_hex(&tx))
+            }
+            Ok(TxResult::Discarded) => None,
+            Err(err) => {
+                log::debug!(
+                    "the backend does not know the funding `{}`: {err}",
+                    funding_txo.txid
+                );
+                None
+            }
+        };
+        Ok(ChannelFundingTx {
+            channel_id: channel_id.to_owned(),
+            funding_txid: funding_txo.txid.to_string(),
+            funding_vout: funding_txo.index,
+            confirmations: channel
+                .confirmations
+                .filter(|confirmations| *confirmations > 0),
+            tx_hex,
+        })
+    }
+
+    /// Fetch the funding transaction of the channel from the backend and
+    /// check that the funding output is worth the channel capacity.
+    pub fn verify_channel_funding(&self, channel_id: &str) -> error::Result<VerifyChannelFunding> {
+        let channel = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .find(|channel| channel.channel_id.to_string() == channel_id)
+            .ok_or(lampo_error!(
+                LampoErrorCode::ChannelNotFound,
+                "channel `{channel_id}` not found"
+            ))?;
+        let funding_txo = channel.funding_txo.ok_or(lampo_error!(
+            LampoErrorCode::InvalidParams,
+            "channel `{channel_id}` is not funded yet"
+        ))?;
+        let tx = match self.onchain.backend.get_transaction(&funding_txo.txid)? {
+            TxResult::Confirmed((tx, ..)) | TxResult::Unconfirmed(tx) => tx,
+            TxResult::Discarded => {
+                return Err(lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "the funding transaction `{}` of channel `{channel_id}` is discarded",
+                    funding_txo.txid
+                ))
+            }
+        };
+        let capacity_sat = channel.channel_value_satoshis;
+        let verified = verify_funding_output(&tx, funding_txo.index, capacity_sat);
+        if let Err(discrepancy) = &verified {
+            log::warn!("the funding of channel `{channel_id}` does not match: {discrepancy}");
+        }
+        Ok(VerifyChannelFunding {
+            channel_id: channel_id.to_owned(),
+            funding_txid: funding_txo.txid.to_string(),
+            funding_vout: funding_txo.index,
+            capacity_sat,
+            funding_output_sat: tx
+                .output
+                .get(funding_txo.index as usize)
+                .map(|output| output.value),
+            verified: verified.is_ok(),
+            discrepancy: verified.err().map(|discrepancy| discrepancy.to_string()),
+        })
+    }
+
+    /// Sign our latest commitment of the channel and broadcast it, with
+    /// the HTLC transactions that can be already spent.
+    ///
+    /// After this the channel monitor refuses any new state of the
+    /// channel, so the channel can only be force closed.
+    pub fn rebroadcast_commitment(&self, channel_id: &str) -> error::Result<RebroadcastCommitment> {
+        let channel = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .find(|channel| channel.channel_id.to_string() == channel_id)
+            .ok_or(lampo_error!(
+                LampoErrorCode::ChannelNotFound,
+                "channel `{channel_id}` not found"
+            ))?;
+        let funding_txo = channel.funding_txo.ok_or(lampo_error!(
+            LampoErrorCode::InvalidParams,
+            "channel `{channel_id}` is not funded yet"
+        ))?;
+        let txs = self
+            .chain_monitor()
+            .get_monitor(funding_txo)
+            .map_err(|_| {
+                lampo_error!(
+                    LampoErrorCode::ChannelNotFound,
+                    "monitor of channel `{channel_id}` not found"
+                )
+            })?
+            .get_latest_holder_commitment_txn(&self.logger);
+        let Some(commitment) = txs.first() else {
+            error::bail!("there is no commitment transaction for channel `{channel_id}`");
+        };
+        log::warn!(
+            "broadcasting the latest commitment `{}` of channel `{channel_id}` with {} HTLC transactions",
+            commitment.txid(),
+            txs.len() - 1
+        );
+        self.onchain
+            .broadcast_transactions(&txs.iter().collect::<Vec<_>>());
+        Ok(RebroadcastCommitment {
+            channel_id: channel_id.to_owned(),
+            txid: commitment.txid().to_string(),
+            tx_hex: lampo_common::bitcoin::consensus::encode::serialize_hex(commitment),
+        })
+    }
+
+    /// Bump the commitment of a force closed anchor channel with a child
+    /// that brings the package to `fee_rate` sats per kw.
+    pub fn bump_channel_close(
+        &self,
+        channel_id: &str,
+        fee_rate: u32,
+    ) -> error::Result<BumpChannelClose> {
+        let Some(close) = self.onchain.commitment_bumper.anchor_close(channel_id) else {
+            let channel = self
+                .manager()
+                .list_channels()
+                .into_iter()
+                .find(|channel| channel.channel_id.to_string() == channel_id);
+            return Err(match channel {
+                Some(channel)
+                    if !channel
+                        .channel_type
+                        .as_ref()
+                        .is_some_and(|features| features.supports_anchors_zero_fee_htlc_tx()) =>
+                {
+                    lampo_error!(
+                        LampoErrorCode::InvalidParams,
+                        "channel `{channel_id}` is not an anchor channel"
+                    )
+                }
+                _ => lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "channel `{channel_id}` has no spendable anchor output"
+                ),
+            });
+        };
+        let commitment_txid = close.commitment_tx.txid();
+        // Once the commitment is confirmed anyone can spend the anchor output.
+        if let TxStatus::Confirmed { .. } = self.onchain.backend.tx_status(&commitment_txid)? {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "the commitment `{commitment_txid}` of channel `{channel_id}` is already confirmed"
+            ));
+        }
+        if fee_rate as u64 <= close.commitment_feerate() {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "the commitment of channel `{channel_id}` already pays `{}` sats per kw",
+                close.commitment_feerate()
+            ));
+        }
+        let (child, child_fee_sat) =
+            self.onchain
+                .bump_commitment(&close, fee_rate)
+                .map_err(|err| match err.downcast::<WalletError>() {
+                    Ok(err) => lampo_error!(err.code(), "{err}"),
+                    Err(err) => err,
+                })?;
+        Ok(BumpChannelClose {
+            channel_id: channel_id.to_owned(),
+            commitment_txid: commitment_txid.to_string(),
+            child_txid: child.txid().to_string(),
+            child_fee_sat,
+            package_fee_rate: close.package_feerate(&child, child_fee_sat),
+        })
+    }
+
+    /// Estimate the fee of the cooperative close of every channel
+    /// at the current feerate, without closing anything.
+    ///
+    /// LDK starts the close negotiation proposing the fee at the
+    /// `ChannelCloseMinimum` feerate, and as funder accepts up to the
+    /// `NonAnchorChannelFee` feerate plus `force_close_avoidance_max_fee_satoshis`.
+    pub fn estimate_close_all(&self) -> EstimateCloseAll {
+        let min_feerate = self
+            .onchain
+            .get_est_sat_per_1000_weight(ConfirmationTarget::ChannelCloseMinimum)
+            .max(FEERATE_FLOOR_SATS_PER_KW);
+        let normal_feerate = self
+            .onchain
+            .get_est_sat_per_1000_weight(ConfirmationTarget::NonAnchorChannelFee)
+            .max(min_feerate);
+        let channels: Vec<CloseEstimate> = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .map(|channel| {
+                let fee_sat = CLOSING_TX_WEIGHT * min_feerate as u64 / 1000;
+                let mut max_fee_sat = CLOSING_TX_WEIGHT * normal_feerate as u64 / 1000;
+                if channel.is_outbound {
+                    max_fee_sat += channel
+                        .config
+                        .map(|config| config.force_close_avoidance_max_fee_satoshis)
+                        .unwrap_or_default();
+                }
+                CloseEstimate {
+                    channel_id: channel.channel_id.to_string(),
+                    peer_id: channel.counterparty.node_id.to_string(),
+                    is_funder: channel.is_outbound,
+                    feerate_sat_per_kw: min_feerate,
+                    fee_sat,
+                    max_fee_sat,
+                }
+            })
+            .collect();
+        EstimateCloseAll {
+            total_fee_sat: channels.iter().map(|channel| channel.fee_sat).sum(),
+            total_max_fee_sat: channels.iter().map(|channel| channel.max_fee_sat).sum(),
+            channels,
+        }
+    }
+
+    /// The smallest capacity of a channel opened by us that leaves both
+    /// sides with a balance above their reserve and the dust limit, after
+    /// we pay the commitment fee and the anchor outputs as funder.
+    ///
+    /// `peer_anchors` tells if the peer supports the anchor outputs, when it
+    /// is connected, and the reserve that it requires is learned from the
+    /// channels that we have with it.
+    pub fn min_channel_capacity(
+        &self,
+        node_id: Option<NodeId>,
+        peer_anchors: Option<bool>,
+    ) -> MinChannelCapacity {
+        let handshake = &self.conf.ldk_conf.channel_handshake_config;
+        let anchors = handshake.negotiate_anchors_zero_fee_htlc_tx && peer_anchors.unwrap_or(true);
+        let (target, weight) = if anchors {
+            (
+                ConfirmationTarget::AnchorChannelFee,
+                COMMITMENT_TX_BASE_ANCHOR_WEIGHT,
+            )
+        } else {
+            (
+                ConfirmationTarget::NonAnchorChannelFee,
+                COMMITMENT_TX_BASE_WEIGHT,
+            )
+        };
+        let feerate = self
+            .onchain
+            .get_est_sat_per_1000_weight(target)
+            .max(FEERATE_FLOOR_SATS_PER_KW);
+        let commitment_fee_sat = weight * feerate as u64 / 1000;
+        let anchor_outputs_sat = if anchors {
+            2 * ANCHOR_OUTPUT_VALUE_SAT
+        } else {
+            0
+        };
+
+        let peer_millionths = node_id
+            .and_then(|node_id| {
+                self.manager()
+                    .list_channels_with_counterparty(&node_id)
+                    .iter()
+                    .filter(|channel| channel.channel_value_satoshis > 0)
+                    .filter_map(|channel| {
+                        let reserve = channel.unspendable_punishment_reserve?;
+                        Some(reserve * 1_000_000 / channel.channel_value_satoshis)
+                    })
+                    .max()
+            })
+            .unwrap_or(DEFAULT_RESERVE_PROPORTIONAL_MILLIONTHS);
+        let our_millionths = handshake.their_channel_reserve_proportional_millionths as u64;
+        // The reserves above 25% are not sane, and they do not
+        // allow the capacity below to converge.
+        let reserve = |millionths: u64, capacity: u64| {
+            (capacity * millionths.min(250_000) / 1_000_000).max(MIN_CHANNEL_RESERVE_SAT)
+        };
+
+        // Both sides must keep their reserve and an output above the dust
+        // limit, the reserves grow with the capacity so we iterate until
+        // the capacity covers them.
+        let fixed_sat = commitment_fee_sat + anchor_outputs_sat + 2 * CHANNEL_DUST_LIMIT_SAT;
+        let mut min_capacity_sat = fixed_sat + 2 * MIN_CHANNEL_RESERVE_SAT;
+        loop {
+            let capacity = fixed_sat
+                + reserve(peer_millionths, min_capacity_sat)
+                + reserve(our_millionths, min_capacity_sat);
+            if capacity <= min_capacity_sat {
+                break;
+            }
+            min_capacity_sat = capacity;
+        }
+        MinChannelCapacity {
+            node_id: node_id.map(|node_id| node_id.to_string()),
+            peer_connected: peer_anchors.is_some(),
+            anchors,
+            min_capacity_sat,
+            reserve_sat: reserve(peer_millionths, min_capacity_sat),
+            counterparty_reserve_sat: reserve(our_millionths, min_capacity_sat),
+            dust_limit_sat: CHANNEL_DUST_LIMIT_SAT,
+            commitment_fee_sat,
+            anchor_outputs_sat,
+            feerate_sat_per_kw: feerate,
+        }
+    }
+
+    /// Return the encrypted static backup of all the channel monitors.
+    pub fn export_channel_backup(&self) -> error::Result<String> {
+        let mut monitors = BTreeMap::new();
+        for key in self.persister.list(
+            CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+            CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+        )? {
+            let buf = self.persister.read(
+                CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+                CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+                &key,
+            )?;
+            monitors.insert(key, buf);
+        }
+        let keys = self.wallet_manager.ldk_keys().keys_manager.clone();
+        let mut nonce = [0; backup::NONCE_LEN];
+        nonce.copy_from_slice(&keys.get_secure_random_bytes()[..backup::NONCE_LEN]);
+        backup::seal(&keys.channel_backup_key(), nonce, &monitors)
+    }
+
+    /// Write the static channel backup inside the node directory.
+    pub fn write_channel_backup(&self) -> error::Result<()> {
+        let backup = self.export_channel_backup()?;
+        std::fs::write(
+            format!("{}/{CHANNEL_BACKUP_FILE}", self.conf.path()),
+            backup,
+        )?;
+        Ok(())
+    }
+
+    /// Import the channel monitors that we lost from the static backup.
+    ///
+    /// The channels are not known by the channel manager anymore, so
+    /// when the peer sends us the `channel_reestablish` we answer with an
+    /// error, and the peer force closes the channel with its latest state.
+    /// The imported monitors are watching the chain to sweep our funds.
+    pub fn import_channel_backup(&self, backup: &str) -> error::Result<Vec<RecoveredChannel>> {
+        let keys = self.wallet_manager.ldk_keys().keys_manager.clone();
+        let monitors = backup::open(&keys.channel_backup_key(), backup)?;
+        let known = self.persister.list(
+            CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+            CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+        )?;
+        let mut recovered = Vec::new();
+        for (key, buf) in monitors {
+            if known.contains(&key) {
+                continue;
+            }
+            let (_, monitor) = <(BlockHash, ChannelMonitor<InMemorySigner>)>::read(
+                &mut Cursor::new(&buf),
+                (&*keys, &*keys),
+            )
+            .map_err(|err| error::anyhow!("{err}"))?;
+            self.persister.write(
+                CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+                CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+                &key,
+                &buf,
+            )?;
+            monitor.load_outputs_to_watch(&self.onchain, &self.logger);
+            let funding = monitor.get_funding_txo().0;
+            let peer_id = monitor.get_counterparty_node_id();
+            self.chain_monitor()
+                .watch_channel(funding, monitor)
+                .map_err(|err| error::anyhow!("{:?}", err))?;
+            log::info!("recovered the channel with funding `{funding}` from the backup");
+            recovered.push(RecoveredChannel {
+                funding_utxo: funding.to_string(),
+                peer_id: peer_id.map(|id| id.to_string()),
+                reconnected: false,
+            });
+        }
+        Ok(recovered)
+    }
+
+    /// Store the closed channel inside the persister, so
+    /// the channel history survive a restart.
+    pub fn store_closed_channel(&self, channel: &ClosedChannel) -> error::Result<()> {
+        let buf = json::to_vec(channel)?;
+        self.persister
+            .write(CLOSED_CHANNELS_NAMESPACE, "", &channel.channel_id, &buf)?;
+        // SAFETY: the lock can not be poisoned.
+        if let Some(index) = self.closed_by_funding.lock().unwrap().as_mut() {
+            if let Some(funding_utxo) = &channel.funding_utxo {
+                let channels = index
+                    .entry(OutPoint::from_str(funding_utxo)?.txid)
+                    .or_default();
+                if !channels.contains(&channel.channel_id) {
+                    channels.push(channel.channel_id.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_closed_channel(&self, channel_id: &str) -> error::Result<ClosedChannel> {
+        let buf = self
+            .persister
+            .read(CLOSED_CHANNELS_NAMESPACE, "", channel_id)?;
+        Ok(json::from_slice(&buf)?)
+    }
+
+    /// The id of the closed channels funded by one of `txids`.
+    fn closed_channels_funded_by(&self, txids: &[Txid]) -> error::Result<Vec<String>> {
+        // SAFETY: the lock can not be poisoned.
+        let mut index = self.closed_by_funding.lock().unwrap();
+        if index.is_none() {
+            let mut closed_by_funding = HashMap::<Txid, Vec<String>>::new();
+            for channel in self.list_closed_channels()?.closed_channels {
+                let Some(funding_utxo) = channel.funding_utxo else {
+                    continue;
+                };
+                closed_by_funding
+                    .entry(OutPoint::from_str(&funding_utxo)?.txid)
+                    .or_default()
+                    .push(channel.channel_id);
+            }
+            *index = Some(closed_by_funding);
+        }
+        // SAFETY: the index is loaded above.
+        let index = index.as_ref().unwrap();
+        Ok(txids
+            .iter()
+            .filter_map(|txid| index.get(txid))
+            .flatten()
+            .cloned()
+            .collect())
+    }
+
+    /// The peer broadcast a revoked commitment transaction of the channel
+    /// funded by `funding`, so the monitor claims its outputs.
+    pub fn is_breached(&self, funding: &OutPoint) -> bool {
+        let funding_txo = LdkOutPoint {
+            txid: funding.txid,
+            index: funding.vout as u16,
+        };
+        self.chain_monitor()
+            .get_monitor(funding_txo)
+            .is_ok_and(|monitor| {
+                monitor.get_claimable_balances().iter().any(|balance| {
+                    matches!(balance, Balance::CounterpartyRevokedOutputClaimable { .. })
+                })
+            })
+    }
+
+    pub fn list_closed_channels(&self) -> error::Result<ClosedChannels> {
+        let mut closed_channels = Vec::new();
+        for key in self.persister.list(CLOSED_CHANNELS_NAMESPACE, "")? {
+            let buf = self.persister.read(CLOSED_CHANNELS_NAMESPACE, "", &key)?;
+            let channel: ClosedChannel = json::from_slice(&buf)?;
+            closed_channels.push(channel);
+        }
+        Ok(ClosedChannels { closed_channels })
+    }
+
+    /// The funding outputs of our channels, the open and the closed ones.
+    pub fn funding_outpoints(&self) -> error::Result<HashSet<OutPoint>> {
+        let mut outpoints = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .filter_map(|channel| channel.funding_txo)
+            .map(|txo| txo.into_bitcoin_outpoint())
+            .collect::<HashSet<_>>();
+        for channel in self.list_closed_channels()?.closed_channels {
+            let Some(funding_utxo) = channel.funding_utxo else {
+                continue;
+            };
+            outpoints.insert(OutPoint::from_str(&funding_utxo)?);
+        }
+        Ok(outpoints)
+    }
+
+    /// Look if the confirmed transaction is spending the funding
+    /// output of a closed channel, and if so store the closing
+    /// transaction with the block context.
+    fn confirm_closing_tx(&self, tx: &Transaction, height: u32) -> error::Result<()> {
+        let txids = tx
+            .input
+            .iter()
+            .map(|input| input.previous_output.txid)
+            .collect::<Vec<_>>();
+        for channel_id in self.closed_channels_funded_by(&txids)? {
+            let mut channel = self.read_closed_channel(&channel_id)?;
+            let Some(ref funding_utxo) = channel.funding_utxo else {
+                continue;
+            };
+            let funding = OutPoint::from_str(funding_utxo)?;
+            if !tx
+                .input
+                .iter()
+                .any(|input| input.previous_output == funding)
+            {
+                continue;
+            }
+            channel.closing_txid = Some(tx.txid().to_string());
+            channel.closing_height = Some(height);
+            // The monitor has seen the transaction, so it knows
+            // if it is a revoked commitment of the peer.
+            if self.is_breached(&funding) {
+                channel.close_type = CloseType::Breach;
+            }
+            self.store_closed_channel(&channel)?;
+        }
+        Ok(())
+    }
+
     pub fn load_channel_monitors(&self, watch: bool) -> error::Result<()> {
         let keys = self.wallet_manager.ldk_keys().inner();
         let mut monitors = read_channel_monitors(self.persister.clone(), keys.clone(), keys)?;
@@ -249,19 +2026,12 @@ impl LampoChannelManager {
         self.score.clone().unwrap()
     }
 
+    pub fn router(&self) -> Arc<LampoRouter> {
+        self.router.clone().unwrap()
+    }
+
     // FIXME: Step 11: Optional: Initialize the NetGraphMsgHandler
-    pub fn network_graph(
-        &mut self,
-    ) -> Arc<
-        DefaultRouter<
-            Arc<LampoGraph>,
-            Arc<LampoLogger>,
-            Arc<LampoKeysManager>,
-            Arc<Mutex<LampoScorer>>,
-            ProbabilisticScoringFeeParameters,
-            LampoScorer,
-        >,
-    > {
+    pub fn network_graph(&mut self) -> Arc<LampoRouter> {
         if self.router.is_none() {
             // Step 9: Initialize routing ProbabilisticScorer
             let network_graph_path = format!("{}/network_graph", self.conf.path());
@@ -274,12 +2044,13 @@ impl LampoChannelManager {
 
             self.graph = Some(network_graph.clone());
             self.score = Some(scorer.clone());
-            self.router = Some(Arc::new(DefaultRouter::new(
+            self.router = Some(Arc::new(LampoRouter::new(
                 network_graph,
                 self.logger.clone(),
                 self.wallet_manager.ldk_keys().keys_manager.clone(),
                 scorer,
                 ProbabilisticScoringFeeParameters::default(),
+                self.conf.max_route_hops,
             )))
         }
         self.router.clone().unwrap()
@@ -290,7 +2061,7 @@ impl LampoChannelManager {
         path: &Path,
         graph: &Arc<LampoGraph>,
     ) -> ProbabilisticScorer<Arc<LampoGraph>, Arc<LampoLogger>> {
-        let params = ProbabilisticScoringDecayParameters::default();
+        let params = self.conf.scoring_decay_parameters();
         if let Ok(file) = File::open(path) {
             let args = (params, Arc::clone(graph), self.logger.clone());
             if let Ok(scorer) = ProbabilisticScorer::read(&mut BufReader::new(file), args) {
@@ -303,12 +2074,63 @@ impl LampoChannelManager {
     pub(crate) fn read_network(&self, path: &Path) -> Arc<LampoGraph> {
         if let Ok(file) = File::open(path) {
             if let Ok(graph) = NetworkGraph::read(&mut BufReader::new(file), self.logger.clone()) {
+                log::info!(
+                    "network graph loaded with {} channels and {} nodes",
+                    graph.read_only().channels().len(),
+                    graph.read_only().nodes().len()
+                );
                 return Arc::new(graph);
             }
+            log::warn!(
+                "impossible to read the network graph at `{}`, starting with an empty one",
+                path.display()
+            );
         }
         Arc::new(NetworkGraph::new(self.conf.network, self.logger.clone()))
     }
 
+    /// Write the network graph on disk, where it is
+    /// loaded from when the node starts.
+    pub fn persist_graph(&self) -> error::Result<()> {
+        let graph = self.graph();
+        self.persister.write(
+            NETWORK_GRAPH_PERSISTENCE_PRIMARY_NAMESPACE,
+            NETWORK_GRAPH_PERSISTENCE_SECONDARY_NAMESPACE,
+            NETWORK_GRAPH_PERSISTENCE_KEY,
+            &graph.encode(),
+        )?;
+        Ok(())
+    }
+
+    /// Forget the liquidity that the scorer learned from the payments,
+    /// so the router no longer penalizes the channels that failed.
+    pub fn reset_scorer(&self) -> error::Result<()> {
+        let scorer = self.scorer();
+        // SAFETY: the lock can not be poisoned.
+        *scorer.lock().unwrap() = ProbabilisticScorer::new(
+            self.conf.scoring_decay_parameters(),
+            self.graph(),
+            self.logger.clone(),
+        );
+        log::info!(target: "lampo", "payment scorer reset");
+        self.persist_scorer()
+    }
+
+    /// Write the scorer on disk, where it is loaded
+    /// from when the node starts.
+    pub fn persist_scorer(&self) -> error::Result<()> {
+        let scorer = self.scorer();
+        // SAFETY: the lock can not be poisoned.
+        let scorer = scorer.lock().unwrap().encode();
+        self.persister.write(
+            SCORER_PERSISTENCE_PRIMARY_NAMESPACE,
+            SCORER_PERSISTENCE_SECONDARY_NAMESPACE,
+            SCORER_PERSISTENCE_KEY,
+            &scorer,
+        )?;
+        Ok(())
+    }
+
     pub fn is_restarting(&self) -> error::Result<bool> {
         Ok(Path::exists(Path::new(&format!(
             "{}/manager",
@@ -408,31 +2230,155 @@ impl LampoChannelManager {
 impl ChannelEvents for LampoChannelManager {
     fn open_channel(
         &self,
-        open_channel: request::OpenChannel,
+        mut open_channel: request::OpenChannel,
     ) -> error::Result<response::OpenChannel> {
-        self.manager()
-            .create_channel(
-                open_channel.node_id()?,
+        self.ensure_wallet_synced()?;
+        let amount_percent = open_channel
+            .amount_percent()
+            .map_err(|err| lampo_error!(LampoErrorCode::InvalidParams, "{err}"))?;
+        if let Some(percent) = amount_percent {
+            if open_channel.amount != 0 {
+                return Err(lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "`amount` and `amount_percent` can not be used together"
+                ));
+            }
+            open_channel.amount = self.funding_amount(percent, open_channel.funding_feerate)?;
+        }
+        let config = self.channel_config(&open_channel)?;
+        let mut funding_options = TransactionOptions {
+            allow_unconfirmed: open_channel.allow_unconfirmed,
+            locktime: open_channel.locktime,
+            sequence: open_channel.sequence,
+            // While users could "cancel" a channel open by RBF-bumping and paying back to
+            // themselves, we don't allow it here as its easy to have users accidentally RBF bump
+            // and pay to the channel funding address, which results in loss of funds. Real
+            // LDK-based applications should enable RBF bumping and RBF bump either to a local
+            // change address or to a new channel output negotiated with the same node.
+            rbf: false,
+            utxos: Vec::new(),
+            subtract_fee_from_amount: false,
+            change_address: None,
+            excluded_utxos: Vec::new(),
+        };
+        funding_options
+            .validate()
+            .map_err(|err| lampo_error!(err.code(), "{err}"))?;
+        let user_channel_id = self.new_user_channel_id();
+        let node_id = open_channel.node_id()?;
+        let balance_sat = self.wallet_manager.get_onchain_balance()? / 1000;
+        let reserve_sat = self.conf.onchain_fee_reserve_sat;
+        if balance_sat.saturating_sub(open_channel.amount) < reserve_sat {
+            return Err(lampo_error!(
+                LampoErrorCode::InsufficientFunds,
+                data: json::json!({
+                    "balance_sat": balance_sat,
+                    "amount_sat": open_channel.amount,
+                    "reserve_sat": reserve_sat,
+                }),
+                "opening a channel of {} sats leaves less than the on chain fee reserve of {reserve_sat} sats (balance {balance_sat} sats)",
+                open_channel.amount
+            ));
+        }
+        self.exclude_anchor_reserve(&mut funding_options)?;
+        if open_channel.dry_run {
+            // SAFETY: the lock can not be poisoned.
+            self.dry_run_channels
+                .lock()
+                .unwrap()
+                .insert(user_channel_id);
+        }
+        if let Some(feerate) = open_channel.funding_feerate {
+            let feerate = self.clamp_feerate(feerate);
+            // SAFETY: the lock can not be poisoned.
+            self.funding_feerates
+                .lock()
+                .unwrap()
+                .insert(user_channel_id, feerate);
+        }
+        // SAFETY: the lock can not be poisoned.
+        self.funding_options
+            .lock()
+            .unwrap()
+            .insert(user_channel_id, funding_options);
+        let events = self.handler().events();
+        // Held until the open is tracked, so the funding of the
+        // channel can not be handled before.
+        // SAFETY: the lock can not be poisoned.
+        let mut pending_opens = self.pending_opens.lock().unwrap();
+        let create_channel = || {
+            self.manager().create_channel(
+                node_id,
                 open_channel.amount,
                 0,
-                0,
+                user_channel_id,
                 None,
-                Some(self.conf.ldk_conf),
+                Some(config),
             )
-            .map_err(|err| error::anyhow!("{:?}", err))?;
+        };
+        let temporary_channel_id = match open_channel.commitment_feerate {
+            Some(feerate) => self
+                .onchain
+                .with_commitment_feerate(self.clamp_feerate(feerate), create_channel),
+            None => create_channel(),
+        }
+        .map_err(|err| {
+            self.take_dry_run(user_channel_id);
+            self.take_funding_feerate(user_channel_id);
+            self.take_funding_options(user_channel_id);
+            error::anyhow!("{:?}", err)
+        })?;
+        if !open_channel.dry_run {
+            pending_opens.insert(temporary_channel_id, (temporary_channel_id, Instant::now()));
+        }
+        drop(pending_opens);
 
-        // Wait for SendRawTransaction to be received so to get the funding transaction
-        // FIXME: we can loop forever here
+        // Wait for SendRawTransaction of our funding transaction, in case
+        // of dry run the transaction is never sent so we wait the end of the
+        // funding transaction generation. The events of the other opens
+        // are skipped by the temporary id of the channel.
+        let mut funding_txid = None;
         let tx: Option<Transaction> = loop {
-            let events = self.handler().events();
             let event = events.recv_timeout(std::time::Duration::from_secs(30))?;
 
-            if let Event::OnChain(OnChainEvent::SendRawTransaction(tx)) = event {
-                break Some(tx);
+            match event {
+                Event::Lightning(LightningEvent::FundingChannelEnd {
+                    temporary_channel_id: channel_id,
+                    funding_transaction,
+                    ..
+                }) if channel_id == temporary_channel_id => {
+                    if open_channel.dry_run {
+                        break Some(funding_transaction);
+                    }
+                    funding_txid = Some(funding_transaction.txid());
+                }
+                Event::OnChain(OnChainEvent::SendRawTransaction(tx))
+                    if funding_txid == Some(tx.txid()) =>
+                {
+                    break Some(tx)
+                }
+                Event::Lightning(LightningEvent::ChannelEvent {
+                    channel_id,
+                    state: ChannelState::OpeningError,
+                    message,
+                }) if channel_id == temporary_channel_id => {
+                    match self.take_funding_failure(user_channel_id) {
+                        Some(code) => return Err(lampo_error!(code, "{message}")),
+                        None => error::bail!("{message}"),
+                    }
+                }
+                _ => continue,
             }
         };
 
         let txid = tx.as_ref().map(|tx| tx.txid());
+        let tx_hex = tx
+            .as_ref()
+            .map(lampo_common::bitcoin::consensus::encode::serialize_hex);
+        let fee_sat = match tx {
+            Some(ref tx) if open_channel.dry_run => Some(self.transaction_fee(tx)?),
+            _ => None,
+        };
 
         Ok(response::OpenChannel {
             node_id: open_channel.node_id,
@@ -442,16 +2388,43 @@ impl ChannelEvents for LampoChannelManager {
             to_self_delay: 2016,
             tx,
             txid,
+            tx_hex,
+            fee_sat,
+            channel_id: None,
+            keysend_payment_hash: None,
+            keysend_state: None,
         })
     }
 
     fn close_channel(&self, channel: request::CloseChannel) -> error::Result<()> {
         let channel_id = channel.channel_id()?;
         let node_id = channel.counterpart_node_id()?;
+        let shutdown_script = channel
+            .destination
+            .as_ref()
+            .map(|destination| {
+                self.conf.shutdown_script(destination).map_err(|err| {
+                    lampo_error!(LampoErrorCode::InvalidParams, "invalid destination: {err}")
+                })
+            })
+            .transpose()?;
 
         self.manager()
-            .close_channel(&channel_id, &node_id)
-            .map_err(|err| error::anyhow!("{:?}", err))?;
+            .close_channel_with_feerate_and_script(&channel_id, &node_id, None, shutdown_script)
+            .map_err(|err| match err {
+                APIError::ChannelUnavailable { err } => {
+                    lampo_error!(LampoErrorCode::ChannelNotFound, "{err}")
+                }
+                // The channel already has an upfront shutdown script.
+                APIError::APIMisuseError { err } => {
+                    lampo_error!(LampoErrorCode::InvalidParams, "{err}")
+                }
+                APIError::IncompatibleShutdownScript { script } => lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "the peer does not support the destination script `{script}`"
+                ),
+                _ => error::anyhow!("{:?}", err),
+            })?;
         Ok(())
     }
     fn change_state_channel(&self, _: ChangeStateChannelEvent) -> error::Result<()> {
@@ -0,0 +1,127 @@
+//! The custom feature bits advertised by the node.
+//!
+//! LDK builds the features of the init and node announcement messages
+//! from the message handlers, so the custom bits are given by a custom
+//! message handler that does not handle any message.
+use std::convert::Infallible;
+
+use lampo_common::error;
+use lampo_common::ldk::io;
+use lampo_common::ldk::ln::features::{InitFeatures, NodeFeatures};
+use lampo_common::ldk::ln::msgs::{DecodeError, LightningError};
+use lampo_common::ldk::ln::peer_handler::CustomMessageHandler;
+use lampo_common::ldk::ln::wire::CustomMessageReader;
+use lampo_common::secp256k1::PublicKey;
+
+pub struct LampoFeatures {
+    /// The custom bits, an even bit is required.
+    bits: Vec<usize>,
+}
+
+impl LampoFeatures {
+    pub fn new(bits: &[usize]) -> error::Result<Self> {
+        let features = Self {
+            bits: bits.to_vec(),
+        };
+        // LDK refuses the bits that it owns.
+        features.features::<NodeFeatures>(
+            NodeFeatures::empty(),
+            NodeFeatures::set_required_custom_bit,
+            NodeFeatures::set_optional_custom_bit,
+        )?;
+        Ok(features)
+    }
+
+    fn features<F>(
+        &self,
+        mut features: F,
+        required: fn(&mut F, usize) -> Result<(), ()>,
+        optional: fn(&mut F, usize) -> Result<(), ()>,
+    ) -> error::Result<F> {
+        for bit in self.bits.iter() {
+            let set = if bit % 2 == 0 { required } else { optional };
+            set(&mut features, *bit)
+                .map_err(|_| error::anyhow!("feature bit `{bit}` is not a custom feature bit"))?;
+        }
+        Ok(features)
+    }
+}
+
+/// The bits that are set inside the little endian `flags`.
+pub fn feature_bits(flags: &[u8]) -> Vec<usize> {
+    flags
+        .iter()
+        .enumerate()
+        .flat_map(|(i, byte)| {
+            (0..8)
+                .filter(move |bit| byte & (1 << bit) != 0)
+                .map(move |bit| i * 8 + bit)
+        })
+        .collect()
+}
+
+impl CustomMessageReader for LampoFeatures {
+    type CustomMessage = Infallible;
+
+    fn read<R: io::Read>(
+        &self,
+        _message_type: u16,
+        _buffer: &mut R,
+    ) -> Result<Option<Self::CustomMessage>, DecodeError> {
+        Ok(None)
+    }
+}
+
+impl CustomMessageHandler for LampoFeatures {
+    fn handle_custom_message(
+        &self,
+        msg: Self::CustomMessage,
+        _sender_node_id: &PublicKey,
+    ) -> Result<(), LightningError> {
+        match msg {}
+    }
+
+    fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, Self::CustomMessage)> {
+        Vec::new()
+    }
+
+    fn provided_node_features(&self) -> NodeFeatures {
+        // SAFETY: the bits are validated by the constructor.
+        self.features(
+            NodeFeatures::empty(),
+            NodeFeatures::set_required_custom_bit,
+            NodeFeatures::set_optional_custom_bit,
+        )
+        .unwrap()
+    }
+
+    fn provided_init_features(&self, _their_node_id: &PublicKey) -> InitFeatures {
+        // SAFETY: the bits are validated by the constructor.
+        self.features(
+            InitFeatures::empty(),
+            InitFeatures::set_required_custom_bit,
+            InitFeatures::set_optional_custom_bit,
+        )
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lampo_common::ldk::ln::features::NodeFeatures;
+    use lampo_common::ldk::ln::peer_handler::CustomMessageHandler;
+
+    use super::{feature_bits, LampoFeatures};
+
+    #[test]
+    fn custom_feature_bits() {
+        let features = LampoFeatures::new(&[257, 300]).unwrap();
+        let bits = feature_bits(features.provided_node_features().le_flags());
+        assert_eq!(bits, vec![257, 300]);
+        assert!(LampoFeatures::new(&[23]).is_err());
+        assert_eq!(
+            feature_bits(NodeFeatures::empty().le_flags()),
+            Vec::<usize>::new()
+        );
+    }
+}
@@ -1,4 +1,5 @@
 //! Inventory Manager Implementation
+use std::str::FromStr;
 use std::sync::Arc;
 
 use lampo_common::error;
@@ -40,18 +41,30 @@ impl InventoryHandler for LampoInventoryManager {
                 let (_, height) = self.channel_manager.onchain.backend.get_best_block()?;
                 let blockheight = height.unwrap_or_default();
                 let lampo_dir = self.channel_manager.conf.root_path.to_string();
-                // We provide a vector here as there may be other types of address in future like tor and ipv6.
+                // The announce addresses in the configured order, then the onion one.
                 let mut address_vec = Vec::new();
-                let address = self.channel_manager.conf.announce_addr.clone();
-                if let Some(addr) = address {
-                    let port = self.channel_manager.conf.port.clone();
-                    // For now we don't iterate as there is only one type of address.
-                    let address_info = NetworkInfo {
-                        address: addr,
-                        port,
-                    };
-                    address_vec.push(address_info);
+                for addr in self.channel_manager.conf.announce_socket_addresses()? {
+                    let addr = addr.to_string();
+                    if let Some((address, port)) = addr.rsplit_once(':') {
+                        address_vec.push(NetworkInfo {
+                            address: address.to_owned(),
+                            port: u64::from_str(port)?,
+                        });
+                    }
                 }
+                let onion = self.peer_manager.onion_address();
+                if let Some((onion, _)) = onion
+                    .as_ref()
+                    .and_then(|addr| addr.rsplit_once(':'))
+                    .filter(|(onion, _)| !address_vec.iter().any(|info| info.address == *onion))
+                {
+                    address_vec.push(NetworkInfo {
+                        address: onion.to_owned(),
+                        port: self.channel_manager.conf.port,
+                    });
+                }
+                let graph = self.channel_manager.graph();
+                let graph = graph.read_only();
                 let getinfo = GetInfo {
                     node_id: self.channel_manager.manager().get_our_node_id().to_string(),
                     peers: self.peer_manager.manager().list_peers().len(),
@@ -61,6 +74,11 @@ impl InventoryHandler for LampoInventoryManager {
                     blockheight,
                     lampo_dir,
                     address: address_vec,
+                    graph_channels: graph.channels().len(),
+                    graph_nodes: graph.nodes().len(),
+                    feature_bits: self.peer_manager.feature_bits(),
+                    backend_pruned: self.channel_manager.onchain.backend_pruned,
+                    max_inbound_htlc_msat: self.channel_manager.conf.max_inbound_htlc_msat,
                 };
                 let getinfo = json::to_value(getinfo)?;
                 chan.send(getinfo)?;
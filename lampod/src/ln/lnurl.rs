@@ -0,0 +1,297 @@
+//! LNURL-pay client (LUD-06), that is also able to resolve
+//! lightning addresses (LUD-16) and to send comments (LUD-12).
+use std::time::Duration;
+
+use lampo_common::bitcoin::bech32::{self, FromBase32};
+use lampo_common::bitcoin::hashes::sha256::Hash as Sha256;
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::conf::Network;
+use lampo_common::error;
+use lampo_common::error::LampoErrorCode;
+use lampo_common::json;
+use lampo_common::ldk::invoice::{Bolt11Invoice, Bolt11InvoiceDescription};
+
+use crate::lampo_error;
+
+/// How long we wait the answer of the LNURL service.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The pay request returned by the LNURL service.
+#[derive(Debug, Clone, json::prelude::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayRequest {
+    pub tag: String,
+    pub callback: String,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    /// The json array of `[mime, content]` that is committed
+    /// by the description hash of the invoice.
+    pub metadata: String,
+    /// The max length of the comment, zero if not allowed.
+    #[serde(default)]
+    pub comment_allowed: usize,
+}
+
+impl PayRequest {
+    /// The `text/plain` entry of the metadata.
+    pub fn description(&self) -> Option<String> {
+        let entries: Vec<(String, json::Value)> = json::from_str(&self.metadata).ok()?;
+        entries
+            .into_iter()
+            .find(|(mime, _)| mime == "text/plain")
+            .and_then(|(_, content)| content.as_str().map(str::to_owned))
+    }
+}
+
+/// The invoice returned by the callback of the LNURL service.
+#[derive(Debug, Clone, json::prelude::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayResponse {
+    pub pr: String,
+    pub success_action: Option<json::Value>,
+}
+
+/// Give back the url behind `lnurl`, that is a bech32 LNURL,
+/// a `lnurlp://` url or a lightning address.
+pub fn decode(lnurl: &str, network: Network) -> error::Result<String> {
+    let lnurl = lnurl.trim();
+    let lnurl = lnurl
+        .strip_prefix("lightning:")
+        .or_else(|| lnurl.strip_prefix("LIGHTNING:"))
+        .unwrap_or(lnurl);
+    let url = if let Some((user, domain)) = lnurl.split_once('@') {
+        if user.is_empty() || domain.is_empty() {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "`{lnurl}` is not a valid lightning address"
+            ));
+        }
+        format!(
+            "{}://{domain}/.well-known/lnurlp/{}",
+            scheme(domain, network),
+            user.to_lowercase()
+        )
+    } else if let Some(rest) = lnurl.strip_prefix("lnurlp://") {
+        let host = rest.split('/').next().unwrap_or_default();
+        format!("{}://{rest}", scheme(host, network))
+    } else {
+        let (hrp, data, _) = bech32::decode(&lnurl.to_lowercase()).map_err(|err| {
+            lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "impossible decode the LNURL `{lnurl}`: {err}"
+            )
+        })?;
+        if hrp != "lnurl" {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "`{lnurl}` is not a LNURL, the prefix is `{hrp}`"
+            ));
+        }
+        String::from_utf8(Vec::<u8>::from_base32(&data)?)?
+    };
+    ensure_secure(&url, network)?;
+    Ok(url)
+}
+
+/// Plain http is used only for onion services and for regtest,
+/// where the services run on the local machine.
+fn scheme(host: &str, network: Network) -> &'static str {
+    let host = host.split(':').next().unwrap_or_default();
+    if host.ends_with(".onion") || network == Network::Regtest {
+        "http"
+    } else {
+        "https"
+    }
+}
+
+fn ensure_secure(url: &str, network: Network) -> error::Result<()> {
+    if url.starts_with("https://") {
+        return Ok(());
+    }
+    let Some(rest) = url.strip_prefix("http://") else {
+        return Err(lampo_error!(
+            LampoErrorCode::InvalidParams,
+            "`{url}` is not a http url"
+        ));
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    if scheme(host, network) != "http" {
+        return Err(lampo_error!(
+            LampoErrorCode::InvalidParams,
+            "`{url}` must use https"
+        ));
+    }
+    Ok(())
+}
+
+/// GET the json at `url`, failing with the reason given
+/// by the service when the status is `ERROR`.
+fn get<T: json::DeserializeOwned>(url: &str) -> error::Result<T> {
+    let response = minreq::get(url)
+        .with_timeout(HTTP_TIMEOUT.as_secs())
+        .send()
+        .map_err(|err| error::anyhow!("impossible reach the LNURL service `{url}`: {err}"))?;
+    let body = response.as_str()?;
+    let value: json::Value = json::from_str(body)
+        .map_err(|err| error::anyhow!("invalid answer from the LNURL service `{url}`: {err}"))?;
+    if value["status"].as_str() == Some("ERROR") {
+        error::bail!(
+            "the LNURL service answered with an error: {}",
+            value["reason"].as_str().unwrap_or("unknown reason")
+        );
+    }
+    if !(200..300).contains(&response.status_code) {
+        error::bail!(
+            "the LNURL service answered with status `{}`",
+            response.status_code
+        );
+    }
+    Ok(json::from_value(value)?)
+}
+
+/// Fetch the pay request at `url`.
+pub fn fetch_pay_request(url: &str) -> error::Result<PayRequest> {
+    let request: PayRequest = get(url)?;
+    if request.tag != "payRequest" {
+        return Err(lampo_error!(
+            LampoErrorCode::InvalidParams,
+            "`{url}` is a `{}` LNURL, not a pay request",
+            request.tag
+        ));
+    }
+    Ok(request)
+}
+
+/// Ask to the callback the invoice of `amount_msat`, and make
+/// sure that it is the invoice for the pay request.
+pub fn fetch_invoice(
+    request: &PayRequest,
+    amount_msat: u64,
+    comment: Option<&str>,
+    network: Network,
+) -> error::Result<(Bolt11Invoice, PayResponse)> {
+    if amount_msat < request.min_sendable || amount_msat > request.max_sendable {
+        return Err(lampo_error!(
+            LampoErrorCode::InvalidParams,
+            "the amount must be between {} and {} msat, received {amount_msat} msat",
+            request.min_sendable,
+            request.max_sendable
+        ));
+    }
+    let mut callback = format!(
+        "{}{}amount={amount_msat}",
+        request.callback,
+        if request.callback.contains('?') {
+            "&"
+        } else {
+            "?"
+        }
+    );
+    if let Some(comment) = comment {
+        let length = comment.chars().count();
+        if length > request.comment_allowed {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "the comment is {length} characters long, the service allows {}",
+                request.comment_allowed
+            ));
+        }
+        callback.push_str(&format!("&comment={}", percent_encode(comment)));
+    }
+    ensure_secure(&callback, network)?;
+
+    let response: PayResponse = get(&callback)?;
+    let invoice = response
+        .pr
+        .parse::<Bolt11Invoice>()
+        .map_err(|err| error::anyhow!("the LNURL service returned an invalid invoice: {err}"))?;
+    check_invoice(&invoice, request, amount_msat)?;
+    Ok((invoice, response))
+}
+
+/// The invoice must be for the requested amount, and it
+/// must commit to the metadata of the pay request.
+fn check_invoice(
+    invoice: &Bolt11Invoice,
+    request: &PayRequest,
+    amount_msat: u64,
+) -> error::Result<()> {
+    if invoice.amount_milli_satoshis() != Some(amount_msat) {
+        error::bail!(
+            "the LNURL service returned an invoice of {:?} msat, requested {amount_msat} msat",
+            invoice.amount_milli_satoshis()
+        );
+    }
+    let metadata_hash = Sha256::hash(request.metadata.as_bytes());
+    match invoice.description() {
+        Bolt11InvoiceDescription::Hash(hash) if hash.0 == metadata_hash => Ok(()),
+        _ => error::bail!(
+            "the invoice returned by the LNURL service does not commit to the metadata"
+        ),
+    }
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lampo_common::conf::Network;
+
+    use super::{decode, percent_encode, PayRequest};
+
+    #[test]
+    fn decode_lnurl_and_lightning_address() {
+        // The example of LUD-01.
+        let lnurl = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS";
+        assert_eq!(
+            decode(lnurl, Network::Bitcoin).unwrap(),
+            "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df"
+        );
+        assert_eq!(
+            decode("Alice@example.com", Network::Bitcoin).unwrap(),
+            "https://example.com/.well-known/lnurlp/alice"
+        );
+        assert_eq!(
+            decode("lightning:alice@127.0.0.1:8080", Network::Regtest).unwrap(),
+            "http://127.0.0.1:8080/.well-known/lnurlp/alice"
+        );
+        assert_eq!(
+            decode("lnurlp://service.onion/api", Network::Bitcoin).unwrap(),
+            "http://service.onion/api"
+        );
+        assert!(decode("@example.com", Network::Bitcoin).is_err());
+        assert!(decode(
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            Network::Bitcoin
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn description_from_the_metadata() {
+        let request = PayRequest {
+            tag: "payRequest".to_owned(),
+            callback: "https://example.com/callback".to_owned(),
+            min_sendable: 1_000,
+            max_sendable: 1_000_000,
+            metadata: r#"[["text/plain","coffee"],["text/identifier","alice@example.com"]]"#
+                .to_owned(),
+            comment_allowed: 0,
+        };
+        assert_eq!(request.description().as_deref(), Some("coffee"));
+        assert_eq!(
+            percent_encode("thanks for the ☕!"),
+            "thanks%20for%20the%20%E2%98%95%21"
+        );
+    }
+}
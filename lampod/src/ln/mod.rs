@@ -1,13 +1,18 @@
 //! Lampo Channel Manager
 mod channel_manager;
+mod features;
 mod inventory_manager;
 mod offchain_manager;
 mod peer_manager;
+mod router;
+mod tor;
 
 pub mod events;
+pub mod lnurl;
 pub mod peer_event;
 
 pub use channel_manager::LampoChannelManager;
 pub use inventory_manager::LampoInventoryManager;
 pub use offchain_manager::OffchainManager;
 pub use peer_manager::LampoPeerManager;
+pub use router::LampoRouter;
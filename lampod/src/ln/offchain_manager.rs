@@ -10,8 +10,9 @@
 //! with the network graph. But this is not so clear yet.
 //!
 //! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::collections::HashSet;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 
 use lampo_common::bitcoin::hashes::sha256::Hash as Sha256;
@@ -19,18 +20,24 @@ use lampo_common::bitcoin::hashes::Hash;
 use lampo_common::bitcoin::secp256k1::PublicKey as pubkey;
 use lampo_common::conf::LampoConf;
 use lampo_common::error;
+use lampo_common::error::LampoErrorCode;
+use lampo_common::json;
 use lampo_common::keys::LampoKeysManager;
 use lampo_common::ldk;
-use lampo_common::ldk::ln::channelmanager::Retry;
 use lampo_common::ldk::ln::channelmanager::{PaymentId, RecipientOnionFields};
+use lampo_common::ldk::ln::channelmanager::{Retry, RetryableSendFailure};
 use lampo_common::ldk::ln::{PaymentHash, PaymentPreimage};
 use lampo_common::ldk::offers::offer::Amount;
 use lampo_common::ldk::offers::offer::Offer;
-use lampo_common::ldk::routing::router::{PaymentParameters, RouteParameters};
+use lampo_common::ldk::routing::gossip::{NodeId, RoutingFees};
+use lampo_common::ldk::routing::router::{Payee, RouteHint, RouteHintHop};
+use lampo_common::ldk::routing::router::{PaymentParameters, RouteParameters, Router};
 use lampo_common::ldk::sign::EntropySource;
+use lampo_common::model::{request, response};
 
 use super::LampoChannelManager;
 use crate::chain::LampoChainManager;
+use crate::lampo_error;
 use crate::utils::logger::LampoLogger;
 
 pub struct OffchainManager {
@@ -39,6 +46,9 @@ pub struct OffchainManager {
     logger: Arc<LampoLogger>,
     lampo_conf: Arc<LampoConf>,
     chain_manager: Arc<LampoChainManager>,
+    /// Held from the check of the in flight payments until the
+    /// payment is sent, so two payments can not take the last slot.
+    payment_slot: Mutex<()>,
 }
 
 impl OffchainManager {
@@ -56,6 +66,7 @@ impl OffchainManager {
             logger,
             lampo_conf,
             chain_manager,
+            payment_slot: Mutex::new(()),
         })
     }
 
@@ -79,26 +90,146 @@ impl OffchainManager {
             None,
         )
         .map_err(|err| error::anyhow!(err))?;
+        self.channel_manager.register_invoice(&invoice);
         Ok(invoice)
     }
 
+    /// Generate an invoice for a payment hash where the preimage
+    /// is not known by lampo, so the payment is held until
+    /// `settle_invoice` is called.
+    pub fn create_invoice_for_hash(
+        &self,
+        payment_hash: PaymentHash,
+        amount_msat: Option<u64>,
+        description: &str,
+        expiring_in: u32,
+    ) -> error::Result<ldk::invoice::Bolt11Invoice> {
+        let currency = ldk::invoice::Currency::try_from(self.lampo_conf.network)?;
+        let invoice = ldk::invoice::utils::create_invoice_from_channelmanager_with_payment_hash(
+            &self.channel_manager.manager(),
+            self.keys_manager.clone(),
+            self.logger.clone(),
+            currency,
+            amount_msat,
+            description.to_string(),
+            expiring_in,
+            payment_hash,
+            None,
+        )
+        .map_err(|err| error::anyhow!(err))?;
+        self.channel_manager.register_invoice(&invoice);
+        Ok(invoice)
+    }
+
+    /// Generate again the `invoice` with the same amount and description,
+    /// expiring in `expiring_in` seconds or in the expiry of `invoice`.
+    ///
+    /// The payment hashes generated by LDK commit to the expiry, so they
+    /// are replaced, while the hashes provided by the user are kept.
+    pub fn reissue_invoice(
+        &self,
+        invoice: &ldk::invoice::Bolt11Invoice,
+        expiring_in: Option<u32>,
+    ) -> error::Result<ldk::invoice::Bolt11Invoice> {
+        let description = match invoice.description() {
+            ldk::invoice::Bolt11InvoiceDescription::Direct(description) => description.to_string(),
+            ldk::invoice::Bolt11InvoiceDescription::Hash(_) => {
+                return Err(lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "impossible reissue an invoice with a description hash"
+                ));
+            }
+        };
+        let expiring_in = expiring_in.unwrap_or(invoice.expiry_time().as_secs() as u32);
+        let payment_hash = PaymentHash(invoice.payment_hash().to_byte_array());
+        let payment_secret = *invoice.payment_secret();
+        let generated = self
+            .channel_manager
+            .manager()
+            .get_payment_preimage(payment_hash, payment_secret)
+            .is_ok();
+        if generated {
+            self.generate_invoice(invoice.amount_milli_satoshis(), &description, expiring_in)
+        } else {
+            self.create_invoice_for_hash(
+                payment_hash,
+                invoice.amount_milli_satoshis(),
+                &description,
+                expiring_in,
+            )
+        }
+    }
+
+    /// Claim an held payment with the preimage.
+    pub fn settle_invoice(&self, preimage: PaymentPreimage) -> error::Result<PaymentHash> {
+        let payment_hash = PaymentHash(Sha256::hash(&preimage.0).to_byte_array());
+        if !self.channel_manager.take_held_payment(&payment_hash) {
+            return Err(lampo_error!(
+                LampoErrorCode::InvoiceNotFound,
+                "no held payment with payment hash `{payment_hash}`"
+            ));
+        }
+        self.channel_manager.manager().claim_funds(preimage);
+        Ok(payment_hash)
+    }
+
     pub fn decode_invoice(&self, invoice_str: &str) -> error::Result<ldk::invoice::Bolt11Invoice> {
-        let invoice = invoice_str.parse::<ldk::invoice::Bolt11Invoice>()?;
+        let invoice = invoice_str
+            .parse::<ldk::invoice::Bolt11Invoice>()
+            .map_err(|err| lampo_error!(LampoErrorCode::InvalidParams, "{err}"))?;
         Ok(invoice)
     }
 
     pub fn decode<T: FromStr>(&self, invoice_str: &str) -> error::Result<T> {
-        let invoice = invoice_str
-            .parse::<T>()
-            .map_err(|_| error::anyhow!("Impossible decode the invoice `{invoice_str}`"))?;
+        let invoice = invoice_str.parse::<T>().map_err(|_| {
+            lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "Impossible decode the invoice `{invoice_str}`"
+            )
+        })?;
         Ok(invoice)
     }
 
+    /// Refuse to pay `destination` when it is our own node, because
+    /// the router is not able to find a route to ourselves and the
+    /// payment will fail with a confusing error.
+    fn ensure_not_ourselves(&self, destination: &pubkey) -> error::Result<()> {
+        if *destination == self.channel_manager.manager().get_our_node_id() {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "cannot pay yourself, the destination `{destination}` is our node"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Make sure that a new outbound payment does not go above the
+    /// `max_inflight_payments`, the returned guard must be held until
+    /// the payment is sent.
+    fn reserve_payment_slot(&self) -> error::Result<MutexGuard<'_, ()>> {
+        // SAFETY: the lock can not be poisoned.
+        let slot = self.payment_slot.lock().unwrap();
+        let Some(limit) = self.lampo_conf.max_inflight_payments else {
+            return Ok(slot);
+        };
+        let inflight = self.channel_manager.inflight_payments();
+        if inflight >= limit {
+            return Err(lampo_error!(
+                LampoErrorCode::TooManyPayments,
+                data: json::json!({ "inflight_payments": inflight, "max_inflight_payments": limit }),
+                "there are {inflight} payments in flight, the limit is {limit}"
+            ));
+        }
+        Ok(slot)
+    }
+
     pub fn pay_offer(&self, offer_str: &str, amount_msat: Option<u64>) -> error::Result<()> {
         // check if it is an invoice or an offer
         let offer_hash = Sha256::hash(offer_str.as_bytes());
         let payment_id = PaymentId(*offer_hash.as_ref());
-        let offer = Offer::from_str(offer_str).map_err(|err| error::anyhow!("{:?}", err))?;
+        let offer = Offer::from_str(offer_str)
+            .map_err(|err| lampo_error!(LampoErrorCode::InvalidParams, "{:?}", err))?;
+        self.ensure_not_ourselves(&offer.signing_pubkey())?;
 
         let amount = match offer.amount() {
             Some(Amount::Bitcoin { amount_msats }) => amount_msats.clone(),
@@ -106,9 +237,13 @@ impl OffchainManager {
                 "Cannot process non-Bitcoin-denominated offer value {:?}",
                 offer.amount()
             ),
-            None => amount_msat.ok_or(error::anyhow!("An amount need to be specified"))?,
+            None => amount_msat.ok_or(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "An amount need to be specified"
+            ))?,
         };
 
+        let _slot = self.reserve_payment_slot()?;
         self.channel_manager
             .manager()
             .pay_for_offer(
@@ -124,14 +259,33 @@ impl OffchainManager {
         Ok(())
     }
 
-    pub fn pay_invoice(&self, invoice_str: &str, amount_msat: Option<u64>) -> error::Result<()> {
-        // check if it is an invoice or an offer
+    /// Build the route parameters of the invoice, with the
+    /// `route_hints` merged with, or replacing, the ones of the invoice.
+    fn invoice_payment_parameters(
+        &self,
+        invoice_str: &str,
+        amount_msat: Option<u64>,
+        circular: bool,
+        route_hints: &[Vec<request::RouteHintHop>],
+        replace_route_hints: bool,
+    ) -> error::Result<(
+        PaymentId,
+        PaymentHash,
+        RecipientOnionFields,
+        RouteParameters,
+    )> {
+        let route_hints = parse_route_hints(route_hints)?;
         let invoice = self.decode_invoice(invoice_str)?;
+        // A circular rebalance is paying our own invoice on purpose.
+        if !circular {
+            self.ensure_not_ourselves(&invoice.get_payee_pub_key())?;
+        }
         let payment_id = PaymentId((*invoice.payment_hash()).to_byte_array());
-        let (payment_hash, onion, route) = if invoice.amount_milli_satoshis().is_none() {
+        let (payment_hash, onion, mut route) = if invoice.amount_milli_satoshis().is_none() {
             ldk::invoice::payment::payment_parameters_from_zero_amount_invoice(
                 &invoice,
-                amount_msat.ok_or(error::anyhow!(
+                amount_msat.ok_or(lampo_error!(
+                    LampoErrorCode::InvalidParams,
                     "invoice with no amount, and amount must be specified"
                 ))?,
             )
@@ -140,14 +294,234 @@ impl OffchainManager {
             ldk::invoice::payment::payment_parameters_from_invoice(&invoice)
                 .map_err(|err| error::anyhow!("{:?}", err))?
         };
+        if replace_route_hints || !route_hints.is_empty() {
+            let mut hints = match &route.payment_params.payee {
+                Payee::Clear { route_hints, .. } if !replace_route_hints => route_hints.clone(),
+                _ => Vec::new(),
+            };
+            hints.extend(route_hints);
+            route.payment_params = route
+                .payment_params
+                .with_route_hints(hints)
+                .map_err(|_| error::anyhow!("impossible to use route hints for this payee"))?;
+        }
+        Ok((payment_id, payment_hash, onion, route))
+    }
+
+    pub fn pay_invoice(
+        &self,
+        invoice_str: &str,
+        amount_msat: Option<u64>,
+        route_hints: &[Vec<request::RouteHintHop>],
+        replace_route_hints: bool,
+    ) -> error::Result<PaymentHash> {
+        let (payment_id, payment_hash, onion, route) = self.invoice_payment_parameters(
+            invoice_str,
+            amount_msat,
+            false,
+            route_hints,
+            replace_route_hints,
+        )?;
+        let _slot = self.reserve_payment_slot()?;
         self.channel_manager
             .manager()
             .send_payment(payment_hash, onion, payment_id, route, Retry::Attempts(10))
+            .map_err(send_failure)?;
+        self.channel_manager.track_payment(payment_hash);
+        Ok(payment_hash)
+    }
+
+    /// Pay an invoice constraining the first hop of the route to
+    /// `use_channel`, or to the channels that are not inside `exclude_channels`.
+    ///
+    /// The route is calculated here, so the payment is not retried
+    /// through other channels.
+    ///
+    /// Paying our own invoice is allowed only with an explicit
+    /// `use_channel`, because this is a circular rebalance.
+    pub fn pay_invoice_with_first_hop(
+        &self,
+        invoice_str: &str,
+        amount_msat: Option<u64>,
+        use_channel: Option<&str>,
+        exclude_channels: &[String],
+        route_hints: &[Vec<request::RouteHintHop>],
+        replace_route_hints: bool,
+    ) -> error::Result<PaymentHash> {
+        let (payment_id, payment_hash, onion, route_params) = self.invoice_payment_parameters(
+            invoice_str,
+            amount_msat,
+            use_channel.is_some(),
+            route_hints,
+            replace_route_hints,
+        )?;
+        let manager = self.channel_manager.manager();
+        let usable_channels = manager.list_usable_channels();
+        let first_hops = usable_channels
+            .iter()
+            .filter(|channel| {
+                let channel_id = channel.channel_id.to_string();
+                use_channel.map_or(true, |id| id == channel_id)
+                    && !exclude_channels.contains(&channel_id)
+            })
+            .collect::<Vec<_>>();
+        if first_hops.is_empty() {
+            let code = match use_channel {
+                Some(_) => LampoErrorCode::ChannelNotFound,
+                None => LampoErrorCode::NoRoute,
+            };
+            return Err(lampo_error!(
+                code,
+                "no usable channels that match the requested first hop"
+            ));
+        }
+
+        let route = self
+            .channel_manager
+            .router()
+            .find_route(
+                &manager.get_our_node_id(),
+                &route_params,
+                Some(first_hops.as_slice()),
+                manager.compute_inflight_htlcs(),
+            )
+            .map_err(|err| {
+                lampo_error!(
+                    LampoErrorCode::NoRoute,
+                    "no route found through the requested channels: {}",
+                    err.err
+                )
+            })?;
+        let _slot = self.reserve_payment_slot()?;
+        manager
+            .send_payment_with_route(&route, payment_hash, onion, payment_id)
             .map_err(|err| error::anyhow!("{:?}", err))?;
-        Ok(())
+        self.channel_manager.track_payment(payment_hash);
+        Ok(payment_hash)
     }
 
-    pub fn keysend(&self, destination: pubkey, amount_msat: u64) -> error::Result<PaymentHash> {
+    /// Find a route to `destination` with the current scorer, together
+    /// with the liquidity that the scorer learned for every hop.
+    pub fn get_route(
+        &self,
+        destination: pubkey,
+        amount_msat: u64,
+    ) -> error::Result<response::Route> {
+        self.ensure_not_ourselves(&destination)?;
+        let route_params = RouteParameters {
+            payment_params: PaymentParameters::from_node_id(destination, 40),
+            final_value_msat: amount_msat,
+            max_total_routing_fee_msat: None,
+        };
+        let manager = self.channel_manager.manager();
+        let usable_channels = manager.list_usable_channels();
+        let first_hops = usable_channels.iter().collect::<Vec<_>>();
+        let route = self
+            .channel_manager
+            .router()
+            .find_route(
+                &manager.get_our_node_id(),
+                &route_params,
+                Some(first_hops.as_slice()),
+                manager.compute_inflight_htlcs(),
+            )
+            .map_err(|err| {
+                lampo_error!(
+                    LampoErrorCode::NoRoute,
+                    "no route found to `{destination}`: {}",
+                    err.err
+                )
+            })?;
+        let scorer = self.channel_manager.scorer();
+        // SAFETY: the lock can not be poisoned.
+        let scorer = scorer.lock().unwrap();
+        let Some(path) = route.paths.first() else {
+            return Err(lampo_error!(
+                LampoErrorCode::NoRoute,
+                "no route found to `{destination}`"
+            ));
+        };
+        let hops = path
+            .hops
+            .iter()
+            .map(|hop| {
+                let liquidity = scorer.estimated_channel_liquidity_range(
+                    hop.short_channel_id,
+                    &NodeId::from_pubkey(&hop.pubkey),
+                );
+                response::RouteHop {
+                    node_id: hop.pubkey.to_string(),
+                    short_channel_id: hop.short_channel_id,
+                    fee_msat: hop.fee_msat,
+                    cltv_expiry_delta: hop.cltv_expiry_delta,
+                    liquidity_min_msat: liquidity.map(|(min, _)| min),
+                    liquidity_max_msat: liquidity.map(|(_, max)| max),
+                }
+            })
+            .collect();
+        Ok(response::Route {
+            amount_msat,
+            fee_msat: route.get_total_fees(),
+            hops,
+        })
+    }
+
+    /// Send a probe of `amount_msat` to every node that is two hops away
+    /// through our usable channels, so the scorer learns the liquidity of
+    /// the channels of our peers. Return how many probes were sent.
+    ///
+    /// A probe never moves funds, its payment hash has no preimage
+    /// so the destination can only fail it.
+    pub fn probe_channels(&self, amount_msat: u64) -> usize {
+        let manager = self.channel_manager.manager();
+        let our_node_id = manager.get_our_node_id();
+        let peers = manager
+            .list_usable_channels()
+            .into_iter()
+            .map(|channel| channel.counterparty.node_id)
+            .collect::<HashSet<_>>();
+        let destinations = {
+            let graph = self.channel_manager.graph();
+            let graph = graph.read_only();
+            peers
+                .iter()
+                .filter_map(|peer| graph.node(&NodeId::from_pubkey(peer)))
+                .flat_map(|node| node.channels.iter())
+                .filter_map(|scid| graph.channel(*scid))
+                .flat_map(|channel| [channel.node_one, channel.node_two])
+                .filter_map(|node_id| node_id.as_pubkey().ok())
+                .filter(|node_id| *node_id != our_node_id && !peers.contains(node_id))
+                .collect::<HashSet<_>>()
+        };
+        let mut sent = 0;
+        for destination in destinations {
+            match manager.send_spontaneous_preflight_probes(destination, amount_msat, 40, None) {
+                Ok(probes) => sent += probes.len(),
+                Err(err) => {
+                    log::debug!(target: "lampo", "impossible to probe `{destination}`: {:?}", err)
+                }
+            }
+        }
+        sent
+    }
+
+    /// Send a spontaneous payment to `destination`, reaching it
+    /// through the `route_hints` when it is behind private channels.
+    pub fn keysend(
+        &self,
+        destination: pubkey,
+        amount_msat: u64,
+        route_hints: &[Vec<request::RouteHintHop>],
+        max_parts: Option<u8>,
+    ) -> error::Result<PaymentHash> {
+        self.ensure_not_ourselves(&destination)?;
+        if max_parts == Some(0) {
+            return Err(lampo_error!(
+                LampoErrorCode::InvalidParams,
+                "`max_parts` must be at least 1"
+            ));
+        }
+        let route_hints = parse_route_hints(route_hints)?;
         let payment_preimage = PaymentPreimage(
             self.chain_manager
                 .wallet_manager
@@ -159,13 +533,25 @@ impl OffchainManager {
         let PaymentPreimage(bytes) = payment_preimage;
         let payment_hash = PaymentHash(Sha256::hash(&bytes).to_byte_array());
         // The 40 here is the max CheckLockTimeVerify which locks the output of the transaction for a certain
-        // period of time.The false here stands for the allow_mpp, which is to allow the multi part route payments.
+        // period of time. The payment is split in multiple parts unless `max_parts` is 1, the
+        // destination must accept the multi part keysend or it fails back all the parts.
+        let allow_mpp = max_parts != Some(1);
+        let mut payment_params = PaymentParameters::for_keysend(destination, 40, allow_mpp);
+        if let Some(max_parts) = max_parts {
+            payment_params.max_path_count = max_parts;
+        }
+        if !route_hints.is_empty() {
+            payment_params = payment_params
+                .with_route_hints(route_hints)
+                .map_err(|_| error::anyhow!("impossible to use route hints for this payee"))?;
+        }
         let route_params = RouteParameters {
-            payment_params: PaymentParameters::for_keysend(destination, 40, false),
+            payment_params,
             final_value_msat: amount_msat,
             max_total_routing_fee_msat: None,
         };
         log::info!("Initialised Keysend");
+        let _slot = self.reserve_payment_slot()?;
         let payment_result = self
             .channel_manager
             .manager()
@@ -176,8 +562,84 @@ impl OffchainManager {
                 route_params,
                 Retry::Timeout(Duration::from_secs(10)),
             )
-            .map_err(|err| error::anyhow!("{:?}", err))?;
+            .map_err(send_failure)?;
+        self.channel_manager.track_payment(payment_result);
         log::info!("Keysend successfully done!");
         Ok(payment_result)
     }
 }
+
+/// Validate the route hints given by the user and convert
+/// them to the LDK ones.
+fn parse_route_hints(route_hints: &[Vec<request::RouteHintHop>]) -> error::Result<Vec<RouteHint>> {
+    route_hints
+        .iter()
+        .map(|hops| {
+            if hops.is_empty() {
+                return Err(lampo_error!(
+                    LampoErrorCode::InvalidParams,
+                    "a route hint must have at least one hop"
+                ));
+            }
+            let hops = hops
+                .iter()
+                .map(|hop| {
+                    let src_node_id = pubkey::from_str(&hop.node_id).map_err(|err| {
+                        lampo_error!(
+                            LampoErrorCode::InvalidParams,
+                            "invalid node id `{}` in route hint: {err}",
+                            hop.node_id
+                        )
+                    })?;
+                    if hop.short_channel_id == 0 {
+                        return Err(lampo_error!(
+                            LampoErrorCode::InvalidParams,
+                            "invalid short channel id `0` in route hint"
+                        ));
+                    }
+                    if hop.cltv_expiry_delta == 0 {
+                        return Err(lampo_error!(
+                            LampoErrorCode::InvalidParams,
+                            "the cltv expiry delta of channel `{}` must be greater than zero",
+                            hop.short_channel_id
+                        ));
+                    }
+                    if let (Some(min), Some(max)) = (hop.htlc_minimum_msat, hop.htlc_maximum_msat) {
+                        if min > max {
+                            return Err(lampo_error!(
+                                LampoErrorCode::InvalidParams,
+                                "the htlc minimum `{min}` of channel `{}` is greater than the maximum `{max}`",
+                                hop.short_channel_id
+                            ));
+                        }
+                    }
+                    Ok(RouteHintHop {
+                        src_node_id,
+                        short_channel_id: hop.short_channel_id,
+                        fees: RoutingFees {
+                            base_msat: hop.fee_base_msat,
+                            proportional_millionths: hop.fee_proportional_millionths,
+                        },
+                        cltv_expiry_delta: hop.cltv_expiry_delta,
+                        htlc_minimum_msat: hop.htlc_minimum_msat,
+                        htlc_maximum_msat: hop.htlc_maximum_msat,
+                    })
+                })
+                .collect::<error::Result<Vec<_>>>()?;
+            Ok(RouteHint(hops))
+        })
+        .collect()
+}
+
+/// Map the failure of a payment that can not be sent to its error code.
+fn send_failure(err: RetryableSendFailure) -> error::Error {
+    match err {
+        RetryableSendFailure::RouteNotFound => {
+            lampo_error!(LampoErrorCode::NoRoute, "no route found to the destination")
+        }
+        RetryableSendFailure::PaymentExpired => {
+            lampo_error!(LampoErrorCode::InvalidParams, "the payment is expired")
+        }
+        _ => error::anyhow!("{:?}", err),
+    }
+}
@@ -1,32 +1,44 @@
 use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 
 use lampo_common::conf::LampoConf;
 use lampo_common::error;
+use lampo_common::error::LampoErrorCode;
+use lampo_common::json;
 use lampo_common::keys::LampoKeysManager;
-use lampo_common::ldk;
 use lampo_common::ldk::blinded_path::EmptyNodeIdLookUp;
+use lampo_common::ldk::ln::features::NodeFeatures;
+use lampo_common::ldk::ln::msgs::{
+    ChannelMessageHandler, OnionMessageHandler, RoutingMessageHandler, SocketAddress,
+};
+use lampo_common::ldk::ln::peer_handler::CustomMessageHandler;
 use lampo_common::ldk::ln::peer_handler::MessageHandler;
 use lampo_common::ldk::ln::peer_handler::{IgnoringMessageHandler, PeerManager};
 use lampo_common::ldk::net;
 use lampo_common::ldk::net::SocketDescriptor;
 use lampo_common::ldk::onion_message::messenger::{DefaultMessageRouter, OnionMessenger};
 use lampo_common::ldk::routing::gossip::{NetworkGraph, P2PGossipSync};
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::model::response::{GossipSync, ListAddresses, StoredPeer, StoredPeers};
 use lampo_common::model::Connect;
 use lampo_common::types::NodeId;
 
 use crate::async_run;
 use crate::chain::{LampoChainManager, WalletManager};
+use crate::lampo_error;
 use crate::ln::LampoChannelManager;
+use crate::persistence::LampoPersistence;
 use crate::utils::logger::LampoLogger;
 
 use super::channel_manager::{LampoArcChannelManager, LampoChainMonitor, LampoGraph};
 use super::events::PeerEvents;
+use super::features::{feature_bits, LampoFeatures};
 use super::peer_event;
+use super::tor::TorHiddenService;
 
 pub type LampoArcOnionMessenger<L> = OnionMessenger<
     Arc<LampoKeysManager>,
@@ -44,28 +56,106 @@ pub type SimpleArcPeerManager<M, T, L> = PeerManager<
     Arc<P2PGossipSync<Arc<NetworkGraph<Arc<L>>>, Arc<T>, Arc<L>>>,
     Arc<LampoArcOnionMessenger<L>>,
     Arc<L>,
-    IgnoringMessageHandler,
+    Arc<LampoFeatures>,
     Arc<LampoKeysManager>,
 >;
 
 type InnerLampoPeerManager =
     SimpleArcPeerManager<LampoChainMonitor, LampoChainManager, LampoLogger>;
 
+/// The namespace inside the persister where the peers
+/// addresses are stored.
+const PEERS_NAMESPACE: &str = "peers";
+
+/// How many times we look at the network graph while
+/// waiting for the gossip of a sync.
+const GOSSIP_SYNC_POLLS: usize = 60;
+
+/// Return the addresses of the node announcement, that are the
+/// announce addresses and the onion address when we have one.
+pub(crate) fn announcement_addresses(
+    mut addresses: Vec<SocketAddress>,
+    onion_addr: Option<&str>,
+) -> error::Result<Vec<SocketAddress>> {
+    if let Some(onion_addr) = onion_addr {
+        let onion_addr = SocketAddress::from_str(onion_addr).map_err(|err| {
+            error::anyhow!("impossible convert `{onion_addr}` to ln socket addr: {err:?}")
+        })?;
+        if !addresses.contains(&onion_addr) {
+            addresses.push(onion_addr);
+        }
+    }
+    Ok(addresses)
+}
+
 pub struct LampoPeerManager {
     peer_manager: Option<Arc<InnerLampoPeerManager>>,
     channel_manager: Option<Arc<LampoChannelManager>>,
+    persister: Arc<LampoPersistence>,
     conf: LampoConf,
     logger: Arc<LampoLogger>,
+    tor: Mutex<Option<TorHiddenService>>,
+    /// The address where the listener is bound.
+    bound_addr: Arc<Mutex<Option<SocketAddr>>>,
+    /// The features of our node announcement.
+    node_features: Option<NodeFeatures>,
 }
 
 impl LampoPeerManager {
-    pub fn new(conf: &LampoConf, logger: Arc<LampoLogger>) -> LampoPeerManager {
+    pub fn new(
+        conf: &LampoConf,
+        logger: Arc<LampoLogger>,
+        persister: Arc<LampoPersistence>,
+    ) -> LampoPeerManager {
         LampoPeerManager {
             peer_manager: None,
             conf: conf.to_owned(),
             logger,
+            persister,
             channel_manager: None,
+            tor: Mutex::new(None),
+            bound_addr: Arc::new(Mutex::new(None)),
+            node_features: None,
+        }
+    }
+
+    /// Store the address of the peer, so we know where
+    /// to find it when we need to reconnect.
+    pub fn store_peer(&self, node_id: NodeId, addr: SocketAddr) -> error::Result<()> {
+        let key = node_id.to_string();
+        let mut peer = match self.persister.read(PEERS_NAMESPACE, "", &key) {
+            Ok(buf) => json::from_slice::<StoredPeer>(&buf)?,
+            Err(_) => StoredPeer {
+                node_id: key.clone(),
+                addresses: vec![],
+            },
+        };
+        let addr = addr.to_string();
+        peer.addresses.retain(|known| known != &addr);
+        peer.addresses.push(addr);
+        self.persister
+            .write(PEERS_NAMESPACE, "", &key, &json::to_vec(&peer)?)?;
+        Ok(())
+    }
+
+    /// Return the stored peers, that are the candidates
+    /// for a reconnection.
+    pub fn stored_peers(&self) -> error::Result<StoredPeers> {
+        let mut peers = Vec::new();
+        for key in self.persister.list(PEERS_NAMESPACE, "")? {
+            let buf = self.persister.read(PEERS_NAMESPACE, "", &key)?;
+            peers.push(json::from_slice::<StoredPeer>(&buf)?);
+        }
+        Ok(StoredPeers { peers })
+    }
+
+    pub fn forget_peer(&self, node_id: NodeId) -> error::Result<()> {
+        let key = node_id.to_string();
+        if !self.persister.list(PEERS_NAMESPACE, "")?.contains(&key) {
+            error::bail!("peer `{node_id}` is not stored");
         }
+        self.persister.remove(PEERS_NAMESPACE, "", &key, false)?;
+        Ok(())
     }
 
     pub fn manager(&self) -> Arc<InnerLampoPeerManager> {
@@ -102,11 +192,31 @@ impl LampoPeerManager {
             self.logger.clone(),
         ));
 
+        let features = Arc::new(LampoFeatures::new(&self.conf.feature_bits)?);
+        let chan_handler = channel_manager.channeld.clone().unwrap();
+        // The same features that the peer manager announces.
+        let node_features = [
+            chan_handler.provided_node_features(),
+            onion_messenger.provided_node_features(),
+            gossip_sync.provided_node_features(),
+            features.provided_node_features(),
+        ]
+        .iter()
+        .fold(Vec::new(), |mut flags, features| {
+            let other = features.le_flags();
+            flags.resize(flags.len().max(other.len()), 0);
+            flags
+                .iter_mut()
+                .zip(other)
+                .for_each(|(flag, other)| *flag |= other);
+            flags
+        });
+
         let lightning_msg_handler = MessageHandler {
-            chan_handler: channel_manager.channeld.clone().unwrap(),
+            chan_handler,
             onion_message_handler: onion_messenger,
             route_handler: gossip_sync,
-            custom_message_handler: IgnoringMessageHandler {},
+            custom_message_handler: features,
         };
 
         let peer_manager = InnerLampoPeerManager::new(
@@ -118,11 +228,107 @@ impl LampoPeerManager {
         );
         self.peer_manager = Some(Arc::new(peer_manager));
         self.channel_manager = Some(channel_manager.clone());
+        self.node_features = Some(NodeFeatures::from_le_bytes(node_features));
         Ok(())
     }
 
+    /// The feature bits that we advertise.
+    pub fn feature_bits(&self) -> Vec<usize> {
+        self.node_features
+            .as_ref()
+            .map(|features| feature_bits(features.le_flags()))
+            .unwrap_or_default()
+    }
+
+    /// Return the onion address of the node, when
+    /// the Tor hidden service is running.
+    pub fn onion_address(&self) -> Option<String> {
+        // SAFETY: the lock can not be poisoned.
+        self.tor
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|tor| tor.onion_address())
+    }
+
+    /// Create the Tor hidden service that maps to our p2p
+    /// port, when the Tor control is configured.
+    fn setup_tor(&self) -> error::Result<()> {
+        let Some(control_port) = self.conf.tor_control_port else {
+            return Ok(());
+        };
+        let tor = TorHiddenService::create(
+            &format!("127.0.0.1:{control_port}"),
+            self.conf.tor_control_password.as_deref(),
+            u16::try_from(self.conf.port)?,
+        )?;
+        log::info!(target: "lampo", "Tor hidden service available at `{}`", tor.onion_address());
+        *self.tor.lock().unwrap() = Some(tor);
+        Ok(())
+    }
+
+    /// The address where we listen for the p2p connections.
+    fn bind_addr(&self) -> String {
+        let addr = self
+            .conf
+            .bind_addr
+            .clone()
+            .or_else(|| {
+                let addr = self.conf.announce_socket_addresses().ok()?;
+                let addr = addr.first()?.to_string();
+                addr.rsplit_once(':').map(|(host, _)| host.to_owned())
+            })
+            .unwrap_or_else(|| "127.0.0.1".to_string());
+        format!("{addr}:{}", self.conf.port)
+    }
+
+    /// The addresses that we announce to the network in the configured
+    /// order, when there is no announce address we announce the one
+    /// where we listen.
+    pub fn announced_addresses(&self) -> error::Result<Vec<SocketAddress>> {
+        let mut addresses = self.conf.announce_socket_addresses()?;
+        if addresses.is_empty() {
+            let bind_addr = self.bind_addr();
+            addresses.push(SocketAddress::from_str(&bind_addr).map_err(|err| {
+                error::anyhow!(
+                    "impossible convert `{bind_addr}` to ln socket addr (wire format): {err:?}"
+                )
+            })?);
+        }
+        announcement_addresses(addresses, self.onion_address().as_deref())
+    }
+
+    /// Return the addresses where we listen, the ones
+    /// that we announce and the onion address.
+    pub fn list_addresses(&self) -> error::Result<ListAddresses> {
+        // SAFETY: the lock can not be poisoned.
+        let bind = self
+            .bound_addr
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        let announce = self
+            .announced_addresses()?
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        Ok(ListAddresses {
+            bind,
+            announce,
+            onion: self.onion_address(),
+        })
+    }
+
+    /// Remove the Tor hidden service, if any.
+    pub fn shutdown(&self) {
+        if let Some(tor) = self.tor.lock().unwrap().take() {
+            log::info!(target: "lampo", "Removing the Tor hidden service `{}`", tor.onion_address());
+        }
+    }
+
     pub fn run(&self) -> error::Result<()> {
-        let listen_port = self.conf.port;
         let Some(ref peer_manager) = self.peer_manager else {
             error::bail!("peer manager is None, at this point this should be not None");
         };
@@ -132,14 +338,12 @@ impl LampoPeerManager {
             .clone()
             .ok_or(error::anyhow!("channel manager is None"))?;
         let alias = self.conf.alias.clone().unwrap_or_default();
-        let addr = self
-            .conf
-            .announce_addr
-            .clone()
-            .unwrap_or_else(|| "127.0.0.1".to_string());
+        let bind_addr = self.bind_addr();
+        self.setup_tor()?;
+        let addresses = self.announced_addresses()?;
+        let bound_addr = self.bound_addr.clone();
         std::thread::spawn(move || {
             let result = async_run!(async move {
-                let bind_addr = format!("{addr}:{listen_port}");
                 log::info!(target: "lampo", "Listening for in-bound connection on {bind_addr}");
                 let listener = match tokio::net::TcpListener::bind(bind_addr.clone()).await {
                     Ok(listener) => listener,
@@ -147,6 +351,7 @@ impl LampoPeerManager {
                         return Err::<(), _>(error::anyhow!("Error binding to address: {}", e));
                     }
                 };
+                *bound_addr.lock().unwrap() = listener.local_addr().ok();
 
                 loop {
                     let alias = alias.clone();
@@ -158,13 +363,15 @@ impl LampoPeerManager {
                     match accept {
                         (tcp_stream, _) => {
                             log::info!(target: "lampo", "Got new connection {}", tcp_stream.peer_addr().unwrap());
-                            let addr = bind_addr.clone();
+                            let addresses = addresses.clone();
                             let _ = tokio::spawn(async move {
                                 // Use LDK's supplied networking battery to facilitate inbound
                                 // connections.
                                 net::setup_inbound(
                                     peer_manager.clone(),
-                                    tcp_stream.into_std().expect("impossible to convert a tpc_stream from tokio to std"),
+                                    tcp_stream.into_std().expect(
+                                        "impossible to convert a tpc_stream from tokio to std",
+                                    ),
                                 )
                                 .await;
 
@@ -186,8 +393,7 @@ impl LampoPeerManager {
                                         peer_manager.broadcast_node_announcement(
                                             [0; 3],
                                             alias.as_bytes().try_into().unwrap_or([0u8; 32]),
-                                            vec![ldk::ln::msgs::SocketAddress::from_str(&addr)
-                                                .expect("impossible to convert an addr to ln socket addr (wire format)")],
+                                            addresses.clone(),
                                         );
                                     }
                                 }
@@ -206,6 +412,98 @@ impl LampoPeerManager {
         Ok(())
     }
 
+    /// The number of channels and nodes inside the network graph.
+    fn graph_size(&self) -> error::Result<(usize, usize)> {
+        let channel_manager = self
+            .channel_manager
+            .as_ref()
+            .ok_or(error::anyhow!("channel manager is None"))?;
+        let graph = channel_manager.graph();
+        let graph = graph.read_only();
+        Ok((graph.channels().len(), graph.nodes().len()))
+    }
+
+    /// Connect again with `node_id`, or with the first connected peer
+    /// that we know how to reach, and wait for the gossip that it sends to us.
+    ///
+    /// LDK does not allow to send a `query_channel_range` on demand, but
+    /// it asks the gossip of the last two weeks to the first peers that
+    /// connect (and of the last hour to the others), so the sync is
+    /// triggered by a new connection with the peer.
+    pub async fn gossip_sync(&self, node_id: Option<NodeId>) -> error::Result<GossipSync> {
+        let manager = self.manager();
+        let peers = manager
+            .list_peers()
+            .into_iter()
+            .map(|peer| peer.counterparty_node_id)
+            .collect::<Vec<_>>();
+        if let Some(node_id) = node_id {
+            if !peers.contains(&node_id) {
+                return Err(lampo_error!(
+                    LampoErrorCode::PeerOffline,
+                    "peer `{node_id}` is not connected"
+                ));
+            }
+        }
+        let stored = self.stored_peers()?.peers;
+        let address_of = |node_id: &NodeId| {
+            stored
+                .iter()
+                .find(|peer| peer.node_id == node_id.to_string())
+                .and_then(|peer| peer.addresses.last())
+                .and_then(|addr| SocketAddr::from_str(addr).ok())
+        };
+        let (node_id, addr) = match node_id {
+            Some(node_id) => {
+                let addr = address_of(&node_id).ok_or(lampo_error!(
+                    LampoErrorCode::PeerOffline,
+                    "the address of peer `{node_id}` is unknown, connect with it first"
+                ))?;
+                (node_id, addr)
+            }
+            None => peers
+                .iter()
+                .find_map(|node_id| Some((*node_id, address_of(node_id)?)))
+                .ok_or(lampo_error!(
+                    LampoErrorCode::PeerOffline,
+                    "there are no peers to sync the gossip from"
+                ))?,
+        };
+
+        let (channels_before, nodes_before) = self.graph_size()?;
+        log::info!(target: "lampo", "syncing the gossip from peer `{node_id}`");
+        manager.disconnect_by_node_id(node_id);
+        while manager.peer_by_node_id(&node_id).is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        self.connect(node_id, addr).await?;
+
+        // Wait until the graph stops to grow.
+        let mut size = self.graph_size()?;
+        let mut stable = 0;
+        for _ in 0..GOSSIP_SYNC_POLLS {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let current = self.graph_size()?;
+            if current != size {
+                size = current;
+                stable = 0;
+                continue;
+            }
+            stable += 1;
+            if stable == 4 {
+                break;
+            }
+        }
+        let (channels, nodes) = size;
+        Ok(GossipSync {
+            peer: node_id.to_string(),
+            channels_learned: channels.saturating_sub(channels_before),
+            nodes_learned: nodes.saturating_sub(nodes_before),
+            channels,
+            nodes,
+        })
+    }
+
     pub fn is_connected_with(&self, peer_id: NodeId) -> bool {
         let Some(ref manager) = self.peer_manager else {
             panic!("at this point the peer manager should be known");
@@ -234,7 +532,10 @@ impl PeerEvents for LampoPeerManager {
     async fn connect(&self, node_id: NodeId, host: SocketAddr) -> error::Result<()> {
         let Some(close_callback) = net::connect_outbound(self.manager(), node_id, host).await
         else {
-            error::bail!("impossible connect with the peer `{node_id}`");
+            return Err(lampo_error!(
+                LampoErrorCode::PeerOffline,
+                "impossible connect with the peer `{node_id}`"
+            ));
         };
         let mut connection_closed_future = Box::pin(close_callback);
         let manager = self.manager();
@@ -248,7 +549,7 @@ impl PeerEvents for LampoPeerManager {
             }
             // Avoid blocking the tokio context by sleeping a bit
             match manager.peer_by_node_id(&node_id) {
-                Some(_) => return Ok(()),
+                Some(_) => return self.store_peer(node_id, host),
                 None => tokio::time::sleep(Duration::from_millis(10)).await,
             }
         }
@@ -257,7 +558,10 @@ impl PeerEvents for LampoPeerManager {
     async fn disconnect(&self, node_id: NodeId) -> error::Result<()> {
         //check the pubkey matches a valid connected peer
         if self.manager().peer_by_node_id(&node_id).is_none() {
-            error::bail!("Error: Could not find peer `{node_id}`");
+            return Err(lampo_error!(
+                LampoErrorCode::PeerOffline,
+                "Error: Could not find peer `{node_id}`"
+            ));
         }
 
         self.manager().disconnect_by_node_id(node_id);
@@ -0,0 +1,132 @@
+//! Lampo Router.
+//!
+//! Wrap the LDK router to keep the paths of our
+//! payments within the `max_route_hops` of the conf.
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use lampo_common::bitcoin::secp256k1::{PublicKey, Secp256k1, Signing, Verification};
+use lampo_common::keys::LampoKeysManager;
+use lampo_common::ldk::blinded_path::payment::ReceiveTlvs;
+use lampo_common::ldk::blinded_path::BlindedPath;
+use lampo_common::ldk::ln::channelmanager::ChannelDetails;
+use lampo_common::ldk::ln::msgs::{ErrorAction, LightningError};
+use lampo_common::ldk::offers::invoice::BlindedPayInfo;
+use lampo_common::ldk::onion_message::messenger::{Destination, MessageRouter, OnionMessagePath};
+use lampo_common::ldk::routing::router::{
+    DefaultRouter, InFlightHtlcs, Route, RouteParameters, Router,
+};
+use lampo_common::ldk::routing::scoring::ProbabilisticScoringFeeParameters;
+
+use super::channel_manager::{LampoGraph, LampoScorer};
+use crate::utils::logger::LampoLogger;
+
+/// How many times the route is searched again without the
+/// channels of the paths longer than `max_route_hops`.
+const MAX_HOPS_ATTEMPTS: usize = 10;
+
+type InnerRouter = DefaultRouter<
+    Arc<LampoGraph>,
+    Arc<LampoLogger>,
+    Arc<LampoKeysManager>,
+    Arc<Mutex<LampoScorer>>,
+    ProbabilisticScoringFeeParameters,
+    LampoScorer,
+>;
+
+pub struct LampoRouter {
+    inner: InnerRouter,
+    /// How many hops a path of our routes can have.
+    max_route_hops: Option<u8>,
+}
+
+impl LampoRouter {
+    pub fn new(
+        graph: Arc<LampoGraph>,
+        logger: Arc<LampoLogger>,
+        keys: Arc<LampoKeysManager>,
+        scorer: Arc<Mutex<LampoScorer>>,
+        score_params: ProbabilisticScoringFeeParameters,
+        max_route_hops: Option<u8>,
+    ) -> Self {
+        Self {
+            inner: DefaultRouter::new(graph, logger, keys, scorer, score_params),
+            max_route_hops,
+        }
+    }
+}
+
+impl Router for LampoRouter {
+    /// Find the route with the LDK router, and search it again without
+    /// the channels after our own of the paths longer than `max_route_hops`,
+    /// until every path is within the limit.
+    fn find_route(
+        &self,
+        payer: &PublicKey,
+        route_params: &RouteParameters,
+        first_hops: Option<&[&ChannelDetails]>,
+        inflight_htlcs: InFlightHtlcs,
+    ) -> Result<Route, LightningError> {
+        let Some(max_hops) = self.max_route_hops else {
+            return self
+                .inner
+                .find_route(payer, route_params, first_hops, inflight_htlcs);
+        };
+        let mut route_params = route_params.clone();
+        for _ in 0..MAX_HOPS_ATTEMPTS {
+            let route =
+                self.inner
+                    .find_route(payer, &route_params, first_hops, inflight_htlcs.clone())?;
+            let too_long = route
+                .paths
+                .iter()
+                .filter(|path| path.hops.len() > max_hops as usize)
+                .flat_map(|path| path.hops.iter().skip(1))
+                .map(|hop| hop.short_channel_id)
+                .collect::<HashSet<_>>();
+            if too_long.is_empty() {
+                return Ok(route);
+            }
+            route_params
+                .payment_params
+                .previously_failed_channels
+                .extend(too_long);
+        }
+        Err(LightningError {
+            err: format!("no route found within {max_hops} hops"),
+            action: ErrorAction::IgnoreError,
+        })
+    }
+
+    fn create_blinded_payment_paths<T: Signing + Verification>(
+        &self,
+        recipient: PublicKey,
+        first_hops: Vec<ChannelDetails>,
+        tlvs: ReceiveTlvs,
+        amount_msats: u64,
+        secp_ctx: &Secp256k1<T>,
+    ) -> Result<Vec<(BlindedPayInfo, BlindedPath)>, ()> {
+        self.inner
+            .create_blinded_payment_paths(recipient, first_hops, tlvs, amount_msats, secp_ctx)
+    }
+}
+
+impl MessageRouter for LampoRouter {
+    fn find_path(
+        &self,
+        sender: PublicKey,
+        peers: Vec<PublicKey>,
+        destination: Destination,
+    ) -> Result<OnionMessagePath, ()> {
+        self.inner.find_path(sender, peers, destination)
+    }
+
+    fn create_blinded_paths<T: Signing + Verification>(
+        &self,
+        recipient: PublicKey,
+        peers: Vec<PublicKey>,
+        secp_ctx: &Secp256k1<T>,
+    ) -> Result<Vec<BlindedPath>, ()> {
+        self.inner.create_blinded_paths(recipient, peers, secp_ctx)
+    }
+}
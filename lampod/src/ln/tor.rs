@@ -0,0 +1,177 @@
+//! Ephemeral Tor hidden service that makes the node
+//! reachable through an onion address.
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+use lampo_common::error;
+
+pub struct TorHiddenService {
+    control: TcpStream,
+    reader: BufReader<TcpStream>,
+    service_id: String,
+    port: u16,
+}
+
+impl TorHiddenService {
+    /// Connect to the Tor control at `control_addr` and create a v3
+    /// hidden service that maps the onion `port` to the local `port`.
+    ///
+    /// The service lives as long as the control connection, so it
+    /// is removed when this is dropped.
+    pub fn create(control_addr: &str, password: Option<&str>, port: u16) -> error::Result<Self> {
+        let control = TcpStream::connect(control_addr).map_err(|err| {
+            error::anyhow!("impossible connect to the tor control `{control_addr}`: {err}")
+        })?;
+        let reader = BufReader::new(control.try_clone()?);
+        let mut tor = Self {
+            control,
+            reader,
+            service_id: String::new(),
+            port,
+        };
+        let auth = match password {
+            Some(password) => format!(
+                "AUTHENTICATE \"{}\"",
+                password.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            None => "AUTHENTICATE".to_owned(),
+        };
+        tor.command(&auth)?;
+        let reply = tor.command(&format!(
+            "ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port={port},127.0.0.1:{port}"
+        ))?;
+        tor.service_id = reply
+            .iter()
+            .find_map(|line| line.strip_prefix("ServiceID="))
+            .ok_or(error::anyhow!("tor did not return the onion service id"))?
+            .to_owned();
+        Ok(tor)
+    }
+
+    /// The onion address with the port, in the format
+    /// used by the node announcement.
+    pub fn onion_address(&self) -> String {
+        format!("{}.onion:{}", self.service_id, self.port)
+    }
+
+    /// Send a command to the Tor control and return the lines
+    /// of the reply without the status code.
+    fn command(&mut self, command: &str) -> error::Result<Vec<String>> {
+        self.control
+            .write_all(format!("{command}\r\n").as_bytes())?;
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                error::bail!("tor control closed the connection");
+            }
+            let line = line.trim_end();
+            if line.len() < 4 {
+                error::bail!("unexpected reply from the tor control: `{line}`");
+            }
+            let (status, text) = line.split_at(3);
+            if status != "250" {
+                error::bail!("tor control refused the command: `{line}`");
+            }
+            lines.push(text[1..].to_owned());
+            // The last line of the reply has a space after the status.
+            if text.starts_with(' ') {
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+impl Drop for TorHiddenService {
+    fn drop(&mut self) {
+        if self.service_id.is_empty() {
+            return;
+        }
+        let service_id = self.service_id.clone();
+        if let Err(err) = self.command(&format!("DEL_ONION {service_id}")) {
+            log::warn!(target: "lampo", "impossible remove the onion service `{service_id}`: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::str::FromStr;
+    use std::thread::JoinHandle;
+
+    use lampo_common::ldk::ln::msgs::SocketAddress;
+
+    use super::TorHiddenService;
+    use crate::ln::peer_manager::announcement_addresses;
+
+    const SERVICE_ID: &str = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad";
+
+    /// Mock of the Tor control that accepts a single connection
+    /// and returns the commands received.
+    fn mock_tor_control() -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let worker = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            let mut commands = Vec::new();
+            for line in reader.lines() {
+                let command = line.unwrap();
+                let reply = if command.starts_with("ADD_ONION") {
+                    format!("250-ServiceID={SERVICE_ID}\r\n250 OK\r\n")
+                } else {
+                    "250 OK\r\n".to_owned()
+                };
+                stream.write_all(reply.as_bytes()).unwrap();
+                let done = command.starts_with("DEL_ONION");
+                commands.push(command);
+                if done {
+                    break;
+                }
+            }
+            commands
+        });
+        (addr, worker)
+    }
+
+    #[test]
+    fn onion_address_is_announced() {
+        let (addr, worker) = mock_tor_control();
+        let tor = TorHiddenService::create(&addr, Some("lampo"), 9735).unwrap();
+        let onion = tor.onion_address();
+        assert_eq!(onion, format!("{SERVICE_ID}.onion:9735"));
+
+        let addresses = announcement_addresses(
+            vec![SocketAddress::from_str("127.0.0.1:9735").unwrap()],
+            Some(&onion),
+        )
+        .unwrap();
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(addresses[1], SocketAddress::from_str(&onion).unwrap());
+        assert!(matches!(
+            addresses[1],
+            SocketAddress::OnionV3 { port: 9735, .. }
+        ));
+
+        drop(tor);
+        let commands = worker.join().unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                "AUTHENTICATE \"lampo\"".to_owned(),
+                "ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port=9735,127.0.0.1:9735".to_owned(),
+                format!("DEL_ONION {SERVICE_ID}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unreachable_tor_control_is_an_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        assert!(TorHiddenService::create(&addr, None, 9735).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+//! The BIP 329 labels of the wallet, stored as JSON Lines
+//! inside the lampo directory.
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use lampo_common::error;
+use lampo_common::utils::bip329::{self, Label, LabelType};
+
+pub const LABELS_FILE: &str = "labels.jsonl";
+
+pub struct LabelStore {
+    path: String,
+    /// The labels indexed by type and reference, a reference
+    /// has only one label for each type.
+    labels: Mutex<BTreeMap<(LabelType, String), Label>>,
+}
+
+impl LabelStore {
+    pub fn new(root_path: &str) -> error::Result<Self> {
+        let path = format!("{root_path}/{LABELS_FILE}");
+        let labels = match std::fs::read_to_string(&path) {
+            Ok(jsonl) => bip329::parse_jsonl(&jsonl)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path,
+            labels: Mutex::new(Self::index(labels)),
+        })
+    }
+
+    fn index(labels: Vec<Label>) -> BTreeMap<(LabelType, String), Label> {
+        labels
+            .into_iter()
+            .map(|label| ((label.label_type, label.reference.clone()), label))
+            .collect()
+    }
+
+    pub fn labels(&self) -> Vec<Label> {
+        // SAFETY: the lock can not be poisoned.
+        self.labels.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Store the labels, overriding the labels of the same objects.
+    /// With `replace` the labels already stored are dropped.
+    pub fn import(&self, labels: Vec<Label>, replace: bool) -> error::Result<()> {
+        // SAFETY: the lock can not be poisoned.
+        let mut stored = self.labels.lock().unwrap();
+        let mut updated = if replace {
+            BTreeMap::new()
+        } else {
+            stored.clone()
+        };
+        updated.extend(Self::index(labels));
+        let labels = updated.values().cloned().collect::<Vec<_>>();
+        std::fs::write(&self.path, bip329::to_jsonl(&labels)?)?;
+        *stored = updated;
+        Ok(())
+    }
+}
@@ -5,6 +5,9 @@
 //! in others words you WILL lost funds, do not trush me!
 use lampo_common::ldk::persister::fs_store::FilesystemStore;
 
+pub mod labels;
+pub mod tower;
+
 /// Lampo Persistence implementation.
 // FIME: it is a simple wrapper around the ldk file persister
 // giving more time to understand how to make a custom one without
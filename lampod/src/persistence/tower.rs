@@ -0,0 +1,266 @@
+//! Watchtower client that replicates the channel monitors.
+//!
+//! LDK protects the channels by replicating the full `ChannelMonitor`
+//! to a tower that watches the chain for us, so the tower must be
+//! trusted: the monitor contains the keys to sweep our funds.
+//!
+//! Every new state of a channel is sent with a
+//! `POST <tower>/monitors/<funding_txid>:<index>?update_id=<id>` that
+//! carries the serialized monitor as body. Only the latest monitor of
+//! a channel is useful, so while a tower is unreachable the older
+//! states are replaced by the new ones and retried later.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lampo_common::chan;
+use lampo_common::error;
+use lampo_common::ldk::chain::chainmonitor::{MonitorUpdateId, Persist};
+use lampo_common::ldk::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate};
+use lampo_common::ldk::chain::transaction::OutPoint;
+use lampo_common::ldk::chain::ChannelMonitorUpdateStatus;
+use lampo_common::ldk::sign::InMemorySigner;
+use lampo_common::ldk::util::ser::Writeable;
+use lampo_common::model::response;
+
+use super::LampoPersistence;
+
+/// How long we wait before retrying the towers that are unreachable.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long we wait the answer of a tower.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The state of a channel to send to the towers.
+#[derive(Debug, Clone)]
+struct MonitorBackup {
+    funding_txo: String,
+    update_id: u64,
+    monitor: Vec<u8>,
+}
+
+struct Tower {
+    uri: String,
+    /// The latest monitor of every channel not sent yet.
+    pending: HashMap<String, MonitorBackup>,
+    /// The unix timestamp of the last state accepted by the tower.
+    last_update: Option<u64>,
+    last_error: Option<String>,
+}
+
+pub struct TowerClient {
+    towers: Mutex<Vec<Tower>>,
+    sender: chan::Sender<MonitorBackup>,
+    receiver: chan::Receiver<MonitorBackup>,
+}
+
+impl TowerClient {
+    pub fn new(uris: &[String]) -> Self {
+        let (sender, receiver) = chan::unbounded();
+        let client = Self {
+            towers: Mutex::new(Vec::new()),
+            sender,
+            receiver,
+        };
+        for uri in uris {
+            client.add_tower(uri);
+        }
+        client
+    }
+
+    /// Register the tower, return false when it is already known.
+    pub fn add_tower(&self, uri: &str) -> bool {
+        let uri = uri.trim_end_matches('/');
+        // SAFETY: the lock can not be poisoned.
+        let mut towers = self.towers.lock().unwrap();
+        if towers.iter().any(|tower| tower.uri == uri) {
+            return false;
+        }
+        log::info!(target: "tower", "watchtower `{uri}` registered");
+        towers.push(Tower {
+            uri: uri.to_owned(),
+            pending: HashMap::new(),
+            last_update: None,
+            last_error: None,
+        });
+        true
+    }
+
+    /// Send the `monitors` to the tower `uri` only, e.g. the
+    /// monitors of the channels opened before its registration.
+    pub fn backup_to(&self, uri: &str, monitors: Vec<(String, u64, Vec<u8>)>) {
+        let uri = uri.trim_end_matches('/');
+        // SAFETY: the lock can not be poisoned.
+        let mut towers = self.towers.lock().unwrap();
+        let Some(tower) = towers.iter_mut().find(|tower| tower.uri == uri) else {
+            return;
+        };
+        for (funding_txo, update_id, monitor) in monitors {
+            tower.pending.insert(
+                funding_txo.clone(),
+                MonitorBackup {
+                    funding_txo,
+                    update_id,
+                    monitor,
+                },
+            );
+        }
+    }
+
+    pub fn list_towers(&self) -> Vec<response::Tower> {
+        // SAFETY: the lock can not be poisoned.
+        self.towers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|tower| response::Tower {
+                uri: tower.uri.clone(),
+                pending_updates: tower.pending.len(),
+                last_update: tower.last_update,
+                last_error: tower.last_error.clone(),
+            })
+            .collect()
+    }
+
+    fn backup(&self, funding_txo: OutPoint, monitor: &ChannelMonitor<InMemorySigner>) {
+        // SAFETY: the lock can not be poisoned.
+        if self.towers.lock().unwrap().is_empty() {
+            return;
+        }
+        let backup = MonitorBackup {
+            funding_txo: format!("{}:{}", funding_txo.txid, funding_txo.index),
+            update_id: monitor.get_latest_update_id(),
+            monitor: monitor.encode(),
+        };
+        let _ = self.sender.send(backup);
+    }
+
+    /// Spawn the thread that sends the new states to the towers.
+    pub fn listen(self: Arc<Self>) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            match self.receiver.recv_timeout(RETRY_INTERVAL) {
+                Ok(backup) => {
+                    // SAFETY: the lock can not be poisoned.
+                    for tower in self.towers.lock().unwrap().iter_mut() {
+                        tower
+                            .pending
+                            .insert(backup.funding_txo.clone(), backup.clone());
+                    }
+                }
+                Err(chan::RecvTimeoutError::Timeout) => {}
+                Err(chan::RecvTimeoutError::Disconnected) => return,
+            }
+            self.flush();
+        })
+    }
+
+    /// Try to send the pending states, a tower that fails is
+    /// retried at the next round.
+    fn flush(&self) {
+        // SAFETY: the lock can not be poisoned.
+        let pending = self
+            .towers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|tower| {
+                (
+                    tower.uri.clone(),
+                    tower.pending.values().cloned().collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for (uri, backups) in pending {
+            for backup in backups {
+                let result = Self::send(&uri, &backup);
+                // SAFETY: the lock can not be poisoned.
+                let mut towers = self.towers.lock().unwrap();
+                let Some(tower) = towers.iter_mut().find(|tower| tower.uri == uri) else {
+                    break;
+                };
+                if let Err(err) = result {
+                    log::warn!(target: "tower", "watchtower `{uri}` is unreachable, retrying later: {err}");
+                    tower.last_error = Some(err.to_string());
+                    break;
+                }
+                // A newer state may be arrived in the meanwhile.
+                if tower
+                    .pending
+                    .get(&backup.funding_txo)
+                    .is_some_and(|pending| pending.update_id == backup.update_id)
+                {
+                    tower.pending.remove(&backup.funding_txo);
+                }
+                tower.last_update = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|now| now.as_secs());
+                tower.last_error = None;
+            }
+        }
+    }
+
+    fn send(uri: &str, backup: &MonitorBackup) -> error::Result<()> {
+        let response = minreq::post(format!(
+            "{uri}/monitors/{}?update_id={}",
+            backup.funding_txo, backup.update_id
+        ))
+        .with_header("Content-Type", "application/octet-stream")
+        .with_body(backup.monitor.clone())
+        .with_timeout(HTTP_TIMEOUT.as_secs())
+        .send()?;
+        if !(200..300).contains(&response.status_code) {
+            error::bail!("the tower answered with status `{}`", response.status_code);
+        }
+        Ok(())
+    }
+}
+
+/// Persist the channel monitors on disk, and send them to the
+/// towers after that they are persisted.
+pub struct TowerPersister {
+    store: Arc<LampoPersistence>,
+    towers: Arc<TowerClient>,
+}
+
+impl TowerPersister {
+    pub fn new(store: Arc<LampoPersistence>, towers: Arc<TowerClient>) -> Self {
+        Self { store, towers }
+    }
+}
+
+impl Persist<InMemorySigner> for TowerPersister {
+    fn persist_new_channel(
+        &self,
+        funding_txo: OutPoint,
+        monitor: &ChannelMonitor<InMemorySigner>,
+        update_id: MonitorUpdateId,
+    ) -> ChannelMonitorUpdateStatus {
+        let status = self
+            .store
+            .as_ref()
+            .persist_new_channel(funding_txo, monitor, update_id);
+        if status == ChannelMonitorUpdateStatus::Completed {
+            self.towers.backup(funding_txo, monitor);
+        }
+        status
+    }
+
+    fn update_persisted_channel(
+        &self,
+        funding_txo: OutPoint,
+        update: Option<&ChannelMonitorUpdate>,
+        monitor: &ChannelMonitor<InMemorySigner>,
+        update_id: MonitorUpdateId,
+    ) -> ChannelMonitorUpdateStatus {
+        let status =
+            self.store
+                .as_ref()
+                .update_persisted_channel(funding_txo, update, monitor, update_id);
+        if status == ChannelMonitorUpdateStatus::Completed {
+            self.towers.backup(funding_txo, monitor);
+        }
+        status
+    }
+}
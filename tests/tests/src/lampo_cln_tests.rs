@@ -2,11 +2,13 @@ use std::str::FromStr;
 use std::time::Duration;
 
 use lampo_common::error;
+use lampo_common::error::LampoErrorCode;
 use lampo_common::event::ln::LightningEvent;
 use lampo_common::event::onchain::OnChainEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
 use lampo_common::json;
+use lampo_common::ldk::util::scid_utils;
 use lampo_common::model::request;
 use lampo_common::model::response;
 use lampo_common::model::response::InvoiceInfo;
@@ -90,6 +92,7 @@ pub fn fund_a_simple_channel_from_lampo() {
                 amount: 100000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
             },
         )
         .unwrap();
@@ -298,6 +301,7 @@ pub fn decode_cln_offer_from_lampo() {
                 amount: 500_000_000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
             },
         )
         // Wait a little bit that the open channel will finish!
@@ -405,6 +409,7 @@ pub fn decode_cln_offer_from_lampo_minimal_offer() {
                 amount: 500_000_000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
             },
         )
         // Wait a little bit that the open channel will finish!
@@ -506,6 +511,7 @@ pub fn fetchinvoice_cln_offer_from_lampo() {
                 amount: 500_000_000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
             },
         )
         // Wait a little bit that the open channel will finish!
@@ -713,6 +719,7 @@ pub fn pay_invoice_to_cln() {
                 amount: 500_000_000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
             },
         )
         // Wait a little bit that the open channel will finish!
@@ -783,6 +790,438 @@ pub fn pay_invoice_to_cln() {
     async_run!(cln.stop()).unwrap();
 }
 
+#[test]
+pub fn pay_invoice_to_cln_using_channel() {
+    init();
+
+    let mut cln = async_run!(cln::Node::with_params(
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let btc = cln.btc();
+    let lampo_manager = LampoTesting::new(btc.clone()).unwrap();
+    let lampo = lampo_manager.lampod();
+
+    let events = lampo.events();
+    let address = lampo_manager.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() >= 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    for amount in [500_000_000, 400_000_000] {
+        let _: json::Value = lampo
+            .call(
+                "fundchannel",
+                request::OpenChannel {
+                    node_id: cln.rpc().getinfo().unwrap().id,
+                    port: Some(cln.port.into()),
+                    amount,
+                    public: true,
+                    addr: Some("127.0.0.1".to_owned()),
+                    allow_unconfirmed: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+    }
+
+    let _ = btc.rpc().generate_to_address(6, &address).unwrap();
+    wait!(|| {
+        let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+        if channels.channels.len() == 2 && channels.channels.iter().all(|chan| chan.ready) {
+            return Ok(());
+        }
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        crate::wait_cln_sync!(cln);
+        Err(())
+    });
+
+    let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+    let used = channels.channels.last().unwrap().clone();
+    let unused = channels.channels.first().unwrap().clone();
+
+    let invoce = cln
+        .rpc()
+        .invoice(
+            Some(100_000_000),
+            "lampo",
+            "pay through a specific channel",
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+    let result: error::Result<json::Value> = lampo.call(
+        "pay",
+        request::Pay {
+            invoice_str: invoce.bolt11,
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: Some(used.channel_id.clone()),
+            route_hints: vec![],
+            replace_route_hints: false,
+        },
+    );
+    assert!(result.is_ok(), "{:?}", result);
+
+    let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+    let after_used = channels
+        .channels
+        .iter()
+        .find(|chan| chan.channel_id == used.channel_id)
+        .unwrap();
+    let after_unused = channels
+        .channels
+        .iter()
+        .find(|chan| chan.channel_id == unused.channel_id)
+        .unwrap();
+    assert!(
+        after_used.outbound_capacity_msat + 100_000_000 <= used.outbound_capacity_msat,
+        "{:?}",
+        channels
+    );
+    assert_eq!(
+        after_unused.outbound_capacity_msat,
+        unused.outbound_capacity_msat
+    );
+    async_run!(cln.stop()).unwrap();
+}
+
+#[test]
+pub fn pay_invoice_to_cln_with_route_hints() {
+    init();
+
+    let mut cln = async_run!(cln::Node::with_params(
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let btc = cln.btc();
+    let mut payee = async_run!(cln::Node::with_btc_and_params(
+        btc.clone(),
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let lampo_manager = LampoTesting::new(btc.clone()).unwrap();
+    let lampo = lampo_manager.lampod();
+
+    // the payee is reachable only through a private channel with cln.
+    let payee_id = payee.rpc().getinfo().unwrap().id;
+    cln.rpc()
+        .connect(&payee_id, Some(&format!("127.0.0.1:{}", payee.port)))
+        .unwrap();
+    let address = cln.rpc().newaddr(None).unwrap();
+    fund_wallet(btc.clone(), &address.bech32.unwrap(), 101).unwrap();
+    crate::wait_cln_sync!(cln);
+    let _: json::Value = cln
+        .rpc()
+        .call(
+            "fundchannel",
+            json::json!({
+                "id": payee_id,
+                "amount": 3_000_000,
+                "announce": false,
+            }),
+        )
+        .unwrap();
+
+    let address = lampo_manager.fund_wallet(101).unwrap();
+    let cln_id = cln.rpc().getinfo().unwrap().id;
+    let _: json::Value = lampo
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: cln_id.clone(),
+                port: Some(cln.port.into()),
+                amount: 500_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let _ = btc.rpc().generate_to_address(6, &address).unwrap();
+    wait!(|| {
+        let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+        let mut cln_channels = cln.rpc().listfunds().unwrap().channels;
+        cln_channels.retain(|chan| chan.state == "CHANNELD_NORMAL");
+        if cln_channels.len() == 2 && channels.channels.iter().all(|chan| chan.ready) {
+            return Ok(());
+        }
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        crate::wait_cln_sync!(cln);
+        Err(())
+    });
+
+    // the invoice does not contain the hint to reach the payee.
+    let invoice: json::Value = payee
+        .rpc()
+        .call(
+            "invoice",
+            json::json!({
+                "amount_msat": 1_000_000,
+                "label": "lampo",
+                "description": "pay with route hints",
+                "exposeprivatechannels": false,
+            }),
+        )
+        .unwrap();
+    let bolt11 = invoice["bolt11"].as_str().unwrap().to_owned();
+    let result: error::Result<json::Value> = lampo.call(
+        "pay",
+        request::Pay {
+            invoice_str: bolt11.clone(),
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![],
+            replace_route_hints: false,
+        },
+    );
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::NoRoute),
+        "{err}"
+    );
+
+    let channels: json::Value = payee
+        .rpc()
+        .call("listpeerchannels", json::json!({}))
+        .unwrap();
+    let scid = channels["channels"][0]["short_channel_id"]
+        .as_str()
+        .unwrap();
+    let parts = scid
+        .split('x')
+        .map(|part| part.parse::<u64>().unwrap())
+        .collect::<Vec<_>>();
+    let short_channel_id = scid_utils::scid_from_parts(parts[0], parts[1], parts[2]).unwrap();
+    let hint = request::RouteHintHop {
+        node_id: cln_id,
+        short_channel_id,
+        fee_base_msat: 1_000,
+        fee_proportional_millionths: 1_000,
+        cltv_expiry_delta: 144,
+        htlc_minimum_msat: None,
+        htlc_maximum_msat: None,
+    };
+
+    let mut invalid = hint.clone();
+    invalid.node_id = "not a node id".to_owned();
+    let result: error::Result<json::Value> = lampo.call(
+        "pay",
+        request::Pay {
+            invoice_str: bolt11.clone(),
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![vec![invalid]],
+            replace_route_hints: false,
+        },
+    );
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    let result: error::Result<response::PayResult> = lampo.call(
+        "pay",
+        request::Pay {
+            invoice_str: bolt11,
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![vec![hint]],
+            replace_route_hints: true,
+        },
+    );
+    assert!(result.is_ok(), "{:?}", result);
+    assert!(
+        matches!(result.unwrap().state, response::PaymentState::Success),
+        "the payment through the route hint must succeed"
+    );
+    async_run!(payee.stop()).unwrap();
+    async_run!(cln.stop()).unwrap();
+}
+
+#[test]
+pub fn reset_scorer_after_failed_payment() {
+    init();
+
+    let mut cln = async_run!(cln::Node::with_params(
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let btc = cln.btc();
+    let mut payee = async_run!(cln::Node::with_btc_and_params(
+        btc.clone(),
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let lampo_manager = LampoTesting::new(btc.clone()).unwrap();
+    let lampo = lampo_manager.lampod();
+
+    // the payee opens the channel with cln, so cln has
+    // no liquidity to forward the payments to the payee.
+    let cln_id = cln.rpc().getinfo().unwrap().id;
+    payee
+        .rpc()
+        .connect(&cln_id, Some(&format!("127.0.0.1:{}", cln.port)))
+        .unwrap();
+    let address = payee.rpc().newaddr(None).unwrap();
+    fund_wallet(btc.clone(), &address.bech32.unwrap(), 101).unwrap();
+    crate::wait_cln_sync!(payee);
+    let _: json::Value = payee
+        .rpc()
+        .call(
+            "fundchannel",
+            json::json!({
+                "id": cln_id,
+                "amount": 3_000_000,
+                "announce": true,
+            }),
+        )
+        .unwrap();
+
+    let address = lampo_manager.fund_wallet(101).unwrap();
+    let _: json::Value = lampo
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: cln_id.clone(),
+                port: Some(cln.port.into()),
+                amount: 500_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let payee_id = payee.rpc().getinfo().unwrap().id;
+    let _ = btc.rpc().generate_to_address(6, &address).unwrap();
+    // wait that lampo knows the channel between cln and the payee.
+    wait!(|| {
+        let route: error::Result<response::Route> = lampo.call(
+            "getroute",
+            request::GetRoute {
+                node_id: payee_id.clone(),
+                amount_msat: 10_000_000,
+            },
+        );
+        if route.is_ok() {
+            return Ok(());
+        }
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        crate::wait_cln_sync!(cln);
+        Err(())
+    });
+    let channels: json::Value = payee
+        .rpc()
+        .call("listpeerchannels", json::json!({}))
+        .unwrap();
+    let scid = channels["channels"][0]["short_channel_id"]
+        .as_str()
+        .unwrap();
+    let parts = scid
+        .split('x')
+        .map(|part| part.parse::<u64>().unwrap())
+        .collect::<Vec<_>>();
+    let short_channel_id = scid_utils::scid_from_parts(parts[0], parts[1], parts[2]).unwrap();
+    let payee_hop = |route: response::Route| {
+        route
+            .hops
+            .into_iter()
+            .find(|hop| hop.short_channel_id == short_channel_id)
+            .expect("the route must go through the channel with the payee")
+    };
+    let get_route = || -> response::Route {
+        lampo
+            .call(
+                "getroute",
+                request::GetRoute {
+                    node_id: payee_id.clone(),
+                    amount_msat: 10_000_000,
+                },
+            )
+            .unwrap()
+    };
+
+    let hop = payee_hop(get_route());
+    assert_eq!(hop.liquidity_max_msat, None, "{:?}", hop);
+
+    let invoice: json::Value = payee
+        .rpc()
+        .call(
+            "invoice",
+            json::json!({
+                "amount_msat": 10_000_000,
+                "label": "lampo",
+                "description": "fail at cln",
+            }),
+        )
+        .unwrap();
+    let result: error::Result<response::PayResult> = lampo.call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice["bolt11"].as_str().unwrap().to_owned(),
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![],
+            replace_route_hints: false,
+        },
+    );
+    assert!(
+        !matches!(
+            result,
+            Ok(response::PayResult {
+                state: response::PaymentState::Success,
+                ..
+            })
+        ),
+        "cln can not forward the payment: {:?}",
+        result
+    );
+
+    // the failure at cln is learned by the scorer and the
+    // route selection sees the channel without liquidity.
+    wait!(|| {
+        let hop = payee_hop(get_route());
+        match hop.liquidity_max_msat {
+            Some(max) if max < 10_000_000 => Ok(()),
+            _ => Err(()),
+        }
+    });
+
+    let _: response::ResetScorer = lampo
+        .call("resetscorer", request::ResetScorer::default())
+        .unwrap();
+    let hop = payee_hop(get_route());
+    assert_eq!(hop.liquidity_min_msat, None, "{:?}", hop);
+    assert_eq!(hop.liquidity_max_msat, None, "{:?}", hop);
+
+    async_run!(payee.stop()).unwrap();
+    async_run!(cln.stop()).unwrap();
+}
+
 #[test]
 fn be_able_to_kesend_payments() {
     init();
@@ -819,6 +1258,7 @@ fn be_able_to_kesend_payments() {
                 amount: 1_500_000_000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
             },
         )
         .unwrap();
@@ -872,6 +1312,8 @@ fn be_able_to_kesend_payments() {
         request::KeySend {
             destination: PublicKey::from_str(info_cln.id.as_str()).unwrap(),
             amount_msat: 100_00_000,
+            route_hints: vec![],
+            max_parts: None,
         },
     );
     assert!(result.is_ok(), "{:?}", result);
@@ -913,11 +1355,12 @@ fn test_closing_two_channels_without_channelid_fails() {
                 amount: 1_500_000_000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
             },
         )
         .unwrap();
 
-    // This would be the second channel
+    // This would be the second channel, funded with the unconfirmed change
     let _: json::Value = lampo
         .call(
             "fundchannel",
@@ -927,6 +1370,8 @@ fn test_closing_two_channels_without_channelid_fails() {
                 amount: 1_000_000_000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                allow_unconfirmed: true,
+                ..Default::default()
             },
         )
         .unwrap();
@@ -979,6 +1424,7 @@ fn test_closing_two_channels_without_channelid_fails() {
         request::CloseChannel {
             node_id: info_cln.id.to_string(),
             channel_id: None,
+            destination: None,
         },
     );
 
@@ -1021,6 +1467,7 @@ fn test_lampo_to_cln_close_channel_with_channel_id_success() {
                 amount: 1_500_000_000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
             },
         )
         .unwrap();
@@ -1074,6 +1521,7 @@ fn test_lampo_to_cln_close_channel_with_channel_id_success() {
         request::CloseChannel {
             node_id: info_cln.id.to_string(),
             channel_id: Some(channels.channels.first().unwrap().channel_id.to_string()),
+            destination: None,
         },
     );
     assert!(result.is_ok(), "{:?}", result);
@@ -1089,7 +1537,7 @@ fn test_lampo_to_cln_close_channel_with_channel_id_success() {
 }
 
 #[test]
-fn test_lampo_to_cln_close_channel_without_channel_id_success() {
+fn test_lampo_to_cln_closed_channels_history() {
     init();
     let mut cln = async_run!(cln::Node::with_params(
         "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
@@ -1123,6 +1571,7 @@ fn test_lampo_to_cln_close_channel_without_channel_id_success() {
                 amount: 1_500_000_000,
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
             },
         )
         .unwrap();
@@ -1170,23 +1619,245 @@ fn test_lampo_to_cln_close_channel_without_channel_id_success() {
     });
 
     let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+    let channel_id = channels.channels.first().unwrap().channel_id.to_string();
+    let _: response::CloseChannel = lampo
+        .call(
+            "close",
+            request::CloseChannel {
+                node_id: info_cln.id.to_string(),
+                channel_id: Some(channel_id.clone()),
+                destination: None,
+            },
+        )
+        .unwrap();
 
-    let result: Result<response::CloseChannel, _> = lampo.call(
-        "close",
-        request::CloseChannel {
-            node_id: info_cln.id.to_string(),
-            channel_id: None,
-        },
-    );
-    assert!(result.is_ok(), "{:?}", result);
-    assert_eq!(
-        result.as_ref().unwrap().channel_id,
-        channels.channels.first().unwrap().channel_id.to_string()
-    );
-    assert_eq!(
-        result.as_ref().unwrap().peer_id,
-        channels.channels.first().unwrap().peer_id.to_string()
-    );
+    // The channel is in the history once LDK reports it as closed.
+    wait!(|| {
+        let closed: response::ClosedChannels =
+            lampo.call("closedchannels", json::json!({})).unwrap();
+        let Some(closed) = closed.closed_channels.first() else {
+            return Err(());
+        };
+        assert_eq!(closed.channel_id, channel_id);
+        assert_eq!(closed.close_type, response::CloseType::Cooperative);
+        Ok(())
+    });
+    async_run!(cln.stop()).unwrap();
+}
+
+#[test]
+fn test_lampo_to_cln_close_channel_to_upfront_shutdown_script() {
+    init();
+    let mut cln = async_run!(cln::Node::with_params(
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let btc = cln.btc();
+    let shutdown_address = cln.rpc().newaddr(None).unwrap().bech32.unwrap();
+    let lampo_manager = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.upfront_shutdown_script = Some(shutdown_address.clone());
+    })
+    .unwrap();
+    let lampo = lampo_manager.lampod();
+    let info_cln = cln.rpc().getinfo().unwrap();
+    let events = lampo.events();
+    let address = lampo_manager.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = lampo
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: info_cln.id.clone(),
+                port: Some(cln.port.into()),
+                amount: 1_500_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let _ = btc.rpc().generate_to_address(6, &address).unwrap();
+    wait!(|| {
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        for _ in 0..100 {
+            let Ok(event) = events.recv_timeout(Duration::from_nanos(100)) else {
+                continue;
+            };
+            match event {
+                Event::Lightning(LightningEvent::ChannelReady { .. }) => return Ok(()),
+                _ => continue,
+            };
+        }
+        Err(())
+    });
+
+    wait!(|| {
+        let mut channels = cln.rpc().listfunds().unwrap().channels;
+        if channels.is_empty() {
+            return Err(());
+        }
+        channels.retain(|chan| chan.state == "CHANNELD_NORMAL");
+        if !channels.is_empty() {
+            return Ok(());
+        }
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        crate::wait_cln_sync!(cln);
+        Err(())
+    });
+
+    let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+    let result: Result<response::CloseChannel, _> = lampo.call(
+        "close",
+        request::CloseChannel {
+            node_id: info_cln.id.to_string(),
+            channel_id: Some(channels.channels.first().unwrap().channel_id.to_string()),
+            destination: None,
+        },
+    );
+    assert!(result.is_ok(), "{:?}", result);
+
+    let mut closing_txid = None;
+    wait!(|| {
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        let closed: response::ClosedChannels =
+            lampo.call("closedchannels", json::json!({})).unwrap();
+        closing_txid = closed
+            .closed_channels
+            .first()
+            .and_then(|closed| closed.closing_txid.clone());
+        closing_txid.as_ref().map(|_| ()).ok_or(())
+    });
+
+    let txid = bitcoincore_rpc::bitcoin::Txid::from_str(&closing_txid.unwrap()).unwrap();
+    let closing_tx = btc.rpc().get_raw_transaction(&txid, None).unwrap();
+    let shutdown_script = bitcoincore_rpc::bitcoin::Address::from_str(&shutdown_address)
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
+    assert!(
+        closing_tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey == shutdown_script),
+        "{:?}",
+        closing_tx
+    );
+    async_run!(cln.stop()).unwrap();
+}
+
+#[test]
+fn test_lampo_to_cln_close_channel_without_channel_id_success() {
+    init();
+    let mut cln = async_run!(cln::Node::with_params(
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let btc = cln.btc();
+    let lampo_manager = LampoTesting::new(btc.clone()).unwrap();
+    let lampo = lampo_manager.lampod();
+    let _info: response::GetInfo = lampo.call("getinfo", json::json!({})).unwrap();
+    let info_cln = cln.rpc().getinfo().unwrap();
+    let events = lampo.events();
+    let address = lampo_manager.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = lampo
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: cln.rpc().getinfo().unwrap().id,
+                port: Some(cln.port.into()),
+                amount: 1_500_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    // Get the transaction confirmed
+    let _ = btc.rpc().generate_to_address(6, &address).unwrap();
+    wait!(|| {
+        log::info!(target: "tests", "wait for confimetion");
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        // Get the transaction confirmed
+        for _ in 0..100 {
+            let Ok(event) = events.recv_timeout(Duration::from_nanos(100)) else {
+                continue;
+            };
+            log::info!(target: "tests", "lampo event: {:?}", event);
+            match event {
+                Event::Lightning(LightningEvent::ChannelReady { .. }) => return Ok(()),
+                _ => continue,
+            };
+        }
+        Err(())
+    });
+
+    wait!(|| {
+        let channels = cln.rpc().listfunds().unwrap().channels;
+        if channels.is_empty() {
+            return Err(());
+        }
+
+        let mut channels = cln.rpc().listfunds().unwrap().channels;
+        let origin_size = channels.len();
+        channels.retain(|chan| chan.state == "CHANNELD_NORMAL");
+        if channels.len() == origin_size {
+            return Ok(());
+        }
+
+        let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+        if !channels.channels.first().unwrap().ready {
+            return Err(());
+        }
+        let address = cln.rpc().newaddr(None).unwrap();
+        fund_wallet(btc.clone(), &address.bech32.unwrap(), 1).unwrap();
+        crate::wait_cln_sync!(cln);
+        Err(())
+    });
+
+    let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+
+    let result: Result<response::CloseChannel, _> = lampo.call(
+        "close",
+        request::CloseChannel {
+            node_id: info_cln.id.to_string(),
+            channel_id: None,
+            destination: None,
+        },
+    );
+    assert!(result.is_ok(), "{:?}", result);
+    assert_eq!(
+        result.as_ref().unwrap().channel_id,
+        channels.channels.first().unwrap().channel_id.to_string()
+    );
+    assert_eq!(
+        result.as_ref().unwrap().peer_id,
+        channels.channels.first().unwrap().peer_id.to_string()
+    );
     async_run!(cln.stop()).unwrap();
 }
 
@@ -1223,6 +1894,7 @@ fn test_close_channel_without_opening_a_channel_fails() {
         request::CloseChannel {
             node_id: info_cln.id.to_string(),
             channel_id: None,
+            destination: None,
         },
     );
     assert!(result.is_err(), "{:?}", result);
@@ -1302,3 +1974,438 @@ fn test_sync_gossip_from_network_cln() {
 
     async_run!(cln1.stop()).unwrap();
 }
+
+#[test]
+pub fn payment_failures_with_fee_insufficient() {
+    init();
+
+    let mut cln = async_run!(cln::Node::with_params(
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let btc = cln.btc();
+    let mut payee = async_run!(cln::Node::with_btc_and_params(
+        btc.clone(),
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let lampo_manager = LampoTesting::new(btc.clone()).unwrap();
+    let lampo = lampo_manager.lampod();
+
+    // the payee is reachable only through a private channel with cln.
+    let payee_id = payee.rpc().getinfo().unwrap().id;
+    cln.rpc()
+        .connect(&payee_id, Some(&format!("127.0.0.1:{}", payee.port)))
+        .unwrap();
+    let address = cln.rpc().newaddr(None).unwrap();
+    fund_wallet(btc.clone(), &address.bech32.unwrap(), 101).unwrap();
+    crate::wait_cln_sync!(cln);
+    let _: json::Value = cln
+        .rpc()
+        .call(
+            "fundchannel",
+            json::json!({
+                "id": payee_id,
+                "amount": 3_000_000,
+                "announce": false,
+            }),
+        )
+        .unwrap();
+
+    let address = lampo_manager.fund_wallet(101).unwrap();
+    let cln_id = cln.rpc().getinfo().unwrap().id;
+    let _: json::Value = lampo
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: cln_id.clone(),
+                port: Some(cln.port.into()),
+                amount: 500_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let _ = btc.rpc().generate_to_address(6, &address).unwrap();
+    wait!(|| {
+        let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+        let mut cln_channels = cln.rpc().listfunds().unwrap().channels;
+        cln_channels.retain(|chan| chan.state == "CHANNELD_NORMAL");
+        if cln_channels.len() == 2 && channels.channels.iter().all(|chan| chan.ready) {
+            return Ok(());
+        }
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        crate::wait_cln_sync!(cln);
+        Err(())
+    });
+
+    let invoice: json::Value = payee
+        .rpc()
+        .call(
+            "invoice",
+            json::json!({
+                "amount_msat": 1_000_000,
+                "label": "lampo",
+                "description": "fee insufficient",
+                "exposeprivatechannels": false,
+            }),
+        )
+        .unwrap();
+    let bolt11 = invoice["bolt11"].as_str().unwrap().to_owned();
+    let payment_hash = invoice["payment_hash"].as_str().unwrap().to_owned();
+
+    let channels: json::Value = payee
+        .rpc()
+        .call("listpeerchannels", json::json!({}))
+        .unwrap();
+    let scid = channels["channels"][0]["short_channel_id"]
+        .as_str()
+        .unwrap();
+    let parts = scid
+        .split('x')
+        .map(|part| part.parse::<u64>().unwrap())
+        .collect::<Vec<_>>();
+    let short_channel_id = scid_utils::scid_from_parts(parts[0], parts[1], parts[2]).unwrap();
+    // cln asks a fee to forward, so a free hint is refused with `fee_insufficient`.
+    let hint = request::RouteHintHop {
+        node_id: cln_id.clone(),
+        short_channel_id,
+        fee_base_msat: 0,
+        fee_proportional_millionths: 0,
+        cltv_expiry_delta: 144,
+        htlc_minimum_msat: None,
+        htlc_maximum_msat: None,
+    };
+    let result: error::Result<response::PayResult> = lampo.call(
+        "pay",
+        request::Pay {
+            invoice_str: bolt11,
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![vec![hint]],
+            replace_route_hints: true,
+        },
+    );
+    assert!(
+        !result.is_ok_and(|pay| matches!(pay.state, response::PaymentState::Success)),
+        "the payment with a free hint must fail"
+    );
+
+    let failures: response::PaymentFailures = lampo
+        .call(
+            "paymentfailures",
+            request::PaymentFailures {
+                payment_hash: Some(payment_hash.clone()),
+                clear: true,
+            },
+        )
+        .unwrap();
+    let failure = failures.failures.first().unwrap();
+    assert_eq!(failure.payment_hash, payment_hash);
+    assert_eq!(failure.failure, "fee_insufficient", "{:?}", failure);
+    assert_eq!(failure.erring_node, Some(cln_id));
+    assert_eq!(failure.erring_channel, Some(short_channel_id));
+
+    let failures: response::PaymentFailures = lampo
+        .call(
+            "paymentfailures",
+            request::PaymentFailures {
+                payment_hash: None,
+                clear: false,
+            },
+        )
+        .unwrap();
+    assert!(failures.failures.is_empty(), "{:?}", failures);
+    async_run!(payee.stop()).unwrap();
+    async_run!(cln.stop()).unwrap();
+}
+
+#[test]
+pub fn keysend_to_cln_with_route_hints() {
+    init();
+
+    let mut cln = async_run!(cln::Node::with_params(
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let btc = cln.btc();
+    let mut payee = async_run!(cln::Node::with_btc_and_params(
+        btc.clone(),
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let lampo_manager = LampoTesting::new(btc.clone()).unwrap();
+    let lampo = lampo_manager.lampod();
+
+    // the payee is reachable only through a private channel with cln.
+    let payee_id = payee.rpc().getinfo().unwrap().id;
+    cln.rpc()
+        .connect(&payee_id, Some(&format!("127.0.0.1:{}", payee.port)))
+        .unwrap();
+    let address = cln.rpc().newaddr(None).unwrap();
+    fund_wallet(btc.clone(), &address.bech32.unwrap(), 101).unwrap();
+    crate::wait_cln_sync!(cln);
+    let _: json::Value = cln
+        .rpc()
+        .call(
+            "fundchannel",
+            json::json!({
+                "id": payee_id,
+                "amount": 3_000_000,
+                "announce": false,
+            }),
+        )
+        .unwrap();
+
+    let address = lampo_manager.fund_wallet(101).unwrap();
+    let cln_id = cln.rpc().getinfo().unwrap().id;
+    let _: json::Value = lampo
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: cln_id.clone(),
+                port: Some(cln.port.into()),
+                amount: 500_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let _ = btc.rpc().generate_to_address(6, &address).unwrap();
+    wait!(|| {
+        let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+        let mut cln_channels = cln.rpc().listfunds().unwrap().channels;
+        cln_channels.retain(|chan| chan.state == "CHANNELD_NORMAL");
+        if cln_channels.len() == 2 && channels.channels.iter().all(|chan| chan.ready) {
+            return Ok(());
+        }
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        crate::wait_cln_sync!(cln);
+        Err(())
+    });
+
+    let destination = PublicKey::from_str(&payee_id).unwrap();
+    let result: error::Result<json::Value> = lampo.call(
+        "keysend",
+        request::KeySend {
+            destination,
+            amount_msat: 1_000_000,
+            route_hints: vec![],
+            max_parts: None,
+        },
+    );
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::NoRoute),
+        "{err}"
+    );
+
+    let channels: json::Value = payee
+        .rpc()
+        .call("listpeerchannels", json::json!({}))
+        .unwrap();
+    let scid = channels["channels"][0]["short_channel_id"]
+        .as_str()
+        .unwrap();
+    let parts = scid
+        .split('x')
+        .map(|part| part.parse::<u64>().unwrap())
+        .collect::<Vec<_>>();
+    let short_channel_id = scid_utils::scid_from_parts(parts[0], parts[1], parts[2]).unwrap();
+    let hint = request::RouteHintHop {
+        node_id: cln_id,
+        short_channel_id,
+        fee_base_msat: 1_000,
+        fee_proportional_millionths: 1_000,
+        cltv_expiry_delta: 144,
+        htlc_minimum_msat: None,
+        htlc_maximum_msat: None,
+    };
+    let result: error::Result<json::Value> = lampo.call(
+        "keysend",
+        request::KeySend {
+            destination,
+            amount_msat: 1_000_000,
+            route_hints: vec![vec![hint]],
+            max_parts: None,
+        },
+    );
+    assert!(result.is_ok(), "{:?}", result);
+
+    // The payee claims the keysend as a paid invoice.
+    wait!(|| {
+        let invoices: json::Value = payee.rpc().call("listinvoices", json::json!({})).unwrap();
+        let claimed = invoices["invoices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|invoice| {
+                invoice["status"] == "paid" && invoice["amount_received_msat"] == 1_000_000
+            });
+        if claimed {
+            return Ok(());
+        }
+        Err(())
+    });
+    async_run!(payee.stop()).unwrap();
+    async_run!(cln.stop()).unwrap();
+}
+
+#[test]
+fn lnurl_pay_to_cln() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    init();
+
+    let mut cln = async_run!(cln::Node::with_params(
+        "--developer --dev-bitcoind-poll=1 --dev-fast-gossip --dev-allow-localhost",
+        "regtest"
+    ))
+    .unwrap();
+    let btc = cln.btc();
+    let lampo_manager = LampoTesting::new(btc.clone()).unwrap();
+    let lampo = lampo_manager.lampod();
+
+    let events = lampo.events();
+    let address = lampo_manager.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() >= 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let _: json::Value = lampo
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: cln.rpc().getinfo().unwrap().id,
+                port: Some(cln.port.into()),
+                amount: 500_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let _ = btc.rpc().generate_to_address(6, &address).unwrap();
+    wait!(|| {
+        let channels: response::Channels = lampo.call("channels", json::json!({})).unwrap();
+        let mut cln_channels = cln.rpc().listfunds().unwrap().channels;
+        cln_channels.retain(|chan| chan.state == "CHANNELD_NORMAL");
+        if !cln_channels.is_empty() && channels.channels.iter().all(|chan| chan.ready) {
+            return Ok(());
+        }
+        let _ = btc.rpc().generate_to_address(1, &address).unwrap();
+        crate::wait_cln_sync!(cln);
+        Err(())
+    });
+
+    // The invoice of the service commits to the metadata.
+    let metadata = r#"[["text/plain","coffee for lampo"],["text/identifier","lampo@127.0.0.1"]]"#;
+    let invoice: json::Value = cln
+        .rpc()
+        .call(
+            "invoice",
+            json::json!({
+                "amount_msat": 1_000_000,
+                "label": "lnurl",
+                "description": metadata,
+                "deschashonly": true,
+            }),
+        )
+        .unwrap();
+    let bolt11 = invoice["bolt11"].as_str().unwrap().to_owned();
+
+    // A mock LNURL service that answers the pay request and
+    // then the callback, giving back the requests received.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let service = listener.local_addr().unwrap();
+    let pay_request = json::json!({
+        "tag": "payRequest",
+        "callback": format!("http://{service}/callback"),
+        "minSendable": 1_000,
+        "maxSendable": 100_000_000,
+        "metadata": metadata,
+        "commentAllowed": 32,
+    });
+    let pay_response = json::json!({ "pr": bolt11, "routes": [] });
+    let server = std::thread::spawn(move || {
+        [pay_request, pay_response]
+            .into_iter()
+            .map(|body| {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 4096];
+                let size = stream.read(&mut request).unwrap();
+                let body = body.to_string();
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                            body.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .unwrap();
+                let request = String::from_utf8_lossy(&request[..size]).to_string();
+                request.lines().next().unwrap_or_default().to_owned()
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let result: response::LnurlPay = lampo
+        .call(
+            "lnurlpay",
+            request::LnurlPay {
+                lnurl: format!("lampo@{service}"),
+                amount_msat: 1_000_000,
+                comment: Some("thanks lampo!".to_owned()),
+            },
+        )
+        .unwrap();
+    assert!(
+        matches!(result.state, response::PaymentState::Success),
+        "{result:?}"
+    );
+    assert!(result.payment_preimage.is_some(), "{result:?}");
+    assert_eq!(result.description.as_deref(), Some("coffee for lampo"));
+    assert_eq!(result.invoice, bolt11);
+
+    let requests = server.join().unwrap();
+    assert!(
+        requests[0].starts_with("GET /.well-known/lnurlp/lampo "),
+        "{requests:?}"
+    );
+    assert!(
+        requests[1].starts_with("GET /callback?amount=1000000&comment=thanks%20lampo%21 "),
+        "{requests:?}"
+    );
+
+    let invoices: json::Value = cln
+        .rpc()
+        .call("listinvoices", json::json!({ "label": "lnurl" }))
+        .unwrap();
+    assert_eq!(invoices["invoices"][0]["status"], "paid");
+    assert_eq!(
+        invoices["invoices"][0]["payment_preimage"].as_str(),
+        result.payment_preimage.as_deref()
+    );
+    async_run!(cln.stop()).unwrap();
+}
@@ -1,17 +1,32 @@
 //! Integration tests between lampo nodes.
 //!
 //! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use lampo_common::bitcoin::hashes::sha256::Hash as Sha256;
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::bitcoin::{Address, Sequence};
+use lampo_common::conf::LampoConf;
 use lampo_common::error;
+use lampo_common::error::LampoErrorCode;
 use lampo_common::event::ln::LightningEvent;
 use lampo_common::event::onchain::OnChainEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
 use lampo_common::json;
+use lampo_common::ldk::util::config::ChannelConfig;
 use lampo_common::model::{request, response};
+use lampo_common::secp256k1::PublicKey;
+use lampo_common::types::{Keychain, TxKind};
+use lampo_common::wallet::{TransactionOptions, WalletError};
 
+use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
 use lampo_testing::prelude::*;
 use lampo_testing::wait;
 use lampo_testing::LampoTesting;
@@ -40,6 +55,46 @@ pub fn init_connection_test_between_lampo() -> error::Result<()> {
     Ok(())
 }
 
+#[test]
+pub fn forget_stored_peer_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node2.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node1.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node1.port,
+        },
+    )?;
+
+    let stored: response::StoredPeers = node2.lampod().call("storedpeers", json::json!({}))?;
+    let peer = stored.peers.first().unwrap();
+    assert_eq!(peer.node_id, node1.info.node_id);
+    assert_eq!(peer.addresses, vec![format!("127.0.0.1:{}", node1.port)]);
+
+    let _: json::Value = node2.lampod().call(
+        "forgetpeer",
+        request::ForgetPeer {
+            node_id: node1.info.node_id.clone(),
+        },
+    )?;
+    let stored: response::StoredPeers = node2.lampod().call("storedpeers", json::json!({}))?;
+    assert!(stored.peers.is_empty(), "{:?}", stored);
+
+    let result: error::Result<json::Value> = node2.lampod().call(
+        "forgetpeer",
+        request::ForgetPeer {
+            node_id: node1.info.node_id.clone(),
+        },
+    );
+    assert!(result.is_err());
+    Ok(())
+}
+
 #[test]
 pub fn fund_a_simple_channel_from() -> error::Result<()> {
     init();
@@ -82,8 +137,7 @@ pub fn fund_a_simple_channel_from() -> error::Result<()> {
                 node_id: node2.info.node_id.clone(),
                 amount: 100000,
                 public: true,
-                port: None,
-                addr: None,
+                ..Default::default()
             },
         )
         .unwrap();
@@ -121,15 +175,90 @@ pub fn fund_a_simple_channel_from() -> error::Result<()> {
 }
 
 #[test]
-pub fn pay_invoice_simple_case_lampo() -> error::Result<()> {
+pub fn channel_liquidity_and_reserves() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel = channels.channels.first().unwrap();
+    let capacity_msat = channel.amount * 1000;
+    let reserve_msat = channel.reserve_sat.expect("the channel reserve") * 1000;
+    let liquidity_msat =
+        channel.inbound_capacity_msat + channel.outbound_capacity_msat + reserve_msat;
+    // The remaining part of the capacity is used to pay the commitment fees.
+    assert!(liquidity_msat <= capacity_msat, "{:?}", channel);
+    assert!(liquidity_msat >= capacity_msat * 90 / 100, "{:?}", channel);
+    // The reserve of the peer depends on the capacity, not on its balance.
+    assert!(channel.counterparty_reserve_sat > 0, "{:?}", channel);
+    assert!(
+        channel.next_outbound_htlc_limit_msat <= channel.outbound_capacity_msat,
+        "{:?}",
+        channel
+    );
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_dry_run() -> error::Result<()> {
     init();
     let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
     let btc = Arc::new(btc);
     let node1 = Arc::new(LampoTesting::new(btc.clone())?);
     let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
 
     let events = node1.lampod().events();
-    let _ = node1.fund_wallet(101)?;
+    let _ = node1.fund_wallet(101).unwrap();
     wait!(|| {
         let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
             events.recv_timeout(Duration::from_millis(100))
@@ -142,89 +271,235 @@ pub fn pay_invoice_simple_case_lampo() -> error::Result<()> {
         Err(())
     });
 
-    let response: json::Value = node1
+    let response: response::OpenChannel = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100000,
+            public: true,
+            dry_run: true,
+            ..Default::default()
+        },
+    )?;
+    assert!(response.tx_hex.is_some());
+    assert!(response.fee_sat.unwrap() > 0);
+
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert!(channels.channels.is_empty(), "{:?}", channels);
+
+    // The funding inputs are still spendable by the wallet.
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    for input in response.tx.unwrap().input {
+        let utxo = funds.transactions.iter().find(|utxo| {
+            utxo.txid == input.previous_output.txid.to_string()
+                && utxo.vout == input.previous_output.vout
+        });
+        assert!(utxo.is_some_and(|utxo| !utxo.reserved), "{:?}", funds);
+    }
+
+    let dry_run = |locktime, sequence| request::OpenChannel {
+        node_id: node2.info.node_id.clone(),
+        amount: 100000,
+        public: true,
+        dry_run: true,
+        locktime,
+        sequence,
+        ..Default::default()
+    };
+    let response: response::OpenChannel = node1
         .lampod()
-        .call(
-            "fundchannel",
-            request::OpenChannel {
-                node_id: node2.info.node_id.clone(),
-                amount: 1_000_000,
-                public: true,
-                addr: Some("127.0.0.1".to_owned()),
-                port: Some(node2.port),
+        .call("fundchannel", dry_run(Some(100), Some(0xFFFFFFFD)))?;
+    let tx = response.tx.unwrap();
+    assert_eq!(tx.lock_time.to_consensus_u32(), 100);
+    assert!(tx.input.iter().all(|input| input.sequence.0 == 0xFFFFFFFD));
+
+    // A final sequence disables the locktime.
+    let result: error::Result<response::OpenChannel> = node1
+        .lampod()
+        .call("fundchannel", dry_run(Some(100), Some(0xFFFFFFFF)));
+    let err = result.err().unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn create_transaction_with_and_without_rbf() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let _ = node.fund_wallet(101)?;
+
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    let script = Address::from_str(&address.address)?
+        .assume_checked()
+        .script_pubkey();
+    let create = |rbf| {
+        node.wallet.create_transaction(
+            script.clone(),
+            100_000,
+            1000,
+            TransactionOptions {
+                rbf,
+                ..Default::default()
             },
         )
-        .unwrap();
-    assert!(response.get("tx").is_some());
+    };
 
-    wait!(|| {
-        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
-            node2.fund_wallet(6).unwrap();
-            if let Event::Lightning(LightningEvent::ChannelReady {
-                counterparty_node_id,
-                ..
-            }) = event
-            {
-                if counterparty_node_id.to_string() == node1.info.node_id {
-                    return Err(());
-                }
-                return Ok(());
-            };
-            // check if lampo see the channel
-            let channels: response::Channels =
-                node2.lampod().call("channels", json::json!({})).unwrap();
-            if channels.channels.is_empty() {
-                return Err(());
-            }
+    let tx = create(true)?;
+    assert!(
+        tx.input.iter().all(|input| input.sequence.is_rbf()),
+        "{:?}",
+        tx
+    );
+    // The transaction is not broadcast, so its coins can be spent again.
+    node.wallet.release_transaction(&tx)?;
+    let tx = create(false)?;
+    assert!(
+        tx.input.iter().all(|input| input.sequence == Sequence::MAX),
+        "{:?}",
+        tx
+    );
+    Ok(())
+}
 
-            if !channels.channels.first().unwrap().ready {
-                return Err(());
-            }
+#[test]
+pub fn create_transaction_over_budget() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let _ = node.fund_wallet(101)?;
 
-            let channels: response::Channels =
-                node1.lampod().call("channels", json::json!({})).unwrap();
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    let script = Address::from_str(&address.address)?
+        .assume_checked()
+        .script_pubkey();
+    let balance_sat = node.wallet.get_onchain_balance()? / 1000;
+    let err = node
+        .wallet
+        .create_transaction(
+            script,
+            balance_sat + 100_000,
+            1000,
+            TransactionOptions::default(),
+        )
+        .unwrap_err();
+    assert!(matches!(err, WalletError::InsufficientFunds(_)), "{err:?}");
+    assert_eq!(err.code(), LampoErrorCode::InsufficientFunds);
+    Ok(())
+}
 
-            if channels.channels.is_empty() {
-                return Err(());
-            }
+#[test]
+pub fn cancel_open_before_funding_broadcast() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
 
-            if channels.channels.first().unwrap().ready {
-                return Ok(());
-            }
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
         }
-        node2.fund_wallet(6).unwrap();
         Err(())
     });
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    assert!(!funds.transactions.is_empty());
 
-    let invoice: response::Invoice = node2.lampod().call(
-        "invoice",
-        request::GenerateInvoice {
-            description: "making sure that we can work betwen lampo version".to_owned(),
-            amount_msat: Some(100_000_000),
-            expiring_in: None,
+    let opener = node1.clone();
+    let node_id = node2.info.node_id.clone();
+    let port = node2.port;
+    let open = std::thread::spawn(move || {
+        opener
+            .lampod()
+            .call::<request::OpenChannel, response::OpenChannel>(
+                "fundchannel",
+                request::OpenChannel {
+                    node_id,
+                    amount: 1_000_000,
+                    public: true,
+                    addr: Some("127.0.0.1".to_owned()),
+                    port: Some(port),
+                    ..Default::default()
+                },
+            )
+    });
+
+    // Cancel as soon as the funding starts, so the peer can not
+    // have signed the funding yet.
+    let temporary_channel_id = loop {
+        if let Event::Lightning(LightningEvent::FundingChannelStart {
+            temporary_channel_id,
+            ..
+        }) = events.recv_timeout(Duration::from_secs(30))?
+        {
+            break temporary_channel_id;
+        }
+    };
+    let cancelled: response::CancelOpen = node1.lampod().call(
+        "cancelopen",
+        request::CancelOpen {
+            channel_id: temporary_channel_id.to_string(),
         },
     )?;
+    assert_eq!(cancelled.peer_id, node2.info.node_id);
+    assert!(open.join().unwrap().is_err());
 
-    log::info!(target: &node2.info.node_id, "invoice generated `{:?}`", invoice);
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if !channels.channels.is_empty() {
+            return Err(());
+        }
+        // Every coin of the wallet is spendable again.
+        let now: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        let released = funds.transactions.iter().all(|utxo| {
+            now.transactions
+                .iter()
+                .any(|now| now.txid == utxo.txid && now.vout == utxo.vout && !now.reserved)
+        });
+        if released {
+            return Ok(());
+        }
+        Err(())
+    });
 
-    let pay: response::PayResult = node1.lampod().call(
-        "pay",
-        request::Pay {
-            invoice_str: invoice.bolt11,
-            amount: None,
+    // A channel that does not exist can not be cancelled.
+    let result: error::Result<response::CancelOpen> = node1.lampod().call(
+        "cancelopen",
+        request::CancelOpen {
+            channel_id: "00".repeat(32),
         },
-    )?;
-    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    );
+    let err = result.err().unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::ChannelNotFound),
+        "{err}"
+    );
     Ok(())
 }
 
 #[test]
-pub fn pay_offer_simple_case_lampo() -> error::Result<()> {
+pub fn cancel_open_after_restart() -> error::Result<()> {
     init();
     let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
     let btc = Arc::new(btc);
     let node1 = Arc::new(LampoTesting::new(btc.clone())?);
     let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node3 = Arc::new(LampoTesting::new(btc.clone())?);
 
     let events = node1.lampod().events();
     let _ = node1.fund_wallet(101)?;
@@ -239,88 +514,138 @@ pub fn pay_offer_simple_case_lampo() -> error::Result<()> {
         }
         Err(())
     });
-
-    let response: json::Value = node1
-        .lampod()
-        .call(
-            "fundchannel",
-            request::OpenChannel {
-                node_id: node2.info.node_id.clone(),
-                amount: 1_000_000,
-                public: true,
-                addr: Some("127.0.0.1".to_owned()),
-                port: Some(node2.port),
-            },
-        )
-        .unwrap();
-    assert!(response.get("tx").is_some());
-
+    let _: response::OpenChannel = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
+            ..Default::default()
+        },
+    )?;
+    let _ = node1.fund_wallet(6)?;
     wait!(|| {
-        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
-            node2.fund_wallet(6).unwrap();
-            if let Event::Lightning(LightningEvent::ChannelReady {
-                counterparty_node_id,
-                ..
-            }) = event
-            {
-                if counterparty_node_id.to_string() == node1.info.node_id {
-                    return Err(());
-                }
-                return Ok(());
-            };
-            // check if lampo see the channel
-            let channels: response::Channels =
-                node2.lampod().call("channels", json::json!({})).unwrap();
-            if channels.channels.is_empty() {
-                return Err(());
-            }
-
-            if !channels.channels.first().unwrap().ready {
-                return Err(());
-            }
-
-            let channels: response::Channels =
-                node1.lampod().call("channels", json::json!({})).unwrap();
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        match channels.channels.first() {
+            Some(channel) if channel.ready => Ok(()),
+            _ => Err(()),
+        }
+    });
 
-            if channels.channels.is_empty() {
-                return Err(());
-            }
+    // The channel with node2 is loaded from the disk by the restarted node.
+    let restarted = Arc::new(node1.restart()?);
+    let channels: response::Channels = restarted.lampod().call("channels", json::json!({}))?;
+    assert_eq!(channels.channels.len(), 1);
+    let funded = channels.channels.first().unwrap().channel_id.clone();
 
-            if channels.channels.first().unwrap().ready {
-                return Ok(());
-            }
-        }
-        node2.fund_wallet(6).unwrap();
-        Err(())
+    let events = restarted.lampod().events();
+    let opener = restarted.clone();
+    let node_id = node3.info.node_id.clone();
+    let port = node3.port;
+    let open = std::thread::spawn(move || {
+        opener
+            .lampod()
+            .call::<request::OpenChannel, response::OpenChannel>(
+                "fundchannel",
+                request::OpenChannel {
+                    node_id,
+                    amount: 1_000_000,
+                    public: true,
+                    addr: Some("127.0.0.1".to_owned()),
+                    port: Some(port),
+                    ..Default::default()
+                },
+            )
     });
+    let temporary_channel_id = loop {
+        if let Event::Lightning(LightningEvent::FundingChannelStart {
+            temporary_channel_id,
+            ..
+        }) = events.recv_timeout(Duration::from_secs(30))?
+        {
+            break temporary_channel_id;
+        }
+    };
 
-    let offer: response::Offer = node2.lampod().call(
-        "offer",
-        request::GenerateOffer {
-            description: Some("making sure that we can work betwen lampo version".to_owned()),
-            amount_msat: Some(100_000_000),
+    // The open in progress does not make the channel opened
+    // before the restart cancellable.
+    let result: error::Result<response::CancelOpen> = restarted.lampod().call(
+        "cancelopen",
+        request::CancelOpen {
+            channel_id: funded.clone(),
         },
-    )?;
+    );
+    let err = result.err().unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
 
-    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
-
-    let pay: response::PayResult = node1.lampod().call(
-        "pay",
-        request::Pay {
-            invoice_str: offer.bolt12,
-            amount: None,
+    let cancelled: response::CancelOpen = restarted.lampod().call(
+        "cancelopen",
+        request::CancelOpen {
+            channel_id: temporary_channel_id.to_string(),
         },
     )?;
-    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    assert_eq!(cancelled.peer_id, node3.info.node_id);
+    assert!(open.join().unwrap().is_err());
+
+    wait!(|| {
+        let channels: response::Channels = restarted
+            .lampod()
+            .call("channels", json::json!({}))
+            .unwrap();
+        if channels.channels.len() != 1 {
+            return Err(());
+        }
+        if channels.channels.first().unwrap().channel_id == funded {
+            return Ok(());
+        }
+        Err(())
+    });
     Ok(())
 }
 
+/// A proxy to the p2p port `port` of a node that stops forwarding what
+/// the node sends back when `freeze` is set, so the node looks alive
+/// to the other side but never answers.
+fn freezable_proxy(port: u64) -> error::Result<(u64, Arc<AtomicBool>)> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let proxy_port = listener.local_addr()?.port() as u64;
+    let freeze = Arc::new(AtomicBool::new(false));
+    let frozen = freeze.clone();
+    std::thread::spawn(move || {
+        let (inbound, _) = listener.accept().unwrap();
+        let outbound = TcpStream::connect(format!("127.0.0.1:{port}")).unwrap();
+        let (mut inbound_reader, mut outbound_writer) =
+            (inbound.try_clone().unwrap(), outbound.try_clone().unwrap());
+        std::thread::spawn(move || std::io::copy(&mut inbound_reader, &mut outbound_writer));
+        let (mut outbound_reader, mut inbound_writer) = (outbound, inbound);
+        let mut buf = [0; 4096];
+        while let Ok(size) = outbound_reader.read(&mut buf) {
+            if size == 0 {
+                break;
+            }
+            if !frozen.load(Ordering::SeqCst) {
+                let _ = inbound_writer.write_all(&buf[..size]);
+            }
+        }
+    });
+    Ok((proxy_port, freeze))
+}
+
 #[test]
-pub fn pay_offer_minimal_offer() -> error::Result<()> {
+pub fn abandon_stalled_channel_open() -> error::Result<()> {
     init();
     let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
     let btc = Arc::new(btc);
-    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node1 = Arc::new(LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.channel_open_timeout_secs = 5;
+    })?);
     let node2 = Arc::new(LampoTesting::new(btc.clone())?);
 
     let events = node1.lampod().events();
@@ -337,83 +662,4981 @@ pub fn pay_offer_minimal_offer() -> error::Result<()> {
         Err(())
     });
 
-    let response: json::Value = node1
-        .lampod()
-        .call(
-            "fundchannel",
-            request::OpenChannel {
-                node_id: node2.info.node_id.clone(),
-                amount: 1_000_000,
-                public: true,
-                addr: Some("127.0.0.1".to_owned()),
-                port: Some(node2.port),
-            },
-        )
-        .unwrap();
-    assert!(response.get("tx").is_some());
+    let (port, freeze) = freezable_proxy(node2.port)?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port,
+        },
+    )?;
 
+    let opener = node1.clone();
+    let node_id = node2.info.node_id.clone();
+    let open = std::thread::spawn(move || {
+        opener
+            .lampod()
+            .call::<request::OpenChannel, response::OpenChannel>(
+                "fundchannel",
+                request::OpenChannel {
+                    node_id,
+                    amount: 1_000_000,
+                    public: true,
+                    ..Default::default()
+                },
+            )
+    });
+
+    // The peer accepted the channel, from now on it never
+    // answers, so the `funding_signed` is never received.
+    loop {
+        if let Event::Lightning(LightningEvent::FundingChannelStart { .. }) =
+            events.recv_timeout(Duration::from_secs(30))?
+        {
+            freeze.store(true, Ordering::SeqCst);
+            break;
+        }
+    }
+    loop {
+        if let Event::Lightning(LightningEvent::FundingChannelEnd { .. }) =
+            events.recv_timeout(Duration::from_secs(30))?
+        {
+            break;
+        }
+    }
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    assert!(
+        funds.transactions.iter().any(|utxo| utxo.reserved),
+        "{:?}",
+        funds
+    );
+
+    let err = open.join().unwrap().err().unwrap();
+    assert!(err.to_string().contains("abandoned"), "{err}");
+
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if !channels.channels.is_empty() {
+            return Err(());
+        }
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.iter().any(|utxo| utxo.reserved) {
+            return Err(());
+        }
+        Ok(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn estimate_close_all_channels() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let mut peers = Vec::new();
+    for _ in 0..3 {
+        let peer = LampoTesting::new(btc.clone())?;
+        let _: response::Connect = node1.lampod().call(
+            "connect",
+            request::Connect {
+                node_id: peer.info.node_id.clone(),
+                addr: "127.0.0.1".to_owned(),
+                port: peer.port,
+            },
+        )?;
+        let _: response::OpenChannel = node1.lampod().call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: peer.info.node_id.clone(),
+                amount: 100000,
+                public: true,
+                allow_unconfirmed: true,
+                ..Default::default()
+            },
+        )?;
+        peers.push(peer);
+    }
+
+    let estimate: response::EstimateCloseAll =
+        node1.lampod().call("estimatecloseall", json::json!({}))?;
+    assert_eq!(estimate.channels.len(), 3, "{:?}", estimate);
+    assert_eq!(
+        estimate.total_fee_sat,
+        estimate
+            .channels
+            .iter()
+            .map(|channel| channel.fee_sat)
+            .sum::<u64>()
+    );
+    assert_eq!(
+        estimate.total_max_fee_sat,
+        estimate
+            .channels
+            .iter()
+            .map(|channel| channel.max_fee_sat)
+            .sum::<u64>()
+    );
+    for channel in estimate.channels {
+        assert!(channel.is_funder);
+        assert!(channel.fee_sat > 0);
+        assert!(channel.fee_sat <= channel.max_fee_sat);
+    }
+
+    // Nothing was closed.
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert_eq!(channels.channels.len(), 3, "{:?}", channels);
+    Ok(())
+}
+
+#[test]
+pub fn import_channel_backup_recovers_lost_channel() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let _: response::OpenChannel = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+
+    // The backup is refreshed on disk when the channel state changes.
+    let backup_path = node1.root_path().path().join("regtest/channel.backup");
+    wait!(|| {
+        if backup_path.exists() {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let backup: response::ChannelBackup = node1
+        .lampod()
+        .call("exportchannelbackup", json::json!({}))?;
+
+    // The same node with the channel state wiped.
+    let node3 = LampoTesting::restore(btc.clone(), &node1.mnemonic)?;
+    assert_eq!(node3.info.node_id, node1.info.node_id);
+    let channels: response::Channels = node3.lampod().call("channels", json::json!({}))?;
+    assert!(channels.channels.is_empty(), "{:?}", channels);
+
+    let imported: response::ImportChannelBackup = node3.lampod().call(
+        "importchannelbackup",
+        request::ImportChannelBackup {
+            backup: backup.backup.clone(),
+        },
+    )?;
+    assert_eq!(imported.recovered.len(), 1, "{:?}", imported);
+    assert_eq!(
+        imported.recovered.first().unwrap().peer_id,
+        Some(node2.info.node_id.clone())
+    );
+
+    // The monitor is known now, so a second import is a no op.
+    let imported: response::ImportChannelBackup = node3.lampod().call(
+        "importchannelbackup",
+        request::ImportChannelBackup {
+            backup: backup.backup,
+        },
+    )?;
+    assert!(imported.recovered.is_empty(), "{:?}", imported);
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_with_funding_and_commitment_feerate() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let funding_feerate = 5000;
+    let commitment_feerate = 1000;
+    let response: response::OpenChannel = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100000,
+            public: true,
+            funding_feerate: Some(funding_feerate),
+            commitment_feerate: Some(commitment_feerate),
+            ..Default::default()
+        },
+    )?;
+
+    let txid = bitcoincore_rpc::bitcoin::Txid::from_str(&response.txid.unwrap().to_string())?;
+    let entry = btc.rpc().get_mempool_entry(&txid)?;
+    let feerate_per_kw = entry.fees.base.to_sat() * 1000 / (entry.vsize * 4);
+    assert!(
+        feerate_per_kw >= funding_feerate as u64 - 10,
+        "funding feerate {feerate_per_kw} sat/kw"
+    );
+
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel = channels.channels.first().unwrap();
+    assert_eq!(channel.feerate_sat_per_kw, Some(commitment_feerate));
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_then_keysend() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _ = node1.fund_wallet(101)?;
+
+    let opener = node1.lampod();
+    let node_id = node2.info.node_id.clone();
+    let port = node2.port;
+    let open = std::thread::spawn(move || -> error::Result<response::OpenChannel> {
+        opener.call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id,
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(port),
+                then_keysend_msat: Some(50_000_000),
+                ..Default::default()
+            },
+        )
+    });
+
+    // The keysend is sent as soon as the channel is confirmed.
+    wait!(|| {
+        if open.is_finished() {
+            return Ok(());
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+    let open = open.join().unwrap()?;
+    assert!(open.channel_id.is_some(), "{:?}", open);
+    assert!(
+        matches!(open.keysend_state, Some(response::PaymentState::Success)),
+        "{:?}",
+        open.keysend_state
+    );
+    let payment_hash = open.keysend_payment_hash.unwrap();
+
+    wait!(|| {
+        let status: response::InvoiceStatus = node2
+            .lampod()
+            .call(
+                "invoicestatus",
+                request::InvoiceStatus {
+                    payment_hash: payment_hash.clone(),
+                },
+            )
+            .unwrap();
+        if status.state == response::InvoiceState::Paid {
+            assert_eq!(status.amount_msat, Some(50_000_000));
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn fund_channels_concurrently() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node3 = Arc::new(LampoTesting::new(btc.clone())?);
+    // Two mature coinbase outputs, one for each funding.
+    let _ = node1.fund_wallet(102)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    // Every open waits the funding of its own channel.
+    let opens = [(&node2, 500_000), (&node3, 700_000)].map(|(peer, amount)| {
+        let opener = node1.lampod();
+        let node_id = peer.info.node_id.clone();
+        let port = peer.port;
+        let open = std::thread::spawn(move || -> error::Result<response::OpenChannel> {
+            opener.call(
+                "fundchannel",
+                request::OpenChannel {
+                    node_id,
+                    amount,
+                    public: true,
+                    addr: Some("127.0.0.1".to_owned()),
+                    port: Some(port),
+                    ..Default::default()
+                },
+            )
+        });
+        (open, amount)
+    });
+    let mut txids = Vec::new();
+    for (open, amount) in opens {
+        let open = open.join().unwrap()?;
+        let tx = open.tx.unwrap();
+        assert!(
+            tx.output.iter().any(|output| output.value == amount),
+            "{:?}",
+            tx
+        );
+        txids.push(tx.txid());
+    }
+    assert_ne!(txids[0], txids[1]);
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_then_keysend_timeout() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _ = node1.fund_wallet(101)?;
+
+    // No block is mined, so the channel never becomes usable.
+    let started = std::time::Instant::now();
+    let result: error::Result<response::OpenChannel> = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 1_000_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
+            then_keysend_msat: Some(50_000_000),
+            then_keysend_timeout_secs: Some(5),
+            ..Default::default()
+        },
+    );
+    let err = result.err().unwrap();
+    assert!(err.to_string().contains("not usable yet"), "{err}");
+    assert!(started.elapsed() < Duration::from_secs(60));
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_with_htlc_limits() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+    let _ = node1.fund_wallet(101).unwrap();
+
+    let open_channel = |min: u64, max: u64| request::OpenChannel {
+        node_id: node2.info.node_id.clone(),
+        amount: 100000,
+        public: true,
+        htlc_minimum_msat: Some(min),
+        max_htlc_value_in_flight_msat: Some(max),
+        ..Default::default()
+    };
+
+    let result: error::Result<response::OpenChannel> = node1
+        .lampod()
+        .call("fundchannel", open_channel(20_000_000, 10_000_000));
+    assert!(result.is_err(), "minimum bigger than the maximum");
+
+    let _: response::OpenChannel = node1
+        .lampod()
+        .call("fundchannel", open_channel(5_000, 30_000_000))?;
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel = channels.channels.first().unwrap();
+    assert_eq!(channel.inbound_htlc_minimum_msat, Some(5_000));
+    assert!(
+        channel
+            .inbound_htlc_maximum_msat
+            .is_some_and(|max| max <= 30_000_000),
+        "{:?}",
+        channel
+    );
+    Ok(())
+}
+
+#[test]
+pub fn list_and_set_channel_fees() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+    let _ = node1.fund_wallet(101).unwrap();
+
+    // The second channel is funded by the change of the first one.
+    let open_channel = || request::OpenChannel {
+        node_id: node2.info.node_id.clone(),
+        amount: 100000,
+        public: true,
+        allow_unconfirmed: true,
+        ..Default::default()
+    };
+    let _: response::OpenChannel = node1.lampod().call("fundchannel", open_channel())?;
+    let _: response::OpenChannel = node1.lampod().call("fundchannel", open_channel())?;
+
+    let fees: response::ChannelFees = node1.lampod().call("listchannelfees", json::json!({}))?;
+    assert_eq!(fees.channels.len(), 2, "{:?}", fees);
+    let defaults = ChannelConfig::default();
+    for fee in fees.channels.iter() {
+        assert_eq!(fee.base_fee_msat, defaults.forwarding_fee_base_msat);
+        assert_eq!(fee.fee_ppm, defaults.forwarding_fee_proportional_millionths);
+        assert_eq!(fee.cltv_delta, defaults.cltv_expiry_delta);
+    }
+
+    let updated = fees.channels.first().unwrap();
+    let other = fees.channels.last().unwrap();
+    let fee: response::ChannelFee = node1.lampod().call(
+        "setchannelfee",
+        request::SetChannelFee {
+            channel_id: updated.channel_id.clone(),
+            base_fee_msat: Some(2_000),
+            fee_ppm: Some(150),
+            cltv_delta: Some(144),
+        },
+    )?;
+    assert_eq!(fee.base_fee_msat, 2_000);
+
+    let fees: response::ChannelFees = node1.lampod().call("listchannelfees", json::json!({}))?;
+    for fee in fees.channels.iter() {
+        if fee.channel_id == updated.channel_id {
+            assert_eq!(fee.base_fee_msat, 2_000);
+            assert_eq!(fee.fee_ppm, 150);
+            assert_eq!(fee.cltv_delta, 144);
+            assert_eq!(fee.htlc_min_msat, updated.htlc_min_msat);
+            assert_eq!(fee.htlc_max_msat, updated.htlc_max_msat);
+        } else {
+            assert_eq!(fee.channel_id, other.channel_id);
+            assert_eq!(fee.base_fee_msat, defaults.forwarding_fee_base_msat);
+            assert_eq!(fee.fee_ppm, defaults.forwarding_fee_proportional_millionths);
+            assert_eq!(fee.cltv_delta, defaults.cltv_expiry_delta);
+        }
+    }
+
+    let result: error::Result<response::ChannelFees> = node1.lampod().call(
+        "listchannelfees",
+        request::ListChannelFees {
+            channel_id: Some("00".repeat(32)),
+        },
+    );
+    let err = result.err().unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::ChannelNotFound),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn list_channel_fees_htlc_limits() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+    let _ = node1.fund_wallet(101)?;
+    let _: response::OpenChannel = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            htlc_minimum_msat: Some(5_000),
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node2.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    // node2 forwards to node1 HTLCs of at least the minimum set by node1.
+    let fees: response::ChannelFees = node2.lampod().call("listchannelfees", json::json!({}))?;
+    let fee = fees.channels.first().unwrap();
+    assert_eq!(fee.htlc_min_msat, Some(5_000), "{:?}", fee);
+    assert!(
+        fee.htlc_max_msat
+            .is_some_and(|max| max > 5_000 && max <= 100_000_000),
+        "{:?}",
+        fee
+    );
+    Ok(())
+}
+
+#[test]
+pub fn update_channel_config_announces_fees() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let node3 = LampoTesting::new(btc.clone())?;
+    let open_channel = |node_id: &str| request::OpenChannel {
+        node_id: node_id.to_owned(),
+        amount: 1_000_000,
+        public: true,
+        ..Default::default()
+    };
+    for (node, peer) in [(&node1, &node2), (&node2, &node3)] {
+        let _: response::Connect = node.lampod().call(
+            "connect",
+            request::Connect {
+                node_id: peer.info.node_id.clone(),
+                addr: "127.0.0.1".to_owned(),
+                port: peer.port,
+            },
+        )?;
+        let _ = node.fund_wallet(101)?;
+        let height = btc.rpc().get_block_count()? as u32;
+        wait!(|| {
+            let info: response::GetInfo = node.lampod().call("getinfo", json::json!({})).unwrap();
+            if info.blockheight >= height {
+                return Ok(());
+            }
+            Err(())
+        });
+        let _: json::Value = node
+            .lampod()
+            .call("fundchannel", open_channel(&peer.info.node_id))?;
+    }
+    wait!(|| {
+        let channels: response::NetworkChannels = node1
+            .lampod()
+            .call("networkchannels", json::json!({}))
+            .unwrap();
+        if channels.channels.len() == 2 {
+            return Ok(());
+        }
+        let _ = node3.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let channels: response::Channels = node2.lampod().call("channels", json::json!({}))?;
+    let outgoing = channels
+        .channels
+        .iter()
+        .find(|channel| channel.peer_id == node3.info.node_id)
+        .expect("the channel with node3");
+    let config: response::UpdateChannelConfig = node2.lampod().call(
+        "updatechannelconfig",
+        request::UpdateChannelConfig {
+            channel_id: outgoing.channel_id.clone(),
+            base_fee_msat: Some(5_000),
+            fee_ppm: Some(0),
+            cltv_delta: None,
+            max_dust_htlc_exposure_msat: None,
+            force_close_avoidance_max_fee_sat: Some(2_000),
+        },
+    )?;
+    assert_eq!(config.base_fee_msat, 5_000);
+    assert_eq!(config.fee_ppm, 0);
+    assert_eq!(config.force_close_avoidance_max_fee_sat, 2_000);
+
+    // node1 learns the new fees from the channel update.
+    wait!(|| {
+        let route: response::Route = node1
+            .lampod()
+            .call(
+                "getroute",
+                request::GetRoute {
+                    node_id: node3.info.node_id.clone(),
+                    amount_msat: 10_000,
+                },
+            )
+            .unwrap();
+        if route.fee_msat == 5_000 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let err = node2
+        .lampod()
+        .call::<_, response::UpdateChannelConfig>(
+            "updatechannelconfig",
+            request::UpdateChannelConfig {
+                channel_id: "00".repeat(32),
+                base_fee_msat: Some(5_000),
+                fee_ppm: None,
+                cltv_delta: None,
+                max_dust_htlc_exposure_msat: None,
+                force_close_avoidance_max_fee_sat: None,
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::ChannelNotFound),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_with_stale_wallet() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    // Every funding needs a fresh sync of the wallet.
+    let node1 = Arc::new(LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.wallet_sync_staleness_secs = 0;
+    })?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+    let _ = node1.fund_wallet(101).unwrap();
+
+    let health: response::Health = node1.lampod().call("health", json::json!({}))?;
+    assert!(health.wallet_last_sync.is_some(), "{:?}", health);
+
+    // The wallet is not available anymore, so it can not be synced.
+    btc.rpc().unload_wallet(Some("lampo-wallet"))?;
+    let result: error::Result<response::OpenChannel> = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100000,
+            public: true,
+            ..Default::default()
+        },
+    );
+    let err = result.err().unwrap().to_string();
+    assert!(err.contains("wallet not synced, try again"), "{err}");
+    Ok(())
+}
+
+#[test]
+pub fn funding_change_uses_fresh_address() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let mut change_scripts = Vec::new();
+    for _ in 0..2 {
+        let response: response::OpenChannel = node1.lampod().call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 100000,
+                public: true,
+                dry_run: true,
+                ..Default::default()
+            },
+        )?;
+        // The only output that is not the channel is the change.
+        let tx = response.tx.unwrap();
+        let change = tx
+            .output
+            .iter()
+            .find(|output| output.value != 100000)
+            .unwrap();
+        change_scripts.push(change.script_pubkey.clone());
+    }
+    assert_ne!(change_scripts[0], change_scripts[1]);
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_with_unconfirmed_change() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node3 = Arc::new(LampoTesting::new(btc.clone())?);
+    for node in [&node2, &node3] {
+        let _: response::Connect = node1.lampod().call(
+            "connect",
+            request::Connect {
+                node_id: node.info.node_id.clone(),
+                addr: "127.0.0.1".to_owned(),
+                port: node.port,
+            },
+        )?;
+    }
+
+    let events = node1.lampod().events();
+    // Only the first coinbase is mature, so the wallet
+    // has a single confirmed output.
+    let _ = node1.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let open_channel = |node: &LampoTesting, allow_unconfirmed| request::OpenChannel {
+        node_id: node.info.node_id.clone(),
+        amount: 100000,
+        public: true,
+        allow_unconfirmed,
+        ..Default::default()
+    };
+    let first: response::OpenChannel = node1
+        .lampod()
+        .call("fundchannel", open_channel(&node2, false))?;
+
+    // The only output left is the unconfirmed change of the first funding.
+    let result: error::Result<response::OpenChannel> = node1
+        .lampod()
+        .call("fundchannel", open_channel(&node3, false));
+    assert!(result.is_err(), "{:?}", result.map(|resp| resp.txid));
+
+    let second: response::OpenChannel = node1
+        .lampod()
+        .call("fundchannel", open_channel(&node3, true))?;
+    let first_txid = first.txid.unwrap();
+    assert!(second
+        .tx
+        .unwrap()
+        .input
+        .iter()
+        .any(|input| input.previous_output.txid == first_txid));
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_onchain_fee_reserve() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.onchain_fee_reserve_sat = 25_000;
+    })?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    let reserve_sat = funds.onchain_fee_reserve_sat;
+    assert!(reserve_sat > 0);
+    let balance_sat = funds
+        .transactions
+        .iter()
+        .map(|utxo| utxo.amount_msat / 1000)
+        .sum::<u64>();
+
+    let open_channel = |amount| request::OpenChannel {
+        node_id: node2.info.node_id.clone(),
+        amount,
+        public: true,
+        dry_run: true,
+        ..Default::default()
+    };
+    let result: error::Result<response::OpenChannel> = node1
+        .lampod()
+        .call("fundchannel", open_channel(balance_sat - reserve_sat + 1));
+    assert!(result.is_err(), "{:?}", result.map(|resp| resp.txid));
+
+    let result: error::Result<response::OpenChannel> =
+        node1.lampod().call("fundchannel", open_channel(100000));
+    assert!(result.is_ok(), "{:?}", result.err());
+    Ok(())
+}
+
+#[test]
+pub fn pay_hold_invoice_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if !channels.channels.first().unwrap().ready {
+                return Err(());
+            }
+
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    // The preimage is generated outside lampo
+    let preimage = [42u8; 32];
+    let payment_hash = Sha256::hash(&preimage).to_string();
+    let preimage = preimage
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    let events = node2.lampod().events();
+    let invoice: response::Invoice = node2.lampod().call(
+        "createinvoice",
+        request::CreateInvoice {
+            payment_hash: payment_hash.clone(),
+            amount_msat: Some(100_000_000),
+            description: "hold invoice".to_owned(),
+            expiry: None,
+        },
+    )?;
+    let invoice_status = |payment_hash: &str| -> response::InvoiceStatus {
+        node2
+            .lampod()
+            .call(
+                "invoicestatus",
+                request::InvoiceStatus {
+                    payment_hash: payment_hash.to_owned(),
+                },
+            )
+            .unwrap()
+    };
+    let status = invoice_status(&payment_hash);
+    assert_eq!(status.state, response::InvoiceState::Unpaid);
+    assert_eq!(status.amount_msat, Some(100_000_000));
+    let status = invoice_status(&Sha256::hash(&[1u8; 32]).to_string());
+    assert_eq!(status.state, response::InvoiceState::Unknown);
+
+    let payer = node1.lampod();
+    let pay = std::thread::spawn(move || -> error::Result<response::PayResult> {
+        payer.call(
+            "pay",
+            request::Pay {
+                invoice_str: invoice.bolt11,
+                amount: None,
+                exclude_channels: vec![],
+                use_channel: None,
+                route_hints: vec![],
+                replace_route_hints: false,
+            },
+        )
+    });
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            if let Event::Lightning(LightningEvent::PaymentEvent {
+                state: response::PaymentState::Held,
+                payment_hash: Some(ref hash),
+                ..
+            }) = event
+            {
+                if hash == &payment_hash {
+                    return Ok(());
+                }
+            }
+        }
+        Err(())
+    });
+    let status = invoice_status(&payment_hash);
+    assert_eq!(status.state, response::InvoiceState::Held);
+    assert_eq!(status.preimage, None);
+
+    // The HTLC is in flight until the invoice is settled.
+    let channels: response::Channels = node2.lampod().call("channels", json::json!({}))?;
+    let dump: response::ChannelDump = node2.lampod().call(
+        "dumpchannel",
+        request::DumpChannel {
+            channel_id: channels.channels.first().unwrap().channel_id.clone(),
+        },
+    )?;
+    let htlc = dump
+        .pending_inbound_htlcs
+        .iter()
+        .find(|htlc| htlc.payment_hash == payment_hash);
+    assert!(
+        htlc.is_some_and(|htlc| htlc.amount_msat == 100_000_000 && htlc.cltv_expiry > 101),
+        "{:?}",
+        dump
+    );
+    assert!(dump.latest_monitor_update_id.is_some(), "{:?}", dump);
+
+    let settle: response::SettleInvoice = node2.lampod().call(
+        "settleinvoice",
+        request::SettleInvoice {
+            preimage: preimage.clone(),
+        },
+    )?;
+    assert_eq!(settle.payment_hash, payment_hash);
+
+    let pay = pay.join().unwrap()?;
+    log::info!(target: &node1.info.node_id, "hold invoice paid `{:?}`", pay);
+    assert!(matches!(pay.state, response::PaymentState::Success));
+    wait!(|| {
+        if invoice_status(&payment_hash).state == response::InvoiceState::Paid {
+            return Ok(());
+        }
+        Err(())
+    });
+    let status = invoice_status(&payment_hash);
+    assert_eq!(status.preimage, Some(preimage));
+    Ok(())
+}
+
+#[test]
+pub fn wait_send_pay_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if !channels.channels.first().unwrap().ready {
+                return Err(());
+            }
+
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    // The preimage is generated outside lampo, so the payment
+    // is pending until the invoice is settled.
+    let preimage = [7u8; 32];
+    let payment_hash = Sha256::hash(&preimage).to_string();
+    let preimage = preimage
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    let invoice: response::Invoice = node2.lampod().call(
+        "createinvoice",
+        request::CreateInvoice {
+            payment_hash: payment_hash.clone(),
+            amount_msat: Some(100_000_000),
+            description: "wait send pay".to_owned(),
+            expiry: None,
+        },
+    )?;
+
+    let wait_send_pay = |timeout_secs| request::WaitSendPay {
+        payment_hash: payment_hash.clone(),
+        timeout_secs: Some(timeout_secs),
+    };
+    let result: error::Result<response::PayResult> =
+        node1.lampod().call("waitsendpay", wait_send_pay(1));
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvoiceNotFound),
+        "{err}"
+    );
+
+    let payer = node1.lampod();
+    let _pay = std::thread::spawn(move || -> error::Result<response::PayResult> {
+        payer.call(
+            "pay",
+            request::Pay {
+                invoice_str: invoice.bolt11,
+                amount: None,
+                exclude_channels: vec![],
+                use_channel: None,
+                route_hints: vec![],
+                replace_route_hints: false,
+            },
+        )
+    });
+    // the payment is known, but it does not resolve before the settle.
+    wait!(|| {
+        let result: error::Result<response::PayResult> =
+            node1.lampod().call("waitsendpay", wait_send_pay(1));
+        match result {
+            Err(err) if LampoTesting::error_code(&err) == Some(LampoErrorCode::Generic) => Ok(()),
+            _ => Err(()),
+        }
+    });
+
+    // wait the payment from another connection.
+    let waiter = node1.lampod();
+    let request = wait_send_pay(120);
+    let wait = std::thread::spawn(move || -> error::Result<response::PayResult> {
+        waiter.call("waitsendpay", request)
+    });
+    std::thread::sleep(Duration::from_secs(2));
+    assert!(
+        !wait.is_finished(),
+        "waitsendpay must block until the payment resolves"
+    );
+
+    let _: response::SettleInvoice = node2.lampod().call(
+        "settleinvoice",
+        request::SettleInvoice {
+            preimage: preimage.clone(),
+        },
+    )?;
+    let result = wait.join().unwrap()?;
+    assert!(
+        matches!(result.state, response::PaymentState::Success),
+        "{:?}",
+        result
+    );
+    assert_eq!(result.payment_hash, Some(payment_hash.clone()));
+    assert_eq!(result.payment_preimage, Some(preimage.clone()));
+
+    // once resolved, the payment is returned immediately.
+    let result: response::PayResult = node1.lampod().call("waitsendpay", wait_send_pay(1))?;
+    assert!(matches!(result.state, response::PaymentState::Success));
+    assert_eq!(result.payment_preimage, Some(preimage));
+    Ok(())
+}
+
+#[test]
+pub fn rpc_error_codes_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            amount_msat: Some(100_000),
+            description: "no route".to_owned(),
+            expiring_in: None,
+            ignore_min_amount: false,
+        },
+    )?;
+    let result: error::Result<json::Value> = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![],
+            replace_route_hints: false,
+        },
+    );
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::NoRoute),
+        "{err}"
+    );
+
+    let result: error::Result<json::Value> = node1.lampod().call(
+        "close",
+        request::CloseChannel {
+            node_id: node2.info.node_id.clone(),
+            channel_id: None,
+            destination: None,
+        },
+    );
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::ChannelNotFound),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn pay_our_own_invoice_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let invoice: response::Invoice = node.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            amount_msat: Some(100_000),
+            description: "to myself".to_owned(),
+            expiring_in: None,
+            ignore_min_amount: false,
+        },
+    )?;
+    let events = node.lampod().events();
+    let result: error::Result<json::Value> = node.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![],
+            replace_route_hints: false,
+        },
+    );
+    let Err(err) = result else {
+        panic!("paying our own invoice must fail: {:?}", result);
+    };
+    assert!(err.to_string().contains("cannot pay yourself"), "{err}");
+    // no HTLC was launched, so no payment event is generated
+    while let Ok(event) = events.recv_timeout(Duration::from_secs(1)) {
+        assert!(
+            !matches!(event, Event::Lightning(LightningEvent::PaymentEvent { .. })),
+            "{:?}",
+            event
+        );
+    }
+    Ok(())
+}
+
+#[test]
+pub fn pay_invoice_simple_case_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if !channels.channels.first().unwrap().ready {
+                return Err(());
+            }
+
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "making sure that we can work betwen lampo version".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+            ignore_min_amount: false,
+        },
+    )?;
+
+    log::info!(target: &node2.info.node_id, "invoice generated `{:?}`", invoice);
+
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![],
+            replace_route_hints: false,
+        },
+    )?;
+    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    Ok(())
+}
+
+#[test]
+pub fn pay_offer_simple_case_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if !channels.channels.first().unwrap().ready {
+                return Err(());
+            }
+
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let offer: response::Offer = node2.lampod().call(
+        "offer",
+        request::GenerateOffer {
+            description: Some("making sure that we can work betwen lampo version".to_owned()),
+            amount_msat: Some(100_000_000),
+        },
+    )?;
+
+    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
+
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: offer.bolt12,
+            amount: None,
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![],
+            replace_route_hints: false,
+        },
+    )?;
+    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    Ok(())
+}
+
+#[test]
+pub fn pay_offer_minimal_offer() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if !channels.channels.first().unwrap().ready {
+                return Err(());
+            }
+
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let offer: response::Offer = node2.lampod().call(
+        "offer",
+        request::GenerateOffer {
+            description: None,
+            amount_msat: None,
+        },
+    )?;
+
+    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
+
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: offer.bolt12,
+            amount: Some(100_000_000),
+            exclude_channels: vec![],
+            use_channel: None,
+            route_hints: vec![],
+            replace_route_hints: false,
+        },
+    )?;
+    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    Ok(())
+}
+
+#[test]
+pub fn decode_offer() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if !channels.channels.first().unwrap().ready {
+                return Err(());
+            }
+
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let offer: response::Offer = node2.lampod().call(
+        "offer",
+        request::GenerateOffer {
+            description: None,
+            amount_msat: None,
+        },
+    )?;
+
+    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
+
+    let decode: response::InvoiceInfo = node2.lampod().call(
+        "decode",
+        request::DecodeInvoice {
+            invoice_str: offer.bolt12,
+        },
+    )?;
+
+    assert_eq!(decode.issuer_id, Some(node2.info.node_id.clone()));
+    log::info!(target: &node2.info.node_id, "decode offer `{:?}`", decode);
+    Ok(())
+}
+
+#[test]
+pub fn new_address_regtest_prefix() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    assert!(address.address.starts_with("bcrt1"), "{}", address.address);
+    // The address must be parsable as a regtest address.
+    let _ = bitcoincore_rpc::bitcoin::Address::from_str(&address.address)?
+        .require_network(bitcoincore_rpc::bitcoin::Network::Regtest)?;
+    Ok(())
+}
+
+#[test]
+pub fn new_addresses_in_batch() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    let batch: response::NewAddresses = node.lampod().call(
+        "newaddrs",
+        request::NewAddresses {
+            count: 50,
+            label_prefix: Some("deposit".to_owned()),
+        },
+    )?;
+    assert_eq!(batch.addresses.len(), 50);
+    let distinct = batch
+        .addresses
+        .iter()
+        .map(|addr| addr.address.clone())
+        .collect::<HashSet<_>>();
+    assert_eq!(distinct.len(), 50);
+    assert_eq!(batch.addresses[7].label.as_deref(), Some("deposit-7"));
+
+    // The keychain advanced by 50, so the next address is the
+    // one at index 50.
+    let next: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    let _: response::ResetAddressIndex = node.lampod().call(
+        "resetaddrindex",
+        request::ResetAddressIndex {
+            keychain: Keychain::External,
+            index: 50,
+        },
+    )?;
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    assert_eq!(address.address, next.address);
+
+    let too_many: error::Result<response::NewAddresses> = node.lampod().call(
+        "newaddrs",
+        request::NewAddresses {
+            count: 100_000,
+            label_prefix: None,
+        },
+    );
+    assert!(too_many.is_err(), "{:?}", too_many);
+    Ok(())
+}
+
+#[test]
+pub fn reset_address_index_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    let mut addresses = Vec::new();
+    for _ in 0..5 {
+        let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+        addresses.push(address.address);
+    }
+
+    // There are no funds in the wallet, so we can reuse the range
+    let reset: response::ResetAddressIndex = node.lampod().call(
+        "resetaddrindex",
+        request::ResetAddressIndex {
+            keychain: Keychain::External,
+            index: 1,
+        },
+    )?;
+    assert_eq!(reset.index, 1);
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    assert_eq!(address.address, addresses[1]);
+
+    // This is funding the address at index 2
+    let funded = node.fund_wallet(101)?;
+    assert_eq!(funded.to_string(), addresses[2]);
+    let reset: error::Result<response::ResetAddressIndex> = node.lampod().call(
+        "resetaddrindex",
+        request::ResetAddressIndex {
+            keychain: Keychain::External,
+            index: 2,
+        },
+    );
+    assert!(reset.is_err(), "{:?}", reset);
+
+    // The range above the funded address can be reused.
+    let _: response::ResetAddressIndex = node.lampod().call(
+        "resetaddrindex",
+        request::ResetAddressIndex {
+            keychain: Keychain::External,
+            index: 3,
+        },
+    )?;
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    assert_eq!(address.address, addresses[3]);
+    Ok(())
+}
+
+#[test]
+pub fn reset_change_index_after_spent_change() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let _ = node.fund_wallet(101)?;
+    let miner = btc.rpc().get_new_address(None, None)?.assume_checked();
+    wait!(|| {
+        let coins: response::Coins = node.lampod().call("listcoins", json::json!({})).unwrap();
+        if coins.coins.iter().any(|coin| coin.confirmed > 0) {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    // The withdraw pays its change to the internal keychain.
+    let _: response::Withdraw = node.lampod().call(
+        "withdraw",
+        request::Withdraw {
+            address: miner.to_string(),
+            amount_sat: 1_000_000,
+            fee_rate: Some(1000),
+            utxos: None,
+            subtract_fee_from_amount: false,
+            change_address: None,
+        },
+    )?;
+    let _ = btc.rpc().generate_to_address(1, &miner)?;
+    let mut change = None;
+    wait!(|| {
+        let coins: response::Coins = node.lampod().call("listcoins", json::json!({})).unwrap();
+        change = coins
+            .coins
+            .into_iter()
+            .find(|coin| coin.keychain == Some(Keychain::Internal) && coin.confirmed > 0);
+        change.as_ref().map(|_| ()).ok_or(())
+    });
+    let change = change.unwrap();
+    let change_index: u32 = change
+        .derivation_path
+        .as_ref()
+        .and_then(|path| path.rsplit('/').next())
+        .unwrap()
+        .parse()?;
+
+    // Spend all the change, so no output of the internal keychain is left.
+    let _: response::Withdraw = node.lampod().call(
+        "withdraw",
+        request::Withdraw {
+            address: miner.to_string(),
+            amount_sat: change.amount_msat / 1000,
+            fee_rate: Some(1000),
+            utxos: Some(vec![format!("{}:{}", change.txid, change.vout)]),
+            subtract_fee_from_amount: true,
+            change_address: None,
+        },
+    )?;
+    let _ = btc.rpc().generate_to_address(1, &miner)?;
+    wait!(|| {
+        let coins: response::Coins = node.lampod().call("listcoins", json::json!({})).unwrap();
+        if coins
+            .coins
+            .iter()
+            .all(|coin| coin.keychain != Some(Keychain::Internal))
+        {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    // The spent change address must not be handed out again.
+    let reset: error::Result<response::ResetAddressIndex> = node.lampod().call(
+        "resetaddrindex",
+        request::ResetAddressIndex {
+            keychain: Keychain::Internal,
+            index: change_index,
+        },
+    );
+    assert!(reset.is_err(), "{:?}", reset);
+    let _: response::ResetAddressIndex = node.lampod().call(
+        "resetaddrindex",
+        request::ResetAddressIndex {
+            keychain: Keychain::Internal,
+            index: change_index + 1,
+        },
+    )?;
+    Ok(())
+}
+
+#[test]
+pub fn list_addresses_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.bind_addr = Some("127.0.0.1".to_owned());
+        conf.announce_addresses = vec!["10.0.0.1".to_owned()];
+    })?;
+
+    let bind = format!("127.0.0.1:{}", node.port);
+    let announce = format!("10.0.0.1:{}", node.port);
+    wait!(|| {
+        let addresses: response::ListAddresses = node
+            .lampod()
+            .call("listaddresses", json::json!({}))
+            .unwrap();
+        if addresses.bind.contains(&bind) {
+            return Ok(());
+        }
+        Err(())
+    });
+    let addresses: response::ListAddresses =
+        node.lampod().call("listaddresses", json::json!({}))?;
+    assert_eq!(addresses.announce, vec![announce.clone()]);
+    assert!(!addresses.bind.contains(&announce), "{:?}", addresses);
+    assert_eq!(addresses.onion, None);
+    Ok(())
+}
+
+#[test]
+pub fn address_indices_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    let before: response::AddressIndices = node.lampod().call("addressindices", json::json!({}))?;
+    for _ in 0..3 {
+        let _: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    }
+    let after: response::AddressIndices = node.lampod().call("addressindices", json::json!({}))?;
+    assert_eq!(after.external, before.external + 3);
+    assert_eq!(after.internal, before.internal);
+    Ok(())
+}
+
+#[test]
+pub fn consolidate_utxos_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    // A coinbase output on five different addresses, the blocks
+    // mined by node2 make them mature.
+    let addresses: response::NewAddresses = node1.lampod().call(
+        "newaddrs",
+        request::NewAddresses {
+            count: 5,
+            label_prefix: None,
+        },
+    )?;
+    for address in addresses.addresses {
+        let address = bitcoincore_rpc::bitcoin::Address::from_str(&address.address)
+            .unwrap()
+            .assume_checked();
+        let _ = btc.rpc().generate_to_address(1, &address)?;
+    }
+    let _ = node2.fund_wallet(100)?;
+    wait!(|| {
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.len() == 5 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+
+    let result: error::Result<response::Consolidate> = node1.lampod().call(
+        "consolidate",
+        request::Consolidate {
+            fee_rate: 1000,
+            max_inputs: Some(0),
+        },
+    );
+    let err = result.err().unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    let consolidate: response::Consolidate = node1.lampod().call(
+        "consolidate",
+        request::Consolidate {
+            fee_rate: 1000,
+            max_inputs: None,
+        },
+    )?;
+    assert_eq!(consolidate.inputs, 5);
+
+    let txid = bitcoincore_rpc::bitcoin::Txid::from_str(&consolidate.txid)?;
+    let tx = btc.rpc().get_raw_transaction(&txid, None)?;
+    assert_eq!(tx.output.len(), 1, "{:?}", tx);
+    assert_eq!(tx.input.len(), 5, "{:?}", tx);
+    for utxo in funds.transactions.iter() {
+        assert!(
+            tx.input.iter().any(|input| {
+                input.previous_output.txid.to_string() == utxo.txid
+                    && input.previous_output.vout == utxo.vout
+            }),
+            "{:?}",
+            tx
+        );
+    }
+    Ok(())
+}
+
+#[test]
+pub fn withdraw_from_selected_utxos() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    // A coinbase output on three different addresses, the blocks
+    // mined by node2 make them mature.
+    let addresses: response::NewAddresses = node1.lampod().call(
+        "newaddrs",
+        request::NewAddresses {
+            count: 3,
+            label_prefix: None,
+        },
+    )?;
+    for address in addresses.addresses {
+        let address = bitcoincore_rpc::bitcoin::Address::from_str(&address.address)
+            .unwrap()
+            .assume_checked();
+        let _ = btc.rpc().generate_to_address(1, &address)?;
+    }
+    let _ = node2.fund_wallet(100)?;
+    wait!(|| {
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.len() == 3 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    let utxo = &funds.transactions[1];
+    let address: response::NewAddress = node2.lampod().call("newaddr", json::json!({}))?;
+    let withdraw = |utxos: Vec<String>, amount_sat| -> error::Result<response::Withdraw> {
+        node1.lampod().call(
+            "withdraw",
+            request::Withdraw {
+                address: address.address.clone(),
+                amount_sat,
+                fee_rate: Some(1000),
+                utxos: Some(utxos),
+                subtract_fee_from_amount: false,
+                change_address: None,
+            },
+        )
+    };
+
+    // The selected output can not pay more than its value.
+    let err = withdraw(
+        vec![format!("{}:{}", utxo.txid, utxo.vout)],
+        utxo.amount_msat / 1000 + 100_000,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InsufficientFunds),
+        "{err}"
+    );
+    let err = withdraw(vec![format!("{}:{}", utxo.txid, 42)], 100_000)
+        .err()
+        .unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    let response = withdraw(vec![format!("{}:{}", utxo.txid, utxo.vout)], 100_000)?;
+    let txid = bitcoincore_rpc::bitcoin::Txid::from_str(&response.txid)?;
+    let tx = btc.rpc().get_raw_transaction(&txid, None)?;
+    assert_eq!(tx.input.len(), 1, "{:?}", tx);
+    assert_eq!(tx.input[0].previous_output.txid.to_string(), utxo.txid);
+    assert_eq!(tx.input[0].previous_output.vout, utxo.vout);
+
+    // The output is reserved by the withdraw until it is confirmed.
+    let err = withdraw(vec![format!("{}:{}", utxo.txid, utxo.vout)], 100_000)
+        .err()
+        .unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn bitcoind_cookie_auth_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let cookie = std::env::temp_dir().join(format!("lampo-{}.cookie", btc.port));
+    std::fs::write(&cookie, format!("{}:{}", btc.user, btc.pass))?;
+
+    let node = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.core_user = None;
+        conf.core_pass = None;
+        conf.core_cookie = Some(cookie.to_string_lossy().to_string());
+    })?;
+    let _: response::GetInfo = node.lampod().call("getinfo", json::json!({}))?;
+    let _: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    std::fs::remove_file(&cookie)?;
+
+    let missing = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.core_user = None;
+        conf.core_pass = None;
+        conf.core_cookie = Some(cookie.to_string_lossy().to_string());
+    });
+    let Err(err) = missing else {
+        panic!("lampo must not start without the cookie file");
+    };
+    assert!(err.to_string().contains("cookie file"), "{err}");
+    Ok(())
+}
+
+#[test]
+pub fn inbound_channel_minimum_depth_per_peer() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let trusted = LampoTesting::new(btc.clone())?;
+    let other = LampoTesting::new(btc.clone())?;
+    let trusted_id = PublicKey::from_str(&trusted.info.node_id)?;
+    let node = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.minimum_depth = Some(3);
+        conf.peer_minimum_depth.insert(trusted_id, 0);
+    })?;
+
+    for funder in [&trusted, &other] {
+        let _: response::Connect = funder.lampod().call(
+            "connect",
+            request::Connect {
+                node_id: node.info.node_id.clone(),
+                addr: "127.0.0.1".to_owned(),
+                port: node.port,
+            },
+        )?;
+        let _ = funder.fund_wallet(101)?;
+    }
+    let height = btc.rpc().get_block_count()? as u32;
+    for funder in [&trusted, &other] {
+        wait!(|| {
+            let info: response::GetInfo = funder.lampod().call("getinfo", json::json!({})).unwrap();
+            if info.blockheight >= height {
+                return Ok(());
+            }
+            Err(())
+        });
+        let _: json::Value = funder.lampod().call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node.info.node_id.clone(),
+                amount: 100_000,
+                public: false,
+                ..Default::default()
+            },
+        )?;
+    }
+
+    let is_ready = |funder: &LampoTesting| -> bool {
+        let channels: response::Channels =
+            funder.lampod().call("channels", json::json!({})).unwrap();
+        channels.channels.first().map_or(false, |chan| chan.ready)
+    };
+    // The channel of the trusted peer is zero conf.
+    wait!(|| {
+        if is_ready(&trusted) {
+            return Ok(());
+        }
+        Err(())
+    });
+    assert!(!is_ready(&other));
+
+    // The other peer needs the global minimum depth.
+    let _ = other.fund_wallet(2)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    assert!(!is_ready(&other));
+    let _ = other.fund_wallet(1)?;
+    wait!(|| {
+        if is_ready(&other) {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn inbound_channel_minimum_depth_of_one() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let funder = LampoTesting::new(btc.clone())?;
+    let funder_id = PublicKey::from_str(&funder.info.node_id)?;
+    // LDK applies a single depth to the channels that are not zero
+    // conf, so the depth of 1 of the funder is also the global one.
+    let fast = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.minimum_depth = Some(1);
+        conf.peer_minimum_depth.insert(funder_id, 1);
+    })?;
+    let slow = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.minimum_depth = Some(3);
+    })?;
+
+    let _ = funder.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = funder.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    for node in [&fast, &slow] {
+        let _: json::Value = funder.lampod().call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node.info.node_id.clone(),
+                amount: 100_000,
+                public: false,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node.port),
+                allow_unconfirmed: true,
+                ..Default::default()
+            },
+        )?;
+    }
+
+    let is_ready = |node: &LampoTesting| -> bool {
+        let channels: response::Channels = node.lampod().call("channels", json::json!({})).unwrap();
+        channels.channels.first().map_or(false, |chan| chan.ready)
+    };
+    // A single block makes usable the channel with the depth of 1.
+    let _ = funder.fund_wallet(1)?;
+    wait!(|| {
+        if is_ready(&fast) {
+            return Ok(());
+        }
+        Err(())
+    });
+    assert!(!is_ready(&slow));
+
+    // The other channel needs three blocks.
+    let _ = funder.fund_wallet(2)?;
+    wait!(|| {
+        if is_ready(&slow) {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn gossip_sync_from_peer_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let node3 = LampoTesting::new(btc.clone())?;
+    for node in [&node2, &node3] {
+        let _: response::Connect = node.lampod().call(
+            "connect",
+            request::Connect {
+                node_id: node1.info.node_id.clone(),
+                addr: "127.0.0.1".to_owned(),
+                port: node1.port,
+            },
+        )?;
+    }
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    // The channel is announced after 6 confirmations.
+    wait!(|| {
+        let channels: response::NetworkChannels = node1
+            .lampod()
+            .call("networkchannels", json::json!({}))
+            .unwrap();
+        if !channels.channels.is_empty() {
+            return Ok(());
+        }
+        let _ = node1.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let before: response::NetworkChannels =
+        node3.lampod().call("networkchannels", json::json!({}))?;
+    let sync: response::GossipSync = node3.lampod().call(
+        "gossipsync",
+        request::GossipSync {
+            node_id: Some(node1.info.node_id.clone()),
+        },
+    )?;
+    assert_eq!(sync.peer, node1.info.node_id);
+    assert!(sync.channels >= 1, "{:?}", sync);
+    assert!(sync.nodes >= 2, "{:?}", sync);
+    assert_eq!(sync.channels, before.channels.len() + sync.channels_learned);
+
+    let after: response::NetworkChannels =
+        node3.lampod().call("networkchannels", json::json!({}))?;
+    assert!(after.channels.iter().any(|channel| {
+        let nodes = [channel.node_one.clone(), channel.node_two.clone()];
+        nodes.contains(&node1.info.node_id) && nodes.contains(&node2.info.node_id)
+    }));
+
+    // A peer that is not connected can not be used to sync.
+    let err = node3
+        .lampod()
+        .call::<_, response::GossipSync>(
+            "gossipsync",
+            request::GossipSync {
+                node_id: Some(node2.info.node_id.clone()),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::PeerOffline)
+    );
+    Ok(())
+}
+
+#[test]
+pub fn reload_persisted_network_graph() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.graph_persist_interval_secs = 1;
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node2.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node1.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node1.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.graph_channels > 0 {
+            return Ok(());
+        }
+        let _ = node1.fund_wallet(6).unwrap();
+        Err(())
+    });
+    // Wait that the graph is written on disk.
+    std::thread::sleep(Duration::from_secs(3));
+    let graph_path = format!(
+        "{}/regtest/network_graph",
+        node1.root_path().path().to_string_lossy()
+    );
+
+    // A node that starts from the data directory loads the graph
+    // without syncing it from the peers.
+    let restarted = LampoTesting::with_conf(btc.clone(), |conf| {
+        std::fs::copy(&graph_path, format!("{}/network_graph", conf.path())).unwrap();
+    })?;
+    let info: response::GetInfo = restarted.lampod().call("getinfo", json::json!({}))?;
+    assert_eq!(info.peers, 0);
+    assert!(info.graph_channels > 0, "{:?}", info);
+    assert!(info.graph_nodes >= 2, "{:?}", info);
+    Ok(())
+}
+
+#[test]
+pub fn rebroadcast_commitment_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.allow_unsafe_rpc = true;
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel_id = channels.channels.first().unwrap().channel_id.clone();
+
+    // The RPC is disabled without `allow-unsafe-rpc`.
+    let err = node2
+        .lampod()
+        .call::<_, response::RebroadcastCommitment>(
+            "rebroadcastcommitment",
+            request::RebroadcastCommitment {
+                channel_id: channel_id.clone(),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::Generic)
+    );
+
+    let _: response::RebroadcastCommitment = node1.lampod().call(
+        "rebroadcastcommitment",
+        request::RebroadcastCommitment {
+            channel_id: channel_id.clone(),
+        },
+    )?;
+    assert_eq!(commitment.channel_id, channel_id);
+    let txid = bitcoincore_rpc::bitcoin::Txid::from_str(&commitment.txid)?;
+    wait!(|| {
+        if btc.rpc().get_raw_transaction(&txid, None).is_ok() {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn close_channel_to_destination_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    // An address of another network is refused.
+    let result: error::Result<response::CloseChannel> = node1.lampod().call(
+        "close",
+        request::CloseChannel {
+            node_id: node2.info.node_id.clone(),
+            channel_id: None,
+            destination: Some("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_owned()),
+        },
+    );
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    let destination = btc.rpc().get_new_address(None, None)?.assume_checked();
+    let _: response::CloseChannel = node1.lampod().call(
+        "close",
+        request::CloseChannel {
+            node_id: node2.info.node_id.clone(),
+            channel_id: None,
+            destination: Some(destination.to_string()),
+        },
+    )?;
+
+    let mut closing_txid = None;
+    wait!(|| {
+        let _ = node2.fund_wallet(1).unwrap();
+        let closed: response::ClosedChannels = node1
+            .lampod()
+            .call("closedchannels", json::json!({}))
+            .unwrap();
+        closing_txid = closed
+            .closed_channels
+            .first()
+            .and_then(|closed| closed.closing_txid.clone());
+        closing_txid.as_ref().map(|_| ()).ok_or(())
+    });
+    let txid = bitcoincore_rpc::bitcoin::Txid::from_str(&closing_txid.unwrap())?;
+    let closing_tx = btc.rpc().get_raw_transaction(&txid, None)?;
+    assert!(
+        closing_tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey == destination.script_pubkey()),
+        "{:?}",
+        closing_tx
+    );
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_with_amount_percent() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    // A coinbase is too big for a channel without large channels,
+    // so the wallet is funded with a smaller output.
+    let miner = btc.rpc().get_new_address(None, None)?.assume_checked();
+    let _ = btc.rpc().generate_to_address(101, &miner)?;
+    let address: response::NewAddress = node1.lampod().call("newaddr", json::json!({}))?;
+    let address = bitcoincore_rpc::bitcoin::Address::from_str(&address.address)
+        .unwrap()
+        .assume_checked();
+    let _ = btc.rpc().send_to_address(
+        &address,
+        bitcoincore_rpc::bitcoin::Amount::from_sat(2_000_000),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    )?;
+    let _ = btc.rpc().generate_to_address(1, &miner)?;
+    let balance = || -> u64 {
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        funds
+            .transactions
+            .iter()
+            .map(|utxo| utxo.amount_msat / 1000)
+            .sum()
+    };
+    wait!(|| {
+        if balance() == 2_000_000 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    let spendable_sat = 2_000_000 - funds.onchain_fee_reserve_sat;
+
+    let open_channel = |amount_percent: &str, dry_run| request::OpenChannel {
+        node_id: node2.info.node_id.clone(),
+        amount: 0,
+        public: true,
+        dry_run,
+        amount_percent: Some(amount_percent.to_owned()),
+        ..Default::default()
+    };
+    // `all` keeps only the on chain fee reserve.
+    let all: response::OpenChannel = node1
+        .lampod()
+        .call("fundchannel", open_channel("all", true))?;
+    assert!(all.amount < spendable_sat, "{}", all.amount);
+    assert!(all.amount > spendable_sat - 5_000, "{}", all.amount);
+
+    let result: error::Result<response::OpenChannel> = node1
+        .lampod()
+        .call("fundchannel", open_channel("150", true));
+    let err = result.err().unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    let half: response::OpenChannel = node1
+        .lampod()
+        .call("fundchannel", open_channel("50", false))?;
+    assert!(half.amount <= spendable_sat / 2, "{}", half.amount);
+    assert!(half.amount > spendable_sat / 2 - 5_000, "{}", half.amount);
+    let outputs_sat = half
+        .tx
+        .as_ref()
+        .map(|tx| tx.output.iter().map(|out| out.value).sum::<u64>())
+        .unwrap();
+    let change_sat = outputs_sat - half.amount;
+    assert!(
+        2_000_000 - outputs_sat < 5_000,
+        "fee of {} sats",
+        2_000_000 - outputs_sat
+    );
+    // The rest of the balance, without the fee, stays on chain.
+    wait!(|| {
+        if balance() == change_sat {
+            return Ok(());
+        }
+        let _ = btc.rpc().generate_to_address(1, &miner).unwrap();
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn probe_channels_liquidity_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.probe_interval_secs = 1;
+        conf.probe_amount_msat = 10_000;
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let node3 = LampoTesting::new(btc.clone())?;
+    let open_channel = |node_id: &str| request::OpenChannel {
+        node_id: node_id.to_owned(),
+        amount: 100_000,
+        public: true,
+        ..Default::default()
+    };
+    for (node, peer) in [(&node1, &node2), (&node2, &node3)] {
+        let _: response::Connect = node.lampod().call(
+            "connect",
+            request::Connect {
+                node_id: peer.info.node_id.clone(),
+                addr: "127.0.0.1".to_owned(),
+                port: peer.port,
+            },
+        )?;
+        let _ = node.fund_wallet(101)?;
+        let height = btc.rpc().get_block_count()? as u32;
+        wait!(|| {
+            let info: response::GetInfo = node.lampod().call("getinfo", json::json!({})).unwrap();
+            if info.blockheight >= height {
+                return Ok(());
+            }
+            Err(())
+        });
+        let _: json::Value = node
+            .lampod()
+            .call("fundchannel", open_channel(&peer.info.node_id))?;
+    }
+
+    // There is nothing to probe until node1 knows the channel
+    // between node2 and node3.
+    let outbound_capacity = || -> u64 {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        channels
+            .channels
+            .iter()
+            .map(|channel| channel.outbound_capacity_msat)
+            .sum()
+    };
+    let capacity_msat = outbound_capacity();
+    wait!(|| {
+        let channels: response::NetworkChannels = node1
+            .lampod()
+            .call("networkchannels", json::json!({}))
+            .unwrap();
+        if channels.channels.len() == 2 {
+            return Ok(());
+        }
+        let _ = node3.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    // The probes reach node3 through node2.
+    let mut probe = None;
+    wait!(|| {
+        let probes: response::Probes = node1.lampod().call("probes", json::json!({})).unwrap();
+        probe = probes
+            .probes
+            .into_iter()
+            .find(|probe| probe.destination == node3.info.node_id);
+        probe.as_ref().map(|_| ()).ok_or(())
+    });
+    let probe = probe.unwrap();
+    assert_eq!(probe.short_channel_ids.len(), 2, "{:?}", probe);
+    assert_eq!(probe.amount_msat, 10_000);
+
+    // The scorer learned the liquidity of the channel of node2.
+    let route: response::Route = node1.lampod().call(
+        "getroute",
+        request::GetRoute {
+            node_id: node3.info.node_id.clone(),
+            amount_msat: 10_000,
+        },
+    )?;
+    let hop = route
+        .hops
+        .iter()
+        .find(|hop| hop.short_channel_id == probe.short_channel_ids[1])
+        .unwrap();
+    assert!(hop.liquidity_max_msat.is_some(), "{:?}", route);
+
+    // The probes do not move funds, once they are failed.
+    wait!(|| {
+        if outbound_capacity() == capacity_msat {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn dust_exposure_rejects_forwards_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let node3 = LampoTesting::new(btc.clone())?;
+    let open_channel = |node_id: &str| request::OpenChannel {
+        node_id: node_id.to_owned(),
+        amount: 1_000_000,
+        public: true,
+        ..Default::default()
+    };
+    for (node, peer) in [(&node1, &node2), (&node2, &node3)] {
+        let _: response::Connect = node.lampod().call(
+            "connect",
+            request::Connect {
+                node_id: peer.info.node_id.clone(),
+                addr: "127.0.0.1".to_owned(),
+                port: peer.port,
+            },
+        )?;
+        let _ = node.fund_wallet(101)?;
+        let height = btc.rpc().get_block_count()? as u32;
+        wait!(|| {
+            let info: response::GetInfo = node.lampod().call("getinfo", json::json!({})).unwrap();
+            if info.blockheight >= height {
+                return Ok(());
+            }
+            Err(())
+        });
+        let _: json::Value = node
+            .lampod()
+            .call("fundchannel", open_channel(&peer.info.node_id))?;
+    }
+    wait!(|| {
+        let channels: response::NetworkChannels = node1
+            .lampod()
+            .call("networkchannels", json::json!({}))
+            .unwrap();
+        if channels.channels.len() == 2 {
+            return Ok(());
+        }
+        let _ = node3.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    // By default the limit grows with the feerate.
+    let channels: response::Channels = node2.lampod().call("channels", json::json!({}))?;
+    let channel = channels
+        .channels
+        .iter()
+        .find(|channel| channel.peer_id == node3.info.node_id)
+        .unwrap();
+    let exposures: response::DustExposures = node2.lampod().call(
+        "setdustexposure",
+        request::SetDustExposure {
+            channel_id: Some(channel.channel_id.clone()),
+            max_dust_htlc_exposure_msat: None,
+        },
+    )?;
+    let exposure = exposures.channels.first().unwrap();
+    assert!(exposure.feerate_multiplier.is_some(), "{:?}", exposure);
+    assert_eq!(exposure.max_dust_htlc_exposure_msat, None);
+
+    // A limit above the capacity of the channel is refused.
+    let result: error::Result<response::DustExposures> = node2.lampod().call(
+        "setdustexposure",
+        request::SetDustExposure {
+            channel_id: Some(channel.channel_id.clone()),
+            max_dust_htlc_exposure_msat: Some(channel.amount_msat + 1),
+        },
+    );
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    let exposures: response::DustExposures = node2.lampod().call(
+        "setdustexposure",
+        request::SetDustExposure {
+            channel_id: Some(channel.channel_id.clone()),
+            max_dust_htlc_exposure_msat: Some(100_000),
+        },
+    )?;
+    let exposure = exposures.channels.first().unwrap();
+    assert_eq!(exposure.max_dust_htlc_exposure_msat, Some(100_000));
+    assert_eq!(exposure.feerate_multiplier, None);
+
+    let pay = |amount_msat: u64| -> error::Result<response::PayResult> {
+        let invoice: response::Invoice = node3.lampod().call(
+            "invoice",
+            request::GenerateInvoice {
+                amount_msat: Some(amount_msat),
+                description: "dust exposure".to_owned(),
+                expiring_in: None,
+                ignore_min_amount: false,
+            },
+        )?;
+        node1.lampod().call(
+            "pay",
+            request::Pay {
+                invoice_str: invoice.bolt11,
+                amount: None,
+                exclude_channels: vec![],
+                use_channel: None,
+                route_hints: vec![],
+                replace_route_hints: false,
+            },
+        )
+    };
+    // An HTLC above the dust limit is forwarded.
+    let result = pay(10_000_000)?;
+    assert!(
+        matches!(result.state, response::PaymentState::Success),
+        "{:?}",
+        result
+    );
+    // A dust HTLC goes over the limit of node2, so it is not forwarded.
+    let result = pay(300_000)?;
+    assert!(
+        matches!(result.state, response::PaymentState::Failure),
+        "{:?}",
+        result
+    );
+    Ok(())
+}
+
+#[test]
+pub fn withdraw_subtract_fee_from_amount() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.iter().any(|utxo| utxo.confirmed > 0) {
+            return Ok(());
+        }
+        Err(())
+    });
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    let utxo = funds
+        .transactions
+        .iter()
+        .find(|utxo| utxo.confirmed > 0 && !utxo.reserved)
+        .unwrap();
+    let address: response::NewAddress = node2.lampod().call("newaddr", json::json!({}))?;
+    let withdraw = |amount_sat| -> error::Result<response::Withdraw> {
+        node1.lampod().call(
+            "withdraw",
+            request::Withdraw {
+                address: address.address.clone(),
+                amount_sat,
+                fee_rate: Some(1000),
+                utxos: Some(vec![format!("{}:{}", utxo.txid, utxo.vout)]),
+                subtract_fee_from_amount: true,
+                change_address: None,
+            },
+        )
+    };
+
+    // Nothing is left after the fee.
+    let err = withdraw(300).err().unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    // All the value of the output is withdrawn, so there is no change.
+    let amount_sat = utxo.amount_msat / 1000;
+    let response = withdraw(amount_sat)?;
+    let txid = bitcoincore_rpc::bitcoin::Txid::from_str(&response.txid)?;
+    let tx = btc.rpc().get_raw_transaction(&txid, None)?;
+    assert_eq!(tx.input.len(), 1, "{:?}", tx);
+    assert_eq!(tx.output.len(), 1, "{:?}", tx);
+    let recipient = bitcoincore_rpc::bitcoin::Address::from_str(&address.address)?
+        .assume_checked()
+        .script_pubkey();
+    assert_eq!(tx.output[0].script_pubkey, recipient);
+    // The recipient pays the fee at 1000 sats per kw, that is 4 sats per vbyte.
+    let fee = tx.vsize() as u64 * 4;
+    assert!(tx.output[0].value < amount_sat, "{:?}", tx);
+    assert!(
+        tx.output[0].value.abs_diff(amount_sat - fee) <= 4,
+        "{:?}",
+        tx
+    );
+    Ok(())
+}
+
+#[test]
+pub fn announce_multiple_addresses_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let onion = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion:9735";
+    let node = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.bind_addr = Some("127.0.0.1".to_owned());
+        conf.announce_addresses = vec![
+            "10.0.0.1".to_owned(),
+            onion.to_owned(),
+            "10.0.0.2:9736".to_owned(),
+            "10.0.0.1".to_owned(),
+        ];
+    })?;
+
+    let clearnet = format!("10.0.0.1:{}", node.port);
+    let addresses: response::ListAddresses =
+        node.lampod().call("listaddresses", json::json!({}))?;
+    assert_eq!(
+        addresses.announce,
+        vec![clearnet, onion.to_owned(), "10.0.0.2:9736".to_owned()]
+    );
+
+    let info: response::GetInfo = node.lampod().call("getinfo", json::json!({}))?;
+    let announced = info
+        .address
+        .iter()
+        .map(|info| format!("{}:{}", info.address, info.port))
+        .collect::<Vec<_>>();
+    assert_eq!(announced, addresses.announce);
+    Ok(())
+}
+
+#[test]
+pub fn faucet_funds_the_wallet() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    let height = btc.rpc().get_block_count()?;
+    faucet::mine_blocks(&node.backend, 2)?;
+    assert_eq!(btc.rpc().get_block_count()?, height + 2);
+
+    let balance = node.wallet.get_onchain_balance()?;
+    let _ = faucet::fund_wallet(&node.backend, node.wallet.as_ref(), 100_000)?;
+    // The balance is in msat, and the faucet confirmed the transaction.
+    wait!(|| {
+        if node.wallet.get_onchain_balance().unwrap() == balance + 100_000_000 {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn max_inflight_payments_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.max_inflight_payments = Some(1);
+    })?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 1_000_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    // The hold invoice keeps the first payment in flight until it is settled.
+    let preimage = [9u8; 32];
+    let payment_hash = Sha256::hash(&preimage).to_string();
+    let preimage = preimage
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    let held: response::Invoice = node2.lampod().call(
+        "createinvoice",
+        request::CreateInvoice {
+            payment_hash: payment_hash.clone(),
+            amount_msat: Some(10_000_000),
+            description: "held payment".to_owned(),
+            expiry: None,
+        },
+    )?;
+    let pay = |invoice_str: String| request::Pay {
+        invoice_str,
+        amount: None,
+        exclude_channels: vec![],
+        use_channel: None,
+        route_hints: vec![],
+        replace_route_hints: false,
+    };
+
+    let payer = node1.lampod();
+    let request = pay(held.bolt11);
+    let first = std::thread::spawn(move || -> error::Result<response::PayResult> {
+        payer.call("pay", request)
+    });
+    wait!(|| {
+        let health: response::Health = node1.lampod().call("health", json::json!({})).unwrap();
+        if health.inflight_payments == 1 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            amount_msat: Some(1_000_000),
+            description: "second payment".to_owned(),
+            expiring_in: None,
+            ignore_min_amount: false,
+        },
+    )?;
+    let result: error::Result<response::PayResult> =
+        node1.lampod().call("pay", pay(invoice.bolt11.clone()));
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::TooManyPayments),
+        "{err}"
+    );
+
+    let _: response::SettleInvoice = node2
+        .lampod()
+        .call("settleinvoice", request::SettleInvoice { preimage })?;
+    let result = first.join().unwrap()?;
+    assert!(
+        matches!(result.state, response::PaymentState::Success),
+        "{result:?}"
+    );
+    wait!(|| {
+        let health: response::Health = node1.lampod().call("health", json::json!({})).unwrap();
+        if health.inflight_payments == 0 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    // The slot is free again.
+    let result: response::PayResult = node1.lampod().call("pay", pay(invoice.bolt11))?;
+    assert!(
+        matches!(result.state, response::PaymentState::Success),
+        "{result:?}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn advertise_custom_feature_bits_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.feature_bits = vec![257, 300];
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({}))?;
+    assert!(info.feature_bits.contains(&257), "{:?}", info.feature_bits);
+    assert!(info.feature_bits.contains(&300), "{:?}", info.feature_bits);
+    // `payment_secret` is required by LDK.
+    assert!(info.feature_bits.contains(&14), "{:?}", info.feature_bits);
+    let info: response::GetInfo = node2.lampod().call("getinfo", json::json!({}))?;
+    assert!(!info.feature_bits.contains(&257), "{:?}", info.feature_bits);
+
+    let _: response::Connect = node2.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node1.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node1.port,
+        },
+    )?;
+    // The init message of node 1 carries the custom bits.
+    wait!(|| {
+        let peers = node2.lampod().peer_manager().manager().list_peers();
+        let Some(peer) = peers
+            .iter()
+            .find(|peer| peer.counterparty_node_id.to_string() == node1.info.node_id)
+        else {
+            return Err(());
+        };
+        let flags = peer.init_features.le_flags();
+        assert!(flags.len() > 37, "{:?}", peer.init_features);
+        assert_ne!(flags[257 / 8] & (1 << (257 % 8)), 0);
+        assert_ne!(flags[300 / 8] & (1 << (300 % 8)), 0);
+        Ok(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn min_invoice_amount_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.min_invoice_msat = Some(100_000);
+        conf.allow_unsafe_rpc = true;
+    })?;
+
+    let invoice = |amount_msat: u64, ignore_min_amount: bool| {
+        node.lampod()
+            .call::<request::GenerateInvoice, response::Invoice>(
+                "invoice",
+                request::GenerateInvoice {
+                    amount_msat: Some(amount_msat),
+                    description: "dust invoice".to_owned(),
+                    expiring_in: None,
+                    ignore_min_amount,
+                },
+            )
+    };
+    let err = invoice(99_999, false).unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+    assert!(invoice(100_000, false).is_ok());
+    // The privileged flag issues the invoice anyway.
+    assert!(invoice(99_999, true).is_ok());
+    Ok(())
+}
+
+#[test]
+pub fn ignore_min_amount_needs_unsafe_rpc() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.min_invoice_msat = Some(100_000);
+    })?;
+
+    // Without `allow-unsafe-rpc` the flag is refused, even for
+    // an amount above the minimum.
+    for amount_msat in [99_999, 100_000] {
+        let err = node
+            .lampod()
+            .call::<request::GenerateInvoice, response::Invoice>(
+                "invoice",
+                request::GenerateInvoice {
+                    amount_msat: Some(amount_msat),
+                    description: "dust invoice".to_owned(),
+                    expiring_in: None,
+                    ignore_min_amount: true,
+                },
+            )
+            .unwrap_err();
+        assert_eq!(
+            LampoTesting::error_code(&err),
+            Some(LampoErrorCode::InvalidParams),
+            "{err}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+pub fn tx_confirmation_eta_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _ = node1.fund_wallet(102)?;
+    wait!(|| {
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        if funds
+            .transactions
+            .iter()
+            .filter(|utxo| utxo.confirmed > 0 && !utxo.reserved)
+            .count()
+            >= 2
+        {
+            return Ok(());
+        }
+        Err(())
+    });
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    let utxos = funds
+        .transactions
+        .iter()
+        .filter(|utxo| utxo.confirmed > 0 && !utxo.reserved)
+        .map(|utxo| format!("{}:{}", utxo.txid, utxo.vout))
+        .collect::<Vec<_>>();
+    let address: response::NewAddress = node2.lampod().call("newaddr", json::json!({}))?;
+    let withdraw = |utxo: &str, fee_rate| -> error::Result<response::Withdraw> {
+        node1.lampod().call(
+            "withdraw",
+            request::Withdraw {
+                address: address.address.clone(),
+                amount_sat: 100_000,
+                fee_rate: Some(fee_rate),
+                utxos: Some(vec![utxo.to_owned()]),
+                subtract_fee_from_amount: false,
+                change_address: None,
+            },
+        )
+    };
+    let eta = |txid: &str| -> error::Result<response::TxConfirmationEta> {
+        node1.lampod().call(
+            "txconfirmationeta",
+            request::TxConfirmationEta {
+                txid: txid.to_owned(),
+            },
+        )
+    };
+
+    let high = withdraw(&utxos[0], 10_000)?;
+    let low = withdraw(&utxos[1], 1_000)?;
+    let high = eta(&high.txid)?;
+    assert_eq!(high.status, response::TxConfirmationStatus::Mempool);
+    assert_eq!(high.eta_blocks, Some(1), "{high:?}");
+    assert_eq!(high.txs_ahead, Some(0), "{high:?}");
+    let low = eta(&low.txid)?;
+    assert_eq!(low.status, response::TxConfirmationStatus::Mempool);
+    // The high fee transaction is mined first.
+    assert!(low.txs_ahead.unwrap() >= 1, "{low:?}");
+    assert!(low.fee_rate < high.fee_rate, "{low:?}");
+
+    let err = eta("not a txid").err().unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+    Ok(())
+}
+
+/// Read a full http request, the body included.
+fn read_http_request(stream: &mut TcpStream) -> std::io::Result<(String, Vec<u8>)> {
+    let mut buff = Vec::new();
+    let mut chunk = [0; 4096];
+    let head_len = loop {
+        let size = stream.read(&mut chunk)?;
+        if size == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buff.extend_from_slice(&chunk[..size]);
+        if let Some(end) = buff.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&buff[..head_len]).to_string();
+    let content_length = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse::<usize>().ok())?
+        })
+        .unwrap_or_default();
+    while buff.len() < head_len + content_length {
+        let size = stream.read(&mut chunk)?;
+        if size == 0 {
+            break;
+        }
+        buff.extend_from_slice(&chunk[..size]);
+    }
+    let request_line = head.lines().next().unwrap_or_default().to_owned();
+    Ok((request_line, buff[head_len..].to_vec()))
+}
+
+#[test]
+pub fn watchtower_receives_channel_updates_lampo() -> error::Result<()> {
+    init();
+    // The mock tower accepts every state and remembers the requests.
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let tower = format!("http://{}", listener.local_addr()?);
+    let (sender, requests) = lampo_common::chan::unbounded();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let Ok(request) = read_http_request(&mut stream) else {
+                continue;
+            };
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+            let _ = sender.send(request);
+        }
+    });
+
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.watchtowers = vec![tower.clone()];
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let towers: response::Towers = node1.lampod().call("listtowers", json::json!({}))?;
+    assert_eq!(towers.towers.len(), 1);
+    assert_eq!(towers.towers[0].uri, tower);
+    // The tower is already registered.
+    let err = node1
+        .lampod()
+        .call::<request::AddTower, response::Towers>(
+            "addtower",
+            request::AddTower { uri: tower.clone() },
+        )
+        .err()
+        .unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 1_000_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
+            ..Default::default()
+        },
+    )?;
+
+    // The monitor of the new channel is sent to the tower.
+    let (request_line, body) = requests.recv_timeout(Duration::from_secs(60))?;
+    assert!(
+        request_line.starts_with("POST /monitors/"),
+        "{request_line}"
+    );
+    assert!(request_line.contains("update_id="), "{request_line}");
+    assert!(!body.is_empty());
+    wait!(|| {
+        let towers: response::Towers = node1.lampod().call("listtowers", json::json!({})).unwrap();
+        if towers.towers[0].last_update.is_some() && towers.towers[0].pending_updates == 0 {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn labels_round_trip_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    let txid = "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd";
+    let labels = format!(
+        "{{\"type\":\"addr\",\"ref\":\"{}\",\"label\":\"savings\"}}\n\
+         {{\"type\":\"tx\",\"ref\":\"{txid}\",\"label\":\"rent\"}}\n\
+         {{\"type\":\"output\",\"ref\":\"{txid}:1\",\"label\":\"change\",\"spendable\":false}}\n",
+        address.address
+    );
+
+    let imported: response::ImportLabels = node.lampod().call(
+        "importlabels",
+        request::ImportLabels {
+            labels,
+            replace: false,
+        },
+    )?;
+    assert_eq!(imported.imported, 3);
+    let exported: response::ExportLabels = node.lampod().call("exportlabels", json::json!({}))?;
+    assert_eq!(exported.count, 3);
+
+    // Clear the labels and import the export back.
+    let _: response::ImportLabels = node.lampod().call(
+        "importlabels",
+        request::ImportLabels {
+            labels: String::new(),
+            replace: true,
+        },
+    )?;
+    let empty: response::ExportLabels = node.lampod().call("exportlabels", json::json!({}))?;
+    assert_eq!(empty.count, 0);
+    let _: response::ImportLabels = node.lampod().call(
+        "importlabels",
+        request::ImportLabels {
+            labels: exported.labels.clone(),
+            replace: false,
+        },
+    )?;
+    let reimported: response::ExportLabels = node.lampod().call("exportlabels", json::json!({}))?;
+    assert_eq!(reimported.labels, exported.labels);
+
+    // A malformed export is refused with the line of the error.
+    let err = node
+        .lampod()
+        .call::<request::ImportLabels, response::ImportLabels>(
+            "importlabels",
+            request::ImportLabels {
+                labels: "{\"type\":\"tx\",\"ref\":\"not a txid\"}".to_owned(),
+                replace: true,
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+    assert!(err.to_string().contains("line 1"), "{err}");
+    let unchanged: response::ExportLabels = node.lampod().call("exportlabels", json::json!({}))?;
+    assert_eq!(unchanged.labels, exported.labels);
+    Ok(())
+}
+
+#[test]
+pub fn bump_channel_close_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let anchors = |conf: &mut LampoConf| {
+        conf.ldk_conf
+            .channel_handshake_config
+            .negotiate_anchors_zero_fee_htlc_tx = true;
+    };
+    // The force close goes through `rebroadcastcommitment`.
+    let node1 = LampoTesting::with_conf(btc.clone(), |conf| {
+        anchors(conf);
+        conf.allow_unsafe_rpc = true;
+    })?;
+    let node2 = LampoTesting::with_conf(btc.clone(), anchors)?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 1_000_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel_id = channels.channels.first().unwrap().channel_id.clone();
+
+    // An open channel has no commitment to bump.
+    let err = node1
+        .lampod()
+        .call::<_, response::BumpChannelClose>(
+            "bumpchannelclose",
+            request::BumpChannelClose {
+                channel_id: channel_id.clone(),
+                fee_rate: 10_000,
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    let _: response::RebroadcastCommitment = node1.lampod().call(
+        "rebroadcastcommitment",
+        request::RebroadcastCommitment {
+            channel_id: channel_id.clone(),
+        },
+    )?;
+
+    // LDK gives us the commitment to broadcast, maybe with a child.
+    let mut bumped = None;
+    wait!(|| {
+        match node1.lampod().call::<_, response::BumpChannelClose>(
+            "bumpchannelclose",
+            request::BumpChannelClose {
+                channel_id: channel_id.clone(),
+                fee_rate: 10_000,
+            },
+        ) {
+            Ok(resp) => {
+                bumped = Some(resp);
+                Ok(())
+            }
+            Err(_) => Err(()),
+        }
+    });
+    let bumped = bumped.unwrap();
+    assert!(bumped.package_fee_rate >= 10_000, "{:?}", bumped);
+
+    // The mempool sees the package at the new feerate.
+    let commitment_txid = bitcoincore_rpc::bitcoin::Txid::from_str(&bumped.commitment_txid)?;
+    let child_txid = bitcoincore_rpc::bitcoin::Txid::from_str(&bumped.child_txid)?;
+    let commitment = btc.rpc().get_mempool_entry(&commitment_txid)?;
+    let child = btc.rpc().get_mempool_entry(&child_txid)?;
+    let commitment_feerate = commitment.fees.base.to_sat() as f64 / commitment.vsize as f64;
+    let package_feerate = child.fees.ancestor.to_sat() as f64 / child.ancestor_size as f64;
+    assert!(
+        package_feerate > commitment_feerate,
+        "package {package_feerate} sat/vB, commitment {commitment_feerate} sat/vB"
+    );
+    // 10_000 sats per kw are 40 sats per vbyte.
+    assert!(package_feerate >= 39.0, "{package_feerate} sat/vB");
+    Ok(())
+}
+
+#[test]
+pub fn anchor_reserve_refuses_withdraw() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let anchors = |conf: &mut LampoConf| {
+        conf.ldk_conf
+            .channel_handshake_config
+            .negotiate_anchors_zero_fee_htlc_tx = true;
+    };
+    let node1 = LampoTesting::with_conf(btc.clone(), |conf| {
+        anchors(conf);
+        conf.anchor_reserve_utxos = 1;
+    })?;
+    let node2 = LampoTesting::with_conf(btc.clone(), anchors)?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 1_000_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call("syncwallet", json::json!({}))?;
+
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    let spendable = funds
+        .transactions
+        .iter()
+        .filter(|utxo| !utxo.reserved && utxo.confirmed > 0)
+        .collect::<Vec<_>>();
+    // SAFETY: the change of the funding transaction is confirmed.
+    let reserved = spendable
+        .iter()
+        .min_by_key(|utxo| utxo.amount_msat)
+        .unwrap();
+    let balance_sat = spendable
+        .iter()
+        .map(|utxo| utxo.amount_msat / 1000)
+        .sum::<u64>();
+    let address: response::NewAddress = node2.lampod().call("newaddr", json::json!({}))?;
+    let withdraw = |utxos, amount_sat| -> error::Result<response::Withdraw> {
+        node1.lampod().call(
+            "withdraw",
+            request::Withdraw {
+                address: address.address.clone(),
+                amount_sat,
+                fee_rate: Some(1000),
+                utxos,
+                subtract_fee_from_amount: false,
+                change_address: None,
+            },
+        )
+    };
+
+    // Only the reserve pays for a withdraw of the whole balance.
+    let err = withdraw(None, balance_sat - reserved.amount_msat / 1000 + 100_000)
+        .err()
+        .unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InsufficientFunds),
+        "{err}"
+    );
+    // The reserve can not be selected either.
+    let err = withdraw(
+        Some(vec![format!("{}:{}", reserved.txid, reserved.vout)]),
+        100_000,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InsufficientFunds),
+        "{err}"
+    );
+
+    // The coin selection spends the other outputs.
+    let response = withdraw(None, 100_000)?;
+    let txid = bitcoincore_rpc::bitcoin::Txid::from_str(&response.txid)?;
+    let tx = btc.rpc().get_raw_transaction(&txid, None)?;
+    assert!(
+        tx.input.iter().all(|input| {
+            input.previous_output.txid.to_string() != reserved.txid
+                || input.previous_output.vout != reserved.vout
+        }),
+        "{:?}",
+        tx
+    );
+    Ok(())
+}
+
+#[test]
+pub fn breach_close_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    // node1 cheats with `rebroadcastcommitment`.
+    let node1 = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.allow_unsafe_rpc = true;
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 1_000_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel_id = channels.channels.first().unwrap().channel_id.clone();
+
+    // The commitment waits in the mempool while the payment revokes it.
+    let commitment: response::RebroadcastCommitment = node1.lampod().call(
+        "rebroadcastcommitment",
+        request::RebroadcastCommitment {
+            channel_id: channel_id.clone(),
+        },
+    )?;
+    let keysend: response::KeySendInfo = node1.lampod().call(
+        "keysend",
+        request::KeySend {
+            destination: PublicKey::from_str(&node2.info.node_id)?,
+            amount_msat: 100_000_000,
+            route_hints: vec![],
+            max_parts: Some(1),
+        },
+    )?;
+    assert!(
+        matches!(keysend.status, response::PaymentState::Success),
+        "{:?}",
+        keysend
+    );
+
+    wait!(|| {
+        let _ = node2.fund_wallet(1).unwrap();
+        let closed: response::ClosedChannels = node2
+            .lampod()
+            .call("closedchannels", json::json!({}))
+            .unwrap();
+        match closed.closed_channels.first() {
+            Some(closed) => {
+                assert_eq!(
+                    closed.close_type,
+                    response::CloseType::Breach,
+                    "{:?}",
+                    closed
+                );
+                Ok(())
+            }
+            None => Err(()),
+        }
+    });
+    Ok(())
+}
+
+#[test]
+pub fn keysend_multi_part_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.ldk_conf.accept_mpp_keysend = true;
+        conf.ldk_conf
+            .channel_handshake_config
+            .max_inbound_htlc_value_in_flight_percent_of_channel = 100;
+    })?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    // Two channels of 500_000 sats to the same destination.
+    for channels_count in 1..=2 {
+        let _: json::Value = node1.lampod().call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 500_000,
+                public: true,
+                ..Default::default()
+            },
+        )?;
+        wait!(|| {
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.len() == channels_count
+                && channels.channels.iter().all(|channel| channel.ready)
+            {
+                return Ok(());
+            }
+            let _ = node2.fund_wallet(6).unwrap();
+            Err(())
+        });
+    }
+
+    // A single part can not carry more than the capacity of a channel.
+    let err = node1
+        .lampod()
+        .call::<_, response::KeySendInfo>(
+            "keysend",
+            request::KeySend {
+                destination: PublicKey::from_str(&node2.info.node_id)?,
+                amount_msat: 700_000_000,
+                route_hints: vec![],
+                max_parts: Some(1),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::NoRoute),
+        "{err}"
+    );
+
+    let keysend: response::KeySendInfo = node1.lampod().call(
+        "keysend",
+        request::KeySend {
+            destination: PublicKey::from_str(&node2.info.node_id)?,
+            amount_msat: 700_000_000,
+            route_hints: vec![],
+            max_parts: None,
+        },
+    )?;
+    assert!(keysend.parts >= 2, "{:?}", keysend);
+    assert!(
+        matches!(keysend.status, response::PaymentState::Success),
+        "{:?}",
+        keysend
+    );
+    assert!(keysend.payment_preimage.is_some(), "{:?}", keysend);
+    assert!(keysend.amount_sent_msat.unwrap() >= 700_000_000);
+    Ok(())
+}
+
+#[test]
+pub fn withdraw_change_to_address_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.iter().any(|utxo| utxo.confirmed > 0) {
+            return Ok(());
+        }
+        Err(())
+    });
+    let address: response::NewAddress = node2.lampod().call("newaddr", json::json!({}))?;
+    let change: response::NewAddress = node2.lampod().call("newaddr", json::json!({}))?;
+    let withdraw = |change_address: &str| -> error::Result<response::Withdraw> {
+        node1.lampod().call(
+            "withdraw",
+            request::Withdraw {
+                address: address.address.clone(),
+                amount_sat: 100_000,
+                fee_rate: Some(1000),
+                utxos: None,
+                subtract_fee_from_amount: false,
+                change_address: Some(change_address.to_owned()),
+            },
+        )
+    };
+
+    // The change address must be of the network of the node.
+    let err = withdraw("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+        .err()
+        .unwrap();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+
+    let response = withdraw(&change.address)?;
+    let txid = bitcoincore_rpc::bitcoin::Txid::from_str(&response.txid)?;
+    let tx = btc.rpc().get_raw_transaction(&txid, None)?;
+    assert_eq!(tx.output.len(), 2, "{:?}", tx);
+    let script = |address: &str| -> error::Result<_> {
+        Ok(bitcoincore_rpc::bitcoin::Address::from_str(address)?
+            .assume_checked()
+            .script_pubkey())
+    };
+    let recipient = script(&address.address)?;
+    let change = script(&change.address)?;
+    assert!(
+        tx.output
+            .iter()
+            .any(|output| output.script_pubkey == recipient && output.value == 100_000),
+        "{:?}",
+        tx
+    );
+    // The rest of the inputs, minus the fee, lands at the change address.
+    assert!(
+        tx.output
+            .iter()
+            .any(|output| output.script_pubkey == change && output.value > 0),
+        "{:?}",
+        tx
+    );
+    Ok(())
+}
+
+#[test]
+pub fn simulate_reorg_unconfirms_wallet_tx_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let _ = node.fund_wallet(101)?;
+    wait!(|| {
+        let funds: response::Utxos = node.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.iter().any(|utxo| utxo.confirmed > 0) {
+            return Ok(());
+        }
+        Err(())
+    });
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    let withdraw: response::Withdraw = node.lampod().call(
+        "withdraw",
+        request::Withdraw {
+            address: address.address.clone(),
+            amount_sat: 100_000,
+            fee_rate: Some(1000),
+            utxos: None,
+            subtract_fee_from_amount: false,
+            change_address: None,
+        },
+    )?;
+    let confirmations = |txid: &str| -> Option<u32> {
+        let funds: response::Utxos = node.lampod().call("funds", json::json!({})).unwrap();
+        funds
+            .transactions
+            .iter()
+            .find(|utxo| utxo.txid == txid)
+            .map(|utxo| utxo.confirmed)
+    };
+    let miner = btc.rpc().get_new_address(None, None)?.assume_checked();
+    let _ = btc.rpc().generate_to_address(1, &miner)?;
+    wait!(|| {
+        if confirmations(&withdraw.txid).is_some_and(|confirmed| confirmed > 0) {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    // The reorg drops the block with the withdraw.
+    let height = btc.rpc().get_block_count()?;
+    let reorg: response::SimulateReorg = node
+        .lampod()
+        .call("simulatereorg", request::SimulateReorg { blocks: 1 })?;
+    assert_eq!(reorg.invalidated_blocks.len(), 1);
+    assert_eq!(reorg.height as u64, height - 1);
+    assert!(reorg.affected_txids.contains(&withdraw.txid), "{:?}", reorg);
+    assert_eq!(btc.rpc().get_block_count()?, height - 1);
+    // The withdraw is back inside the mempool, so it is unconfirmed.
+    wait!(|| {
+        if !confirmations(&withdraw.txid).is_some_and(|confirmed| confirmed > 0) {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn funds_balance_by_keychain_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        if funds.external_balance.confirmed_sat > 0 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    assert_eq!(funds.internal_balance, response::Balance::default());
+
+    let address: response::NewAddress = node2.lampod().call("newaddr", json::json!({}))?;
+    let _: response::Withdraw = node1.lampod().call(
+        "withdraw",
+        request::Withdraw {
+            address: address.address.clone(),
+            amount_sat: 100_000,
+            fee_rate: Some(1000),
+            utxos: None,
+            subtract_fee_from_amount: false,
+            change_address: None,
+        },
+    )?;
+    let miner = btc.rpc().get_new_address(None, None)?.assume_checked();
+    let _ = btc.rpc().generate_to_address(1, &miner)?;
+    // The receive lands on the external keychain of node2, while
+    // the change of the spend lands on the internal keychain of node1.
+    wait!(|| {
+        let funds: response::Utxos = node2.lampod().call("funds", json::json!({})).unwrap();
+        if funds.external_balance.confirmed_sat == 100_000 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let funds: response::Utxos = node2.lampod().call("funds", json::json!({}))?;
+    assert_eq!(funds.internal_balance, response::Balance::default());
+    wait!(|| {
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        if funds.internal_balance.confirmed_sat > 0 {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn sweep_private_key_lampo() -> error::Result<()> {
+    use lampo_common::bitcoin::{Network, PrivateKey};
+    use lampo_common::secp256k1::{Secp256k1, SecretKey};
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.allow_unsafe_rpc = true;
+    })?;
+    let _ = node.fund_wallet(101)?;
+    wait!(|| {
+        let funds: response::Utxos = node.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.iter().any(|utxo| utxo.confirmed > 0) {
+            return Ok(());
+        }
+        Err(())
+    });
+    // The key of a paper wallet, that we fund from the node.
+    let secp = Secp256k1::new();
+    let key = PrivateKey::new(SecretKey::from_slice(&[0x42; 32])?, Network::Regtest);
+    let key_address = Address::p2wpkh(&key.public_key(&secp), Network::Regtest)?;
+    let _: response::Withdraw = node.lampod().call(
+        "withdraw",
+        request::Withdraw {
+            address: key_address.to_string(),
+            amount_sat: 1_000_000,
+            fee_rate: Some(1000),
+            utxos: None,
+            subtract_fee_from_amount: false,
+            change_address: None,
+        },
+    )?;
+    let miner = btc.rpc().get_new_address(None, None)?.assume_checked();
+    let _ = btc.rpc().generate_to_address(1, &miner)?;
+
+    let sweep: response::SweepPrivateKey = node.lampod().call(
+        "sweepprivatekey",
+        request::SweepPrivateKey {
+            wif: key.to_wif(),
+            destination: None,
+            fee_rate: Some(1000),
+        },
+    )?;
+    assert_eq!(sweep.inputs, 1);
+    assert_eq!(sweep.amount_sat + sweep.fee_sat, 1_000_000);
+    let _ = btc.rpc().generate_to_address(1, &miner)?;
+    wait!(|| {
+        let funds: response::Utxos = node.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.iter().any(|utxo| {
+            utxo.txid == sweep.txid
+                && utxo.confirmed > 0
+                && utxo.amount_msat == sweep.amount_sat * 1000
+        }) {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    // Nothing is left to sweep.
+    let err = node
+        .lampod()
+        .call::<_, response::SweepPrivateKey>(
+            "sweepprivatekey",
+            request::SweepPrivateKey {
+                wif: key.to_wif(),
+                destination: None,
+                fee_rate: Some(1000),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InsufficientFunds),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn sweep_private_key_needs_unsafe_rpc() -> error::Result<()> {
+    use lampo_common::bitcoin::{Network, PrivateKey};
+    use lampo_common::secp256k1::SecretKey;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let key = PrivateKey::new(SecretKey::from_slice(&[0x43; 32])?, Network::Regtest);
+    let err = node
+        .lampod()
+        .call::<_, response::SweepPrivateKey>(
+            "sweepprivatekey",
+            request::SweepPrivateKey {
+                wif: key.to_wif(),
+                destination: None,
+                fee_rate: Some(1000),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::Generic)
+    );
+    assert!(err.to_string().contains("allow-unsafe-rpc"), "{err}");
+    Ok(())
+}
+
+#[test]
+pub fn max_channels_per_peer_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.max_channels_per_peer = Some(1);
+    })?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let fund_channel = || -> error::Result<json::Value> {
+        node1.lampod().call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 500_000,
+                public: true,
+                ..Default::default()
+            },
+        )
+    };
+    let _ = fund_channel()?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    // The second channel is above the limit of node2.
+    assert!(fund_channel().is_err());
+    let channels: response::Channels = node2.lampod().call("channels", json::json!({}))?;
+    assert_eq!(channels.channels.len(), 1, "{:?}", channels);
+    Ok(())
+}
+
+#[test]
+pub fn channel_funding_tx_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let open: response::OpenChannel = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 500_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    let txid = open.txid.expect("the funding txid").to_string();
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel_id = channels.channels[0].channel_id.clone();
+    let funding: response::ChannelFundingTx = node1.lampod().call(
+        "channelfundingtx",
+        request::ChannelFundingTx {
+            channel_id: channel_id.clone(),
+        },
+    )?;
+    assert_eq!(funding.channel_id, channel_id);
+    assert_eq!(funding.funding_txid, txid);
+    assert!(funding.confirmations.is_some_and(|confs| confs > 0));
+    assert!(funding.tx_hex.is_some(), "{:?}", funding);
+
+    let err = node1
+        .lampod()
+        .call::<request::ChannelFundingTx, response::ChannelFundingTx>(
+            "channelfundingtx",
+            request::ChannelFundingTx {
+                channel_id: "00".repeat(32),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::ChannelNotFound),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn max_route_hops_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.max_route_hops = Some(1);
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 500_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
     wait!(|| {
-        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
-            node2.fund_wallet(6).unwrap();
-            if let Event::Lightning(LightningEvent::ChannelReady {
-                counterparty_node_id,
-                ..
-            }) = event
-            {
-                if counterparty_node_id.to_string() == node1.info.node_id {
-                    return Err(());
-                }
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    // A node behind node2 needs a route of two hops.
+    let err = node1
+        .lampod()
+        .call::<_, response::KeySendInfo>(
+            "keysend",
+            request::KeySend {
+                destination: PublicKey::from_str(
+                    "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+                )?,
+                amount_msat: 1_000_000,
+                route_hints: vec![vec![request::RouteHintHop {
+                    node_id: node2.info.node_id.clone(),
+                    short_channel_id: 42 << 40,
+                    fee_base_msat: 1_000,
+                    fee_proportional_millionths: 100,
+                    cltv_expiry_delta: 144,
+                    htlc_minimum_msat: None,
+                    htlc_maximum_msat: None,
+                }]],
+                max_parts: None,
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::NoRoute),
+        "{err}"
+    );
+
+    let keysend: response::KeySendInfo = node1.lampod().call(
+        "keysend",
+        request::KeySend {
+            destination: PublicKey::from_str(&node2.info.node_id)?,
+            amount_msat: 1_000_000,
+            route_hints: vec![],
+            max_parts: None,
+        },
+    )?;
+    assert!(
+        matches!(keysend.status, response::PaymentState::Success),
+        "{:?}",
+        keysend
+    );
+    Ok(())
+}
+
+#[test]
+pub fn last_forward_error_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let node3 = LampoTesting::new(btc.clone())?;
+    // node1 -> node2 -> node3, where node2 has no outbound
+    // liquidity toward node3 because node3 funded the channel.
+    for funder in [&node1, &node3] {
+        let _: response::Connect = funder.lampod().call(
+            "connect",
+            request::Connect {
+                node_id: node2.info.node_id.clone(),
+                addr: "127.0.0.1".to_owned(),
+                port: node2.port,
+            },
+        )?;
+        let _ = funder.fund_wallet(101)?;
+        let height = btc.rpc().get_block_count()? as u32;
+        wait!(|| {
+            let info: response::GetInfo = funder.lampod().call("getinfo", json::json!({})).unwrap();
+            if info.blockheight >= height {
                 return Ok(());
-            };
-            // check if lampo see the channel
+            }
+            Err(())
+        });
+        let _: json::Value = funder.lampod().call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 500_000,
+                public: true,
+                ..Default::default()
+            },
+        )?;
+        wait!(|| {
             let channels: response::Channels =
-                node2.lampod().call("channels", json::json!({})).unwrap();
-            if channels.channels.is_empty() {
-                return Err(());
+                funder.lampod().call("channels", json::json!({})).unwrap();
+            if channels
+                .channels
+                .first()
+                .is_some_and(|channel| channel.ready && channel.short_channel_id.is_some())
+            {
+                return Ok(());
             }
+            let _ = node2.fund_wallet(6).unwrap();
+            Err(())
+        });
+    }
+    let channels: response::Channels = node2.lampod().call("channels", json::json!({}))?;
+    let incoming = channels
+        .channels
+        .iter()
+        .find(|channel| channel.peer_id == node1.info.node_id)
+        .expect("the channel with node1");
+    let outgoing = channels
+        .channels
+        .iter()
+        .find(|channel| channel.peer_id == node3.info.node_id)
+        .expect("the channel with node3");
 
-            if !channels.channels.first().unwrap().ready {
-                return Err(());
-            }
+    let keysend = node1.lampod().call::<_, response::KeySendInfo>(
+        "keysend",
+        request::KeySend {
+            destination: PublicKey::from_str(&node3.info.node_id)?,
+            amount_msat: 10_000_000,
+            route_hints: vec![vec![request::RouteHintHop {
+                node_id: node2.info.node_id.clone(),
+                short_channel_id: outgoing.short_channel_id.unwrap(),
+                fee_base_msat: 1_000,
+                fee_proportional_millionths: 100,
+                cltv_expiry_delta: 144,
+                htlc_minimum_msat: None,
+                htlc_maximum_msat: None,
+            }]],
+            max_parts: Some(1),
+        },
+    );
+    assert!(keysend.is_err(), "{:?}", keysend);
 
-            let channels: response::Channels =
-                node1.lampod().call("channels", json::json!({})).unwrap();
+    wait!(|| {
+        let errors: response::ForwardErrors = node2
+            .lampod()
+            .call(
+                "lastforwarderror",
+                request::LastForwardError {
+                    channel_id: Some(incoming.channel_id.clone()),
+                },
+            )
+            .unwrap();
+        if errors.errors.is_empty() {
+            return Err(());
+        }
+        Ok(())
+    });
+    let errors: response::ForwardErrors = node2.lampod().call(
+        "lastforwarderror",
+        request::LastForwardError { channel_id: None },
+    )?;
+    assert_eq!(errors.errors.len(), 1, "{:?}", errors);
+    let error = &errors.errors[0];
+    assert_eq!(error.incoming_channel_id, incoming.channel_id);
+    assert_eq!(error.failure, "temporary_channel_failure");
+    assert_eq!(error.failure_code, 0x1007);
+    assert_eq!(
+        error.outgoing_channel_id.as_deref(),
+        Some(outgoing.channel_id.as_str())
+    );
+    Ok(())
+}
 
-            if channels.channels.is_empty() {
-                return Err(());
-            }
+#[test]
+pub fn verify_channel_funding_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
 
-            if channels.channels.first().unwrap().ready {
-                return Ok(());
-            }
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
         }
-        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 500_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
         Err(())
     });
 
-    let offer: response::Offer = node2.lampod().call(
-        "offer",
-        request::GenerateOffer {
-            description: None,
-            amount_msat: None,
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel_id = channels.channels[0].channel_id.clone();
+    let verify: response::VerifyChannelFunding = node1.lampod().call(
+        "verifychannelfunding",
+        request::VerifyChannelFunding {
+            channel_id: channel_id.clone(),
         },
     )?;
+    assert!(verify.verified, "{:?}", verify);
+    assert_eq!(verify.capacity_sat, 500_000);
+    assert_eq!(verify.funding_output_sat, Some(500_000));
+    assert!(verify.discrepancy.is_none(), "{:?}", verify);
 
-    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
+    let err = node1
+        .lampod()
+        .call::<request::VerifyChannelFunding, response::VerifyChannelFunding>(
+            "verifychannelfunding",
+            request::VerifyChannelFunding {
+                channel_id: "00".repeat(32),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::ChannelNotFound),
+        "{err}"
+    );
+    Ok(())
+}
 
-    let pay: response::PayResult = node1.lampod().call(
-        "pay",
-        request::Pay {
-            invoice_str: offer.bolt12,
-            amount: Some(100_000_000),
+#[test]
+pub fn create_timelock_output_lampo() -> error::Result<()> {
+    use lampo_common::bitcoin::absolute::LockTime;
+    use lampo_common::bitcoin::consensus::encode::serialize_hex;
+    use lampo_common::bitcoin::ecdsa::Signature;
+    use lampo_common::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+    use lampo_common::bitcoin::{
+        Network, OutPoint, PrivateKey, ScriptBuf, Transaction, TxIn, TxOut, Txid, Witness,
+    };
+    use lampo_common::secp256k1::{Message, Secp256k1, SecretKey};
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.allow_unsafe_rpc = true;
+    })?;
+    let _ = node.fund_wallet(101)?;
+    wait!(|| {
+        let funds: response::Utxos = node.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.iter().any(|utxo| utxo.confirmed > 0) {
+            return Ok(());
+        }
+        Err(())
+    });
+    let secp = Secp256k1::new();
+    let key = PrivateKey::new(SecretKey::from_slice(&[0x24; 32])?, Network::Regtest);
+    let blocks = 10;
+    let amount_sat = 100_000;
+    let timelock: response::CreateTimelockOutput = node.lampod().call(
+        "createtimelockoutput",
+        request::CreateTimelockOutput {
+            amount_sat,
+            locktime_or_blocks: blocks as u32,
+            destination_pubkey: key.public_key(&secp).to_string(),
+            absolute: false,
+            fee_rate: Some(1000),
         },
     )?;
-    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    let miner = btc.rpc().get_new_address(None, None)?.assume_checked();
+    let _ = btc.rpc().generate_to_address(1, &miner)?;
+
+    // Spend the output with the key as soon as the timelock allows it.
+    let redeem_script = ScriptBuf::from_hex(&timelock.redeem_script)?;
+    let mut spend = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_str(&timelock.txid)?, timelock.vout),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::from_height(blocks),
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: amount_sat - 1_000,
+            script_pubkey: miner.script_pubkey(),
+        }],
+    };
+    let sighash = SighashCache::new(&spend).segwit_signature_hash(
+        0,
+        &redeem_script,
+        amount_sat,
+        EcdsaSighashType::All,
+    )?;
+    let signature = Signature::sighash_all(
+        secp.sign_ecdsa(&Message::from_slice(&sighash.to_byte_array())?, &key.inner),
+    );
+    spend.input[0].witness = Witness::from_slice(&[signature.to_vec(), redeem_script.to_bytes()]);
+    let spend_hex = serialize_hex(&spend);
+
+    let accept = btc.rpc().test_mempool_accept(&[spend_hex.clone()])?;
+    assert!(!accept[0].allowed, "{:?}", accept);
+    let _ = btc.rpc().generate_to_address(blocks as u64, &miner)?;
+    let accept = btc.rpc().test_mempool_accept(&[spend_hex])?;
+    assert!(accept[0].allowed, "{:?}", accept);
     Ok(())
 }
 
 #[test]
-pub fn decode_offer() -> error::Result<()> {
+pub fn channel_spendable_after_reserve_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    let _ = node1.fund_wallet(101)?;
+    let height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        let info: response::GetInfo = node1.lampod().call("getinfo", json::json!({})).unwrap();
+        if info.blockheight >= height {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 500_000,
+            public: true,
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        let _ = node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel = &channels.channels[0];
+    let reserve_msat = channel.reserve_sat.expect("the channel reserve") * 1000;
+    let our_amount_msat = channel.our_amount_msat.expect("our channel balance");
+    assert!(reserve_msat > 0, "{:?}", channel);
+    assert!(
+        channel.spendable_msat + reserve_msat <= our_amount_msat,
+        "{:?}",
+        channel
+    );
+
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    assert_eq!(funds.channels.len(), 1, "{:?}", funds.channels);
+    assert_eq!(funds.channels[0].channel_id, channel.channel_id);
+    assert_eq!(funds.channels[0].spendable_msat, channel.spendable_msat);
+    assert_eq!(funds.channels[0].our_amount_msat, Some(our_amount_msat));
+    Ok(())
+}
+
+#[test]
+pub fn reissue_invoice_lampo() -> error::Result<()> {
     init();
     let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
     let btc = Arc::new(btc);
@@ -444,6 +5667,7 @@ pub fn decode_offer() -> error::Result<()> {
                 public: true,
                 addr: Some("127.0.0.1".to_owned()),
                 port: Some(node2.port),
+                ..Default::default()
             },
         )
         .unwrap();
@@ -488,24 +5712,343 @@ pub fn decode_offer() -> error::Result<()> {
         Err(())
     });
 
-    let offer: response::Offer = node2.lampod().call(
-        "offer",
-        request::GenerateOffer {
-            description: None,
-            amount_msat: None,
+    let payment_hash = |bolt11: &str| {
+        lampo_common::ldk::invoice::Bolt11Invoice::from_str(bolt11)
+            .unwrap()
+            .payment_hash()
+            .to_string()
+    };
+    let invoice_status = |payment_hash: &str| -> response::InvoiceStatus {
+        node2
+            .lampod()
+            .call(
+                "invoicestatus",
+                request::InvoiceStatus {
+                    payment_hash: payment_hash.to_owned(),
+                },
+            )
+            .unwrap()
+    };
+    let pay = |bolt11: &str| -> error::Result<response::PayResult> {
+        node1.lampod().call(
+            "pay",
+            request::Pay {
+                invoice_str: bolt11.to_owned(),
+                amount: None,
+                exclude_channels: vec![],
+                use_channel: None,
+                route_hints: vec![],
+                replace_route_hints: false,
+            },
+        )
+    };
+
+    let expired: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            amount_msat: Some(100_000_000),
+            description: "reissue".to_owned(),
+            expiring_in: Some(1),
+            ignore_min_amount: false,
         },
     )?;
+    let expired_hash = payment_hash(&expired.bolt11);
+    std::thread::sleep(Duration::from_secs(2));
+    assert_eq!(
+        invoice_status(&expired_hash).state,
+        response::InvoiceState::Expired
+    );
+    assert!(pay(&expired.bolt11).is_err());
 
-    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
+    let reissued: response::Invoice = node2.lampod().call(
+        "reissueinvoice",
+        request::ReissueInvoice {
+            payment_hash: Some(expired_hash.clone()),
+            bolt11: None,
+            expiry: Some(3600),
+        },
+    )?;
+    let reissued_hash = payment_hash(&reissued.bolt11);
+    // The payment hash of lampo commits to the old expiry.
+    assert_ne!(reissued_hash, expired_hash);
+    let status = invoice_status(&reissued_hash);
+    assert_eq!(status.state, response::InvoiceState::Unpaid);
+    assert_eq!(status.amount_msat, Some(100_000_000));
 
-    let decode: response::InvoiceInfo = node2.lampod().call(
-        "decode",
-        request::DecodeInvoice {
-            invoice_str: offer.bolt12,
+    let result = pay(&reissued.bolt11)?;
+    assert!(
+        matches!(result.state, response::PaymentState::Success),
+        "{:?}",
+        result
+    );
+    wait!(|| {
+        if invoice_status(&reissued_hash).state == response::InvoiceState::Paid {
+            return Ok(());
+        }
+        Err(())
+    });
+    assert_eq!(
+        invoice_status(&reissued_hash).amount_msat,
+        Some(100_000_000)
+    );
+
+    // The paid invoice can not be reissued.
+    let result: error::Result<response::Invoice> = node2.lampod().call(
+        "reissueinvoice",
+        request::ReissueInvoice {
+            payment_hash: None,
+            bolt11: Some(reissued.bolt11),
+            expiry: None,
         },
+    );
+    let err = result.unwrap_err();
+    assert_eq!(
+        LampoTesting::error_code(&err),
+        Some(LampoErrorCode::InvalidParams),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn list_coins_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    // Move the next receive address to the index 2.
+    loop {
+        let indices: response::AddressIndices =
+            node1.lampod().call("addressindices", json::json!({}))?;
+        assert!(indices.external <= 2, "{:?}", indices);
+        if indices.external == 2 {
+            break;
+        }
+        let _: response::NewAddress = node1.lampod().call("newaddr", json::json!({}))?;
+    }
+    let address: response::NewAddress = node1.lampod().call("newaddr", json::json!({}))?;
+    let _ = btc.rpc().generate_to_address(
+        1,
+        &bitcoincore_rpc::bitcoin::Address::from_str(&address.address)
+            .unwrap()
+            .assume_checked(),
     )?;
+    let _ = node2.fund_wallet(100)?;
+    wait!(|| {
+        let coins: response::Coins = node1.lampod().call("listcoins", json::json!({})).unwrap();
+        if !coins.coins.is_empty() {
+            return Ok(());
+        }
+        Err(())
+    });
 
-    assert_eq!(decode.issuer_id, Some(node2.info.node_id.clone()));
-    log::info!(target: &node2.info.node_id, "decode offer `{:?}`", decode);
+    let coins: response::Coins = node1.lampod().call("listcoins", json::json!({}))?;
+    assert_eq!(coins.coins.len(), 1, "{:?}", coins);
+    let coin = &coins.coins[0];
+    assert_eq!(coin.address.as_deref(), Some(address.address.as_str()));
+    assert_eq!(coin.keychain, Some(Keychain::External));
+    let path = coin.derivation_path.clone().unwrap_or_default();
+    assert!(path.ends_with("/0/2"), "{path}");
+
+    let funds: response::Utxos = node1.lampod().call("funds", json::json!({}))?;
+    assert_eq!(funds.transactions[0].amount_msat, coin.amount_msat);
+    Ok(())
+}
+
+#[test]
+pub fn max_inbound_htlc_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::with_conf(btc.clone(), |conf| {
+        conf.max_inbound_htlc_msat = Some(50_000_000);
+    })?);
+    assert_eq!(node2.info.max_inbound_htlc_msat, Some(50_000_000));
+    assert_eq!(node1.info.max_inbound_htlc_msat, None);
+    let _ = node1.fund_wallet(101)?;
+
+    // The cap does not reject the inbound channels.
+    let _: response::OpenChannel = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 1_000_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
+            ..Default::default()
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            node2.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.ready)
+        {
+            return Ok(());
+        }
+        node1.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let keysend = |amount_msat| -> error::Result<response::KeySendInfo> {
+        node1.lampod().call(
+            "keysend",
+            request::KeySend {
+                destination: PublicKey::from_str(&node2.info.node_id)?,
+                amount_msat,
+                route_hints: vec![],
+                max_parts: Some(1),
+            },
+        )
+    };
+    // The HTLC above the cap of node2 is failed back.
+    let result = keysend(80_000_000);
+    assert!(result.is_err(), "{:?}", result);
+    // The HTLC below the cap is claimed.
+    let _ = keysend(20_000_000)?;
+    Ok(())
+}
+
+#[test]
+fn min_channel_capacity_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    // Without the peer we use the reserve suggested by BOLT 2.
+    let estimate: response::MinChannelCapacity = node2.lampod().call(
+        "minchannelcapacity",
+        request::MinChannelCapacity { node_id: None },
+    )?;
+    assert!(!estimate.peer_connected);
+    assert!(
+        estimate.min_capacity_sat > estimate.reserve_sat + estimate.counterparty_reserve_sat,
+        "{:?}",
+        estimate
+    );
+
+    let _: response::Connect = node2.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node1.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node1.port,
+        },
+    )?;
+    let estimate: response::MinChannelCapacity = node2.lampod().call(
+        "minchannelcapacity",
+        request::MinChannelCapacity {
+            node_id: Some(node1.info.node_id.clone()),
+        },
+    )?;
+    assert!(estimate.peer_connected);
+    assert!(
+        estimate.min_capacity_sat
+            > estimate.reserve_sat
+                + estimate.counterparty_reserve_sat
+                + 2 * estimate.dust_limit_sat,
+        "{:?}",
+        estimate
+    );
+    Ok(())
+}
+
+#[test]
+pub fn tx_history_self_transfer_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    let addresses: response::NewAddresses = node1.lampod().call(
+        "newaddrs",
+        request::NewAddresses {
+            count: 3,
+            label_prefix: None,
+        },
+    )?;
+    for address in addresses.addresses {
+        let address = bitcoincore_rpc::bitcoin::Address::from_str(&address.address)
+            .unwrap()
+            .assume_checked();
+        let _ = btc.rpc().generate_to_address(1, &address)?;
+    }
+    let _ = node2.fund_wallet(100)?;
+    wait!(|| {
+        let funds: response::Utxos = node1.lampod().call("funds", json::json!({})).unwrap();
+        if funds.transactions.len() == 3 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let consolidate: response::Consolidate = node1.lampod().call(
+        "consolidate",
+        request::Consolidate {
+            fee_rate: 1000,
+            max_inputs: None,
+        },
+    )?;
+    let _ = node2.fund_wallet(1)?;
+    wait!(|| {
+        let history: response::TxHistory = node1
+            .lampod()
+            .call("txhistory", request::TxHistory { kind: None })
+            .unwrap();
+        if history
+            .transactions
+            .iter()
+            .any(|tx| tx.txid == consolidate.txid && tx.height.is_some())
+        {
+            return Ok(());
+        }
+        node2.fund_wallet(1).unwrap();
+        Err(())
+    });
+
+    let history: response::TxHistory = node1
+        .lampod()
+        .call("txhistory", request::TxHistory { kind: None })?;
+    assert_eq!(history.transactions.len(), 4, "{:?}", history);
+    let consolidation = history
+        .transactions
+        .iter()
+        .find(|tx| tx.txid == consolidate.txid)
+        .unwrap();
+    assert_eq!(
+        consolidation.kind,
+        TxKind::SelfTransfer,
+        "{:?}",
+        consolidation
+    );
+    assert!(consolidation.fee_sat.is_some_and(|fee| fee > 0));
+    assert_eq!(
+        consolidation.sent_sat,
+        consolidation.received_sat + consolidation.fee_sat.unwrap_or_default()
+    );
+    // The coinbase outputs are received from somebody else.
+    assert!(history
+        .transactions
+        .iter()
+        .filter(|tx| tx.txid != consolidate.txid)
+        .all(|tx| tx.kind == TxKind::Receive));
+
+    let self_transfers: response::TxHistory = node1.lampod().call(
+        "txhistory",
+        request::TxHistory {
+            kind: Some(TxKind::SelfTransfer),
+        },
+    )?;
+    assert_eq!(self_transfers.transactions.len(), 1);
+    assert_eq!(self_transfers.transactions[0].txid, consolidate.txid);
     Ok(())
 }